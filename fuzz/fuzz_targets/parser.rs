@@ -0,0 +1,50 @@
+// cargo +nightly fuzz run parser
+//
+// Feeds arbitrary bytes to the parser in two pieces, split where the first byte says. Besides not
+// panicking or allocating past the limits, it must make the same of the bytes as when they arrive
+// all at once.
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+
+use redis_starter_rust::deser::{ParseError, ProtoLimits, RespParser};
+
+const LIMITS: ProtoLimits = ProtoLimits {
+    max_bulk_len: 4096,
+    max_multibulk_len: 256,
+    max_nesting: 16,
+    max_query_buf: 64 * 1024,
+};
+
+fn parse_pieces(pieces: &[&[u8]]) -> Vec<String> {
+    let mut parser = RespParser::with_limits(LIMITS);
+    let mut buf = BytesMut::new();
+    let mut parsed = Vec::new();
+    for piece in pieces {
+        buf.extend_from_slice(piece);
+        loop {
+            match parser.parse(&mut buf) {
+                Ok((val, _)) => parsed.push(format!("{val:?}")),
+                Err(ParseError::Incomplete) => break,
+                Err(err) => {
+                    parsed.push(err.to_string());
+                    return parsed;
+                }
+            }
+        }
+    }
+    assert!(buf.len() <= LIMITS.max_query_buf);
+    parsed
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((first, _)) = data.split_first() else {
+        return;
+    };
+    let split = *first as usize % (data.len() + 1);
+    assert_eq!(
+        parse_pieces(&[data]),
+        parse_pieces(&[&data[..split], &data[split..]])
+    );
+});
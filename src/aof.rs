@@ -0,0 +1,366 @@
+// Append-only file: every write command is logged in RESP format and replayed at startup.
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Result};
+
+use crate::commands::{
+    parse_cmd, Command, ExpireFlags, ListEnd, SetExpiry, SetFlags, StreamTrim, XAddId, XAddOpts,
+    XClaimOpts, XReadFrom, ZAddFlags,
+};
+use crate::common::Bytes;
+use crate::db_val::DbVal;
+use crate::rdb::RdbEntry;
+use crate::resp::{parse_len, serialize, Value};
+use crate::stream::{PendingEntry, StreamId, TrimStrategy};
+
+// Big collections are rewritten in chunks of this many items per command, as in redis
+const REWRITE_ITEMS_PER_CMD: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    Always,
+    EverySec,
+    No,
+}
+
+impl std::str::FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format_err!("Invalid appendfsync policy: `{s}`")),
+        }
+    }
+}
+
+pub struct Aof {
+    file: File,
+    path: PathBuf,
+    policy: FsyncPolicy,
+    // bytes written since last fsync
+    unsynced: bool,
+    // while a rewrite is in progress, writes are also collected here,
+    // to be appended to the rewritten file before it replaces the current one
+    rewrite_buf: Option<Vec<u8>>,
+}
+
+impl Aof {
+    pub fn open(path: &Path, policy: FsyncPolicy) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format_err!("Could not open aof `{p}`: {e}", p = path.display()))?;
+
+        Ok(Aof {
+            file,
+            path: path.to_path_buf(),
+            policy,
+            unsynced: false,
+            rewrite_buf: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Log a write command. Data always reaches the OS before we return; whether it
+    /// is also fsync'ed to disk depends on the policy.
+    pub fn append(&mut self, cmd: &Command) -> Result<()> {
+        let bytes = serialize(&cmd.to_bulk_array())?;
+        self.file.write_all(bytes.as_bytes())?;
+        if let Some(rewrite_buf) = &mut self.rewrite_buf {
+            rewrite_buf.extend_from_slice(bytes.as_bytes());
+        }
+
+        if self.policy == FsyncPolicy::Always {
+            self.file.sync_data()?;
+        } else {
+            self.unsynced = true;
+        }
+        Ok(())
+    }
+
+    pub fn start_rewrite(&mut self) {
+        self.rewrite_buf = Some(Vec::new());
+    }
+
+    pub fn abort_rewrite(&mut self) {
+        self.rewrite_buf = None;
+    }
+
+    /// Append the writes buffered during the rewrite to the rewritten file at `tmp_path`,
+    /// then atomically move it into place and continue logging to it.
+    pub fn finish_rewrite(&mut self, tmp_path: &Path) -> Result<()> {
+        let buffered = self.rewrite_buf.take().unwrap_or_default();
+
+        let mut file = OpenOptions::new().append(true).open(tmp_path)?;
+        file.write_all(&buffered)?;
+        file.sync_data()?;
+        std::fs::rename(tmp_path, &self.path)?;
+
+        self.file = file;
+        self.unsynced = false;
+        Ok(())
+    }
+
+    /// Called once per second, fsyncs in the background under the `everysec` policy
+    pub fn fsync_if_due(&mut self) {
+        if self.policy != FsyncPolicy::EverySec || !self.unsynced {
+            return;
+        }
+        self.unsynced = false;
+        match self.file.try_clone() {
+            Ok(file) => {
+                tokio::task::spawn_blocking(move || {
+                    file.sync_data()
+                        .unwrap_or_else(|e| println!("Aof: background fsync failed: {e}"))
+                });
+            }
+            Err(e) => println!("Aof: could not clone file handle for fsync: {e}"),
+        }
+    }
+}
+
+/// Write a fresh aof, containing just `cmds`, to `path`
+pub fn write_aof_file(path: &Path, cmds: &[Command]) -> Result<()> {
+    let file = File::create(path)
+        .map_err(|e| format_err!("Could not create `{p}`: {e}", p = path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for cmd in cmds {
+        writer.write_all(serialize(&cmd.to_bulk_array())?.as_bytes())?;
+    }
+    writer.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// XCLAIM that makes pending entry `id` of `group` exactly what `entry` says,
+/// for replicas and rewrites
+pub fn claim_as_is(key: &Bytes, group: &Bytes, id: StreamId, entry: &PendingEntry) -> Command {
+    let opts = XClaimOpts {
+        time: Some(entry.delivery_time),
+        retry_count: Some(entry.delivery_count),
+        force: true,
+        justid: true,
+        ..Default::default()
+    };
+    Command::XClaim(
+        key.clone(),
+        group.clone(),
+        entry.consumer.clone(),
+        0,
+        vec![id],
+        opts,
+    )
+}
+
+/// Commands that recreate `entry` when replayed, expiry included, as an absolute time
+pub fn rewrite_commands(entry: RdbEntry) -> Vec<Command> {
+    let RdbEntry { key, val, expiry } = entry;
+    let mut cmds: Vec<Command> = match val {
+        DbVal::Str(val) => {
+            // the expiry fits in the SET itself
+            let ex = expiry.map(SetExpiry::PxAt);
+            return vec![Command::SetKV(key, val, ex, SetFlags::default())];
+        }
+        DbVal::List(list) => Vec::from(list)
+            .chunks(REWRITE_ITEMS_PER_CMD)
+            .map(|elems| Command::Push(key.clone(), elems.to_vec(), ListEnd::Right))
+            .collect(),
+        DbVal::Hash(hash) => hash
+            .into_iter()
+            .collect::<Vec<_>>()
+            .chunks(REWRITE_ITEMS_PER_CMD)
+            .map(|pairs| Command::HSet(key.clone(), pairs.to_vec()))
+            .collect(),
+        DbVal::Set(set) => set
+            .into_iter()
+            .collect::<Vec<_>>()
+            .chunks(REWRITE_ITEMS_PER_CMD)
+            .map(|members| Command::SAdd(key.clone(), members.to_vec()))
+            .collect(),
+        DbVal::ZSet(zset) => zset
+            .iter()
+            .map(|(member, score)| (score, member.clone()))
+            .collect::<Vec<_>>()
+            .chunks(REWRITE_ITEMS_PER_CMD)
+            .map(|pairs| Command::ZAdd(key.clone(), pairs.to_vec(), ZAddFlags::default()))
+            .collect(),
+        DbVal::Stream(stream) => {
+            let mut cmds: Vec<Command> = stream
+                .iter()
+                .map(|(id, fields)| {
+                    let id = XAddId::Explicit(*id);
+                    Command::XAdd(key.clone(), id, fields.clone(), XAddOpts::default())
+                })
+                .collect();
+            if stream.is_empty() {
+                // XADD can't create an empty stream: add an entry and trim it right away
+                let id = stream.last_id.max(StreamId::new(0, 1));
+                let trim = StreamTrim {
+                    strategy: TrimStrategy::MaxLen(0),
+                    approx: false,
+                    limit: None,
+                };
+                let opts = XAddOpts {
+                    nomkstream: false,
+                    trim: Some(trim),
+                };
+                let fields = vec![("x".into(), "y".into())];
+                cmds.push(Command::XAdd(
+                    key.clone(),
+                    XAddId::Explicit(id),
+                    fields,
+                    opts,
+                ));
+            }
+            // entries may have been trimmed after the last one, its id must not be reused
+            cmds.push(Command::XSetId(
+                key.clone(),
+                stream.last_id,
+                Some(stream.entries_added),
+                Some(stream.max_deleted_id),
+            ));
+            for (name, group) in &stream.groups {
+                cmds.push(Command::XGroupCreate(
+                    key.clone(),
+                    name.clone(),
+                    XReadFrom::After(group.last_id),
+                    false,
+                    group.entries_read,
+                ));
+                for consumer in group.consumers.keys() {
+                    cmds.push(Command::XGroupCreateConsumer(
+                        key.clone(),
+                        name.clone(),
+                        consumer.clone(),
+                    ));
+                }
+                for (id, entry) in &group.pending {
+                    cmds.push(claim_as_is(&key, name, *id, entry));
+                }
+            }
+            cmds
+        }
+    };
+    if let Some(ex) = expiry {
+        cmds.push(Command::ExpireAt(key, ex as i64, ExpireFlags::default()));
+    }
+    cmds
+}
+
+/// Read back all commands in an aof file.
+/// An incomplete command at the very end (e.g. a crash mid-write) is dropped, and so is
+/// a transaction without its EXEC: the file is truncated to what is kept.
+pub fn read_aof_file(path: &Path) -> Result<Vec<Command>> {
+    let data = std::fs::read(path)
+        .map_err(|e| format_err!("Could not read aof `{p}`: {e}", p = path.display()))?;
+
+    let mut rdr = AofReader {
+        data: &data,
+        pos: 0,
+    };
+    let mut cmds = Vec::new();
+    // where the MULTI of a transaction that has no EXEC yet is: its index and byte offset
+    let mut open_multi = None;
+    let mut valid_len = data.len();
+    loop {
+        let start = rdr.pos;
+        if start == data.len() {
+            break;
+        }
+        match rdr.command() {
+            Ok(Some(val)) => {
+                let cmd = parse_cmd(&val)?;
+                match cmd {
+                    Command::Multi => open_multi = Some((cmds.len(), start)),
+                    Command::Exec => open_multi = None,
+                    _ => {}
+                }
+                cmds.push(cmd);
+            }
+            Ok(None) => {
+                println!(
+                    "read_aof_file: dropping truncated command at the end of {p} (byte {start})",
+                    p = path.display()
+                );
+                valid_len = start;
+                break;
+            }
+            Err(e) => {
+                return Err(format_err!(
+                    "Bad aof format in {p} at byte {start}: {e}",
+                    p = path.display()
+                ))
+            }
+        }
+    }
+    // a transaction cut short, e.g. by a crash, is not applied at all
+    if let Some((multi_idx, multi_start)) = open_multi {
+        println!(
+            "read_aof_file: dropping {n} commands of an unfinished transaction at the end of {p} (byte {multi_start})",
+            n = cmds.len() - multi_idx,
+            p = path.display()
+        );
+        cmds.truncate(multi_idx);
+        valid_len = multi_start;
+    }
+    if valid_len < data.len() {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(valid_len as u64)?;
+    }
+    Ok(cmds)
+}
+
+struct AofReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> AofReader<'a> {
+    /// Read one line, without the trailing CRLF. None if the data ends before the CRLF.
+    fn line(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.data[self.pos..];
+        let lf_pos = rest.iter().position(|b| *b == b'\n')?;
+        self.pos += lf_pos + 1;
+        Some(&rest[..lf_pos])
+    }
+
+    /// Commands are arrays of bulk strings, that is all an aof contains.
+    /// Ok(None) means the data ended in the middle of the command.
+    fn command(&mut self) -> Result<Option<Value>> {
+        let Some(header) = self.line() else {
+            return Ok(None);
+        };
+        if header.first() != Some(&b'*') {
+            return Err(format_err!("expected `*`"));
+        }
+        let n_elems = parse_len(&header[1..])?;
+
+        let mut elems = Vec::with_capacity(n_elems.min(1024));
+        for _ in 0..n_elems {
+            let Some(header) = self.line() else {
+                return Ok(None);
+            };
+            if header.first() != Some(&b'$') {
+                return Err(format_err!("expected `$`"));
+            }
+            let len = parse_len(&header[1..])?;
+            if self.data.len() - self.pos < len.saturating_add(2) {
+                return Ok(None);
+            }
+            let bs = &self.data[self.pos..self.pos + len];
+            self.pos += len + 2;
+            elems.push(Value::BulkString(bs.into()));
+        }
+        Ok(Some(Value::Array(elems)))
+    }
+}
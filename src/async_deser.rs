@@ -1,109 +1,112 @@
-use std::future::Future;
-use std::pin::Pin;
+use std::io;
 
-use anyhow::Result;
+use anyhow::{format_err, Result};
+use bytes::BytesMut;
 
-use crate::io_util::debug_peek;
-use crate::misc_util::peer_addr_str_v2;
+use crate::deser::{ParseError, ProtoLimits, RespParser};
 use crate::resp::{parse_len, Value};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt};
-use tokio::{io::BufStream, net::TcpStream};
-
-pub async fn deserialize(bstream: &mut BufStream<TcpStream>) -> Result<(Value, usize)> {
-    let mut deser = RespDeserializer::from_reader(bstream);
-
-    let (val, deser_byte_cnt) = deserialize_v1(&mut deser).await?;
-    Ok((val, deser_byte_cnt))
-}
-
-pub struct RespDeserializer<'a> {
-    bstream: &'a mut BufStream<TcpStream>,
-    #[allow(dead_code)]
-    addr: String,
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// how much to try reading at once, like redis' PROTO_IOBUF_LEN
+const READ_SIZE: usize = 16 * 1024;
+
+/// Reads values off a connection, or just bytes in memory. Bytes read past the end of a value
+/// stay in its buffer for the next one, so there is one per connection, kept for as long as it.
+#[derive(Debug)]
+pub struct RespDeserializer<R> {
+    bstream: R,
+    parser: RespParser,
+    buf: BytesMut,
 }
 
 const LF: u8 = b'\n';
 
-impl<'a> RespDeserializer<'a> {
-    pub fn from_reader(bstream: &'a mut BufStream<TcpStream>) -> Self {
-        let addr = peer_addr_str_v2(bstream);
-        Self { bstream, addr }
+impl<R> RespDeserializer<R> {
+    pub fn new(bstream: R) -> Self {
+        Self::with_limits(bstream, ProtoLimits::default())
     }
 
-    pub async fn deserialize_file(&mut self) -> Result<Value> {
-        let first_byte = self.bstream.read_u8().await?;
+    pub fn with_limits(bstream: R, limits: ProtoLimits) -> Self {
+        Self {
+            bstream,
+            parser: RespParser::with_limits(limits),
+            buf: BytesMut::new(),
+        }
+    }
 
-        let mut bytes: Vec<u8> = Vec::with_capacity(64);
-        assert_eq!(first_byte, b'$');
+    /// The stream, e.g. to write replies to
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.bstream
+    }
 
-        self.bstream.read_until(LF, &mut bytes).await?;
-        let len = parse_len(&bytes)?;
-        println!("Reading FILE of length: {len}");
-        let mut bytes_ = vec![0u8; len];
-        self.bstream.read_exact(bytes_.as_mut_slice()).await?;
-        debug_peek("After reading file: ", self.bstream, 128).await;
+    pub fn get_ref(&self) -> &R {
+        &self.bstream
+    }
 
-        Ok(Value::FileContents(bytes_.into()))
+    /// The same deserializer over `f(stream)`, keeping whatever was read but not parsed yet
+    pub fn map_stream<T>(self, f: impl FnOnce(R) -> T) -> RespDeserializer<T> {
+        RespDeserializer {
+            bstream: f(self.bstream),
+            parser: self.parser,
+            buf: self.buf,
+        }
     }
 }
 
-type DeserPinBox<'a> = Pin<Box<dyn Future<Output = Result<(Value, usize)>> + Send + 'a>>;
-
-pub fn deserialize_v1<'a>(me: &'a mut RespDeserializer) -> DeserPinBox<'a> {
-    let mut bytes: Vec<u8> = Vec::with_capacity(64);
-
-    Box::pin(
-        async {
-            // debug_peek("deserialize starts: ", self.bstream, 128).await;
-            let first_byte = me.bstream.read_u8().await?;
-            // println!("{addr}: first_byte:`{ch}`", addr=self.addr, ch=first_byte as char);
-            let mut deser_byte_cnt = 1;
-            let output = match first_byte {
-                b'+' => {
-                    // SimpleString
-                    deser_byte_cnt += me.bstream.read_until(LF, &mut bytes).await?;
-                    let len = bytes.len() - 2; // leave out "\r\n"
-                    Ok((Value::SimpleString((&bytes[..len]).into()), deser_byte_cnt))
-                }
-                b':' => {
-                    deser_byte_cnt += me.bstream.read_until(LF, &mut bytes).await?;
-                    let i = String::from_utf8(bytes)?.trim_end().parse::<i64>()?;
-                    Ok((Value::Int(i), deser_byte_cnt))
-                }
-                b'$' => {
-                    deser_byte_cnt += me.bstream.read_until(LF, &mut bytes).await?;
-                    let len = parse_len(&bytes)?;
-                    // println!("Reading bulkstring of length: {len}");
+impl<R: AsyncRead + Unpin> RespDeserializer<R> {
+    /// The next value, with the number of bytes it took.
+    /// Cancel safe: if the future is dropped, what was read so far stays for the next call.
+    pub async fn deserialize(&mut self) -> Result<(Value, usize)> {
+        loop {
+            match self.parser.parse(&mut self.buf) {
+                Err(ParseError::Incomplete) => {}
+                res => return Ok(res?),
+            }
+            if !self.read_more().await? {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
 
-                    let mut bytes_ = vec![0u8; len];
-                    // println!("After reading bulkstring: bytes_ has {n}", n=bytes_.len());
-                    deser_byte_cnt += me.bstream.read_exact(bytes_.as_mut_slice()).await?;
-                    // debug_peek("PEEEEKING:", self.bstream, 128).await;
-                    deser_byte_cnt += me.bstream.read_until(LF, &mut bytes).await?;
+    /// Resolves once there is something to parse, or with false once the peer closed.
+    /// Cancel safe too, what is read here is parsed by the next `deserialize`.
+    pub async fn readable(&mut self) -> bool {
+        if !self.buf.is_empty() {
+            return true;
+        }
+        self.read_more().await.unwrap_or(false)
+    }
 
-                    Ok((Value::BulkString(bytes_.into()), deser_byte_cnt))
-                }
-                b'*' => {
-                    deser_byte_cnt += me.bstream.read_until(LF, &mut bytes).await?;
-                    let array_len = parse_len(&bytes)?;
+    /// Append what the stream has to the buffer, false at the end of the stream
+    async fn read_more(&mut self) -> io::Result<bool> {
+        self.buf.reserve(READ_SIZE);
+        Ok(self.bstream.read_buf(&mut self.buf).await? > 0)
+    }
 
-                    let mut elems: Vec<Value> = Vec::new();
-                    for _i in 0..array_len {
-                        // debug_peek(format!("reading array elem: {i}").as_str(), &self.bstream, 16).await;
-                        let (elem, cnt_inc) = deserialize_v1(me).await?;
-                        deser_byte_cnt += cnt_inc;
-                        elems.push(elem);
-                        // println!("elems has: {n}: {elems:?}", n=elems.len());
-                    }
-                    Ok((Value::Array(elems), deser_byte_cnt))
-                }
-                _ => Err(anyhow::format_err!(
-                    "Invalid starting byte = `{first_byte}`"
-                )),
-            }; // match
-               // println!("deser: RESULT  deser_byte_cnt={deser_byte_cnt}");
-            #[allow(clippy::let_and_return)]
-            output
-        }, // async
-    ) // pin
+    /// The rdb file a master sends after +FULLRESYNC: a bulk string without the final CRLF
+    pub async fn deserialize_file(&mut self) -> Result<Value> {
+        let lf = loop {
+            if let Some(lf) = self.buf.iter().position(|byte| *byte == LF) {
+                break lf;
+            }
+            if !self.read_more().await? {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        };
+        let header = self.buf.split_to(lf + 1);
+        if header[0] != b'$' {
+            return Err(format_err!("expected '$' before the file, got: {header:?}"));
+        }
+        let len = parse_len(&header[1..])?;
+        println!("Reading FILE of length: {len}");
+        while self.buf.len() < len {
+            // grown as the bytes arrive, not allocated upfront for whatever length was announced
+            let missing = len - self.buf.len();
+            self.buf.reserve(missing.min(self.buf.len().max(READ_SIZE)));
+            if self.bstream.read_buf(&mut self.buf).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        Ok(Value::FileContents(self.buf.split_to(len).into()))
+    }
 }
@@ -0,0 +1,51 @@
+// Source of the current time for Db: expiries, WAIT timeouts, save timestamps...
+// Tests use a ManualClock to move time forward without sleeping.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::misc_util;
+
+pub trait Clock: Send + Sync {
+    /// Millis since the unix epoch
+    fn now_millis(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        misc_util::now_millis()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test can keep
+/// one and hand another to Db.
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone)]
+pub struct ManualClock {
+    millis: Arc<AtomicU64>,
+}
+
+#[allow(dead_code)]
+impl ManualClock {
+    pub fn new(start_millis: u64) -> Self {
+        ManualClock {
+            millis: Arc::new(AtomicU64::new(start_millis)),
+        }
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
@@ -1,50 +1,470 @@
 use anyhow::{format_err, Result};
 
 use crate::common::Bytes;
-use crate::resp::{Value, Value::*};
+use crate::long_double::LongDouble;
+use crate::pubsub::SubKind;
+use crate::resp::{format_double, Protocol, Value, Value::*};
+use crate::stream::{StreamFields, StreamId, TrimStrategy};
+use crate::zset::{LexBound, ScoreBound};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Command {
     Ping,
     Echo(Bytes),
     Get(Bytes),
-    SetKV(Bytes, Bytes, Option<u64>),
+    SetKV(Bytes, Bytes, Option<SetExpiry>, SetFlags),
     Info(String),
     ReplConf(String, String),
     ReplConfGetAck(String),
     ReplConfAck(i64),
     Psync(String, i64),
     Wait(i64, i64),
-    WaitInternal(i64, i64),
+    WaitInternal(i64, i64, u64), // n_repls, deadline in millis since epoch, offset to reach
+    Save,
+    BgSave,
+    LastSave,
+    BgRewriteAof,
+    Del(Vec<Bytes>),
+    Exists(Vec<Bytes>),
+    Type(Bytes),
+    Keys(Bytes),
+    Rename(Bytes, Bytes),
+    RenameNx(Bytes, Bytes),
+    Copy(Bytes, Bytes, bool),          // src, dst, replace
+    Expire(Bytes, i64, ExpireFlags),   // deadline relative to now, in millis
+    ExpireAt(Bytes, i64, ExpireFlags), // absolute deadline, in millis since epoch
+    Ttl(Bytes),
+    PTtl(Bytes),
+    ExpireTime(Bytes),
+    PExpireTime(Bytes),
+    Persist(Bytes),
+    IncrBy(Bytes, i64),        // also INCR, DECR and DECRBY
+    IncrByFloat(Bytes, Bytes), // increment as given, already checked to be a valid float
+    Append(Bytes, Bytes),
+    Strlen(Bytes),
+    GetRange(Bytes, i64, i64),
+    SetRange(Bytes, usize, Bytes),
+    GetDel(Bytes),
+    GetEx(Bytes, SetExpiry), // KeepTtl when no option is given
+    MGet(Vec<Bytes>),
+    MSet(Vec<(Bytes, Bytes)>),
+    MSetNx(Vec<(Bytes, Bytes)>),
+    Push(Bytes, Vec<Bytes>, ListEnd),      // LPUSH / RPUSH
+    Pop(Bytes, Option<usize>, ListEnd),    // LPOP / RPOP, with the count if one was given
+    BPop(Vec<Bytes>, ListEnd, u64),        // BLPOP / BRPOP, timeout in millis, 0 for none
+    LMove(Bytes, Bytes, ListEnd, ListEnd), // src, dst, end to pop from, end to push to
+    BLMove(Bytes, Bytes, ListEnd, ListEnd, u64),
+    LRange(Bytes, i64, i64),
+    LLen(Bytes),
+    HSet(Bytes, Vec<(Bytes, Bytes)>),
+    HGet(Bytes, Bytes),
+    HGetAll(Bytes),
+    HDel(Bytes, Vec<Bytes>),
+    SAdd(Bytes, Vec<Bytes>),
+    SRem(Bytes, Vec<Bytes>),
+    SMembers(Bytes),
+    SIsMember(Bytes, Bytes),
+    ZAdd(Bytes, Vec<(f64, Bytes)>, ZAddFlags), // (score, member) pairs
+    ZRange(Bytes, ZRangeSpec),
+    ZScore(Bytes, Bytes),
+    ZRem(Bytes, Vec<Bytes>),
+    XAdd(Bytes, XAddId, StreamFields, XAddOpts),
+    XRange(Bytes, StreamId, StreamId, Option<usize>, bool), // start, end, count, rev
+    XRead(Vec<(Bytes, XReadFrom)>, Option<usize>, Option<u64>), // count, block timeout in millis
+    XLen(Bytes),
+    XTrim(Bytes, StreamTrim),
+    XSetId(Bytes, StreamId, Option<u64>, Option<StreamId>), // entries added, max deleted id
+    XGroupCreate(Bytes, Bytes, XReadFrom, bool, Option<u64>), // group, id, mkstream, entries read
+    XGroupSetId(Bytes, Bytes, XReadFrom, Option<u64>),      // group, id, entries read
+    XGroupDestroy(Bytes, Bytes),
+    XGroupCreateConsumer(Bytes, Bytes, Bytes), // group, consumer
+    XGroupDelConsumer(Bytes, Bytes, Bytes),    // group, consumer
+    // group, consumer, streams, count, block timeout in millis, noack
+    XReadGroup(
+        Bytes,
+        Bytes,
+        Vec<(Bytes, XReadGroupFrom)>,
+        Option<usize>,
+        Option<u64>,
+        bool,
+    ),
+    XAck(Bytes, Bytes, Vec<StreamId>),             // group, ids
+    XPending(Bytes, Bytes, Option<XPendingRange>), // group, which entries to list if not a summary
+    // group, consumer, min idle time in millis, ids
+    XClaim(Bytes, Bytes, Bytes, u64, Vec<StreamId>, XClaimOpts),
+    // group, consumer, min idle time in millis, start, count, justid
+    XAutoClaim(Bytes, Bytes, Bytes, u64, StreamId, usize, bool),
+    Subscribe(SubKind, Vec<Bytes>),   // SUBSCRIBE / PSUBSCRIBE
+    Unsubscribe(SubKind, Vec<Bytes>), // UNSUBSCRIBE / PUNSUBSCRIBE, empty for all
+    Publish(Bytes, Bytes),            // channel, message
+    PubSubChannels(Option<Bytes>),    // pattern
+    PubSubNumSub(Vec<Bytes>),
+    PubSubNumPat,
+    Multi,
+    Exec,
+    Discard,
+    Watch(Vec<Bytes>),
+    Unwatch,
+    // protocol to switch to, username and password, client name
+    Hello(Option<Protocol>, Option<(Bytes, Bytes)>, Option<Bytes>),
+}
+
+/// The end of a list that LPUSH, RPOP & co. work on
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    fn keyword(&self) -> &'static str {
+        match self {
+            ListEnd::Left => "LEFT",
+            ListEnd::Right => "RIGHT",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetExpiry {
+    Px(u64),   // relative, in millis (EX is converted to this)
+    PxAt(u64), // absolute, in millis since epoch (EXAT is converted to this)
+    KeepTtl,   // keep the expiry the key already has, if any
+    Persist,   // remove any expiry (GETEX PERSIST)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct SetFlags {
+    pub nx: bool,  // only set if the key does not exist
+    pub xx: bool,  // only set if the key already exists
+    pub get: bool, // reply with the old value instead of OK
+}
+
+/// Conditions of EXPIRE & co. A key without expiry counts as having an infinite ttl for GT / LT.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ExpireFlags {
+    pub nx: bool, // only if the key has no expiry
+    pub xx: bool, // only if the key has an expiry
+    pub gt: bool, // only if the new expiry is later than the current one
+    pub lt: bool, // only if the new expiry is earlier than the current one
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ZAddFlags {
+    pub nx: bool,   // only add new members
+    pub xx: bool,   // only update existing members
+    pub gt: bool,   // only update when the new score is greater
+    pub lt: bool,   // only update when the new score is less
+    pub ch: bool,   // count changed members too, not just added ones
+    pub incr: bool, // increment the score of the single given member, like ZINCRBY
+}
+
+/// What ZRANGE returns: its range and options, with bounds always as (min, max) even for REV
+#[derive(Debug, PartialEq, Clone)]
+pub struct ZRangeSpec {
+    pub by: ZRangeBy,
+    pub rev: bool,
+    pub limit: Option<(i64, i64)>, // offset, count
+    pub with_scores: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ZRangeBy {
+    Index(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// The id given to XADD
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XAddId {
+    Auto,         // `*`
+    AutoSeq(u64), // `ms-*`
+    Explicit(StreamId),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct XAddOpts {
+    pub nomkstream: bool, // don't create the stream if it doesn't exist
+    pub trim: Option<StreamTrim>,
+}
+
+/// MAXLEN / MINID option of XADD and XTRIM
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    pub approx: bool, // `~`: trimming more lazily is allowed. We always trim exactly.
+    pub limit: Option<usize>, // at most this many entries removed, only with `~`
 }
 
-pub fn bad_num_of_arguments_err(cmd: &str, args: &[Value]) -> Result<Command> {
+/// Where XREAD reads a stream from: after an id, or after its last entry at the time of the call
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XReadFrom {
+    After(StreamId),
+    Last, // `$`
+}
+
+/// Where XREADGROUP reads a stream from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XReadGroupFrom {
+    New,               // `>`: entries never delivered to the group
+    Pending(StreamId), // entries after this id that are pending for the consumer
+}
+
+/// The extended form of XPENDING: `[IDLE min-idle-time] start end count [consumer]`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct XPendingRange {
+    pub min_idle: u64, // in millis, 0 without IDLE
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<Bytes>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct XClaimOpts {
+    pub idle: Option<u64>,         // idle time to give claimed entries, in millis
+    pub time: Option<u64>,         // or their delivery time, in millis since epoch
+    pub retry_count: Option<u64>,  // delivery count to give them, instead of adding one
+    pub force: bool,               // add entries to the PEL that are not there yet
+    pub justid: bool,              // reply with ids only, not counted as a delivery
+    pub last_id: Option<StreamId>, // move the group's last delivered id up to this
+}
+
+// Error messages are sent back to clients as they are, so they follow redis' wording
+
+pub fn bad_num_of_arguments_err<T>(cmd: &str) -> Result<T> {
+    Err(format_err!(
+        "ERR wrong number of arguments for '{cmd}' command",
+        cmd = cmd.to_lowercase()
+    ))
+}
+
+pub fn unknown_command_err(cmd: &str, args: &[Value]) -> Result<Command> {
+    let args_str: String = args
+        .iter()
+        .map(|arg| format!("'{a}' ", a = arg.try_to_string().unwrap_or_default()))
+        .collect();
+    let args_str: String = args_str.chars().take(128).collect();
     Err(format_err!(
-        "Invalid number for arguments for `{cmd}`: {n}\nargs={args:?}",
-        n = args.len()
+        "ERR unknown command '{cmd}', with args beginning with: {args_str}"
     ))
 }
 
+pub fn syntax_err() -> anyhow::Error {
+    format_err!("ERR syntax error")
+}
+
+/// Case-insensitive comparison of an argument with an upper case option keyword
+pub fn is_keyword(arg: &Bytes, keyword: &str) -> bool {
+    arg.as_bytes().eq_ignore_ascii_case(keyword.as_bytes())
+}
+
+fn int_arg(val: &Value) -> Result<i64> {
+    val.try_to_int()
+        .map_err(|_| format_err!("ERR value is not an integer or out of range"))
+}
+
+fn bulk_arg(val: &Value) -> Result<Bytes> {
+    match val {
+        BulkString(bs) => Ok(bs.clone()),
+        _ => Err(format_err!("ERR invalid argument: {val:?}")),
+    }
+}
+
+fn string_arg(val: &Value) -> Result<String> {
+    val.try_to_string()
+        .map_err(|_| format_err!("ERR invalid argument: {val:?}"))
+}
+
 impl Command {
+    /// Whether a client subscribed to channels or patterns may run this
+    pub fn allowed_while_subscribed(&self) -> bool {
+        matches!(
+            self,
+            Self::Ping | Self::Subscribe(..) | Self::Unsubscribe(..)
+        )
+    }
+
+    /// Whether the reply may have to wait for other clients, e.g. BLPOP on an empty list
+    pub fn may_block(&self) -> bool {
+        matches!(
+            self,
+            Self::BPop(..)
+                | Self::BLMove(..)
+                | Self::XRead(_, _, Some(_))
+                | Self::XReadGroup(_, _, _, _, Some(_), _)
+                | Self::Wait(..)
+        )
+    }
+
+    /// Keys the command changes, as propagated to replicas and the aof.
+    /// Empty for commands that don't change any key.
+    pub fn written_keys(&self) -> Vec<&Bytes> {
+        match self {
+            Self::SetKV(key, ..)
+            | Self::Expire(key, ..)
+            | Self::ExpireAt(key, ..)
+            | Self::Persist(key)
+            | Self::IncrBy(key, _)
+            | Self::IncrByFloat(key, _)
+            | Self::Append(key, _)
+            | Self::SetRange(key, ..)
+            | Self::GetDel(key)
+            | Self::GetEx(key, _)
+            | Self::Push(key, ..)
+            | Self::Pop(key, ..)
+            | Self::HSet(key, _)
+            | Self::HDel(key, _)
+            | Self::SAdd(key, _)
+            | Self::SRem(key, _)
+            | Self::ZAdd(key, ..)
+            | Self::ZRem(key, _)
+            | Self::XAdd(key, ..)
+            | Self::XTrim(key, _)
+            | Self::XSetId(key, ..)
+            | Self::XGroupCreate(key, ..)
+            | Self::XGroupSetId(key, ..)
+            | Self::XGroupDestroy(key, _)
+            | Self::XGroupCreateConsumer(key, ..)
+            | Self::XGroupDelConsumer(key, ..)
+            | Self::XAck(key, ..)
+            | Self::XClaim(key, ..)
+            | Self::XAutoClaim(key, ..) => vec![key],
+            Self::Copy(_, dst, _) => vec![dst],
+            Self::Rename(src, dst)
+            | Self::RenameNx(src, dst)
+            | Self::LMove(src, dst, ..)
+            | Self::BLMove(src, dst, ..) => vec![src, dst],
+            Self::Del(keys) | Self::BPop(keys, ..) => keys.iter().collect(),
+            Self::MSet(pairs) | Self::MSetNx(pairs) => pairs.iter().map(|(k, _)| k).collect(),
+            Self::XReadGroup(_, _, streams, ..) => streams.iter().map(|(k, _)| k).collect(),
+            Self::Ping
+            | Self::Echo(_)
+            | Self::Get(_)
+            | Self::Info(_)
+            | Self::ReplConf(..)
+            | Self::ReplConfGetAck(_)
+            | Self::ReplConfAck(_)
+            | Self::Psync(..)
+            | Self::Wait(..)
+            | Self::WaitInternal(..)
+            | Self::Save
+            | Self::BgSave
+            | Self::LastSave
+            | Self::BgRewriteAof
+            | Self::Exists(_)
+            | Self::Type(_)
+            | Self::Keys(_)
+            | Self::Ttl(_)
+            | Self::PTtl(_)
+            | Self::ExpireTime(_)
+            | Self::PExpireTime(_)
+            | Self::Strlen(_)
+            | Self::GetRange(..)
+            | Self::MGet(_)
+            | Self::LRange(..)
+            | Self::LLen(_)
+            | Self::HGet(..)
+            | Self::HGetAll(_)
+            | Self::SMembers(_)
+            | Self::SIsMember(..)
+            | Self::ZRange(..)
+            | Self::ZScore(..)
+            | Self::XRange(..)
+            | Self::XRead(..)
+            | Self::XLen(_)
+            | Self::XPending(..)
+            | Self::Subscribe(..)
+            | Self::Unsubscribe(..)
+            | Self::Publish(..)
+            | Self::PubSubChannels(_)
+            | Self::PubSubNumSub(_)
+            | Self::PubSubNumPat
+            | Self::Multi
+            | Self::Exec
+            | Self::Discard
+            | Self::Watch(_)
+            | Self::Unwatch
+            | Self::Hello(..) => vec![],
+        }
+    }
+
     pub fn to_bulk_array(&self) -> Value {
         match self {
             Self::Ping => vec![Value::from("PING")].into(),
             Self::Echo(bs) => vec!["ECHO".into(), bs.into()].into(),
             Self::Get(bs) => vec!["GET".into(), bs.into()].into(),
-            Self::SetKV(kbs, vbs, ex) => match ex {
-                None => vec!["SET".into(), kbs.into(), vbs.into()].into(),
-                Some(ex) => {
-                    let ex_str = format!("{}", ex);
-                    vec![
-                        "SET".into(),
-                        kbs.into(),
-                        vbs.into(),
-                        "px".into(),
-                        ex_str.as_str().into(),
-                    ]
-                    .into()
+            Self::SetKV(kbs, vbs, ex, flags) => {
+                let mut parts: Vec<Value> = vec!["SET".into(), kbs.into(), vbs.into()];
+                match ex {
+                    None => {}
+                    Some(SetExpiry::Px(ms)) => {
+                        parts.extend(["px".into(), ms.to_string().as_str().into()])
+                    }
+                    Some(SetExpiry::PxAt(ms)) => {
+                        parts.extend(["pxat".into(), ms.to_string().as_str().into()])
+                    }
+                    Some(SetExpiry::KeepTtl) => parts.push("keepttl".into()),
+                    // same as no option at all
+                    Some(SetExpiry::Persist) => {}
+                }
+                if flags.nx {
+                    parts.push("nx".into());
                 }
-            },
+                if flags.xx {
+                    parts.push("xx".into());
+                }
+                if flags.get {
+                    parts.push("get".into());
+                }
+                parts.into()
+            }
+            Self::Expire(key, ms, flags) => expire_bulk_array("PEXPIRE", key, *ms, flags),
+            Self::ExpireAt(key, ms, flags) => expire_bulk_array("PEXPIREAT", key, *ms, flags),
+            Self::Ttl(key) => vec!["TTL".into(), key.into()].into(),
+            Self::PTtl(key) => vec!["PTTL".into(), key.into()].into(),
+            Self::ExpireTime(key) => vec!["EXPIRETIME".into(), key.into()].into(),
+            Self::PExpireTime(key) => vec!["PEXPIRETIME".into(), key.into()].into(),
+            Self::Persist(key) => vec!["PERSIST".into(), key.into()].into(),
+            Self::IncrBy(key, by) => {
+                vec!["INCRBY".into(), key.into(), by.to_string().as_str().into()].into()
+            }
+            Self::IncrByFloat(key, by) => vec!["INCRBYFLOAT".into(), key.into(), by.into()].into(),
+            Self::Append(key, val) => vec!["APPEND".into(), key.into(), val.into()].into(),
+            Self::Strlen(key) => vec!["STRLEN".into(), key.into()].into(),
+            Self::GetRange(key, start, end) => vec![
+                "GETRANGE".into(),
+                key.into(),
+                start.to_string().as_str().into(),
+                end.to_string().as_str().into(),
+            ]
+            .into(),
+            Self::SetRange(key, offset, val) => vec![
+                "SETRANGE".into(),
+                key.into(),
+                offset.to_string().as_str().into(),
+                val.into(),
+            ]
+            .into(),
+            Self::GetDel(key) => vec!["GETDEL".into(), key.into()].into(),
+            Self::GetEx(key, ex) => {
+                let mut parts: Vec<Value> = vec!["GETEX".into(), key.into()];
+                match ex {
+                    SetExpiry::Px(ms) => {
+                        parts.extend(["px".into(), ms.to_string().as_str().into()])
+                    }
+                    SetExpiry::PxAt(ms) => {
+                        parts.extend(["pxat".into(), ms.to_string().as_str().into()])
+                    }
+                    SetExpiry::KeepTtl => {}
+                    SetExpiry::Persist => parts.push("persist".into()),
+                }
+                parts.into()
+            }
+            Self::MGet(keys) => cmd_with_keys("MGET", keys),
+            Self::MSet(pairs) => cmd_with_pairs("MSET", pairs),
+            Self::MSetNx(pairs) => cmd_with_pairs("MSETNX", pairs),
             Self::Info(s) => vec!["INFO".into(), s.as_str().into()].into(),
             Self::ReplConf(key, val) => {
                 vec!["REPLCONF".into(), key.as_str().into(), val.as_str().into()].into()
@@ -70,134 +490,1773 @@ impl Command {
                 timeout.to_string().as_str().into(),
             ]
             .into(),
-            Self::WaitInternal(n_repls, timeout) => vec![
+            Self::WaitInternal(n_repls, timeout, offset) => vec![
                 "WAIT_INTERNAL".into(),
                 n_repls.to_string().as_str().into(),
                 timeout.to_string().as_str().into(),
+                offset.to_string().as_str().into(),
+            ]
+            .into(),
+            Self::Save => vec![Value::from("SAVE")].into(),
+            Self::BgSave => vec![Value::from("BGSAVE")].into(),
+            Self::LastSave => vec![Value::from("LASTSAVE")].into(),
+            Self::BgRewriteAof => vec![Value::from("BGREWRITEAOF")].into(),
+            Self::Del(keys) => cmd_with_keys("DEL", keys),
+            Self::Exists(keys) => cmd_with_keys("EXISTS", keys),
+            Self::Type(key) => vec!["TYPE".into(), key.into()].into(),
+            Self::Keys(pattern) => vec!["KEYS".into(), pattern.into()].into(),
+            Self::Rename(src, dst) => vec!["RENAME".into(), src.into(), dst.into()].into(),
+            Self::RenameNx(src, dst) => vec!["RENAMENX".into(), src.into(), dst.into()].into(),
+            Self::Copy(src, dst, replace) => {
+                let mut parts: Vec<Value> = vec!["COPY".into(), src.into(), dst.into()];
+                if *replace {
+                    parts.push("REPLACE".into());
+                }
+                parts.into()
+            }
+            Self::Push(key, elems, end) => {
+                let name = match end {
+                    ListEnd::Left => "LPUSH",
+                    ListEnd::Right => "RPUSH",
+                };
+                cmd_with_key_and_items(name, key, elems)
+            }
+            Self::Pop(key, count, end) => {
+                let name = match end {
+                    ListEnd::Left => "LPOP",
+                    ListEnd::Right => "RPOP",
+                };
+                let mut parts: Vec<Value> = vec![name.into(), key.into()];
+                if let Some(count) = count {
+                    parts.push(count.to_string().as_str().into());
+                }
+                parts.into()
+            }
+            Self::BPop(keys, end, timeout) => {
+                let name = match end {
+                    ListEnd::Left => "BLPOP",
+                    ListEnd::Right => "BRPOP",
+                };
+                let mut parts: Vec<Value> = vec![name.into()];
+                parts.extend(keys.iter().map(Value::from));
+                parts.push(timeout_secs(*timeout).as_str().into());
+                parts.into()
+            }
+            Self::LMove(src, dst, from, to) => vec![
+                "LMOVE".into(),
+                src.into(),
+                dst.into(),
+                from.keyword().into(),
+                to.keyword().into(),
             ]
             .into(),
+            Self::BLMove(src, dst, from, to, timeout) => vec![
+                "BLMOVE".into(),
+                src.into(),
+                dst.into(),
+                from.keyword().into(),
+                to.keyword().into(),
+                timeout_secs(*timeout).as_str().into(),
+            ]
+            .into(),
+            Self::LRange(key, start, stop) => vec![
+                "LRANGE".into(),
+                key.into(),
+                start.to_string().as_str().into(),
+                stop.to_string().as_str().into(),
+            ]
+            .into(),
+            Self::LLen(key) => vec!["LLEN".into(), key.into()].into(),
+            Self::HSet(key, pairs) => {
+                let mut parts: Vec<Value> = vec!["HSET".into(), key.into()];
+                for (field, val) in pairs {
+                    parts.extend([field.into(), val.into()]);
+                }
+                parts.into()
+            }
+            Self::HGet(key, field) => vec!["HGET".into(), key.into(), field.into()].into(),
+            Self::HGetAll(key) => vec!["HGETALL".into(), key.into()].into(),
+            Self::HDel(key, fields) => cmd_with_key_and_items("HDEL", key, fields),
+            Self::SAdd(key, members) => cmd_with_key_and_items("SADD", key, members),
+            Self::SRem(key, members) => cmd_with_key_and_items("SREM", key, members),
+            Self::SMembers(key) => vec!["SMEMBERS".into(), key.into()].into(),
+            Self::SIsMember(key, member) => {
+                vec!["SISMEMBER".into(), key.into(), member.into()].into()
+            }
+            Self::ZAdd(key, pairs, flags) => {
+                let mut parts: Vec<Value> = vec!["ZADD".into(), key.into()];
+                for (is_set, flag) in [
+                    (flags.nx, "NX"),
+                    (flags.xx, "XX"),
+                    (flags.gt, "GT"),
+                    (flags.lt, "LT"),
+                    (flags.ch, "CH"),
+                    (flags.incr, "INCR"),
+                ] {
+                    if is_set {
+                        parts.push(flag.into());
+                    }
+                }
+                for (score, member) in pairs {
+                    parts.extend([format_double(*score).as_str().into(), member.into()]);
+                }
+                parts.into()
+            }
+            Self::ZRange(key, spec) => zrange_bulk_array(key, spec),
+            Self::ZScore(key, member) => vec!["ZSCORE".into(), key.into(), member.into()].into(),
+            Self::ZRem(key, members) => cmd_with_key_and_items("ZREM", key, members),
+            Self::XAdd(key, id, fields, opts) => {
+                let mut parts: Vec<Value> = vec!["XADD".into(), key.into()];
+                if opts.nomkstream {
+                    parts.push("NOMKSTREAM".into());
+                }
+                if let Some(trim) = &opts.trim {
+                    parts.extend(trim_args(trim));
+                }
+                let id = match id {
+                    XAddId::Auto => "*".to_string(),
+                    XAddId::AutoSeq(ms) => format!("{ms}-*"),
+                    XAddId::Explicit(id) => id.to_string(),
+                };
+                parts.push(id.as_str().into());
+                for (field, val) in fields {
+                    parts.extend([field.into(), val.into()]);
+                }
+                parts.into()
+            }
+            Self::XRange(key, start, end, count, rev) => {
+                let mut parts: Vec<Value> = if *rev {
+                    vec![
+                        "XREVRANGE".into(),
+                        key.into(),
+                        end.to_string().as_str().into(),
+                    ]
+                } else {
+                    vec![
+                        "XRANGE".into(),
+                        key.into(),
+                        start.to_string().as_str().into(),
+                    ]
+                };
+                let other_end = if *rev { start } else { end };
+                parts.push(other_end.to_string().as_str().into());
+                if let Some(count) = count {
+                    parts.extend(["COUNT".into(), count.to_string().as_str().into()]);
+                }
+                parts.into()
+            }
+            Self::XRead(streams, count, block) => {
+                let mut parts: Vec<Value> = vec!["XREAD".into()];
+                if let Some(count) = count {
+                    parts.extend(["COUNT".into(), count.to_string().as_str().into()]);
+                }
+                if let Some(block) = block {
+                    parts.extend(["BLOCK".into(), block.to_string().as_str().into()]);
+                }
+                parts.push("STREAMS".into());
+                parts.extend(streams.iter().map(|(key, _)| Value::from(key)));
+                parts.extend(streams.iter().map(|(_, from)| xread_from_arg(from)));
+                parts.into()
+            }
+            Self::XLen(key) => vec!["XLEN".into(), key.into()].into(),
+            Self::XTrim(key, trim) => {
+                let mut parts: Vec<Value> = vec!["XTRIM".into(), key.into()];
+                parts.extend(trim_args(trim));
+                parts.into()
+            }
+            Self::XSetId(key, last_id, entries_added, max_deleted_id) => {
+                let mut parts: Vec<Value> = vec![
+                    "XSETID".into(),
+                    key.into(),
+                    last_id.to_string().as_str().into(),
+                ];
+                if let Some(entries_added) = entries_added {
+                    parts.extend([
+                        "ENTRIESADDED".into(),
+                        entries_added.to_string().as_str().into(),
+                    ]);
+                }
+                if let Some(max_deleted_id) = max_deleted_id {
+                    parts.extend([
+                        "MAXDELETEDID".into(),
+                        max_deleted_id.to_string().as_str().into(),
+                    ]);
+                }
+                parts.into()
+            }
+            Self::XGroupCreate(key, group, id, mkstream, entries_read) => {
+                let mut parts: Vec<Value> = vec![
+                    "XGROUP".into(),
+                    "CREATE".into(),
+                    key.into(),
+                    group.into(),
+                    xread_from_arg(id),
+                ];
+                if *mkstream {
+                    parts.push("MKSTREAM".into());
+                }
+                parts.extend(entries_read_args(*entries_read));
+                parts.into()
+            }
+            Self::XGroupSetId(key, group, id, entries_read) => {
+                let mut parts: Vec<Value> = vec![
+                    "XGROUP".into(),
+                    "SETID".into(),
+                    key.into(),
+                    group.into(),
+                    xread_from_arg(id),
+                ];
+                parts.extend(entries_read_args(*entries_read));
+                parts.into()
+            }
+            Self::XGroupDestroy(key, group) => {
+                vec!["XGROUP".into(), "DESTROY".into(), key.into(), group.into()].into()
+            }
+            Self::XGroupCreateConsumer(key, group, consumer) => vec![
+                "XGROUP".into(),
+                "CREATECONSUMER".into(),
+                key.into(),
+                group.into(),
+                consumer.into(),
+            ]
+            .into(),
+            Self::XGroupDelConsumer(key, group, consumer) => vec![
+                "XGROUP".into(),
+                "DELCONSUMER".into(),
+                key.into(),
+                group.into(),
+                consumer.into(),
+            ]
+            .into(),
+            Self::XReadGroup(group, consumer, streams, count, block, noack) => {
+                let mut parts: Vec<Value> = vec![
+                    "XREADGROUP".into(),
+                    "GROUP".into(),
+                    group.into(),
+                    consumer.into(),
+                ];
+                if let Some(count) = count {
+                    parts.extend(["COUNT".into(), count.to_string().as_str().into()]);
+                }
+                if let Some(block) = block {
+                    parts.extend(["BLOCK".into(), block.to_string().as_str().into()]);
+                }
+                if *noack {
+                    parts.push("NOACK".into());
+                }
+                parts.push("STREAMS".into());
+                parts.extend(streams.iter().map(|(key, _)| Value::from(key)));
+                for (_, from) in streams {
+                    let from = match from {
+                        XReadGroupFrom::New => ">".to_string(),
+                        XReadGroupFrom::Pending(id) => id.to_string(),
+                    };
+                    parts.push(from.as_str().into());
+                }
+                parts.into()
+            }
+            Self::XAck(key, group, ids) => {
+                let mut parts: Vec<Value> = vec!["XACK".into(), key.into(), group.into()];
+                parts.extend(ids.iter().map(|id| Value::from(id.to_string().as_str())));
+                parts.into()
+            }
+            Self::XPending(key, group, range) => {
+                let mut parts: Vec<Value> = vec!["XPENDING".into(), key.into(), group.into()];
+                if let Some(range) = range {
+                    if range.min_idle > 0 {
+                        parts.extend(["IDLE".into(), range.min_idle.to_string().as_str().into()]);
+                    }
+                    parts.extend([
+                        range.start.to_string().as_str().into(),
+                        range.end.to_string().as_str().into(),
+                        range.count.to_string().as_str().into(),
+                    ]);
+                    if let Some(consumer) = &range.consumer {
+                        parts.push(consumer.into());
+                    }
+                }
+                parts.into()
+            }
+            Self::XClaim(key, group, consumer, min_idle, ids, opts) => {
+                let mut parts: Vec<Value> = vec![
+                    "XCLAIM".into(),
+                    key.into(),
+                    group.into(),
+                    consumer.into(),
+                    min_idle.to_string().as_str().into(),
+                ];
+                parts.extend(ids.iter().map(|id| Value::from(id.to_string().as_str())));
+                for (opt, val) in [
+                    ("IDLE", opts.idle),
+                    ("TIME", opts.time),
+                    ("RETRYCOUNT", opts.retry_count),
+                ] {
+                    if let Some(val) = val {
+                        parts.extend([opt.into(), val.to_string().as_str().into()]);
+                    }
+                }
+                if opts.force {
+                    parts.push("FORCE".into());
+                }
+                if opts.justid {
+                    parts.push("JUSTID".into());
+                }
+                if let Some(last_id) = opts.last_id {
+                    parts.extend(["LASTID".into(), last_id.to_string().as_str().into()]);
+                }
+                parts.into()
+            }
+            Self::XAutoClaim(key, group, consumer, min_idle, start, count, justid) => {
+                let mut parts: Vec<Value> = vec![
+                    "XAUTOCLAIM".into(),
+                    key.into(),
+                    group.into(),
+                    consumer.into(),
+                    min_idle.to_string().as_str().into(),
+                    start.to_string().as_str().into(),
+                    "COUNT".into(),
+                    count.to_string().as_str().into(),
+                ];
+                if *justid {
+                    parts.push("JUSTID".into());
+                }
+                parts.into()
+            }
+            Self::Subscribe(kind, names) => {
+                let word = match kind {
+                    SubKind::Channel => "SUBSCRIBE",
+                    SubKind::Pattern => "PSUBSCRIBE",
+                };
+                let mut parts: Vec<Value> = vec![word.into()];
+                parts.extend(names.iter().map(Value::from));
+                parts.into()
+            }
+            Self::Unsubscribe(kind, names) => {
+                let word = match kind {
+                    SubKind::Channel => "UNSUBSCRIBE",
+                    SubKind::Pattern => "PUNSUBSCRIBE",
+                };
+                let mut parts: Vec<Value> = vec![word.into()];
+                parts.extend(names.iter().map(Value::from));
+                parts.into()
+            }
+            Self::Publish(channel, message) => {
+                vec!["PUBLISH".into(), channel.into(), message.into()].into()
+            }
+            Self::PubSubChannels(pattern) => {
+                let mut parts: Vec<Value> = vec!["PUBSUB".into(), "CHANNELS".into()];
+                parts.extend(pattern.iter().map(Value::from));
+                parts.into()
+            }
+            Self::PubSubNumSub(channels) => {
+                let mut parts: Vec<Value> = vec!["PUBSUB".into(), "NUMSUB".into()];
+                parts.extend(channels.iter().map(Value::from));
+                parts.into()
+            }
+            Self::PubSubNumPat => vec!["PUBSUB".into(), "NUMPAT".into()].into(),
+            Self::Multi => vec![Value::from("MULTI")].into(),
+            Self::Exec => vec![Value::from("EXEC")].into(),
+            Self::Discard => vec![Value::from("DISCARD")].into(),
+            Self::Watch(keys) => {
+                let mut parts: Vec<Value> = vec!["WATCH".into()];
+                parts.extend(keys.iter().map(Value::from));
+                parts.into()
+            }
+            Self::Unwatch => vec![Value::from("UNWATCH")].into(),
+            Self::Hello(protocol, auth, name) => {
+                let mut parts: Vec<Value> = vec!["HELLO".into()];
+                if let Some(protocol) = protocol {
+                    parts.push(protocol.version().to_string().as_str().into());
+                }
+                if let Some((username, password)) = auth {
+                    parts.extend(["AUTH".into(), username.into(), password.into()]);
+                }
+                if let Some(name) = name {
+                    parts.extend(["SETNAME".into(), name.into()]);
+                }
+                parts.into()
+            }
         }
     }
 }
 
+/// `$` or an id, as XGROUP takes them
+fn xread_from_arg(from: &XReadFrom) -> Value {
+    match from {
+        XReadFrom::After(id) => id.to_string().as_str().into(),
+        XReadFrom::Last => "$".into(),
+    }
+}
+
+fn entries_read_args(entries_read: Option<u64>) -> Vec<Value> {
+    match entries_read {
+        Some(n) => vec!["ENTRIESREAD".into(), n.to_string().as_str().into()],
+        None => vec![],
+    }
+}
+
+/// Timeouts of blocking commands are given in seconds
+fn timeout_secs(millis: u64) -> String {
+    (millis as f64 / 1000.0).to_string()
+}
+
+fn cmd_with_key_and_items(name: &str, key: &Bytes, items: &[Bytes]) -> Value {
+    let mut parts: Vec<Value> = vec![name.into(), key.into()];
+    parts.extend(items.iter().map(Value::from));
+    parts.into()
+}
+
+fn zrange_bulk_array(key: &Bytes, spec: &ZRangeSpec) -> Value {
+    let mut parts: Vec<Value> = vec!["ZRANGE".into(), key.into()];
+    let (min, max, by) = match &spec.by {
+        ZRangeBy::Index(start, stop) => (start.to_string(), stop.to_string(), None),
+        ZRangeBy::Score(min, max) => (min.to_string(), max.to_string(), Some("BYSCORE")),
+        ZRangeBy::Lex(min, max) => (min.to_string(), max.to_string(), Some("BYLEX")),
+    };
+    // REV takes the bounds the other way around, except for plain indices
+    match (spec.rev, by) {
+        (true, Some(_)) => parts.extend([max.as_str().into(), min.as_str().into()]),
+        _ => parts.extend([min.as_str().into(), max.as_str().into()]),
+    }
+    if let Some(by) = by {
+        parts.push(by.into());
+    }
+    if spec.rev {
+        parts.push("REV".into());
+    }
+    if let Some((offset, count)) = spec.limit {
+        parts.extend([
+            "LIMIT".into(),
+            offset.to_string().as_str().into(),
+            count.to_string().as_str().into(),
+        ]);
+    }
+    if spec.with_scores {
+        parts.push("WITHSCORES".into());
+    }
+    parts.into()
+}
+
+fn trim_args(trim: &StreamTrim) -> Vec<Value> {
+    let (strategy, threshold) = match trim.strategy {
+        TrimStrategy::MaxLen(max_len) => ("MAXLEN", max_len.to_string()),
+        TrimStrategy::MinId(min_id) => ("MINID", min_id.to_string()),
+    };
+    let mut parts: Vec<Value> = vec![strategy.into()];
+    if trim.approx {
+        parts.push("~".into());
+    }
+    parts.push(threshold.as_str().into());
+    if let Some(limit) = trim.limit {
+        parts.extend(["LIMIT".into(), limit.to_string().as_str().into()]);
+    }
+    parts
+}
+
+fn cmd_with_keys(name: &str, keys: &[Bytes]) -> Value {
+    let mut parts: Vec<Value> = vec![name.into()];
+    parts.extend(keys.iter().map(Value::from));
+    parts.into()
+}
+
+fn cmd_with_pairs(name: &str, pairs: &[(Bytes, Bytes)]) -> Value {
+    let mut parts: Vec<Value> = vec![name.into()];
+    for (key, val) in pairs {
+        parts.extend([key.into(), val.into()]);
+    }
+    parts.into()
+}
+
+fn expire_bulk_array(name: &str, key: &Bytes, ms: i64, flags: &ExpireFlags) -> Value {
+    let mut parts: Vec<Value> = vec![name.into(), key.into(), ms.to_string().as_str().into()];
+    for (is_set, flag) in [
+        (flags.nx, "NX"),
+        (flags.xx, "XX"),
+        (flags.gt, "GT"),
+        (flags.lt, "LT"),
+    ] {
+        if is_set {
+            parts.push(flag.into());
+        }
+    }
+    parts.into()
+}
+
 pub fn parse_cmd(val: &Value) -> Result<Command> {
     use Value::*;
 
     match val {
         Array(elems) if !elems.is_empty() => {
             if let BulkString(bs) = &elems[0] {
-                let word0 = bs.to_string()?;
+                let word0 = String::from_utf8_lossy(bs.as_bytes()).to_string();
                 let args = &elems[1..];
-                match word0.as_str() {
+                match word0.to_uppercase().as_str() {
                     "PING" => Ok(Command::Ping),
                     "ECHO" => parse_echo(args),
                     "GET" => parse_get(args),
                     "INFO" => parse_info(args),
                     "SET" => parse_set(args),
                     "REPLCONF" => {
-                        let arg0 = args[0].try_to_string()?;
-                        let arg1 = args[1].try_to_string()?;
-
-                        Ok(if arg0 == "GETACK" {
-                            Command::ReplConfGetAck(arg1)
-                        } else if arg0 == "ACK" {
-                            Command::ReplConfAck(args[1].try_to_int()?)
-                        } else {
-                            Command::ReplConf(arg0, arg1)
+                        if args.len() < 2 {
+                            return bad_num_of_arguments_err(&word0);
+                        }
+                        let arg0 = string_arg(&args[0])?;
+                        let arg1 = string_arg(&args[1])?;
+
+                        Ok(match arg0.to_uppercase().as_str() {
+                            "GETACK" => Command::ReplConfGetAck(arg1),
+                            "ACK" => Command::ReplConfAck(int_arg(&args[1])?),
+                            _ => Command::ReplConf(arg0.to_lowercase(), arg1),
                         })
                     }
                     "PSYNC" => parse_psync(args),
                     "WAIT" => {
                         if args.len() != 2 {
-                            return Err(format_err!(
-                                "Invalid number of arguments for WAIT {n}, {args:?}",
-                                n = args.len()
-                            ));
+                            return bad_num_of_arguments_err(&word0);
                         }
-                        Ok(Command::Wait(args[0].try_to_int()?, args[1].try_to_int()?))
+                        Ok(Command::Wait(int_arg(&args[0])?, int_arg(&args[1])?))
+                    }
+                    "SAVE" => parse_no_args(&word0, args, Command::Save),
+                    "BGSAVE" => parse_no_args(&word0, args, Command::BgSave),
+                    "LASTSAVE" => parse_no_args(&word0, args, Command::LastSave),
+                    "BGREWRITEAOF" => parse_no_args(&word0, args, Command::BgRewriteAof),
+                    "DEL" => parse_keys(&word0, args).map(Command::Del),
+                    "EXISTS" => parse_keys(&word0, args).map(Command::Exists),
+                    "TYPE" => parse_one_arg(&word0, args).map(Command::Type),
+                    "KEYS" => parse_one_arg(&word0, args).map(Command::Keys),
+                    "RENAME" => parse_two_args(&word0, args).map(|(s, d)| Command::Rename(s, d)),
+                    "RENAMENX" => {
+                        parse_two_args(&word0, args).map(|(s, d)| Command::RenameNx(s, d))
                     }
-                    _ => {
-                        panic!("Don't know about command: `{word0}`")
+                    "COPY" => parse_copy(args),
+                    "EXPIRE" => {
+                        parse_expire(&word0, args, 1000).map(|(k, t, f)| Command::Expire(k, t, f))
                     }
+                    "PEXPIRE" => {
+                        parse_expire(&word0, args, 1).map(|(k, t, f)| Command::Expire(k, t, f))
+                    }
+                    "EXPIREAT" => {
+                        parse_expire(&word0, args, 1000).map(|(k, t, f)| Command::ExpireAt(k, t, f))
+                    }
+                    "PEXPIREAT" => {
+                        parse_expire(&word0, args, 1).map(|(k, t, f)| Command::ExpireAt(k, t, f))
+                    }
+                    "TTL" => parse_one_arg(&word0, args).map(Command::Ttl),
+                    "PTTL" => parse_one_arg(&word0, args).map(Command::PTtl),
+                    "EXPIRETIME" => parse_one_arg(&word0, args).map(Command::ExpireTime),
+                    "PEXPIRETIME" => parse_one_arg(&word0, args).map(Command::PExpireTime),
+                    "PERSIST" => parse_one_arg(&word0, args).map(Command::Persist),
+                    "INCR" => parse_one_arg(&word0, args).map(|k| Command::IncrBy(k, 1)),
+                    "DECR" => parse_one_arg(&word0, args).map(|k| Command::IncrBy(k, -1)),
+                    "INCRBY" => parse_incr_by(&word0, args, false),
+                    "DECRBY" => parse_incr_by(&word0, args, true),
+                    "INCRBYFLOAT" => parse_incr_by_float(args),
+                    "APPEND" => parse_two_args(&word0, args).map(|(k, v)| Command::Append(k, v)),
+                    "STRLEN" => parse_one_arg(&word0, args).map(Command::Strlen),
+                    "GETRANGE" => parse_getrange(args),
+                    "SETRANGE" => parse_setrange(args),
+                    "GETDEL" => parse_one_arg(&word0, args).map(Command::GetDel),
+                    "GETEX" => parse_getex(args),
+                    "MGET" => parse_keys(&word0, args).map(Command::MGet),
+                    "MSET" => parse_pairs(&word0, args).map(Command::MSet),
+                    "MSETNX" => parse_pairs(&word0, args).map(Command::MSetNx),
+                    "LPUSH" => parse_key_and_items(&word0, args)
+                        .map(|(k, elems)| Command::Push(k, elems, ListEnd::Left)),
+                    "RPUSH" => parse_key_and_items(&word0, args)
+                        .map(|(k, elems)| Command::Push(k, elems, ListEnd::Right)),
+                    "LPOP" => parse_pop(&word0, args, ListEnd::Left),
+                    "RPOP" => parse_pop(&word0, args, ListEnd::Right),
+                    "BLPOP" => parse_bpop(&word0, args, ListEnd::Left),
+                    "BRPOP" => parse_bpop(&word0, args, ListEnd::Right),
+                    "LMOVE" => parse_lmove(args),
+                    "BLMOVE" => parse_blmove(args),
+                    "LRANGE" => parse_lrange(args),
+                    "LLEN" => parse_one_arg(&word0, args).map(Command::LLen),
+                    "HSET" => parse_hset(args),
+                    "HGET" => parse_two_args(&word0, args).map(|(k, f)| Command::HGet(k, f)),
+                    "HGETALL" => parse_one_arg(&word0, args).map(Command::HGetAll),
+                    "HDEL" => parse_key_and_items(&word0, args).map(|(k, fs)| Command::HDel(k, fs)),
+                    "SADD" => parse_key_and_items(&word0, args).map(|(k, ms)| Command::SAdd(k, ms)),
+                    "SREM" => parse_key_and_items(&word0, args).map(|(k, ms)| Command::SRem(k, ms)),
+                    "SMEMBERS" => parse_one_arg(&word0, args).map(Command::SMembers),
+                    "SISMEMBER" => {
+                        parse_two_args(&word0, args).map(|(k, m)| Command::SIsMember(k, m))
+                    }
+                    "ZADD" => parse_zadd(args),
+                    "ZRANGE" => parse_zrange(args),
+                    "ZSCORE" => parse_two_args(&word0, args).map(|(k, m)| Command::ZScore(k, m)),
+                    "ZREM" => parse_key_and_items(&word0, args).map(|(k, ms)| Command::ZRem(k, ms)),
+                    "XADD" => parse_xadd(args),
+                    "XRANGE" => parse_xrange(&word0, args, false),
+                    "XREVRANGE" => parse_xrange(&word0, args, true),
+                    "XREAD" => parse_xread(&word0, args, false),
+                    "XREADGROUP" => parse_xread(&word0, args, true),
+                    "XLEN" => parse_one_arg(&word0, args).map(Command::XLen),
+                    "XTRIM" => parse_xtrim(args),
+                    "XSETID" => parse_xsetid(args),
+                    "XGROUP" => parse_xgroup(args),
+                    "XACK" => parse_xack(args),
+                    "XPENDING" => parse_xpending(args),
+                    "XCLAIM" => parse_xclaim(args),
+                    "XAUTOCLAIM" => parse_xautoclaim(args),
+                    "SUBSCRIBE" => parse_keys(&word0, args)
+                        .map(|chs| Command::Subscribe(SubKind::Channel, chs)),
+                    "PSUBSCRIBE" => parse_keys(&word0, args)
+                        .map(|pats| Command::Subscribe(SubKind::Pattern, pats)),
+                    "UNSUBSCRIBE" => args
+                        .iter()
+                        .map(bulk_arg)
+                        .collect::<Result<_>>()
+                        .map(|chs| Command::Unsubscribe(SubKind::Channel, chs)),
+                    "PUNSUBSCRIBE" => args
+                        .iter()
+                        .map(bulk_arg)
+                        .collect::<Result<_>>()
+                        .map(|pats| Command::Unsubscribe(SubKind::Pattern, pats)),
+                    "PUBLISH" => parse_two_args(&word0, args).map(|(c, m)| Command::Publish(c, m)),
+                    "PUBSUB" => parse_pubsub(args),
+                    "MULTI" => parse_no_args(&word0, args, Command::Multi),
+                    "EXEC" => parse_no_args(&word0, args, Command::Exec),
+                    "DISCARD" => parse_no_args(&word0, args, Command::Discard),
+                    "WATCH" => parse_keys(&word0, args).map(Command::Watch),
+                    "UNWATCH" => parse_no_args(&word0, args, Command::Unwatch),
+                    "HELLO" => parse_hello(args),
+                    _ => unknown_command_err(&word0, args),
                 }
             } else {
-                Err(format_err!("Unexpected elems[0]={e:?}", e = elems[0]))
+                Err(format_err!(
+                    "ERR Protocol error: expected command name, got {e:?}",
+                    e = elems[0]
+                ))
             }
         }
         SimpleString(v) => {
-            let word0 = String::from_utf8(v.as_vec().clone())?;
-            match word0.as_str() {
+            let word0 = String::from_utf8_lossy(v.as_bytes()).to_string();
+            match word0.to_uppercase().as_str() {
                 "PING" => Ok(Command::Ping),
-                _ => Err(format_err!("Invalid SimpleString={word0}")),
+                _ => unknown_command_err(&word0, &[]),
             }
         }
-        _ => Err(format_err!("Could not parse command from value = {val:?}")),
+        _ => Err(format_err!(
+            "ERR Protocol error: could not parse command from {val:?}"
+        )),
+    }
+}
+
+fn parse_no_args(cmd_name: &str, args: &[Value], cmd: Command) -> Result<Command> {
+    if !args.is_empty() {
+        bad_num_of_arguments_err(cmd_name)
+    } else {
+        Ok(cmd)
     }
 }
 
+fn parse_one_arg(cmd_name: &str, args: &[Value]) -> Result<Bytes> {
+    match args {
+        [arg] => bulk_arg(arg),
+        _ => bad_num_of_arguments_err(cmd_name),
+    }
+}
+
+fn parse_two_args(cmd_name: &str, args: &[Value]) -> Result<(Bytes, Bytes)> {
+    match args {
+        [arg0, arg1] => Ok((bulk_arg(arg0)?, bulk_arg(arg1)?)),
+        _ => bad_num_of_arguments_err(cmd_name),
+    }
+}
+
+/// One or more keys
+fn parse_keys(cmd_name: &str, args: &[Value]) -> Result<Vec<Bytes>> {
+    if args.is_empty() {
+        return bad_num_of_arguments_err(cmd_name);
+    }
+    args.iter().map(bulk_arg).collect()
+}
+
+/// A key followed by one or more elements, fields or members
+fn parse_key_and_items(cmd_name: &str, args: &[Value]) -> Result<(Bytes, Vec<Bytes>)> {
+    let Some((key, items)) = args.split_first() else {
+        return bad_num_of_arguments_err(cmd_name);
+    };
+    Ok((bulk_arg(key)?, parse_keys(cmd_name, items)?))
+}
+
 fn parse_echo(args: &[Value]) -> Result<Command> {
     if args.len() != 1 {
-        bad_num_of_arguments_err("ECHO", args)
+        bad_num_of_arguments_err("ECHO")
     } else {
         match &args[0] {
             BulkString(bs) => Ok(Command::Echo(bs.clone())),
-            _ => Err(format_err!("Invalid argument for ECHO {e:?}", e = args[0])),
+            _ => Err(format_err!(
+                "ERR invalid argument for 'echo': {e:?}",
+                e = args[0]
+            )),
         }
     }
 }
 
 fn parse_get(args: &[Value]) -> Result<Command> {
     if args.len() != 1 {
-        bad_num_of_arguments_err("GET", args)
+        bad_num_of_arguments_err("GET")
     } else {
         match &args[0] {
             Value::BulkString(bs) => Ok(Command::Get(bs.clone())),
-            _ => Err(format_err!("Invalid argument for GET: {e:?}", e = args[1])),
+            _ => Err(format_err!(
+                "ERR invalid argument for 'get': {e:?}",
+                e = args[0]
+            )),
         }
     }
 }
 
 fn parse_set(args: &[Value]) -> Result<Command> {
-    match args.len() {
-        2 => {
-            if let (BulkString(k), BulkString(v)) = (&args[0], &args[1]) {
-                Ok(Command::SetKV(k.clone(), v.clone(), None))
-            } else {
-                Err(format_err!(
-                    "Expected two bulkstrings as arguments for Set: {args:?}"
-                ))
+    if args.len() < 2 {
+        return bad_num_of_arguments_err("SET");
+    }
+    let (BulkString(k), BulkString(v)) = (&args[0], &args[1]) else {
+        return Err(syntax_err());
+    };
+
+    let mut ex: Option<SetExpiry> = None;
+    let mut flags = SetFlags::default();
+    let mut opts = args[2..].iter();
+    while let Some(opt) = opts.next() {
+        let BulkString(opt) = opt else {
+            return Err(syntax_err());
+        };
+        if is_keyword(opt, "NX") && !flags.xx {
+            flags.nx = true;
+        } else if is_keyword(opt, "XX") && !flags.nx {
+            flags.xx = true;
+        } else if is_keyword(opt, "GET") {
+            flags.get = true;
+        } else if is_keyword(opt, "KEEPTTL") && ex.is_none() {
+            ex = Some(SetExpiry::KeepTtl);
+        } else if ex.is_none() {
+            ex = Some(expiry_opt("SET", opt, &mut opts)?.ok_or_else(syntax_err)?);
+        } else {
+            return Err(syntax_err());
+        }
+    }
+
+    Ok(Command::SetKV(k.clone(), v.clone(), ex, flags))
+}
+
+fn parse_copy(args: &[Value]) -> Result<Command> {
+    if args.len() < 2 {
+        return bad_num_of_arguments_err("COPY");
+    }
+    let (src, dst) = (bulk_arg(&args[0])?, bulk_arg(&args[1])?);
+
+    let mut replace = false;
+    let mut opts = args[2..].iter();
+    while let Some(opt) = opts.next() {
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "REPLACE") {
+            replace = true;
+        } else if is_keyword(&opt, "DB") {
+            // there is only one database
+            if int_arg(opts.next().ok_or_else(syntax_err)?)? != 0 {
+                return Err(format_err!("ERR DB index is out of range"));
             }
+        } else {
+            return Err(syntax_err());
+        }
+    }
+    Ok(Command::Copy(src, dst, replace))
+}
+
+/// `key time [NX | XX | GT | LT]...`, with time converted to millis
+fn parse_expire(cmd_name: &str, args: &[Value], unit_ms: i64) -> Result<(Bytes, i64, ExpireFlags)> {
+    if args.len() < 2 {
+        return bad_num_of_arguments_err(cmd_name);
+    }
+    let key = bulk_arg(&args[0])?;
+    let time_ms = int_arg(&args[1])?.checked_mul(unit_ms).ok_or_else(|| {
+        format_err!(
+            "ERR invalid expire time in '{cmd}' command",
+            cmd = cmd_name.to_lowercase()
+        )
+    })?;
+
+    let mut flags = ExpireFlags::default();
+    for opt in &args[2..] {
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "NX") {
+            flags.nx = true;
+        } else if is_keyword(&opt, "XX") {
+            flags.xx = true;
+        } else if is_keyword(&opt, "GT") {
+            flags.gt = true;
+        } else if is_keyword(&opt, "LT") {
+            flags.lt = true;
+        } else {
+            return Err(format_err!(
+                "ERR Unsupported option {opt}",
+                opt = String::from_utf8_lossy(opt.as_bytes())
+            ));
+        }
+    }
+    if flags.nx && (flags.xx || flags.gt || flags.lt) {
+        return Err(format_err!(
+            "ERR NX and XX, GT or LT options at the same time are not compatible"
+        ));
+    }
+    if flags.gt && flags.lt {
+        return Err(format_err!(
+            "ERR GT and LT options at the same time are not compatible"
+        ));
+    }
+    Ok((key, time_ms, flags))
+}
+
+/// EX / PX / EXAT / PXAT followed by their time argument, which is converted to millis.
+/// None if `opt` is none of these.
+fn expiry_opt<'a>(
+    cmd_name: &str,
+    opt: &Bytes,
+    rest: &mut impl Iterator<Item = &'a Value>,
+) -> Result<Option<SetExpiry>> {
+    let (unit_ms, absolute) = if is_keyword(opt, "EX") {
+        (1000, false)
+    } else if is_keyword(opt, "PX") {
+        (1, false)
+    } else if is_keyword(opt, "EXAT") {
+        (1000, true)
+    } else if is_keyword(opt, "PXAT") {
+        (1, true)
+    } else {
+        return Ok(None);
+    };
+    let time = int_arg(rest.next().ok_or_else(syntax_err)?)?;
+    let time_ms = u64::try_from(time)
+        .ok()
+        .filter(|t| *t > 0)
+        .and_then(|t| t.checked_mul(unit_ms))
+        .ok_or_else(|| {
+            format_err!(
+                "ERR invalid expire time in '{cmd}' command",
+                cmd = cmd_name.to_lowercase()
+            )
+        })?;
+    Ok(Some(if absolute {
+        SetExpiry::PxAt(time_ms)
+    } else {
+        SetExpiry::Px(time_ms)
+    }))
+}
+
+fn parse_incr_by(cmd_name: &str, args: &[Value], negate: bool) -> Result<Command> {
+    let [key, by] = args else {
+        return bad_num_of_arguments_err(cmd_name);
+    };
+    let by = int_arg(by)?;
+    let by = if negate {
+        by.checked_neg()
+            .ok_or_else(|| format_err!("ERR decrement would overflow"))?
+    } else {
+        by
+    };
+    Ok(Command::IncrBy(bulk_arg(key)?, by))
+}
+
+fn parse_incr_by_float(args: &[Value]) -> Result<Command> {
+    let [key, by] = args else {
+        return bad_num_of_arguments_err("INCRBYFLOAT");
+    };
+    let by = bulk_arg(by)?;
+    if LongDouble::parse(by.as_bytes()).is_none() {
+        return Err(format_err!("ERR value is not a valid float"));
+    }
+    Ok(Command::IncrByFloat(bulk_arg(key)?, by))
+}
+
+/// A float the way redis accepts it: no surrounding spaces, no nan
+pub fn parse_float(bs: &Bytes) -> Option<f64> {
+    std::str::from_utf8(bs.as_bytes())
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|f| !f.is_nan())
+}
+
+fn parse_getrange(args: &[Value]) -> Result<Command> {
+    let [key, start, end] = args else {
+        return bad_num_of_arguments_err("GETRANGE");
+    };
+    Ok(Command::GetRange(
+        bulk_arg(key)?,
+        int_arg(start)?,
+        int_arg(end)?,
+    ))
+}
+
+fn parse_setrange(args: &[Value]) -> Result<Command> {
+    let [key, offset, val] = args else {
+        return bad_num_of_arguments_err("SETRANGE");
+    };
+    let offset =
+        usize::try_from(int_arg(offset)?).map_err(|_| format_err!("ERR offset is out of range"))?;
+    Ok(Command::SetRange(bulk_arg(key)?, offset, bulk_arg(val)?))
+}
+
+fn parse_getex(args: &[Value]) -> Result<Command> {
+    let Some((key, opts)) = args.split_first() else {
+        return bad_num_of_arguments_err("GETEX");
+    };
+    let mut ex = SetExpiry::KeepTtl;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        // only one option is allowed
+        if ex != SetExpiry::KeepTtl {
+            return Err(syntax_err());
         }
-        4 => match (&args[0], &args[1], &args[2], &args[3]) {
-            (BulkString(k), BulkString(v), BulkString(arg3), BulkString(ex_str))
-                if arg3 == &Bytes::from("px") =>
-            {
-                let ex_int = ex_str.to_string()?.parse::<i64>()?;
-                Ok(Command::SetKV(k.clone(), v.clone(), Some(ex_int as u64)))
+        let opt = bulk_arg(opt)?;
+        ex = if is_keyword(&opt, "PERSIST") {
+            SetExpiry::Persist
+        } else {
+            expiry_opt("GETEX", &opt, &mut opts)?.ok_or_else(syntax_err)?
+        };
+    }
+    Ok(Command::GetEx(bulk_arg(key)?, ex))
+}
+
+/// One or more key value pairs
+fn parse_pairs(cmd_name: &str, args: &[Value]) -> Result<Vec<(Bytes, Bytes)>> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return bad_num_of_arguments_err(cmd_name);
+    }
+    args.chunks(2)
+        .map(|pair| Ok((bulk_arg(&pair[0])?, bulk_arg(&pair[1])?)))
+        .collect()
+}
+
+fn parse_pop(cmd_name: &str, args: &[Value], end: ListEnd) -> Result<Command> {
+    let (key, count) = match args {
+        [key] => (key, None),
+        [key, count] => {
+            let count = usize::try_from(int_arg(count)?)
+                .map_err(|_| format_err!("ERR value is out of range, must be positive"))?;
+            (key, Some(count))
+        }
+        _ => return bad_num_of_arguments_err(cmd_name),
+    };
+    Ok(Command::Pop(bulk_arg(key)?, count, end))
+}
+
+/// `key [key ...] timeout`
+fn parse_bpop(cmd_name: &str, args: &[Value], end: ListEnd) -> Result<Command> {
+    let Some((timeout, keys)) = args.split_last() else {
+        return bad_num_of_arguments_err(cmd_name);
+    };
+    let keys = parse_keys(cmd_name, keys)?;
+    Ok(Command::BPop(keys, end, timeout_arg(timeout)?))
+}
+
+fn parse_lmove(args: &[Value]) -> Result<Command> {
+    let [src, dst, from, to] = args else {
+        return bad_num_of_arguments_err("LMOVE");
+    };
+    Ok(Command::LMove(
+        bulk_arg(src)?,
+        bulk_arg(dst)?,
+        list_end_arg(from)?,
+        list_end_arg(to)?,
+    ))
+}
+
+fn parse_blmove(args: &[Value]) -> Result<Command> {
+    let [src, dst, from, to, timeout] = args else {
+        return bad_num_of_arguments_err("BLMOVE");
+    };
+    Ok(Command::BLMove(
+        bulk_arg(src)?,
+        bulk_arg(dst)?,
+        list_end_arg(from)?,
+        list_end_arg(to)?,
+        timeout_arg(timeout)?,
+    ))
+}
+
+fn list_end_arg(val: &Value) -> Result<ListEnd> {
+    let arg = bulk_arg(val)?;
+    if is_keyword(&arg, "LEFT") {
+        Ok(ListEnd::Left)
+    } else if is_keyword(&arg, "RIGHT") {
+        Ok(ListEnd::Right)
+    } else {
+        Err(syntax_err())
+    }
+}
+
+/// Timeout of a blocking command: seconds, possibly with decimals, converted to millis
+fn timeout_arg(val: &Value) -> Result<u64> {
+    let secs = parse_float(&bulk_arg(val)?)
+        .filter(|secs| secs.is_finite())
+        .ok_or_else(|| format_err!("ERR timeout is not a float or out of range"))?;
+    if secs < 0.0 {
+        return Err(format_err!("ERR timeout is negative"));
+    }
+    Ok((secs * 1000.0) as u64)
+}
+
+fn parse_lrange(args: &[Value]) -> Result<Command> {
+    let [key, start, stop] = args else {
+        return bad_num_of_arguments_err("LRANGE");
+    };
+    Ok(Command::LRange(
+        bulk_arg(key)?,
+        int_arg(start)?,
+        int_arg(stop)?,
+    ))
+}
+
+fn parse_hset(args: &[Value]) -> Result<Command> {
+    let Some((key, pairs)) = args.split_first() else {
+        return bad_num_of_arguments_err("HSET");
+    };
+    Ok(Command::HSet(bulk_arg(key)?, parse_pairs("HSET", pairs)?))
+}
+
+/// `key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]`
+fn parse_zadd(args: &[Value]) -> Result<Command> {
+    if args.len() < 3 {
+        return bad_num_of_arguments_err("ZADD");
+    }
+    let key = bulk_arg(&args[0])?;
+
+    let mut flags = ZAddFlags::default();
+    let mut rest = &args[1..];
+    while let Some((opt, tail)) = rest.split_first() {
+        let opt = bulk_arg(opt)?;
+        let flag = if is_keyword(&opt, "NX") {
+            &mut flags.nx
+        } else if is_keyword(&opt, "XX") {
+            &mut flags.xx
+        } else if is_keyword(&opt, "GT") {
+            &mut flags.gt
+        } else if is_keyword(&opt, "LT") {
+            &mut flags.lt
+        } else if is_keyword(&opt, "CH") {
+            &mut flags.ch
+        } else if is_keyword(&opt, "INCR") {
+            &mut flags.incr
+        } else {
+            break;
+        };
+        *flag = true;
+        rest = tail;
+    }
+
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(syntax_err());
+    }
+    if flags.nx && flags.xx {
+        return Err(format_err!(
+            "ERR XX and NX options at the same time are not compatible"
+        ));
+    }
+    if flags.gt && flags.lt || (flags.gt || flags.lt) && flags.nx {
+        return Err(format_err!(
+            "ERR GT, LT, and/or NX options at the same time are not compatible"
+        ));
+    }
+    if flags.incr && rest.len() > 2 {
+        return Err(format_err!(
+            "ERR INCR option supports a single increment-element pair"
+        ));
+    }
+    let pairs = rest
+        .chunks(2)
+        .map(|pair| {
+            let score = parse_float(&bulk_arg(&pair[0])?)
+                .ok_or_else(|| format_err!("ERR value is not a valid float"))?;
+            Ok((score, bulk_arg(&pair[1])?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Command::ZAdd(key, pairs, flags))
+}
+
+/// `key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`
+fn parse_zrange(args: &[Value]) -> Result<Command> {
+    if args.len() < 3 {
+        return bad_num_of_arguments_err("ZRANGE");
+    }
+    let (key, start, stop) = (
+        bulk_arg(&args[0])?,
+        bulk_arg(&args[1])?,
+        bulk_arg(&args[2])?,
+    );
+
+    let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut opts = args[3..].iter();
+    while let Some(opt) = opts.next() {
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "BYSCORE") {
+            by_score = true;
+        } else if is_keyword(&opt, "BYLEX") {
+            by_lex = true;
+        } else if is_keyword(&opt, "REV") {
+            rev = true;
+        } else if is_keyword(&opt, "WITHSCORES") {
+            with_scores = true;
+        } else if is_keyword(&opt, "LIMIT") {
+            let offset = int_arg(opts.next().ok_or_else(syntax_err)?)?;
+            let count = int_arg(opts.next().ok_or_else(syntax_err)?)?;
+            limit = Some((offset, count));
+        } else {
+            return Err(syntax_err());
+        }
+    }
+    if by_score && by_lex {
+        return Err(syntax_err());
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(format_err!(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        ));
+    }
+    if with_scores && by_lex {
+        return Err(format_err!(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX"
+        ));
+    }
+
+    // with REV, score and lex ranges are given from max to min
+    let (min, max) = if rev && (by_score || by_lex) {
+        (stop, start)
+    } else {
+        (start, stop)
+    };
+    let by = if by_score {
+        let bound = |bs: &Bytes| {
+            ScoreBound::parse(bs).ok_or_else(|| format_err!("ERR min or max is not a float"))
+        };
+        ZRangeBy::Score(bound(&min)?, bound(&max)?)
+    } else if by_lex {
+        let bound = |bs: &Bytes| {
+            LexBound::parse(bs)
+                .ok_or_else(|| format_err!("ERR min or max not valid string range item"))
+        };
+        ZRangeBy::Lex(bound(&min)?, bound(&max)?)
+    } else {
+        let index = |bs: Bytes| int_arg(&BulkString(bs));
+        ZRangeBy::Index(index(min)?, index(max)?)
+    };
+    Ok(Command::ZRange(
+        key,
+        ZRangeSpec {
+            by,
+            rev,
+            limit,
+            with_scores,
+        },
+    ))
+}
+
+fn invalid_stream_id_err() -> anyhow::Error {
+    format_err!("ERR Invalid stream ID specified as stream command argument")
+}
+
+/// `ms-seq`, or `ms` with `missing_seq` as sequence number
+fn stream_id_arg(val: &Value, missing_seq: u64) -> Result<StreamId> {
+    StreamId::parse(&bulk_arg(val)?, missing_seq).ok_or_else(invalid_stream_id_err)
+}
+
+/// `key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] <* | id> field value
+/// [field value ...]`
+fn parse_xadd(args: &[Value]) -> Result<Command> {
+    let Some((key, mut rest)) = args.split_first() else {
+        return bad_num_of_arguments_err("XADD");
+    };
+    let mut opts = XAddOpts::default();
+    while let Some((opt, tail)) = rest.split_first() {
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "NOMKSTREAM") {
+            opts.nomkstream = true;
+            rest = tail;
+        } else if let Some((trim, tail)) = trim_opt(&opt, tail)? {
+            if opts.trim.is_some() {
+                return Err(format_err!(
+                    "ERR syntax error, MAXLEN and MINID options at the same time are not compatible"
+                ));
             }
-            _ => Err(format_err!(
-                "Invalid 4 argument command: `SET`\nargs={args:?}"
-            )),
+            opts.trim = Some(trim);
+            rest = tail;
+        } else {
+            break;
+        }
+    }
+
+    let Some((id, fields)) = rest.split_first() else {
+        return bad_num_of_arguments_err("XADD");
+    };
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return bad_num_of_arguments_err("XADD");
+    }
+    let id_arg = bulk_arg(id)?;
+    let id = match id_arg.as_bytes() {
+        b"*" => XAddId::Auto,
+        [ms @ .., b'-', b'*'] => {
+            let ms = StreamId::parse(&ms.into(), 0).ok_or_else(invalid_stream_id_err)?;
+            XAddId::AutoSeq(ms.ms)
+        }
+        _ => XAddId::Explicit(stream_id_arg(id, 0)?),
+    };
+    if id == XAddId::Explicit(StreamId::MIN) {
+        return Err(format_err!(
+            "ERR The ID specified in XADD must be greater than 0-0"
+        ));
+    }
+    let fields = fields
+        .chunks(2)
+        .map(|pair| Ok((bulk_arg(&pair[0])?, bulk_arg(&pair[1])?)))
+        .collect::<Result<StreamFields>>()?;
+    Ok(Command::XAdd(bulk_arg(key)?, id, fields, opts))
+}
+
+/// `MAXLEN | MINID [= | ~] threshold [LIMIT count]` if `opt` is MAXLEN or MINID,
+/// along with the arguments after it
+fn trim_opt<'a>(opt: &Bytes, rest: &'a [Value]) -> Result<Option<(StreamTrim, &'a [Value])>> {
+    let is_max_len = is_keyword(opt, "MAXLEN");
+    if !is_max_len && !is_keyword(opt, "MINID") {
+        return Ok(None);
+    }
+    let mut rest = rest;
+    let mut approx = false;
+    if let Some((BulkString(op), tail)) = rest.split_first() {
+        if matches!(op.as_bytes(), b"=" | b"~") {
+            approx = op.as_bytes() == b"~";
+            rest = tail;
+        }
+    }
+    let (threshold, mut rest) = rest.split_first().ok_or_else(syntax_err)?;
+    let strategy = if is_max_len {
+        let max_len = int_arg(threshold)?;
+        TrimStrategy::MaxLen(
+            u64::try_from(max_len)
+                .map_err(|_| format_err!("ERR The MAXLEN argument must be >= 0."))?,
+        )
+    } else {
+        TrimStrategy::MinId(stream_id_arg(threshold, 0)?)
+    };
+
+    let mut limit = None;
+    if let Some((opt, tail)) = rest.split_first() {
+        if is_keyword(&bulk_arg(opt)?, "LIMIT") {
+            let count = int_arg(tail.first().ok_or_else(syntax_err)?)?;
+            let count = usize::try_from(count)
+                .map_err(|_| format_err!("ERR The LIMIT argument must be >= 0."))?;
+            if !approx {
+                return Err(format_err!(
+                    "ERR syntax error, LIMIT cannot be used without the special ~ option"
+                ));
+            }
+            limit = Some(count);
+            rest = &tail[1..];
+        }
+    }
+    let trim = StreamTrim {
+        strategy,
+        approx,
+        limit,
+    };
+    Ok(Some((trim, rest)))
+}
+
+/// `key start end [COUNT count]`, with end and start swapped for XREVRANGE
+fn parse_xrange(cmd_name: &str, args: &[Value], rev: bool) -> Result<Command> {
+    if args.len() != 3 && args.len() != 5 {
+        return bad_num_of_arguments_err(cmd_name);
+    }
+    let key = bulk_arg(&args[0])?;
+    let (start, end) = if rev {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let (start, end) = (range_start_arg(start)?, range_end_arg(end)?);
+
+    let mut count = None;
+    if let [opt, n] = &args[3..] {
+        if !is_keyword(&bulk_arg(opt)?, "COUNT") {
+            return Err(syntax_err());
+        }
+        // a negative count is taken as 0
+        count = Some(usize::try_from(int_arg(n)?).unwrap_or(0));
+    }
+    Ok(Command::XRange(key, start, end, count, rev))
+}
+
+/// Start of an id range: `-`, an id, or `(id` to leave it out
+fn range_start_arg(val: &Value) -> Result<StreamId> {
+    match bulk_arg(val)?.as_bytes() {
+        b"-" => Ok(StreamId::MIN),
+        [b'(', id @ ..] => StreamId::parse(&id.into(), 0)
+            .ok_or_else(invalid_stream_id_err)?
+            .next()
+            .ok_or_else(|| format_err!("ERR invalid start ID for the interval")),
+        _ => stream_id_arg(val, 0),
+    }
+}
+
+/// End of an id range: `+`, an id, or `(id` to leave it out
+fn range_end_arg(val: &Value) -> Result<StreamId> {
+    match bulk_arg(val)?.as_bytes() {
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => StreamId::parse(&id.into(), u64::MAX)
+            .ok_or_else(invalid_stream_id_err)?
+            .prev()
+            .ok_or_else(|| format_err!("ERR invalid end ID for the interval")),
+        _ => stream_id_arg(val, u64::MAX),
+    }
+}
+
+/// XREAD: `[COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`
+/// XREADGROUP: `GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS
+/// key [key ...] id [id ...]`
+fn parse_xread(cmd_name: &str, args: &[Value], with_group: bool) -> Result<Command> {
+    let mut count = None;
+    let mut block = None;
+    let mut group = None;
+    let mut noack = false;
+    let mut rest = args;
+    loop {
+        let Some((opt, tail)) = rest.split_first() else {
+            return Err(syntax_err());
+        };
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "STREAMS") {
+            rest = tail;
+            break;
+        }
+        if is_keyword(&opt, "NOACK") && with_group {
+            noack = true;
+            rest = tail;
+            continue;
+        }
+        let val = tail.first().ok_or_else(syntax_err)?;
+        if is_keyword(&opt, "COUNT") {
+            // 0 or negative means no limit
+            count = usize::try_from(int_arg(val)?).ok().filter(|n| *n > 0);
+        } else if is_keyword(&opt, "BLOCK") {
+            let timeout = val
+                .try_to_int()
+                .map_err(|_| format_err!("ERR timeout is not an integer or out of range"))?;
+            let timeout =
+                u64::try_from(timeout).map_err(|_| format_err!("ERR timeout is negative"))?;
+            block = Some(timeout);
+        } else if is_keyword(&opt, "GROUP") {
+            if !with_group {
+                return Err(format_err!(
+                    "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead."
+                ));
+            }
+            let consumer = tail.get(1).ok_or_else(syntax_err)?;
+            group = Some((bulk_arg(val)?, bulk_arg(consumer)?));
+            rest = &tail[2..];
+            continue;
+        } else {
+            return Err(syntax_err());
+        }
+        rest = &tail[1..];
+    }
+
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(format_err!(
+            "ERR Unbalanced '{cmd}' list of streams: for each stream key an ID or '$' must be specified.",
+            cmd = cmd_name.to_lowercase()
+        ));
+    }
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    let keys = keys.iter().map(bulk_arg).collect::<Result<Vec<_>>>()?;
+
+    if !with_group {
+        let streams = keys
+            .into_iter()
+            .zip(ids)
+            .map(|(key, id)| {
+                let from = match bulk_arg(id)?.as_bytes() {
+                    b"$" => XReadFrom::Last,
+                    b">" => return Err(format_err!(
+                        "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."
+                    )),
+                    _ => XReadFrom::After(stream_id_arg(id, 0)?),
+                };
+                Ok((key, from))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Command::XRead(streams, count, block));
+    }
+
+    let Some((group, consumer)) = group else {
+        return Err(format_err!("ERR Missing GROUP option for XREADGROUP"));
+    };
+    let streams = keys
+        .into_iter()
+        .zip(ids)
+        .map(|(key, id)| {
+            let from = match bulk_arg(id)?.as_bytes() {
+                b">" => XReadGroupFrom::New,
+                b"$" => return Err(format_err!(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+                )),
+                _ => XReadGroupFrom::Pending(stream_id_arg(id, 0)?),
+            };
+            Ok((key, from))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Command::XReadGroup(
+        group, consumer, streams, count, block, noack,
+    ))
+}
+
+/// `key MAXLEN | MINID [= | ~] threshold [LIMIT count]`
+fn parse_xtrim(args: &[Value]) -> Result<Command> {
+    let [key, opt, rest @ ..] = args else {
+        return bad_num_of_arguments_err("XTRIM");
+    };
+    match trim_opt(&bulk_arg(opt)?, rest)? {
+        Some((trim, [])) => Ok(Command::XTrim(bulk_arg(key)?, trim)),
+        _ => Err(syntax_err()),
+    }
+}
+
+/// `key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]`
+fn parse_xsetid(args: &[Value]) -> Result<Command> {
+    let [key, last_id, opts @ ..] = args else {
+        return bad_num_of_arguments_err("XSETID");
+    };
+    let last_id = stream_id_arg(last_id, 0)?;
+    let (mut entries_added, mut max_deleted_id) = (None, None);
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        let opt = bulk_arg(opt)?;
+        let val = opts.next().ok_or_else(syntax_err)?;
+        if is_keyword(&opt, "ENTRIESADDED") {
+            let n = u64::try_from(int_arg(val)?)
+                .map_err(|_| format_err!("ERR entries_added must be positive"))?;
+            entries_added = Some(n);
+        } else if is_keyword(&opt, "MAXDELETEDID") {
+            let id = stream_id_arg(val, 0)?;
+            if id > last_id {
+                return Err(format_err!(
+                    "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+                ));
+            }
+            max_deleted_id = Some(id);
+        } else {
+            return Err(syntax_err());
+        }
+    }
+    Ok(Command::XSetId(
+        bulk_arg(key)?,
+        last_id,
+        entries_added,
+        max_deleted_id,
+    ))
+}
+
+/// `CREATE key group id | $ [MKSTREAM] [ENTRIESREAD entries-read]`,
+/// `SETID key group id | $ [ENTRIESREAD entries-read]`, `DESTROY key group`,
+/// `CREATECONSUMER key group consumer` or `DELCONSUMER key group consumer`
+fn parse_xgroup(args: &[Value]) -> Result<Command> {
+    let Some((sub, args)) = args.split_first() else {
+        return bad_num_of_arguments_err("XGROUP");
+    };
+    let sub = string_arg(sub)?;
+    let sub_upper = sub.to_uppercase();
+    let full_name = format!("XGROUP|{sub_upper}");
+    let bad_opt_err = || {
+        format_err!(
+            "ERR unknown subcommand or wrong number of arguments for '{sub}'. Try XGROUP HELP."
+        )
+    };
+    match sub_upper.as_str() {
+        "CREATE" | "SETID" => {
+            let [key, group, id, opts @ ..] = args else {
+                return bad_num_of_arguments_err(&full_name);
+            };
+            let id = match bulk_arg(id)?.as_bytes() {
+                b"$" => XReadFrom::Last,
+                _ => XReadFrom::After(stream_id_arg(id, 0)?),
+            };
+            let (mut mkstream, mut entries_read) = (false, None);
+            let mut opts = opts.iter();
+            while let Some(opt) = opts.next() {
+                let opt = bulk_arg(opt)?;
+                if is_keyword(&opt, "MKSTREAM") && sub_upper == "CREATE" {
+                    mkstream = true;
+                } else if is_keyword(&opt, "ENTRIESREAD") {
+                    let n = int_arg(opts.next().ok_or_else(bad_opt_err)?)?;
+                    // -1 stands for unknown, like not giving it
+                    entries_read = match n {
+                        -1 => None,
+                        n => Some(u64::try_from(n).map_err(|_| {
+                            format_err!("ERR value for ENTRIESREAD must be positive or -1")
+                        })?),
+                    };
+                } else {
+                    return Err(bad_opt_err());
+                }
+            }
+            let (key, group) = (bulk_arg(key)?, bulk_arg(group)?);
+            Ok(if sub_upper == "CREATE" {
+                Command::XGroupCreate(key, group, id, mkstream, entries_read)
+            } else {
+                Command::XGroupSetId(key, group, id, entries_read)
+            })
+        }
+        "DESTROY" => match args {
+            [key, group] => Ok(Command::XGroupDestroy(bulk_arg(key)?, bulk_arg(group)?)),
+            _ => bad_num_of_arguments_err(&full_name),
         },
-        _ => bad_num_of_arguments_err("SET", args),
+        "CREATECONSUMER" | "DELCONSUMER" => {
+            let [key, group, consumer] = args else {
+                return bad_num_of_arguments_err(&full_name);
+            };
+            let (key, group, consumer) = (bulk_arg(key)?, bulk_arg(group)?, bulk_arg(consumer)?);
+            Ok(if sub_upper == "CREATECONSUMER" {
+                Command::XGroupCreateConsumer(key, group, consumer)
+            } else {
+                Command::XGroupDelConsumer(key, group, consumer)
+            })
+        }
+        _ => Err(format_err!(
+            "ERR unknown subcommand '{sub}'. Try XGROUP HELP."
+        )),
     }
 }
 
-fn parse_info(args: &[Value]) -> Result<Command> {
-    match &args[0] {
-        BulkString(bs) => {
-            let arg1 = bs.to_string()?;
-            Ok(Command::Info(arg1))
+/// `key group id [id ...]`
+fn parse_xack(args: &[Value]) -> Result<Command> {
+    let [key, group, ids @ ..] = args else {
+        return bad_num_of_arguments_err("XACK");
+    };
+    if ids.is_empty() {
+        return bad_num_of_arguments_err("XACK");
+    }
+    let ids = ids
+        .iter()
+        .map(|id| stream_id_arg(id, 0))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Command::XAck(bulk_arg(key)?, bulk_arg(group)?, ids))
+}
+
+/// `key group [[IDLE min-idle-time] start end count [consumer]]`
+fn parse_xpending(args: &[Value]) -> Result<Command> {
+    let [key, group, rest @ ..] = args else {
+        return bad_num_of_arguments_err("XPENDING");
+    };
+    let (key, group) = (bulk_arg(key)?, bulk_arg(group)?);
+    if rest.is_empty() {
+        return Ok(Command::XPending(key, group, None));
+    }
+
+    let mut min_idle = 0;
+    let mut rest = rest;
+    if rest.len() >= 5 && is_keyword(&bulk_arg(&rest[0])?, "IDLE") {
+        // negative is the same as none
+        min_idle = u64::try_from(int_arg(&rest[1])?).unwrap_or(0);
+        rest = &rest[2..];
+    }
+    let (start, end, count, consumer) = match rest {
+        [start, end, count] => (start, end, count, None),
+        [start, end, count, consumer] => (start, end, count, Some(bulk_arg(consumer)?)),
+        _ => return Err(syntax_err()),
+    };
+    let range = XPendingRange {
+        min_idle,
+        start: range_start_arg(start)?,
+        end: range_end_arg(end)?,
+        // a negative count is taken as 0
+        count: usize::try_from(int_arg(count)?).unwrap_or(0),
+        consumer,
+    };
+    Ok(Command::XPending(key, group, Some(range)))
+}
+
+/// `key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+/// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]`
+fn parse_xclaim(args: &[Value]) -> Result<Command> {
+    let [key, group, consumer, min_idle, rest @ ..] = args else {
+        return bad_num_of_arguments_err("XCLAIM");
+    };
+    if rest.is_empty() {
+        return bad_num_of_arguments_err("XCLAIM");
+    }
+    let min_idle = min_idle_arg(min_idle, "XCLAIM")?;
+
+    // ids go on until the first argument that is not one
+    let n_ids = rest
+        .iter()
+        .take_while(|arg| stream_id_arg(arg, 0).is_ok())
+        .count();
+    let ids = rest[..n_ids]
+        .iter()
+        .map(|id| stream_id_arg(id, 0))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut opts = XClaimOpts::default();
+    let mut rest = rest[n_ids..].iter();
+    while let Some(opt) = rest.next() {
+        let opt_name = bulk_arg(opt)?;
+        let opt_name = opt_name.to_string().unwrap_or_default().to_uppercase();
+        let mut int_val = || {
+            rest.next()
+                .and_then(|val| val.try_to_int().ok())
+                .ok_or_else(|| format_err!("ERR Invalid {opt_name} option argument for XCLAIM"))
+        };
+        match opt_name.as_str() {
+            // the last one of IDLE and TIME given wins
+            "IDLE" => {
+                opts.idle = Some(u64::try_from(int_val()?).unwrap_or(0));
+                opts.time = None;
+            }
+            "TIME" => {
+                // negative means now, like not giving it
+                opts.time = u64::try_from(int_val()?).ok();
+                opts.idle = None;
+            }
+            // negative is ignored
+            "RETRYCOUNT" => opts.retry_count = u64::try_from(int_val()?).ok(),
+            "FORCE" => opts.force = true,
+            "JUSTID" => opts.justid = true,
+            "LASTID" => opts.last_id = Some(stream_id_arg(rest.next().ok_or_else(syntax_err)?, 0)?),
+            _ => {
+                return Err(format_err!(
+                    "ERR Unrecognized XCLAIM option '{o}'",
+                    o = opt.try_to_string().unwrap_or_default()
+                ))
+            }
         }
+    }
+    Ok(Command::XClaim(
+        bulk_arg(key)?,
+        bulk_arg(group)?,
+        bulk_arg(consumer)?,
+        min_idle,
+        ids,
+        opts,
+    ))
+}
+
+/// `key group consumer min-idle-time start [COUNT count] [JUSTID]`
+fn parse_xautoclaim(args: &[Value]) -> Result<Command> {
+    let [key, group, consumer, min_idle, start, opts @ ..] = args else {
+        return bad_num_of_arguments_err("XAUTOCLAIM");
+    };
+    let min_idle = min_idle_arg(min_idle, "XAUTOCLAIM")?;
+    let start = range_start_arg(start)?;
+    let (mut count, mut justid) = (100, false);
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        let opt = bulk_arg(opt)?;
+        if is_keyword(&opt, "COUNT") {
+            let n = int_arg(opts.next().ok_or_else(syntax_err)?)?;
+            // up to 10 PEL entries are looked at per entry to claim, which must not overflow
+            count = usize::try_from(n)
+                .ok()
+                .filter(|n| (1..=usize::MAX / 10).contains(n))
+                .ok_or_else(|| format_err!("ERR COUNT must be > 0"))?;
+        } else if is_keyword(&opt, "JUSTID") {
+            justid = true;
+        } else {
+            return Err(syntax_err());
+        }
+    }
+    Ok(Command::XAutoClaim(
+        bulk_arg(key)?,
+        bulk_arg(group)?,
+        bulk_arg(consumer)?,
+        min_idle,
+        start,
+        count,
+        justid,
+    ))
+}
+
+fn parse_pubsub(args: &[Value]) -> Result<Command> {
+    let Some((sub, args)) = args.split_first() else {
+        return bad_num_of_arguments_err("PUBSUB");
+    };
+    let sub = string_arg(sub)?;
+    let sub_upper = sub.to_uppercase();
+    let full_name = format!("PUBSUB|{sub_upper}");
+    match sub_upper.as_str() {
+        "CHANNELS" => match args {
+            [] => Ok(Command::PubSubChannels(None)),
+            [pattern] => Ok(Command::PubSubChannels(Some(bulk_arg(pattern)?))),
+            _ => bad_num_of_arguments_err(&full_name),
+        },
+        "NUMSUB" => Ok(Command::PubSubNumSub(
+            args.iter().map(bulk_arg).collect::<Result<_>>()?,
+        )),
+        "NUMPAT" => parse_no_args(&full_name, args, Command::PubSubNumPat),
         _ => Err(format_err!(
-            "Invalid argument for INFO command: arg0={arg0:?}",
-            arg0 = &args[0]
+            "ERR unknown subcommand '{sub}'. Try PUBSUB HELP."
         )),
     }
 }
 
+fn parse_hello(args: &[Value]) -> Result<Command> {
+    let Some((version, mut opts)) = args.split_first() else {
+        return Ok(Command::Hello(None, None, None));
+    };
+    let protocol = match version.try_to_int() {
+        Ok(2) => Protocol::Resp2,
+        Ok(3) => Protocol::Resp3,
+        Ok(_) => return Err(format_err!("NOPROTO unsupported protocol version")),
+        Err(_) => {
+            return Err(format_err!(
+                "ERR Protocol version is not an integer or out of range"
+            ))
+        }
+    };
+
+    let (mut auth, mut name) = (None, None);
+    while let Some((opt, rest)) = opts.split_first() {
+        let opt = bulk_arg(opt)?;
+        opts = match rest {
+            [username, password, rest @ ..] if is_keyword(&opt, "AUTH") => {
+                auth = Some((bulk_arg(username)?, bulk_arg(password)?));
+                rest
+            }
+            [client_name, rest @ ..] if is_keyword(&opt, "SETNAME") => {
+                name = Some(bulk_arg(client_name)?);
+                rest
+            }
+            _ => {
+                return Err(format_err!(
+                    "ERR Syntax error in HELLO option '{opt}'",
+                    opt = opt.to_string().unwrap_or_default()
+                ))
+            }
+        };
+    }
+    Ok(Command::Hello(Some(protocol), auth, name))
+}
+
+/// Min idle time of XCLAIM & co. in millis, negative taken as 0
+fn min_idle_arg(val: &Value, cmd_name: &str) -> Result<u64> {
+    let min_idle = val
+        .try_to_int()
+        .map_err(|_| format_err!("ERR Invalid min-idle-time argument for {cmd_name}"))?;
+    Ok(u64::try_from(min_idle).unwrap_or(0))
+}
+
+fn parse_info(args: &[Value]) -> Result<Command> {
+    match args.first() {
+        None => Ok(Command::Info("default".into())),
+        Some(arg0) => Ok(Command::Info(string_arg(arg0)?.to_lowercase())),
+    }
+}
+
 fn parse_psync(args: &[Value]) -> Result<Command> {
-    let repl_id = args[0].try_to_string()?;
-    let offset = args[1].try_to_string()?.parse::<i64>()?;
+    if args.len() != 2 {
+        return bad_num_of_arguments_err("PSYNC");
+    }
+    let repl_id = string_arg(&args[0])?;
+    let offset = int_arg(&args[1])?;
     Ok(Command::Psync(repl_id, offset))
 }
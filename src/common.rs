@@ -1,33 +1,82 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
 use anyhow::Result;
+use bytes::BytesMut;
+
+/// A binary safe string. Cloning shares the bytes instead of copying them, so big values
+/// parsed off a connection's read buffer are stored and replied with as they were read.
+/// Strings that APPEND & co. change get a buffer of their own, to grow in place from then on.
+#[derive(Clone)]
+pub struct Bytes(Repr);
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct Bytes(Vec<u8>);
+#[derive(Clone)]
+enum Repr {
+    Shared(bytes::Bytes),
+    Owned(BytesMut), // cloning this one copies
+}
 
 impl Bytes {
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.as_bytes().len()
     }
 
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.as_bytes().is_empty()
     }
 
     pub fn as_bytes(&self) -> &[u8] {
-        &self.0
+        match &self.0 {
+            Repr::Shared(b) => b,
+            Repr::Owned(b) => b,
+        }
     }
 
-    pub fn as_vec(&self) -> &Vec<u8> {
-        &self.0
+    /// Copies the bytes the first time, if they are shared
+    pub fn as_bytes_mut(&mut self) -> &mut BytesMut {
+        if let Repr::Shared(b) = &self.0 {
+            self.0 = Repr::Owned(BytesMut::from(&b[..]));
+        }
+        match &mut self.0 {
+            Repr::Owned(b) => b,
+            Repr::Shared(_) => unreachable!("made owned above"),
+        }
     }
 
     pub fn to_string(&self) -> Result<String> {
-        Ok(String::from_utf8(self.0.clone())?)
+        Ok(String::from_utf8(self.as_bytes().to_vec())?)
     }
 
     #[allow(dead_code)]
     pub fn into_inner(self) -> Vec<u8> {
-        self.0
+        self.as_bytes().to_vec()
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Bytes {}
+
+impl PartialOrd for Bytes {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Bytes {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl Hash for Bytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state)
     }
 }
 
@@ -44,7 +93,7 @@ impl std::fmt::Debug for Bytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let len = self.len();
         let mut ret = String::with_capacity(len * 4);
-        for byte in &self.0 {
+        for byte in self.as_bytes() {
             match byte {
                 b'\n' => ret.push_str("\\n"),
                 b'\r' => ret.push_str("\\r"),
@@ -58,20 +107,33 @@ impl std::fmt::Debug for Bytes {
     }
 }
 
+impl From<BytesMut> for Bytes {
+    fn from(b: BytesMut) -> Self {
+        Self(Repr::Shared(b.freeze()))
+    }
+}
+
 impl From<Vec<u8>> for Bytes {
     fn from(v: Vec<u8>) -> Self {
-        Self(v)
+        Self(Repr::Shared(v.into()))
     }
 }
 
 impl From<&[u8]> for Bytes {
     fn from(v: &[u8]) -> Self {
-        Self(Vec::from(v))
+        Self(Repr::Shared(bytes::Bytes::copy_from_slice(v)))
     }
 }
 
 impl From<&str> for Bytes {
     fn from(s: &str) -> Self {
-        Self(Vec::from(s.as_bytes()))
+        s.as_bytes().into()
     }
 }
+
+/// An integer the way redis' string2ll reads it: only what it would print back the same,
+/// so no "+1", "007", "-0" or spaces
+pub fn parse_int(bs: &[u8]) -> Option<i64> {
+    let i = std::str::from_utf8(bs).ok()?.parse::<i64>().ok()?;
+    (i.to_string().as_bytes() == bs).then_some(i)
+}
@@ -1,4 +1,8 @@
 use std::env::args;
+use std::path::PathBuf;
+
+use crate::aof::FsyncPolicy;
+use crate::deser::ProtoLimits;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Role {
@@ -21,6 +25,13 @@ pub struct InstanceConfig {
     pub port: u32,
     pub role: Role,
     pub replicaof: Option<String>,
+    pub dir: String,
+    pub dbfilename: String,
+    pub repl_backlog_size: usize,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    pub proto_limits: ProtoLimits, // for what clients send
 }
 
 impl Default for InstanceConfig {
@@ -29,6 +40,13 @@ impl Default for InstanceConfig {
             port: 6379,
             role: Role::Master,
             replicaof: None,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            repl_backlog_size: 1024 * 1024,
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            proto_limits: ProtoLimits::default(),
         }
     }
 }
@@ -41,6 +59,14 @@ impl InstanceConfig {
         self.role.clone()
     }
 
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.appendfilename)
+    }
+
     pub fn from_command_args() -> Self {
         let mut output = InstanceConfig::default();
         let args = args().collect::<Vec<_>>();
@@ -52,6 +78,26 @@ impl InstanceConfig {
                     output.role = Role::Slave;
                     output.replicaof = Some(args[i + 1].clone())
                 }
+                "--dir" => output.dir = args[i + 1].clone(),
+                "--dbfilename" => output.dbfilename = args[i + 1].clone(),
+                "--appendonly" => output.appendonly = args[i + 1] == "yes",
+                "--appendfilename" => output.appendfilename = args[i + 1].clone(),
+                "--appendfsync" => output.appendfsync = args[i + 1].parse().unwrap(),
+                "--repl-backlog-size" => {
+                    output.repl_backlog_size = args[i + 1].parse::<usize>().unwrap()
+                }
+                "--proto-max-bulk-len" => {
+                    output.proto_limits.max_bulk_len = args[i + 1].parse::<usize>().unwrap()
+                }
+                "--proto-max-multibulk-len" => {
+                    output.proto_limits.max_multibulk_len = args[i + 1].parse::<usize>().unwrap()
+                }
+                "--proto-max-nesting" => {
+                    output.proto_limits.max_nesting = args[i + 1].parse::<usize>().unwrap()
+                }
+                "--client-query-buffer-limit" => {
+                    output.proto_limits.max_query_buf = args[i + 1].parse::<usize>().unwrap()
+                }
                 _ => {}
             });
 
@@ -1,288 +1,2791 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufStream;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::{
+    channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+};
 
+use crate::aof::{claim_as_is, read_aof_file, rewrite_commands, write_aof_file, Aof};
 use crate::async_deser::RespDeserializer;
-use crate::commands::Command;
-use crate::common::Bytes;
-use crate::config::InstanceConfig;
+use crate::commands::{
+    Command, ExpireFlags, ListEnd, SetExpiry, SetFlags, StreamTrim, XAddId, XAddOpts,
+    XClaimOpts, XPendingRange, XReadFrom, XReadGroupFrom, ZAddFlags, ZRangeBy, ZRangeSpec,
+};
+use crate::common::{parse_int, Bytes};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{InstanceConfig, Role};
+use crate::db_val::DbVal;
+use crate::expires::VolatileKeys;
+use crate::glob::glob_match;
+use crate::long_double::LongDouble;
 // use crate::io_util::debug_peek;
-use crate::misc_util::hex_decode;
+use crate::repl_backlog::ReplBacklog;
 use crate::replica_handler::handle_replica;
 use crate::svc::ClientInfo;
 use crate::svc::ToReplica;
-use crate::svc::{handle_stream_async, Query, ToDb};
+use crate::svc::{handle_stream_async, Query, ToDb, Transaction};
 // use crate::misc_util::peer_addr_str;
-use crate::async_deser::deserialize;
 use crate::misc_util::peer_addr_str_v2;
-use crate::misc_util::{make_replication_id, now_millis};
+use crate::misc_util::make_replication_id;
+use crate::pubsub::PubSub;
+use crate::rdb::{parse_rdb, read_rdb_file, serialize_rdb, write_rdb_file, RdbContents, RdbEntry};
 use crate::resp::QueryResult;
-use crate::resp::{s_str, serialize, Value};
+use crate::resp::{format_double, s_str, serialize, Protocol, Value};
+use crate::stream::{ConsumerGroup, Stream, StreamFields, StreamId};
+use crate::watch::WatchedKeys;
+use crate::zset::SortedSet;
 // use crate::async_deser::receive_value_from_stream;
 
+#[derive(Clone)]
 pub struct ValAndExpiry {
-    val: Bytes,
+    val: DbVal,
     ex: u64, // absolute expiry time in millis since epoch
 }
 
 impl ValAndExpiry {
-    pub fn new(val: Bytes, ex_interv: Option<u64>) -> Self {
-        match ex_interv {
-            Some(interv) => ValAndExpiry {
-                val,
-                ex: now_millis() + interv,
+    pub fn with_deadline(val: DbVal, ex: Option<u64>) -> Self {
+        ValAndExpiry {
+            val,
+            ex: ex.unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// A client waiting in BLPOP, XREAD & co. for data in one of `keys`
+struct BlockedClient {
+    keys: Vec<Bytes>,
+    op: BlockedOp,
+    sx: Sender<QueryResult>,
+    deadline: Option<u64>, // millis since epoch, by Db's clock. None to wait forever
+}
+
+/// What a blocked client does with the first of its keys that gets data
+#[derive(Clone)]
+enum BlockedOp {
+    Pop(ListEnd),                       // BLPOP / BRPOP
+    Move(Bytes, ListEnd, ListEnd),      // BLMOVE: dst, end to pop from, end to push to
+    Read(Vec<StreamId>, Option<usize>), // XREAD: entries after these ids, one per key, count
+    // XREADGROUP: group, consumer, count, noack
+    ReadGroup(Bytes, Bytes, Option<usize>, bool),
+}
+
+impl BlockedOp {
+    fn timeout_reply(&self) -> Value {
+        match self {
+            BlockedOp::Pop(_) | BlockedOp::Read(..) | BlockedOp::ReadGroup(..) => Value::NullArray,
+            BlockedOp::Move(..) => Value::NullBulkString,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ReplicaInfo {
+    // host_port: String,
+    sender: UnboundedSender<ToReplica>,
+    acked_byte_cnt: u64,
+}
+
+// Same limit as redis' default proto-max-bulk-len, for SETRANGE
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+// The redis version whose behaviour we follow, as HELLO reports it
+const REDIS_VERSION: &str = "7.2.0";
+
+// Active expire cycle: keys sampled per round, and max time spent per cycle
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+pub struct Db {
+    h: HashMap<Bytes, ValAndExpiry>,
+    expires: VolatileKeys, // keys in h with an expiry, only changed via insert_entry & co.
+    cfg: InstanceConfig,
+    tx: Sender<ToDb>,
+    clock: Arc<dyn Clock>,
+    // Clients blocked in BLPOP, XREAD & co. by block id,
+    // and the ids waiting on each key, oldest first
+    blocked: HashMap<u64, BlockedClient>,
+    waiting_on_key: HashMap<Bytes, VecDeque<u64>>,
+    ready_keys: Vec<Bytes>, // keys with waiters that got elements, served after each command
+    next_block_id: u64,
+    pubsub: PubSub,
+    watched: WatchedKeys,
+    // while running the commands of an EXEC, and whether a MULTI went to replicas & aof already
+    in_exec: bool,
+    multi_propagated: bool,
+    // Used by replicas
+    repl_byte_cnt: usize,
+    master_replid: Option<String>, // replid of the master we last synced with
+    // Used by Master
+    replicas: HashMap<String, ReplicaInfo>,
+    // receiving ends of replica channels, from PSYNC until the connection is handed over,
+    // by client id, with the replica's address
+    pending_repl_receivers: HashMap<u64, (String, UnboundedReceiver<ToReplica>)>,
+    replication_id: String,
+    replication_offset: u64,
+    backlog: Option<ReplBacklog>, // created when the first replica attaches
+    // Persistence
+    last_save: u64, // unix time in secs of last successful SAVE / BGSAVE
+    bgsave_in_progress: bool,
+    aof: Option<Aof>,
+    aof_rewrite_in_progress: bool,
+}
+
+impl Db {
+    pub fn new(cfg: InstanceConfig, tx: Sender<ToDb>) -> Self {
+        Self::with_clock(cfg, tx, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(cfg: InstanceConfig, tx: Sender<ToDb>, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now_millis();
+        Db {
+            h: HashMap::new(),
+            expires: VolatileKeys::new(now),
+            cfg,
+            tx,
+            clock,
+            blocked: HashMap::new(),
+            waiting_on_key: HashMap::new(),
+            ready_keys: Vec::new(),
+            next_block_id: 0,
+            pubsub: PubSub::default(),
+            watched: WatchedKeys::default(),
+            in_exec: false,
+            multi_propagated: false,
+            repl_byte_cnt: 0,
+            master_replid: None,
+            replicas: HashMap::new(),
+            pending_repl_receivers: HashMap::new(),
+            replication_id: make_replication_id(now),
+            replication_offset: 0,
+            backlog: None,
+            last_save: now / 1000,
+            bgsave_in_progress: false,
+            aof: None,
+            aof_rewrite_in_progress: false,
+        }
+    }
+
+    /// Load the dataset at startup: from the aof when appendonly is on, otherwise from the rdb file.
+    pub async fn load_data(&mut self) -> Result<()> {
+        if self.cfg.appendonly {
+            self.load_aof().await?;
+        } else {
+            self.load_rdb()?;
+        }
+        Ok(())
+    }
+
+    /// Replay the aof, if it exists, and open it for logging further writes.
+    /// Returns the number of commands replayed.
+    pub async fn load_aof(&mut self) -> Result<usize> {
+        let path = self.cfg.aof_path();
+        let mut n_replayed = 0usize;
+
+        if path.exists() {
+            let cmds = read_aof_file(&path)?;
+            // replies go nowhere
+            let (sx, _rx) = channel::<QueryResult>(1);
+            for cmd in cmds {
+                let query = Query::new(cmd, 0, "aof:0".to_string());
+                self.execute(&query, sx.clone()).await;
+                n_replayed += 1;
+            }
+            println!(
+                "Db::load_aof: replayed {n_replayed} commands from {p}",
+                p = path.display()
+            );
+        }
+
+        // opened only now, so that replayed commands are not logged again
+        self.aof = Some(Aof::open(&path, self.cfg.appendfsync)?);
+        Ok(n_replayed)
+    }
+
+    /// Populate the store from the rdb file given by --dir and --dbfilename, if it exists.
+    /// Returns the number of keys loaded.
+    pub fn load_rdb(&mut self) -> Result<usize> {
+        let path = self.cfg.rdb_path();
+        if !path.exists() {
+            println!(
+                "Db::load_rdb: no rdb file at {p}, starting empty",
+                p = path.display()
+            );
+            return Ok(0);
+        }
+
+        let contents = read_rdb_file(&path)?;
+        let version = contents.version;
+        let n_loaded = self.load_rdb_contents(contents);
+        println!(
+            "Db::load_rdb: loaded {n_loaded} keys from {p} (rdb version {version})",
+            p = path.display(),
+        );
+        Ok(n_loaded)
+    }
+
+    fn load_rdb_contents(&mut self, contents: RdbContents) -> usize {
+        let now = self.clock.now_millis();
+        let mut n_loaded = 0usize;
+        for entry in contents.entries {
+            // keys that expired while the server was down are not loaded at all
+            if entry.expiry.is_some_and(|ex| ex <= now) {
+                continue;
+            }
+            self.insert_entry(
+                entry.key,
+                ValAndExpiry::with_deadline(entry.val, entry.expiry),
+            );
+            n_loaded += 1;
+        }
+        n_loaded
+    }
+
+    pub async fn run(mut self, mut rx: Receiver<ToDb>) {
+        // spawn replication coroutine
+        if let Some(master_host_port) = self.cfg.replicaof.clone() {
+            println!("Db::run: running replication handshake");
+            let sync_res = sync_with_master(&master_host_port, self.cfg.port, "?", -1).await;
+            match sync_res {
+                Ok((proxy, outcome)) => {
+                    println!("Db::run: replication handshake FINISHED");
+                    self.on_master_link_up(proxy, outcome);
+                }
+                Err(e) => {
+                    println!("Db::run: replication handshake failed: {e}");
+                    self.on_master_link_down();
+                }
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // periodic housekeeping, see Db::cron
+        let mut cron_interval = tokio::time::interval(Duration::from_millis(1000));
+        // 10 times per second, like redis' default hz
+        let mut expire_interval = tokio::time::interval(Duration::from_millis(100));
+        // blocked clients' deadlines are checked more often, only while there are some
+        let mut block_interval = tokio::time::interval(Duration::from_millis(10));
+
+        // long running co-routine that gets commands from only channel and executes them on the Db
+        println!("Db::run: Starting Query Loop");
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                _ = cron_interval.tick() => {
+                    self.cron();
+                    continue;
+                }
+                _ = expire_interval.tick() => {
+                    self.active_expire_cycle();
+                    continue;
+                }
+                _ = block_interval.tick(), if !self.blocked.is_empty() => {
+                    self.unblock_timed_out_clients();
+                    continue;
+                }
+            };
+            match msg {
+                Some(ToDb::QueryAndSender(qry, sx)) => {
+                    println!("Query loop received: {qry:?}");
+                    let sx1 = sx.clone();
+                    let resp_val = self.execute(&qry, sx1).await;
+                    let repl_byte_cnt_inc = resp_val.repl_byte_cnt_inc;
+                    if !resp_val.vals.is_empty() {
+                        // fails if the client went away meanwhile, which is fine
+                        sx.send(resp_val).await.unwrap_or_else(|e| {
+                            println!("Query loop: could not send reply, e:{e:?}")
+                        });
+                    } else {
+                        println!("Not sending resp_val via channel as there are no values...");
+                    }
+                    self.repl_byte_cnt += repl_byte_cnt_inc;
+                }
+                Some(ToDb::PassedReplStream(bstream, replica_addr, client_id)) => {
+                    println!("Query loop received ReplStream({replica_addr})");
+
+                    // The replica was registered at PSYNC time, so this receiver already
+                    // holds every write made after its snapshot was taken
+                    let repl_receiver = match self.pending_repl_receivers.remove(&client_id) {
+                        Some((_, repl_receiver)) => repl_receiver,
+                        None => self.register_replica(&replica_addr),
+                    };
+
+                    tokio::spawn(handle_replica(
+                        bstream,
+                        replica_addr,
+                        repl_receiver,
+                        self.tx.clone(),
+                    ));
+                }
+                Some(ToDb::ReplicaDisconnected(replica_addr)) => {
+                    println!("Query loop: replica {replica_addr} disconnected");
+                    self.replicas.remove(&replica_addr);
+                    // e.g. from a second PSYNC on the replica's link, never handed over
+                    self.pending_repl_receivers
+                        .retain(|_, (addr, _)| *addr != replica_addr);
+                }
+                Some(ToDb::MasterLinkUp(proxy, outcome)) => self.on_master_link_up(proxy, outcome),
+                Some(ToDb::MasterLinkDown) => self.on_master_link_down(),
+                Some(ToDb::BgSaveFinished(res)) => {
+                    self.bgsave_in_progress = false;
+                    match res {
+                        Ok(saved_at) => {
+                            println!("Background saving terminated with success");
+                            self.last_save = saved_at;
+                        }
+                        Err(e) => println!("Background saving error: {e}"),
+                    }
+                }
+                Some(ToDb::AofRewriteFinished(res)) => {
+                    self.aof_rewrite_in_progress = false;
+                    let res = res.and_then(|tmp_path| {
+                        self.finish_aof_rewrite(&tmp_path)
+                            .map_err(|e| e.to_string())
+                    });
+                    match res {
+                        Ok(()) => println!("Background AOF rewrite finished successfully"),
+                        Err(e) => {
+                            println!("Background AOF rewrite error: {e}");
+                            if let Some(aof) = &mut self.aof {
+                                aof.abort_rewrite();
+                            }
+                        }
+                    }
+                }
+                Some(ToDb::Exec(qry, multi, sx)) => {
+                    println!(
+                        "Query loop received EXEC of {n} commands",
+                        n = multi.queued.len()
+                    );
+                    let resp_val = self.execute_transaction(&qry, &multi).await;
+                    self.repl_byte_cnt += resp_val.repl_byte_cnt_inc;
+                    sx.send(resp_val).await.unwrap_or_else(|e| {
+                        println!("Query loop: could not send EXEC reply, e:{e:?}")
+                    });
+                }
+                Some(ToDb::ClientClosed(client_id)) => {
+                    self.pubsub.remove_client(client_id);
+                    self.watched.unwatch_all(client_id);
+                    // a replica that went away between PSYNC and the handover
+                    let pending = self.pending_repl_receivers.remove(&client_id);
+                    if let Some((replica_addr, _)) = pending {
+                        println!("Query loop: replica {replica_addr} closed before its handover");
+                        self.replicas.remove(&replica_addr);
+                    }
+                }
+                None => {
+                    println!("handle_commands: Incomming command channel closed. STOPPING");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Periodic tasks, run once per second from the query loop
+    fn cron(&mut self) {
+        if let Some(aof) = &mut self.aof {
+            aof.fsync_if_due();
+        }
+    }
+
+    /// Sample keys with an expiry and delete the expired ones, for as long as more than
+    /// a quarter of each sample turns out expired and the time budget allows.
+    /// Keys nobody reads again would otherwise stay in memory forever.
+    pub fn active_expire_cycle(&mut self) {
+        if self.is_replica() {
+            return;
+        }
+        let started = Instant::now();
+        loop {
+            let sample = self.expires.sample(ACTIVE_EXPIRE_SAMPLE);
+            let n_expired = sample
+                .iter()
+                .filter(|key| self.expire_if_needed(key))
+                .count();
+            if n_expired * 4 <= sample.len() || started.elapsed() > ACTIVE_EXPIRE_BUDGET {
+                break;
+            }
+        }
+    }
+
+    /// Replica side: apply the result of PSYNC and start processing the replication stream
+    fn on_master_link_up(&mut self, proxy: ProxyToMaster, outcome: PsyncOutcome) {
+        match outcome {
+            PsyncOutcome::FullResync {
+                replid,
+                offset,
+                contents,
+            } => {
+                self.h.clear();
+                self.expires.clear();
+                self.watched.touch_all();
+                let n_loaded = self.load_rdb_contents(contents);
+                println!(
+                    "Db: full resync, loaded {n_loaded} keys (master_replid={replid} offset={offset})"
+                );
+                // our offset continues from the master's offset at the time of the snapshot
+                self.repl_byte_cnt = offset as usize;
+                self.master_replid = Some(replid);
+            }
+            PsyncOutcome::Continue { replid } => {
+                println!(
+                    "Db: partial resync accepted, continuing from offset={offset}",
+                    offset = self.repl_byte_cnt
+                );
+                self.master_replid = Some(replid);
+            }
+        }
+
+        let repl_tx = self.tx.clone();
+        let master_addr = peer_addr_str_v2(proxy.conn.get_ref());
+        tokio::spawn(handle_stream_async(proxy.conn, master_addr, repl_tx, true));
+    }
+
+    /// Replica side: keep trying to reconnect, asking for a partial resync when possible
+    fn on_master_link_down(&mut self) {
+        let Some(master_host_port) = self.cfg.replicaof.clone() else {
+            return;
+        };
+        let (replid, offset) = match &self.master_replid {
+            // PSYNC takes the offset of the first byte we are missing
+            Some(replid) => (replid.clone(), self.repl_byte_cnt as i64 + 1),
+            None => ("?".to_string(), -1),
+        };
+        let listening_port = self.cfg.port;
+        let tx = self.tx.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(1000)).await;
+                println!("reconnecting to master {master_host_port} with PSYNC {replid} {offset}");
+                match sync_with_master(&master_host_port, listening_port, &replid, offset).await {
+                    Ok((proxy, outcome)) => {
+                        tx.send(ToDb::MasterLinkUp(proxy, outcome))
+                            .await
+                            .unwrap_or_else(|e| println!("Could not notify Db, e:{e:?}"));
+                        break;
+                    }
+                    Err(e) => println!("reconnecting to master failed: {e}"),
+                }
+            }
+        });
+    }
+
+    pub async fn execute(&mut self, query: &Query, sx1: Sender<QueryResult>) -> QueryResult {
+        use Command::*;
+
+        // self.repl_byte_cnt += query.deser_byte_cnt;
+
+        let client = query.client_id;
+        // RESP3 clients get messages as pushes, so they can go on running any command
+        let subscribed =
+            self.pubsub.subscription_count(client) > 0 && query.protocol == Protocol::Resp2;
+        if subscribed && !query.cmd.allowed_while_subscribed() {
+            let name = match query.cmd.to_bulk_array() {
+                Value::Array(parts) => parts[0].try_to_string().unwrap_or_default(),
+                _ => unreachable!("commands are arrays"),
+            };
+            let msg = format!(
+                "ERR Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                name = name.to_lowercase()
+            );
+            return QueryResult {
+                vals: vec![Value::SimpleError(msg)],
+                repl_byte_cnt_inc: query.deser_byte_cnt,
+                pass_stream: false,
+            };
+        }
+
+        let result: Vec<Value> = match &query.cmd {
+            // subscribed clients get pings in the same shape as messages
+            Ping if subscribed => vec![Value::Array(vec!["pong".into(), "".into()])],
+            Ping => vec![s_str("PONG")],
+            Echo(a) => vec![Value::BulkString(a.clone())],
+            SetKV(key, val, ex, flags) => vec![reply(self.exec_set(key, val, ex, flags))],
+            Get(key) => vec![reply(self.exec_get(key))],
+            Del(keys) => vec![self.exec_del(keys)],
+            Exists(keys) => vec![self.exec_exists(keys)],
+            Type(key) => vec![self.exec_type(key)],
+            Keys(pattern) => vec![self.exec_keys(pattern)],
+            Rename(src, dst) => vec![self.exec_rename(src, dst, false)],
+            RenameNx(src, dst) => vec![self.exec_rename(src, dst, true)],
+            Copy(src, dst, replace) => vec![self.exec_copy(src, dst, *replace)],
+            Expire(key, ms, flags) => {
+                let deadline = (self.clock.now_millis() as i64).saturating_add(*ms);
+                vec![self.exec_expire(key, deadline, flags)]
+            }
+            ExpireAt(key, deadline, flags) => vec![self.exec_expire(key, *deadline, flags)],
+            Ttl(key) => vec![self.exec_ttl(key, |ms| (ms + 500) / 1000)],
+            PTtl(key) => vec![self.exec_ttl(key, |ms| ms)],
+            ExpireTime(key) => vec![self.exec_expiretime(key, |ms| ms / 1000)],
+            PExpireTime(key) => vec![self.exec_expiretime(key, |ms| ms)],
+            Persist(key) => vec![self.exec_persist(key)],
+            IncrBy(key, by) => vec![reply(self.exec_incr_by(key, *by))],
+            IncrByFloat(key, by) => vec![reply(self.exec_incr_by_float(key, by))],
+            Append(key, val) => vec![reply(self.exec_append(key, val))],
+            Strlen(key) => vec![reply(self.exec_strlen(key))],
+            GetRange(key, start, end) => vec![reply(self.exec_getrange(key, *start, *end))],
+            SetRange(key, offset, val) => vec![reply(self.exec_setrange(key, *offset, val))],
+            GetDel(key) => vec![reply(self.exec_getdel(key))],
+            GetEx(key, ex) => vec![reply(self.exec_getex(key, ex))],
+            MGet(keys) => vec![self.exec_mget(keys)],
+            MSet(pairs) => vec![self.exec_mset(pairs, false)],
+            MSetNx(pairs) => vec![self.exec_mset(pairs, true)],
+            Push(key, elems, end) => vec![reply(self.exec_push(key, elems, *end))],
+            Pop(key, count, end) => vec![reply(self.exec_pop(key, *count, *end))],
+            BPop(keys, end, timeout) => {
+                let maybe_val = self.exec_bpop(keys, *end, *timeout, sx1);
+                maybe_val.into_iter().collect()
+            }
+            LMove(src, dst, from, to) => vec![reply(self.exec_lmove(src, dst, *from, *to))],
+            BLMove(src, dst, from, to, timeout) => {
+                let maybe_val = self.exec_blmove(src, dst, *from, *to, *timeout, sx1);
+                maybe_val.into_iter().collect()
+            }
+            LRange(key, start, stop) => vec![reply(self.exec_lrange(key, *start, *stop))],
+            LLen(key) => vec![reply(self.exec_llen(key))],
+            HSet(key, pairs) => vec![reply(self.exec_hset(key, pairs))],
+            HGet(key, field) => vec![reply(self.exec_hget(key, field))],
+            HGetAll(key) => vec![reply(self.exec_hgetall(key))],
+            HDel(key, fields) => vec![reply(self.exec_hdel(key, fields))],
+            SAdd(key, members) => vec![reply(self.exec_sadd(key, members))],
+            SRem(key, members) => vec![reply(self.exec_srem(key, members))],
+            SMembers(key) => vec![reply(self.exec_smembers(key))],
+            SIsMember(key, member) => vec![reply(self.exec_sismember(key, member))],
+            ZAdd(key, pairs, flags) => vec![reply(self.exec_zadd(key, pairs, flags))],
+            ZRange(key, spec) => vec![reply(self.exec_zrange(key, spec, query.protocol))],
+            ZScore(key, member) => vec![reply(self.exec_zscore(key, member))],
+            ZRem(key, members) => vec![reply(self.exec_zrem(key, members))],
+            XAdd(key, id, fields, opts) => vec![reply(self.exec_xadd(key, id, fields, opts))],
+            XRange(key, start, end, count, rev) => {
+                vec![reply(self.exec_xrange(key, *start, *end, *count, *rev))]
+            }
+            XRead(streams, count, block) => {
+                let maybe_val = self.exec_xread(streams, *count, *block, sx1);
+                maybe_val.into_iter().collect()
+            }
+            XLen(key) => vec![reply(self.exec_xlen(key))],
+            XTrim(key, trim) => vec![reply(self.exec_xtrim(key, trim))],
+            XSetId(key, last_id, entries_added, max_deleted_id) => {
+                let res = self.exec_xsetid(key, *last_id, *entries_added, *max_deleted_id);
+                vec![reply(res)]
+            }
+            XGroupCreate(key, group, id, mkstream, entries_read) => {
+                let res = self.exec_xgroup_create(key, group, id, *mkstream, *entries_read);
+                vec![reply(res)]
+            }
+            XGroupSetId(key, group, id, entries_read) => {
+                vec![reply(self.exec_xgroup_setid(key, group, id, *entries_read))]
+            }
+            XGroupDestroy(key, group) => vec![reply(self.exec_xgroup_destroy(key, group))],
+            XGroupCreateConsumer(key, group, consumer) => {
+                vec![reply(self.exec_xgroup_createconsumer(key, group, consumer))]
+            }
+            XGroupDelConsumer(key, group, consumer) => {
+                vec![reply(self.exec_xgroup_delconsumer(key, group, consumer))]
+            }
+            XReadGroup(group, consumer, streams, count, block, noack) => {
+                let maybe_val =
+                    self.exec_xreadgroup(group, consumer, streams, *count, *block, *noack, sx1);
+                maybe_val.into_iter().collect()
+            }
+            XAck(key, group, ids) => vec![reply(self.exec_xack(key, group, ids))],
+            XPending(key, group, range) => vec![reply(self.exec_xpending(key, group, range))],
+            XClaim(key, group, consumer, min_idle, ids, opts) => {
+                let res = self.exec_xclaim(key, group, consumer, *min_idle, ids, opts);
+                vec![reply(res)]
+            }
+            XAutoClaim(key, group, consumer, min_idle, start, count, justid) => {
+                let res =
+                    self.exec_xautoclaim(key, group, consumer, *min_idle, *start, *count, *justid);
+                vec![reply(res)]
+            }
+            Subscribe(kind, names) => match &query.pushes {
+                Some(pushes) => self.pubsub.subscribe(client, pushes, *kind, names),
+                None => vec![Value::SimpleError(
+                    "ERR this client can't subscribe".to_string(),
+                )],
+            },
+            Unsubscribe(kind, names) => self.pubsub.unsubscribe(client, *kind, names),
+            Publish(channel, message) => vec![self.exec_publish(channel, message)],
+            PubSubChannels(pattern) => {
+                let channels = self.pubsub.active_channels(pattern.as_ref());
+                vec![Value::Array(channels.iter().map(Value::from).collect())]
+            }
+            PubSubNumSub(channels) => {
+                let counts = channels
+                    .iter()
+                    .map(|ch| {
+                        (
+                            ch.into(),
+                            Value::Int(self.pubsub.num_subscribers(ch) as i64),
+                        )
+                    })
+                    .collect();
+                vec![Value::Map(counts)]
+            }
+            PubSubNumPat => vec![Value::Int(self.pubsub.num_patterns() as i64)],
+            Watch(keys) => {
+                for key in keys {
+                    // so that a key already expired doesn't count as changed later
+                    self.expire_if_needed(key);
+                }
+                self.watched.watch(client, keys);
+                vec![Value::ok()]
+            }
+            Unwatch | Discard => {
+                self.watched.unwatch_all(client);
+                vec![Value::ok()]
+            }
+            // only seen when replaying the aof: connections keep their own MULTI state
+            // and hand transactions over to execute_transaction
+            Multi => vec![Value::ok()],
+            Exec => vec![Value::SimpleError("ERR EXEC without MULTI".to_string())],
+            Hello(version, auth, name) => {
+                let protocol = version.unwrap_or(query.protocol);
+                let hello = self.exec_hello(protocol, auth, name, query.client_id);
+                vec![reply(hello)]
+            }
+            Info(arg) => vec![self.exec_info(arg)],
+            Save => vec![self.exec_save()],
+            BgSave => vec![self.exec_bgsave()],
+            LastSave => vec![Value::Int(self.last_save as i64)],
+            BgRewriteAof => vec![self.exec_bgrewriteaof()],
+            Psync(id, offset) => {
+                let vals = self.exec_psync(id, *offset, query);
+                return QueryResult {
+                    vals,
+                    repl_byte_cnt_inc: 0,
+                    pass_stream: true,
+                };
+            }
+            ReplConf(key, val) => {
+                vec![self.exec_repl_conf(key, val)]
+            }
+            ReplConfGetAck(_) => {
+                vec![self.exec_repl_conf_get_ack()]
+            }
+            ReplConfAck(byte_cnt) => {
+                self.exec_repl_conf_ack(*byte_cnt as u64, &query.client_info);
+                vec![]
+            }
+            Wait(n_repls, timeout) => {
+                let deadline = (self.clock.now_millis() as i64).saturating_add(*timeout);
+                // replicas have to ack what was written so far, not the GETACK as well
+                let offset = self.replication_offset;
+                if !self.in_exec {
+                    self.request_acks();
+                }
+                let maybe_val = self.exec_wait(*n_repls as usize, offset, deadline, query, sx1).await;
+                match maybe_val {
+                    Some(val) => vec![val],
+                    None => vec![],
+                }
+            }
+            WaitInternal(n_repls, deadline, offset) => {
+                let maybe_val = self.exec_wait(*n_repls as usize, *offset, *deadline, query, sx1).await;
+                match maybe_val {
+                    Some(val) => vec![val],
+                    None => vec![],
+                }
+            }
+        };
+        // in a transaction, blocked clients only see its end result
+        if !self.in_exec {
+            self.serve_blocked_clients();
+        }
+
+        QueryResult {
+            vals: result,
+            repl_byte_cnt_inc: query.deser_byte_cnt,
+            pass_stream: false,
+        }
+    }
+
+    /// EXEC: run the commands queued since MULTI, all at once, unless one of them could
+    /// not be queued or a key the client watches changed since WATCH.
+    /// Writes reach replicas and the aof wrapped in MULTI / EXEC.
+    pub async fn execute_transaction(&mut self, exec: &Query, multi: &Transaction) -> QueryResult {
+        let client = exec.client_id;
+        let queued_byte_cnt: usize = multi.queued.iter().map(|qry| qry.deser_byte_cnt).sum();
+        let repl_byte_cnt_inc = multi.multi_byte_cnt + queued_byte_cnt + exec.deser_byte_cnt;
+
+        // watched keys that expired since count as changed
+        for key in self.watched.keys_of(client) {
+            self.expire_if_needed(&key);
+        }
+        let unchanged = self.watched.unwatch_all(client);
+
+        let vals = if multi.has_errors {
+            let msg = "EXECABORT Transaction discarded because of previous errors.";
+            vec![Value::SimpleError(msg.to_string())]
+        } else if !unchanged {
+            vec![Value::NullArray]
+        } else {
+            self.in_exec = true;
+            let mut replies = Vec::with_capacity(multi.queued.len());
+            for query in &multi.queued {
+                // nothing blocks inside a transaction, replies all come right away
+                let (sx, _rx) = channel(1);
+                replies.extend(self.execute(query, sx).await.vals);
+            }
+            self.in_exec = false;
+            if std::mem::take(&mut self.multi_propagated) {
+                self.propagate(&Command::Exec);
+            }
+            self.serve_blocked_clients();
+            vec![Value::Array(replies)]
+        };
+
+        QueryResult {
+            vals,
+            repl_byte_cnt_inc,
+            pass_stream: false,
+        }
+    }
+
+    /// Start propagating writes to a replica. Writes are buffered in the returned channel
+    /// until a handle_replica task starts consuming it.
+    fn register_replica(&mut self, replica_addr: &str) -> UnboundedReceiver<ToReplica> {
+        let (to_replica, repl_receiver) = unbounded_channel::<ToReplica>();
+        self.replicas.insert(
+            replica_addr.to_string(),
+            ReplicaInfo {
+                // host_port: replica_addr,
+                acked_byte_cnt: 0,
+                sender: to_replica,
+            },
+        );
+        repl_receiver
+    }
+
+    fn exec_psync(&mut self, replid: &str, offset: i64, query: &Query) -> Vec<Value> {
+        let replica_addr = query.client_info.addr().to_string();
+
+        // The replica asks for the bytes starting at (1-based) `offset`
+        let missing_bytes = match &self.backlog {
+            Some(backlog) if replid == self.replication_id && offset > 0 => {
+                backlog.bytes_from(offset as u64 - 1)
+            }
+            _ => None,
+        };
+
+        let repl_receiver = self.register_replica(&replica_addr);
+        self.pending_repl_receivers
+            .insert(query.client_id, (replica_addr.clone(), repl_receiver));
+
+        if let Some(missing_bytes) = missing_bytes {
+            println!(
+                "Db::exec_psync: partial resync of {replica_addr}, sending {n} bytes from backlog",
+                n = missing_bytes.len()
+            );
+            if !missing_bytes.is_empty() {
+                self.replicas[&replica_addr]
+                    .sender
+                    .send(ToReplica::Bytes(missing_bytes, "partial resync".into()))
+                    .unwrap_or_else(|e| {
+                        println!("Unable to send msg to replica via channel, e:{e:?} ")
+                    });
+            }
+            let reply_str = format!("CONTINUE {repl_id}", repl_id = self.replication_id);
+            return vec![s_str(&reply_str)];
+        }
+
+        if replid != "?" {
+            println!("Db::exec_psync: can't continue from {replid} {offset}, doing full resync");
+        }
+        if self.backlog.is_none() {
+            self.backlog = Some(ReplBacklog::new(
+                self.cfg.repl_backlog_size,
+                self.replication_offset,
+            ));
+        }
+
+        let reply_str = format!(
+            "FULLRESYNC {repl_id} {offset}",
+            repl_id = self.replication_id,
+            offset = self.replication_offset
+        );
+        let rdb_bytes = serialize_rdb(&self.rdb_entries(), self.clock.now_millis() / 1000);
+        vec![s_str(&reply_str), Value::FileContents(rdb_bytes.into())]
+    }
+
+    /// Log a write command to the aof and send it down the replication stream:
+    /// to the backlog and to all replicas
+    fn propagate(&mut self, cmd: &Command) {
+        for key in cmd.written_keys() {
+            self.watched.touch(key);
+        }
+        self.propagate_multi_if_needed();
+        if let Some(aof) = &mut self.aof {
+            aof.append(cmd).unwrap_or_else(|e| {
+                println!(
+                    "Db::propagate: writing to {p} failed: {e}",
+                    p = aof.path().display()
+                )
+            });
+        }
+        self.propagate_to_replicas(cmd);
+    }
+
+    /// Writes of a transaction are preceded by a MULTI, sent along with the first of them
+    fn propagate_multi_if_needed(&mut self) {
+        if self.in_exec && !self.multi_propagated {
+            self.multi_propagated = true;
+            self.propagate(&Command::Multi);
+        }
+    }
+
+    /// Send `cmd` to replicas only, for commands that don't change the dataset, like PUBLISH
+    fn propagate_to_replicas(&mut self, cmd: &Command) {
+        self.propagate_multi_if_needed();
+        if self.replicas.is_empty() && self.backlog.is_none() {
+            return;
+        }
+        let value = cmd.to_bulk_array();
+        let bytes = serialize(&value).unwrap().into_inner();
+        self.replication_offset += bytes.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.append(&bytes);
+        }
+
+        println!(
+            "Db::propagate: attempting replication to {n} replicas.",
+            n = self.replicas.len()
+        );
+
+        for (repl_key, replica) in self.replicas.iter() {
+            let msg_to_replica = ToReplica::Bytes(
+                bytes.clone(),
+                format!("attempting replication to {repl_key} -- {cmd:?}"),
+            );
+
+            replica.sender.send(msg_to_replica).unwrap_or_else(|e| {
+                println!("Unable to send msg to replica via channel, e:{e:?} ")
+            });
+        }
+    }
+
+    fn exec_set(
+        &mut self,
+        key: &Bytes,
+        val: &Bytes,
+        ex: &Option<SetExpiry>,
+        flags: &SetFlags,
+    ) -> Result<Value, Value> {
+        let old = self.live_entry(key);
+        let exists = old.is_some();
+        let current = old.map_or(u64::MAX, |val_ex| val_ex.ex);
+        // SET replaces a value of any type, but GET can only reply with a string
+        let old_val = match old.map(|val_ex| &val_ex.val) {
+            Some(DbVal::Str(old_val)) => Some(old_val.clone()),
+            Some(_) if flags.get => return Err(wrong_type_err()),
+            _ => None,
+        };
+
+        let do_set = !(flags.nx && exists || flags.xx && !exists);
+        if do_set {
+            let deadline = Some(self.resolve_expiry(ex, current)).filter(|d| *d != u64::MAX);
+            self.insert_entry(
+                key.clone(),
+                ValAndExpiry::with_deadline(val.clone().into(), deadline),
+            );
+
+            // Replicas and the aof get the outcome, with the expiry as an absolute time,
+            // so they end up with the same deadline no matter when they apply it
+            self.propagate(&Command::SetKV(
+                key.clone(),
+                val.clone(),
+                deadline.map(SetExpiry::PxAt),
+                SetFlags::default(),
+            ));
+        }
+
+        Ok(match (flags.get, do_set) {
+            (true, _) => old_val.map_or(Value::NullBulkString, Value::BulkString),
+            (false, true) => Value::ok(),
+            (false, false) => Value::NullBulkString,
+        })
+    }
+
+    fn is_replica(&self) -> bool {
+        self.cfg.role() == Role::Slave
+    }
+
+    // All changes to keys or expiries go through insert_entry, remove_entry and set_expiry,
+    // which keep `expires` in sync with `h`
+
+    fn insert_entry(&mut self, key: Bytes, val_ex: ValAndExpiry) {
+        if val_ex.ex != u64::MAX {
+            self.expires.insert(&key);
+        } else {
+            self.expires.remove(&key);
+        }
+        // e.g. RENAME of a list onto a key that clients are blocked on
+        if matches!(val_ex.val, DbVal::List(_) | DbVal::Stream(_)) {
+            self.signal_key_ready(&key);
+        }
+        self.h.insert(key, val_ex);
+    }
+
+    fn remove_entry(&mut self, key: &Bytes) -> Option<ValAndExpiry> {
+        self.expires.remove(key);
+        self.h.remove(key)
+    }
+
+    fn set_expiry(&mut self, key: &Bytes, ex: u64) {
+        if let Some(val_ex) = self.h.get_mut(key) {
+            val_ex.ex = ex;
+            if ex != u64::MAX {
+                self.expires.insert(key);
+            } else {
+                self.expires.remove(key);
+            }
+        }
+    }
+
+    /// Delete `key` if it has expired, propagating a DEL so that replicas and the aof follow.
+    /// Replicas never delete on their own clock, they only hide the key until the DEL arrives.
+    /// Returns true if the key was deleted.
+    fn expire_if_needed(&mut self, key: &Bytes) -> bool {
+        let expired = self
+            .h
+            .get(key)
+            .is_some_and(|val_ex| val_ex.ex <= self.clock.now_millis());
+        if !expired || self.is_replica() {
+            return false;
+        }
+        self.remove_entry(key);
+        self.propagate(&Command::Del(vec![key.clone()]));
+        true
+    }
+
+    /// The entry for `key`, unless it is missing or expired
+    fn live_entry(&mut self, key: &Bytes) -> Option<&ValAndExpiry> {
+        self.expire_if_needed(key);
+        self.h.get(key).filter(|val_ex| val_ex.ex > self.clock.now_millis())
+    }
+
+    /// The value at `key` as picked by `as_type`, e.g. DbVal::as_list_mut. None if the key
+    /// does not exist, a WRONGTYPE error if it holds a value of another type.
+    fn get_typed<T>(
+        &mut self,
+        key: &Bytes,
+        as_type: fn(&mut DbVal) -> Option<&mut T>,
+    ) -> Result<Option<&mut T>, Value> {
+        if self.live_entry(key).is_none() {
+            return Ok(None);
+        }
+        let val_ex = self.h.get_mut(key).expect("checked above");
+        as_type(&mut val_ex.val)
+            .map(Some)
+            .ok_or_else(wrong_type_err)
+    }
+
+    /// Like get_typed, but a missing key is first created, without expiry, holding `empty()`
+    fn get_typed_or_insert<T>(
+        &mut self,
+        key: &Bytes,
+        as_type: fn(&mut DbVal) -> Option<&mut T>,
+        empty: fn() -> DbVal,
+    ) -> Result<&mut T, Value> {
+        if self.live_entry(key).is_none() {
+            self.insert_entry(key.clone(), ValAndExpiry::with_deadline(empty(), None));
+        }
+        self.get_typed(key, as_type)
+            .map(|val| val.expect("inserted above"))
+    }
+
+    /// Delete `key` if it holds a collection that is now empty
+    fn remove_if_empty(&mut self, key: &Bytes) {
+        if self
+            .h
+            .get(key)
+            .is_some_and(|val_ex| val_ex.val.is_empty_collection())
+        {
+            self.remove_entry(key);
+        }
+    }
+
+    /// Absolute deadline (u64::MAX for none) for an expiry option given to SET or GETEX
+    fn resolve_expiry(&self, ex: &Option<SetExpiry>, current: u64) -> u64 {
+        match ex {
+            None | Some(SetExpiry::Persist) => u64::MAX,
+            Some(SetExpiry::Px(interv)) => self.clock.now_millis().saturating_add(*interv),
+            Some(SetExpiry::PxAt(deadline)) => *deadline,
+            Some(SetExpiry::KeepTtl) => current,
+        }
+    }
+
+    fn exec_get(&mut self, key: &Bytes) -> Result<Value, Value> {
+        match self.get_typed(key, DbVal::as_str_mut)? {
+            Some(val) => Ok(Value::BulkString(val.clone())),
+            None => {
+                println!("Key not found: `{key:?}`");
+                Ok(Value::NullBulkString)
+            }
+        }
+    }
+
+    fn exec_del(&mut self, keys: &[Bytes]) -> Value {
+        let deleted: Vec<Bytes> = keys
+            .iter()
+            .filter(|key| !self.expire_if_needed(key) && self.remove_entry(key).is_some())
+            .cloned()
+            .collect();
+
+        let n_deleted = deleted.len();
+        if n_deleted > 0 {
+            self.propagate(&Command::Del(deleted));
+        }
+        Value::Int(n_deleted as i64)
+    }
+
+    fn exec_exists(&mut self, keys: &[Bytes]) -> Value {
+        // a key given more than once is counted more than once, as in redis
+        let n_found = keys
+            .iter()
+            .filter(|key| self.live_entry(key).is_some())
+            .count();
+        Value::Int(n_found as i64)
+    }
+
+    fn exec_type(&mut self, key: &Bytes) -> Value {
+        match self.live_entry(key) {
+            Some(val_ex) => s_str(val_ex.val.type_name()),
+            None => s_str("none"),
+        }
+    }
+
+    /// Messages are also published on replicas, for their own subscribers
+    fn exec_publish(&mut self, channel: &Bytes, message: &Bytes) -> Value {
+        let n_received = self.pubsub.publish(channel, message);
+        self.propagate_to_replicas(&Command::Publish(channel.clone(), message.clone()));
+        Value::Int(n_received as i64)
+    }
+
+    fn exec_keys(&self, pattern: &Bytes) -> Value {
+        let now = self.clock.now_millis();
+        let keys: Vec<Value> = self
+            .h
+            .iter()
+            .filter(|(key, val_ex)| {
+                val_ex.ex > now && glob_match(pattern.as_bytes(), key.as_bytes())
+            })
+            .map(|(key, _)| Value::from(key))
+            .collect();
+        keys.into()
+    }
+
+    /// RENAME / RENAMENX, the expiry moves along with the value
+    fn exec_rename(&mut self, src: &Bytes, dst: &Bytes, only_if_new: bool) -> Value {
+        if self.live_entry(src).is_none() {
+            return Value::SimpleError("ERR no such key".into());
+        }
+        if only_if_new && self.live_entry(dst).is_some() {
+            return Value::Int(0);
+        }
+
+        if src != dst {
+            let val_ex = self.remove_entry(src).expect("checked above");
+            self.insert_entry(dst.clone(), val_ex);
+        }
+        if only_if_new {
+            self.propagate(&Command::RenameNx(src.clone(), dst.clone()));
+            Value::Int(1)
+        } else {
+            self.propagate(&Command::Rename(src.clone(), dst.clone()));
+            Value::ok()
+        }
+    }
+
+    fn exec_copy(&mut self, src: &Bytes, dst: &Bytes, replace: bool) -> Value {
+        if src == dst {
+            return Value::SimpleError("ERR source and destination objects are the same".into());
+        }
+        let Some(val_ex) = self.live_entry(src).cloned() else {
+            return Value::Int(0);
+        };
+        if !replace && self.live_entry(dst).is_some() {
+            return Value::Int(0);
+        }
+
+        self.insert_entry(dst.clone(), val_ex);
+        self.propagate(&Command::Copy(src.clone(), dst.clone(), replace));
+        Value::Int(1)
+    }
+
+    /// Replace the value of `key`, keeping its expiry, or create it without expiry
+    fn set_val_keep_ttl(&mut self, key: &Bytes, val: Bytes) {
+        match self.live_entry(key).is_some() {
+            true => self.h.get_mut(key).expect("checked above").val = DbVal::Str(val),
+            false => self.insert_entry(key.clone(), ValAndExpiry::with_deadline(val.into(), None)),
+        }
+    }
+
+    fn exec_incr_by(&mut self, key: &Bytes, by: i64) -> Result<Value, Value> {
+        let current = match self.get_typed(key, DbVal::as_str_mut)? {
+            None => Some(0),
+            Some(val) => parse_int(val.as_bytes()),
+        };
+        let Some(current) = current else {
+            return Err(Value::SimpleError(
+                "ERR value is not an integer or out of range".into(),
+            ));
+        };
+        let Some(new) = current.checked_add(by) else {
+            return Err(Value::SimpleError(
+                "ERR increment or decrement would overflow".into(),
+            ));
+        };
+
+        self.set_val_keep_ttl(key, new.to_string().as_str().into());
+        self.propagate(&Command::IncrBy(key.clone(), by));
+        Ok(Value::Int(new))
+    }
+
+    fn exec_incr_by_float(&mut self, key: &Bytes, by: &Bytes) -> Result<Value, Value> {
+        // long doubles like in redis, whose rounding differs from f64's
+        let current = match self.get_typed(key, DbVal::as_str_mut)? {
+            None => LongDouble::parse(b"0"),
+            Some(val) => LongDouble::parse(val.as_bytes()),
+        };
+        let Some(current) = current else {
+            return Err(Value::SimpleError("ERR value is not a valid float".into()));
+        };
+        let by = LongDouble::parse(by.as_bytes()).expect("checked by parse_cmd");
+        let Some(new) = current.checked_add(by) else {
+            return Err(Value::SimpleError(
+                "ERR increment would produce NaN or Infinity".into(),
+            ));
+        };
+
+        let new: Bytes = new.to_string().as_str().into();
+        self.set_val_keep_ttl(key, new.clone());
+        // propagated as the resulting value, so float formatting can't make replicas drift
+        self.propagate(&Command::SetKV(
+            key.clone(),
+            new.clone(),
+            Some(SetExpiry::KeepTtl),
+            SetFlags::default(),
+        ));
+        Ok(Value::BulkString(new))
+    }
+
+    fn exec_append(&mut self, key: &Bytes, val: &Bytes) -> Result<Value, Value> {
+        let new_len = match self.get_typed(key, DbVal::as_str_mut)? {
+            Some(current) => {
+                current.as_bytes_mut().extend_from_slice(val.as_bytes());
+                current.len()
+            }
+            None => {
+                self.insert_entry(
+                    key.clone(),
+                    ValAndExpiry::with_deadline(val.clone().into(), None),
+                );
+                val.len()
+            }
+        };
+        self.propagate(&Command::Append(key.clone(), val.clone()));
+        Ok(Value::Int(new_len as i64))
+    }
+
+    fn exec_strlen(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let len = self
+            .get_typed(key, DbVal::as_str_mut)?
+            .map_or(0, |val| val.len());
+        Ok(Value::Int(len as i64))
+    }
+
+    /// Negative offsets count from the end, both ends are inclusive and clamped to the string
+    fn exec_getrange(&mut self, key: &Bytes, start: i64, end: i64) -> Result<Value, Value> {
+        let Some(val) = self.get_typed(key, DbVal::as_str_mut)? else {
+            return Ok(Value::BulkString("".into()));
+        };
+        Ok(match index_range(start, end, val.len()) {
+            Some((start, end)) => Value::BulkString(val.as_bytes()[start..=end].into()),
+            None => Value::BulkString("".into()),
+        })
+    }
+
+    fn exec_setrange(&mut self, key: &Bytes, offset: usize, val: &Bytes) -> Result<Value, Value> {
+        let current_len = self.get_typed(key, DbVal::as_str_mut)?.map(|val| val.len());
+        if val.is_empty() {
+            // nothing to write, and no key gets created
+            return Ok(Value::Int(current_len.unwrap_or(0) as i64));
+        }
+        if offset.saturating_add(val.len()) > MAX_STRING_LEN {
+            return Err(Value::SimpleError(
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)".into(),
+            ));
+        }
+
+        let current = self
+            .get_typed_or_insert(key, DbVal::as_str_mut, || DbVal::Str(Vec::new().into()))?
+            .as_bytes_mut();
+        let end = offset + val.len();
+        if current.len() < end {
+            // the gap, if any, is zero padded
+            current.resize(end, 0);
+        }
+        current[offset..end].copy_from_slice(val.as_bytes());
+        let new_len = current.len();
+
+        self.propagate(&Command::SetRange(key.clone(), offset, val.clone()));
+        Ok(Value::Int(new_len as i64))
+    }
+
+    fn exec_getdel(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let Some(val) = self.get_typed(key, DbVal::as_str_mut)?.cloned() else {
+            return Ok(Value::NullBulkString);
+        };
+        self.remove_entry(key);
+        self.propagate(&Command::Del(vec![key.clone()]));
+        Ok(Value::BulkString(val))
+    }
+
+    fn exec_getex(&mut self, key: &Bytes, ex: &SetExpiry) -> Result<Value, Value> {
+        let Some(val) = self.get_typed(key, DbVal::as_str_mut)?.cloned() else {
+            return Ok(Value::NullBulkString);
+        };
+        let current = self.h[key].ex;
+
+        let new = self.resolve_expiry(&Some(*ex), current);
+        if new == current {
+            // e.g. GETEX without options, nothing to change or propagate
+            return Ok(Value::BulkString(val));
+        }
+        if new <= self.clock.now_millis() {
+            self.remove_entry(key);
+            self.propagate(&Command::Del(vec![key.clone()]));
+        } else if new == u64::MAX {
+            self.set_expiry(key, new);
+            self.propagate(&Command::Persist(key.clone()));
+        } else {
+            self.set_expiry(key, new);
+            self.propagate(&Command::ExpireAt(
+                key.clone(),
+                new as i64,
+                ExpireFlags::default(),
+            ));
+        }
+        Ok(Value::BulkString(val))
+    }
+
+    fn exec_mget(&mut self, keys: &[Bytes]) -> Value {
+        let vals: Vec<Value> = keys
+            .iter()
+            .map(|key| match self.live_entry(key) {
+                Some(ValAndExpiry {
+                    val: DbVal::Str(val),
+                    ..
+                }) => Value::BulkString(val.clone()),
+                // keys holding other types read as missing, no WRONGTYPE here
+                _ => Value::NullBulkString,
+            })
+            .collect();
+        vals.into()
+    }
+
+    /// MSET, or MSETNX when `only_if_none_exist`, which then sets either all keys or none
+    fn exec_mset(&mut self, pairs: &[(Bytes, Bytes)], only_if_none_exist: bool) -> Value {
+        if only_if_none_exist && pairs.iter().any(|(key, _)| self.live_entry(key).is_some()) {
+            return Value::Int(0);
+        }
+        for (key, val) in pairs {
+            self.insert_entry(
+                key.clone(),
+                ValAndExpiry::with_deadline(val.clone().into(), None),
+            );
+        }
+        self.propagate(&Command::MSet(pairs.to_vec()));
+
+        if only_if_none_exist {
+            Value::Int(1)
+        } else {
+            Value::ok()
+        }
+    }
+
+    fn exec_push(&mut self, key: &Bytes, elems: &[Bytes], end: ListEnd) -> Result<Value, Value> {
+        let len = self.push_to_list(key, elems, end)?;
+        self.propagate(&Command::Push(key.clone(), elems.to_vec(), end));
+        Ok(Value::Int(len as i64))
+    }
+
+    /// Push onto the list at `key`, creating it if needed, and let clients blocked on it know.
+    /// Returns the new length.
+    fn push_to_list(&mut self, key: &Bytes, elems: &[Bytes], end: ListEnd) -> Result<usize, Value> {
+        let list =
+            self.get_typed_or_insert(key, DbVal::as_list_mut, || DbVal::List(VecDeque::new()))?;
+        for elem in elems {
+            match end {
+                ListEnd::Left => list.push_front(elem.clone()),
+                ListEnd::Right => list.push_back(elem.clone()),
+            }
+        }
+        let len = list.len();
+        self.signal_key_ready(key);
+        Ok(len)
+    }
+
+    /// LPOP / RPOP: a single element, or an array of up to `count` elements if one is given
+    fn exec_pop(
+        &mut self,
+        key: &Bytes,
+        count: Option<usize>,
+        end: ListEnd,
+    ) -> Result<Value, Value> {
+        let Some(list) = self.get_typed(key, DbVal::as_list_mut)? else {
+            return Ok(match count {
+                Some(_) => Value::NullArray,
+                None => Value::NullBulkString,
+            });
+        };
+        let n_popped = count.unwrap_or(1).min(list.len());
+        let popped: Vec<Bytes> = (0..n_popped)
+            .filter_map(|_| match end {
+                ListEnd::Left => list.pop_front(),
+                ListEnd::Right => list.pop_back(),
+            })
+            .collect();
+
+        if !popped.is_empty() {
+            self.remove_if_empty(key);
+            self.propagate(&Command::Pop(key.clone(), count, end));
+        }
+        Ok(match count {
+            Some(_) => popped.iter().map(Value::from).collect::<Vec<_>>().into(),
+            None => popped
+                .into_iter()
+                .next()
+                .map_or(Value::NullBulkString, Value::BulkString),
+        })
+    }
+
+    /// BLPOP / BRPOP: pop from the first of `keys` holding a list, or block until one gets
+    /// elements. None when blocked, the reply is sent once served or timed out.
+    fn exec_bpop(
+        &mut self,
+        keys: &[Bytes],
+        end: ListEnd,
+        timeout: u64,
+        sx: Sender<QueryResult>,
+    ) -> Option<Value> {
+        for key in keys {
+            match self.get_typed(key, DbVal::as_list_mut) {
+                Err(err) => return Some(err),
+                // lists are never empty
+                Ok(Some(_)) => return Some(self.pop_with_key(key, end)),
+                Ok(None) => {}
+            }
+        }
+        self.block_client(keys.to_vec(), BlockedOp::Pop(end), timeout, sx)
+    }
+
+    /// Pop an element for BLPOP / BRPOP, from a list known to exist. Replicas and the aof
+    /// get a plain LPOP / RPOP, as in redis.
+    fn pop_with_key(&mut self, key: &Bytes, end: ListEnd) -> Value {
+        match self.exec_pop(key, None, end) {
+            Ok(elem) => vec![key.into(), elem].into(),
+            Err(err) => err,
+        }
+    }
+
+    /// LMOVE: pop from `src` and push onto `dst`, which may be the same list
+    fn exec_lmove(
+        &mut self,
+        src: &Bytes,
+        dst: &Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Value, Value> {
+        if self.get_typed(src, DbVal::as_list_mut)?.is_none() {
+            return Ok(Value::NullBulkString);
+        }
+        // checked before popping, so that nothing is lost
+        self.get_typed(dst, DbVal::as_list_mut)?;
+
+        let list = self
+            .get_typed(src, DbVal::as_list_mut)?
+            .expect("checked above");
+        let elem = match from {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }
+        .expect("lists are never empty");
+        self.remove_if_empty(src);
+        self.push_to_list(dst, std::slice::from_ref(&elem), to)?;
+
+        self.propagate(&Command::LMove(src.clone(), dst.clone(), from, to));
+        Ok(Value::BulkString(elem))
+    }
+
+    fn exec_blmove(
+        &mut self,
+        src: &Bytes,
+        dst: &Bytes,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: u64,
+        sx: Sender<QueryResult>,
+    ) -> Option<Value> {
+        match self.get_typed(src, DbVal::as_list_mut) {
+            Err(err) => Some(err),
+            Ok(Some(_)) => Some(reply(self.exec_lmove(src, dst, from, to))),
+            Ok(None) => {
+                let op = BlockedOp::Move(dst.clone(), from, to);
+                self.block_client(vec![src.clone()], op, timeout, sx)
+            }
+        }
+    }
+
+    /// Park a client until one of `keys` gets elements, or `timeout` millis pass (0 for never).
+    /// Inside a transaction nothing blocks: the reply is the timeout one, right away.
+    fn block_client(
+        &mut self,
+        keys: Vec<Bytes>,
+        op: BlockedOp,
+        timeout: u64,
+        sx: Sender<QueryResult>,
+    ) -> Option<Value> {
+        if self.in_exec {
+            return Some(op.timeout_reply());
+        }
+        let block_id = self.next_block_id;
+        self.next_block_id += 1;
+        for key in &keys {
+            self.waiting_on_key
+                .entry(key.clone())
+                .or_default()
+                .push_back(block_id);
+        }
+
+        // checked by unblock_timed_out_clients, from the query loop
+        let deadline = (timeout > 0).then(|| self.clock.now_millis().saturating_add(timeout));
+        let client = BlockedClient {
+            keys,
+            op,
+            sx,
+            deadline,
+        };
+        self.blocked.insert(block_id, client);
+        None
+    }
+
+    /// Forget a blocked client, on every key it waits on
+    fn unblock_client(&mut self, block_id: u64) -> Option<BlockedClient> {
+        let client = self.blocked.remove(&block_id)?;
+        for key in &client.keys {
+            if let Some(ids) = self.waiting_on_key.get_mut(key) {
+                ids.retain(|id| *id != block_id);
+                if ids.is_empty() {
+                    self.waiting_on_key.remove(key);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    /// Reply to blocked clients whose deadline passed, and forget those that disconnected
+    pub fn unblock_timed_out_clients(&mut self) {
+        let now = self.clock.now_millis();
+        let done: Vec<u64> = self
+            .blocked
+            .iter()
+            .filter(|(_, client)| {
+                client.deadline.is_some_and(|deadline| deadline <= now) || client.sx.is_closed()
+            })
+            .map(|(block_id, _)| *block_id)
+            .collect();
+        for block_id in done {
+            let client = self.unblock_client(block_id).expect("collected above");
+            let val = client.op.timeout_reply();
+            // fails if the client disconnected, which is fine
+            let _ = client.sx.try_send(QueryResult {
+                vals: vec![val],
+                repl_byte_cnt_inc: 0,
+                pass_stream: false,
+            });
+        }
+    }
+
+    fn signal_key_ready(&mut self, key: &Bytes) {
+        if self.waiting_on_key.contains_key(key) && !self.ready_keys.contains(key) {
+            self.ready_keys.push(key.clone());
+        }
+    }
+
+    /// Hand the data the last command added to clients blocked on those keys, oldest
+    /// first. BLMOVE may push onto yet another key with waiters, hence the loop.
+    fn serve_blocked_clients(&mut self) {
+        while !self.ready_keys.is_empty() {
+            for key in std::mem::take(&mut self.ready_keys) {
+                self.serve_key(&key);
+            }
+        }
+    }
+
+    fn serve_key(&mut self, key: &Bytes) {
+        let Some(waiting) = self.waiting_on_key.get(key) else {
+            return;
+        };
+        // Pops and XREADGROUPs on the same group take an element each, while any number
+        // of XREADs can get the same entries.
+        // Served clients are unblocked along the way, hence the copy.
+        let waiting: Vec<u64> = waiting.iter().copied().collect();
+        for block_id in waiting {
+            let Some(client) = self.blocked.get(&block_id) else {
+                continue;
+            };
+            if client.sx.is_closed() {
+                // disconnected, the data stays for the next client
+                self.unblock_client(block_id);
+                continue;
+            }
+            let (keys, op) = (client.keys.clone(), client.op.clone());
+            let Some(val) = self.serve_blocked_op(key, &keys, &op) else {
+                continue;
+            };
+            let client = self.unblock_client(block_id).expect("checked above");
+            client
+                .sx
+                .try_send(QueryResult {
+                    vals: vec![val],
+                    repl_byte_cnt_inc: 0,
+                    pass_stream: false,
+                })
+                .unwrap_or_else(|e| println!("Db::serve_key: could not reply, e:{e:?}"));
+        }
+    }
+
+    /// The reply to a client blocked in `op` on `keys`, now that `key` got data.
+    /// None if there is nothing for it, e.g. because earlier clients took it all.
+    fn serve_blocked_op(&mut self, key: &Bytes, keys: &[Bytes], op: &BlockedOp) -> Option<Value> {
+        let has_list = matches!(self.get_typed(key, DbVal::as_list_mut), Ok(Some(_)));
+        match op {
+            BlockedOp::Pop(end) => has_list.then(|| self.pop_with_key(key, *end)),
+            BlockedOp::Move(dst, from, to) => {
+                has_list.then(|| reply(self.exec_lmove(key, dst, *from, *to)))
+            }
+            BlockedOp::Read(ids, count) => match self.xread_reply(keys, ids, *count) {
+                Ok(val) => val,
+                Err(err) => Some(err),
+            },
+            BlockedOp::ReadGroup(group, consumer, count, noack) => {
+                let streams: Vec<(Bytes, XReadGroupFrom)> = keys
+                    .iter()
+                    .map(|key| (key.clone(), XReadGroupFrom::New))
+                    .collect();
+                match self.xreadgroup_reply(group, consumer, &streams, *count, *noack) {
+                    Ok(val) => val,
+                    Err(err) => Some(err),
+                }
+            }
+        }
+    }
+
+    fn exec_lrange(&mut self, key: &Bytes, start: i64, stop: i64) -> Result<Value, Value> {
+        let elems: Vec<Value> = match self.get_typed(key, DbVal::as_list_mut)? {
+            Some(list) => match index_range(start, stop, list.len()) {
+                Some((start, stop)) => list.range(start..=stop).map(Value::from).collect(),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        Ok(elems.into())
+    }
+
+    fn exec_llen(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let len = self
+            .get_typed(key, DbVal::as_list_mut)?
+            .map_or(0, |list| list.len());
+        Ok(Value::Int(len as i64))
+    }
+
+    /// Replies with the number of fields that were not in the hash before
+    fn exec_hset(&mut self, key: &Bytes, pairs: &[(Bytes, Bytes)]) -> Result<Value, Value> {
+        let hash =
+            self.get_typed_or_insert(key, DbVal::as_hash_mut, || DbVal::Hash(HashMap::new()))?;
+        let n_added = pairs
+            .iter()
+            .filter(|(field, val)| hash.insert(field.clone(), val.clone()).is_none())
+            .count();
+        self.propagate(&Command::HSet(key.clone(), pairs.to_vec()));
+        Ok(Value::Int(n_added as i64))
+    }
+
+    fn exec_hget(&mut self, key: &Bytes, field: &Bytes) -> Result<Value, Value> {
+        let val = self
+            .get_typed(key, DbVal::as_hash_mut)?
+            .and_then(|hash| hash.get(field).cloned());
+        Ok(val.map_or(Value::NullBulkString, Value::BulkString))
+    }
+
+    /// Fields and their values, RESP2 clients get them interleaved in a flat array
+    fn exec_hgetall(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let mut parts: Vec<(Value, Value)> = Vec::new();
+        if let Some(hash) = self.get_typed(key, DbVal::as_hash_mut)? {
+            for (field, val) in hash.iter() {
+                parts.push((field.into(), val.into()));
+            }
+        }
+        Ok(Value::Map(parts))
+    }
+
+    fn exec_hdel(&mut self, key: &Bytes, fields: &[Bytes]) -> Result<Value, Value> {
+        let Some(hash) = self.get_typed(key, DbVal::as_hash_mut)? else {
+            return Ok(Value::Int(0));
+        };
+        let n_deleted = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        if n_deleted > 0 {
+            self.remove_if_empty(key);
+            self.propagate(&Command::HDel(key.clone(), fields.to_vec()));
+        }
+        Ok(Value::Int(n_deleted as i64))
+    }
+
+    fn exec_sadd(&mut self, key: &Bytes, members: &[Bytes]) -> Result<Value, Value> {
+        let set =
+            self.get_typed_or_insert(key, DbVal::as_set_mut, || DbVal::Set(HashSet::new()))?;
+        let n_added = members
+            .iter()
+            .filter(|member| set.insert((*member).clone()))
+            .count();
+        if n_added > 0 {
+            self.propagate(&Command::SAdd(key.clone(), members.to_vec()));
+        }
+        Ok(Value::Int(n_added as i64))
+    }
+
+    fn exec_srem(&mut self, key: &Bytes, members: &[Bytes]) -> Result<Value, Value> {
+        let Some(set) = self.get_typed(key, DbVal::as_set_mut)? else {
+            return Ok(Value::Int(0));
+        };
+        let n_removed = members.iter().filter(|member| set.remove(*member)).count();
+        if n_removed > 0 {
+            self.remove_if_empty(key);
+            self.propagate(&Command::SRem(key.clone(), members.to_vec()));
+        }
+        Ok(Value::Int(n_removed as i64))
+    }
+
+    fn exec_smembers(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let members: Vec<Value> = match self.get_typed(key, DbVal::as_set_mut)? {
+            Some(set) => set.iter().map(Value::from).collect(),
+            None => Vec::new(),
+        };
+        Ok(Value::Set(members))
+    }
+
+    fn exec_sismember(&mut self, key: &Bytes, member: &Bytes) -> Result<Value, Value> {
+        let is_member = self
+            .get_typed(key, DbVal::as_set_mut)?
+            .is_some_and(|set| set.contains(member));
+        Ok(Value::Int(is_member as i64))
+    }
+
+    /// Replies with the number of members added (and changed, with CH),
+    /// or with the new score when INCR is given
+    fn exec_zadd(
+        &mut self,
+        key: &Bytes,
+        pairs: &[(f64, Bytes)],
+        flags: &ZAddFlags,
+    ) -> Result<Value, Value> {
+        let zset =
+            self.get_typed_or_insert(
+                key,
+                DbVal::as_zset_mut,
+                || DbVal::ZSet(SortedSet::default()),
+            )?;
+        let (mut n_added, mut n_changed) = (0, 0);
+        let mut new_score = None; // of the last member updated, what INCR replies with
+        let mut got_nan = false;
+        for (score, member) in pairs {
+            let current = zset.score(member);
+            let new = match (flags.incr, current) {
+                (true, Some(current)) => current + score,
+                _ => *score,
+            };
+            if new.is_nan() {
+                // e.g. INCR of +inf by -inf. INCR takes a single member, so nothing changed yet
+                got_nan = true;
+                break;
+            }
+            let skip = match current {
+                Some(current) => {
+                    flags.nx || flags.gt && new <= current || flags.lt && new >= current
+                }
+                None => flags.xx,
+            };
+            if skip {
+                continue;
+            }
+            zset.insert(member.clone(), new);
+            match current {
+                None => n_added += 1,
+                Some(current) if current != new => n_changed += 1,
+                Some(_) => {}
+            }
+            new_score = Some(new);
+        }
+
+        // XX on a missing key leaves an empty zset behind
+        self.remove_if_empty(key);
+        if got_nan {
+            return Err(Value::SimpleError(
+                "ERR resulting score is not a number (NaN)".into(),
+            ));
+        }
+        if n_added + n_changed > 0 {
+            self.propagate(&Command::ZAdd(key.clone(), pairs.to_vec(), *flags));
+        }
+
+        Ok(if flags.incr {
+            new_score.map_or(Value::NullBulkString, Value::Double)
+        } else if flags.ch {
+            Value::Int(n_added + n_changed)
+        } else {
+            Value::Int(n_added)
+        })
+    }
+
+    fn exec_zrange(
+        &mut self,
+        key: &Bytes,
+        spec: &ZRangeSpec,
+        protocol: Protocol,
+    ) -> Result<Value, Value> {
+        let Some(zset) = self.get_typed(key, DbVal::as_zset_mut)? else {
+            return Ok(Vec::<Value>::new().into());
+        };
+        let mut in_range: Vec<(&Bytes, f64)> = match &spec.by {
+            ZRangeBy::Index(start, stop) => match index_range(*start, *stop, zset.len()) {
+                // REV indices count from the highest score
+                Some((start, stop)) if spec.rev => zset
+                    .iter()
+                    .rev()
+                    .skip(start)
+                    .take(stop - start + 1)
+                    .collect(),
+                Some((start, stop)) => zset.iter().skip(start).take(stop - start + 1).collect(),
+                None => Vec::new(),
+            },
+            ZRangeBy::Score(min, max) => {
+                let range = zset.range_by_score(*min, *max);
+                if spec.rev {
+                    range.rev().collect()
+                } else {
+                    range.collect()
+                }
+            }
+            ZRangeBy::Lex(min, max) => {
+                let range = zset.range_by_lex(min, max);
+                if spec.rev {
+                    range.rev().collect()
+                } else {
+                    range.collect()
+                }
+            }
+        };
+        if let Some((offset, count)) = spec.limit {
+            // a negative offset gives nothing, a negative count everything from the offset on
+            let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+            let count = usize::try_from(count).unwrap_or(usize::MAX);
+            in_range = in_range.into_iter().skip(offset).take(count).collect();
+        }
+
+        let mut parts: Vec<Value> = Vec::new();
+        for (member, score) in in_range {
+            match (spec.with_scores, protocol) {
+                (false, _) => parts.push(member.into()),
+                (true, Protocol::Resp2) => parts.extend([member.into(), score_value(score)]),
+                (true, Protocol::Resp3) => {
+                    parts.push(Value::Array(vec![member.into(), Value::Double(score)]))
+                }
+            }
+        }
+        Ok(parts.into())
+    }
+
+    fn exec_zscore(&mut self, key: &Bytes, member: &Bytes) -> Result<Value, Value> {
+        let score = self
+            .get_typed(key, DbVal::as_zset_mut)?
+            .and_then(|zset| zset.score(member));
+        Ok(score.map_or(Value::NullBulkString, Value::Double))
+    }
+
+    fn exec_zrem(&mut self, key: &Bytes, members: &[Bytes]) -> Result<Value, Value> {
+        let Some(zset) = self.get_typed(key, DbVal::as_zset_mut)? else {
+            return Ok(Value::Int(0));
+        };
+        let n_removed = members
+            .iter()
+            .filter(|member| zset.remove(member).is_some())
+            .count();
+        if n_removed > 0 {
+            self.remove_if_empty(key);
+            self.propagate(&Command::ZRem(key.clone(), members.to_vec()));
+        }
+        Ok(Value::Int(n_removed as i64))
+    }
+
+    /// XADD: replies with the id of the new entry
+    fn exec_xadd(
+        &mut self,
+        key: &Bytes,
+        id: &XAddId,
+        fields: &StreamFields,
+        opts: &XAddOpts,
+    ) -> Result<Value, Value> {
+        let now = self.clock.now_millis();
+        let stream = if opts.nomkstream {
+            match self.get_typed(key, DbVal::as_stream_mut)? {
+                Some(stream) => stream,
+                None => return Ok(Value::NullBulkString),
+            }
+        } else {
+            self.get_typed_or_insert(key, DbVal::as_stream_mut, || {
+                DbVal::Stream(Stream::default())
+            })?
+        };
+
+        let last_id = stream.last_id;
+        let new_id = match id {
+            XAddId::Auto => stream.next_auto_id(now).ok_or_else(|| {
+                Value::SimpleError(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items"
+                        .into(),
+                )
+            })?,
+            XAddId::AutoSeq(ms) if *ms == last_id.ms => match last_id.seq.checked_add(1) {
+                Some(seq) => StreamId::new(*ms, seq),
+                None => return Err(xadd_id_too_small_err()),
+            },
+            XAddId::AutoSeq(ms) if *ms > last_id.ms => StreamId::new(*ms, 0),
+            XAddId::Explicit(id) if *id > last_id => *id,
+            _ => return Err(xadd_id_too_small_err()),
+        };
+        stream.insert(new_id, fields.clone());
+        if let Some(trim) = &opts.trim {
+            stream.trim(trim.strategy, trim.limit);
+        }
+        self.signal_key_ready(key);
+
+        // with the id as it was generated, so that replicas get the same
+        self.propagate(&Command::XAdd(
+            key.clone(),
+            XAddId::Explicit(new_id),
+            fields.clone(),
+            *opts,
+        ));
+        Ok(Value::BulkString(new_id.to_string().as_str().into()))
+    }
+
+    /// XRANGE / XREVRANGE, from `start` to `end` both included
+    fn exec_xrange(
+        &mut self,
+        key: &Bytes,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Result<Value, Value> {
+        let Some(stream) = self.get_typed(key, DbVal::as_stream_mut)? else {
+            return Ok(Vec::<Value>::new().into());
+        };
+        if count == Some(0) {
+            return Ok(Value::NullArray);
+        }
+        Ok(stream_entries_value(stream.range(start, end, count, rev)))
+    }
+
+    /// XREAD: entries after the given ids. With a timeout (0 for none) and nothing to read yet,
+    /// blocks until one of the streams gets entries: None is returned then.
+    fn exec_xread(
+        &mut self,
+        streams: &[(Bytes, XReadFrom)],
+        count: Option<usize>,
+        block: Option<u64>,
+        sx: Sender<QueryResult>,
+    ) -> Option<Value> {
+        let mut keys = Vec::with_capacity(streams.len());
+        let mut ids = Vec::with_capacity(streams.len());
+        for (key, from) in streams {
+            let id = match from {
+                XReadFrom::After(id) => *id,
+                XReadFrom::Last => match self.get_typed(key, DbVal::as_stream_mut) {
+                    Ok(stream) => stream.map_or(StreamId::MIN, |stream| stream.last_id),
+                    Err(err) => return Some(err),
+                },
+            };
+            keys.push(key.clone());
+            ids.push(id);
+        }
+
+        match self.xread_reply(&keys, &ids, count) {
+            Err(err) => Some(err),
+            Ok(Some(val)) => Some(val),
+            Ok(None) => match block {
+                None => Some(Value::NullArray),
+                Some(timeout) => self.block_client(keys, BlockedOp::Read(ids, count), timeout, sx),
             },
-            None => ValAndExpiry { val, ex: u64::MAX },
         }
     }
-}
 
-#[derive(Debug)]
-struct ReplicaInfo {
-    // host_port: String,
-    sender: Sender<ToReplica>,
-    acked_byte_cnt: u64,
-}
+    /// For each of `keys` with entries after its id in `ids`: the key and up to `count` of
+    /// those entries. None if no stream has any.
+    fn xread_reply(
+        &mut self,
+        keys: &[Bytes],
+        ids: &[StreamId],
+        count: Option<usize>,
+    ) -> Result<Option<Value>, Value> {
+        let mut per_stream: Vec<Value> = Vec::new();
+        for (key, id) in keys.iter().zip(ids) {
+            let Some(stream) = self.get_typed(key, DbVal::as_stream_mut)? else {
+                continue;
+            };
+            let Some(start) = id.next() else {
+                continue;
+            };
+            let entries = stream.range(start, StreamId::MAX, count, false);
+            if !entries.is_empty() {
+                per_stream.push(vec![key.into(), stream_entries_value(entries)].into());
+            }
+        }
+        Ok((!per_stream.is_empty()).then(|| per_stream.into()))
+    }
+
+    fn exec_xlen(&mut self, key: &Bytes) -> Result<Value, Value> {
+        let len = self
+            .get_typed(key, DbVal::as_stream_mut)?
+            .map_or(0, |stream| stream.len());
+        Ok(Value::Int(len as i64))
+    }
 
-pub struct Db {
-    h: HashMap<Bytes, ValAndExpiry>,
-    cfg: InstanceConfig,
-    tx: Sender<ToDb>,
-    // Used by replicas
-    repl_byte_cnt: usize,
-    // Used by Master
-    replicas: HashMap<String, ReplicaInfo>,
-    replication_id: String,
-    replication_offset: u64,
-}
+    /// Replies with the number of entries removed
+    fn exec_xtrim(&mut self, key: &Bytes, trim: &StreamTrim) -> Result<Value, Value> {
+        let Some(stream) = self.get_typed(key, DbVal::as_stream_mut)? else {
+            return Ok(Value::Int(0));
+        };
+        let n_removed = stream.trim(trim.strategy, trim.limit);
+        if n_removed > 0 {
+            self.propagate(&Command::XTrim(key.clone(), *trim));
+        }
+        Ok(Value::Int(n_removed as i64))
+    }
 
-impl Db {
-    pub fn new(cfg: InstanceConfig, tx: Sender<ToDb>) -> Self {
-        Db {
-            h: HashMap::new(),
-            cfg,
-            tx,
-            repl_byte_cnt: 0,
-            replicas: HashMap::new(),
-            replication_id: make_replication_id(now_millis()),
-            replication_offset: 0,
+    fn exec_xsetid(
+        &mut self,
+        key: &Bytes,
+        last_id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) -> Result<Value, Value> {
+        let Some(stream) = self.get_typed(key, DbVal::as_stream_mut)? else {
+            return Err(Value::SimpleError("ERR no such key".into()));
+        };
+        if stream.last_entry_id().is_some_and(|top| last_id < top) {
+            return Err(Value::SimpleError(
+                "ERR The ID specified in XSETID is smaller than the target stream top item".into(),
+            ));
+        }
+        if entries_added.is_some_and(|n| n < stream.len() as u64) {
+            return Err(Value::SimpleError(
+                "ERR The entries_added specified in XSETID is smaller than the target stream length"
+                    .into(),
+            ));
+        }
+        stream.last_id = last_id;
+        if let Some(entries_added) = entries_added {
+            stream.entries_added = entries_added;
         }
+        if let Some(max_deleted_id) = max_deleted_id {
+            stream.max_deleted_id = max_deleted_id;
+        }
+        self.propagate(&Command::XSetId(
+            key.clone(),
+            last_id,
+            entries_added,
+            max_deleted_id,
+        ));
+        Ok(Value::ok())
     }
 
-    pub async fn run(mut self, mut rx: Receiver<ToDb>) {
-        // spawn replication coroutine
-        if let Some(master_host_port) = self.cfg.replicaof.clone() {
-            let mut proxy = ProxyToMaster::new(master_host_port.as_str()).await;
+    /// The stream XGROUP works on, which must exist
+    fn xgroup_stream(&mut self, key: &Bytes) -> Result<&mut Stream, Value> {
+        self.get_typed(key, DbVal::as_stream_mut)?.ok_or_else(|| {
+            Value::SimpleError(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you \
+                 may want to use the MKSTREAM option to create an empty stream automatically."
+                    .into(),
+            )
+        })
+    }
 
-            println!("Db::run: running replication handshake");
-            self.run_replication_handshake(&mut proxy).await.unwrap();
+    /// Group `group` of the stream XGROUP works on, both of which must exist
+    fn xgroup_group(&mut self, key: &Bytes, group: &Bytes) -> Result<&mut ConsumerGroup, Value> {
+        self.xgroup_stream(key)?
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| {
+                Value::SimpleError(format!(
+                    "NOGROUP No such consumer group '{g}' for key name '{k}'",
+                    g = String::from_utf8_lossy(group.as_bytes()),
+                    k = String::from_utf8_lossy(key.as_bytes())
+                ))
+            })
+    }
 
-            println!("Db::run: replication handshake FINISHED");
-            let bstream = proxy.bstream;
-            let repl_tx = self.tx.clone();
-            tokio::spawn(handle_stream_async(bstream, repl_tx, true));
-            // handle_stream_async(stream, repl_tx, true).await
+    fn exec_xgroup_create(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        id: &XReadFrom,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    ) -> Result<Value, Value> {
+        let stream = if mkstream {
+            self.get_typed_or_insert(key, DbVal::as_stream_mut, || {
+                DbVal::Stream(Stream::default())
+            })?
+        } else {
+            self.xgroup_stream(key)?
+        };
+        if stream.groups.contains_key(group) {
+            return Err(Value::SimpleError(
+                "BUSYGROUP Consumer Group name already exists".into(),
+            ));
         }
+        let last_id = match id {
+            XReadFrom::After(id) => *id,
+            XReadFrom::Last => stream.last_id,
+        };
+        stream
+            .groups
+            .insert(group.clone(), ConsumerGroup::new(last_id, entries_read));
 
-        tokio::time::sleep(Duration::from_millis(500)).await;
+        self.propagate(&Command::XGroupCreate(
+            key.clone(),
+            group.clone(),
+            XReadFrom::After(last_id),
+            mkstream,
+            entries_read,
+        ));
+        Ok(Value::ok())
+    }
 
-        // long running co-routine that gets commands from only channel and executes them on the Db
-        println!("Db::run: Starting Query Loop");
-        loop {
-            match rx.recv().await {
-                Some(ToDb::QueryAndSender(qry, sx)) => {
-                    println!("Query loop received: {qry:?}");
-                    let sx1 = sx.clone();
-                    let resp_val = self.execute(&qry, sx1).await;
-                    let repl_byte_cnt_inc = resp_val.repl_byte_cnt_inc;
-                    if !resp_val.vals.is_empty() {
-                        sx.send(resp_val).await.unwrap();
-                    } else {
-                        println!("Not sending resp_val via channel as there are no values...");
-                    }
-                    self.repl_byte_cnt += repl_byte_cnt_inc;
-                }
-                Some(ToDb::PassedReplStream(bstream)) => {
-                    let replica_addr = peer_addr_str_v2(&bstream).replace(' ', ":");
+    fn exec_xgroup_setid(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        id: &XReadFrom,
+        entries_read: Option<u64>,
+    ) -> Result<Value, Value> {
+        let stream_last_id = self.xgroup_stream(key)?.last_id;
+        let cg = self.xgroup_group(key, group)?;
+        cg.last_id = match id {
+            XReadFrom::After(id) => *id,
+            XReadFrom::Last => stream_last_id,
+        };
+        cg.entries_read = entries_read;
 
-                    println!("Query loop received ReplStream({replica_addr})");
+        let last_id = XReadFrom::After(cg.last_id);
+        self.propagate(&Command::XGroupSetId(
+            key.clone(),
+            group.clone(),
+            last_id,
+            entries_read,
+        ));
+        Ok(Value::ok())
+    }
 
-                    let (to_replica, repl_receiver) = channel::<ToReplica>(100);
+    fn exec_xgroup_destroy(&mut self, key: &Bytes, group: &Bytes) -> Result<Value, Value> {
+        if self.xgroup_stream(key)?.groups.remove(group).is_none() {
+            return Ok(Value::Int(0));
+        }
+        // clients blocked reading the group learn that it is gone
+        self.signal_key_ready(key);
+        self.propagate(&Command::XGroupDestroy(key.clone(), group.clone()));
+        Ok(Value::Int(1))
+    }
 
-                    tokio::spawn(handle_replica(bstream, repl_receiver, self.tx.clone()));
+    fn exec_xgroup_createconsumer(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        consumer: &Bytes,
+    ) -> Result<Value, Value> {
+        let now = self.clock.now_millis();
+        let cg = self.xgroup_group(key, group)?;
+        if cg.consumers.contains_key(consumer) {
+            return Ok(Value::Int(0));
+        }
+        cg.touch_consumer(consumer, now);
+        self.propagate(&Command::XGroupCreateConsumer(
+            key.clone(),
+            group.clone(),
+            consumer.clone(),
+        ));
+        Ok(Value::Int(1))
+    }
 
-                    if !self.replicas.contains_key(&replica_addr) {
-                        self.replicas.insert(
-                            replica_addr.clone(),
-                            ReplicaInfo {
-                                // host_port: replica_addr,
-                                acked_byte_cnt: 0,
-                                sender: to_replica,
-                            },
-                        );
-                    }
-                }
-                None => {
-                    println!("handle_commands: Incomming command channel closed. STOPPING");
-                    break;
-                }
-            }
+    /// Replies with the number of pending entries the consumer had
+    fn exec_xgroup_delconsumer(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        consumer: &Bytes,
+    ) -> Result<Value, Value> {
+        let Some(n_pending) = self.xgroup_group(key, group)?.remove_consumer(consumer) else {
+            return Ok(Value::Int(0));
+        };
+        self.propagate(&Command::XGroupDelConsumer(
+            key.clone(),
+            group.clone(),
+            consumer.clone(),
+        ));
+        Ok(Value::Int(n_pending as i64))
+    }
+
+    /// The stream at `key`, provided it has consumer group `group`
+    fn stream_with_group(&mut self, key: &Bytes, group: &Bytes) -> Result<&mut Stream, Value> {
+        match self.get_typed(key, DbVal::as_stream_mut)? {
+            Some(stream) if stream.groups.contains_key(group) => Ok(stream),
+            _ => Err(no_group_err(key, group, "")),
         }
     }
 
-    pub async fn run_replication_handshake(&mut self, proxy: &mut ProxyToMaster) -> Result<()> {
-        // Run by replica side
-        let ping_resp = proxy.send_command(Command::Ping).await?;
-        println!("master's response to ping: {ping_resp:?}");
+    /// XREADGROUP: like XREAD, except that each new entry goes to a single consumer of the
+    /// group, and stays pending for it until acked. Reading pending entries never blocks.
+    #[allow(clippy::too_many_arguments)]
+    fn exec_xreadgroup(
+        &mut self,
+        group: &Bytes,
+        consumer: &Bytes,
+        streams: &[(Bytes, XReadGroupFrom)],
+        count: Option<usize>,
+        block: Option<u64>,
+        noack: bool,
+        sx: Sender<QueryResult>,
+    ) -> Option<Value> {
+        match self.xreadgroup_reply(group, consumer, streams, count, noack) {
+            Err(err) => Some(err),
+            Ok(Some(val)) => Some(val),
+            Ok(None) => match block {
+                None => Some(Value::NullArray),
+                Some(timeout) => {
+                    let keys = streams.iter().map(|(key, _)| key.clone()).collect();
+                    let op = BlockedOp::ReadGroup(group.clone(), consumer.clone(), count, noack);
+                    self.block_client(keys, op, timeout, sx)
+                }
+            },
+        }
+    }
 
-        let repl_conf_1 = Command::ReplConf("listening-port".into(), format!("{}", self.cfg.port));
-        let repl_conf_1_resp = proxy.send_command(repl_conf_1).await?;
-        println!("master's response to repl_conf_1: {repl_conf_1_resp:?}");
+    /// For each of `streams`: the key and what `consumer` read from it. Streams without
+    /// new entries are left out, None if that leaves none.
+    fn xreadgroup_reply(
+        &mut self,
+        group_name: &Bytes,
+        consumer: &Bytes,
+        streams: &[(Bytes, XReadGroupFrom)],
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Option<Value>, Value> {
+        // nothing is read unless all streams have the group
+        for (key, _) in streams {
+            match self.get_typed(key, DbVal::as_stream_mut)? {
+                Some(stream) if stream.groups.contains_key(group_name) => {}
+                _ => {
+                    let suffix = " in XREADGROUP with GROUP option";
+                    return Err(no_group_err(key, group_name, suffix));
+                }
+            }
+        }
 
-        let repl_conf_2 = Command::ReplConf("capa".into(), "psync2".into());
-        let repl_conf_2_resp = proxy.send_command(repl_conf_2).await?;
-        println!("master's response to repl_conf_2: {repl_conf_2_resp:?}");
-        // debug_peek(&proxy.stream, 64).await;
+        let now = self.clock.now_millis();
+        let mut per_stream: Vec<Value> = Vec::new();
+        let mut to_propagate: Vec<Command> = Vec::new();
+        for (key, from) in streams {
+            let stream = self
+                .get_typed(key, DbVal::as_stream_mut)?
+                .expect("checked above");
+            let group = stream.groups.get_mut(group_name).expect("checked above");
+            if group.touch_consumer(consumer, now) {
+                to_propagate.push(Command::XGroupCreateConsumer(
+                    key.clone(),
+                    group_name.clone(),
+                    consumer.clone(),
+                ));
+            }
 
-        let psync = Command::Psync("?".into(), -1);
-        let psync_resp = proxy.send_command(psync).await.unwrap();
-        println!("master's response to psync: {psync_resp:?}");
+            match from {
+                XReadGroupFrom::New => {
+                    let entries: Vec<(StreamId, StreamFields)> = match group.last_id.next() {
+                        Some(start) => stream
+                            .range(start, StreamId::MAX, count, false)
+                            .into_iter()
+                            .map(|(id, fields)| (id, fields.clone()))
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    if entries.is_empty() {
+                        continue;
+                    }
+                    for (id, _) in &entries {
+                        stream.advance_group(group_name, *id);
+                    }
+                    let group = stream.groups.get_mut(group_name).expect("checked above");
+                    if !noack {
+                        for (id, _) in &entries {
+                            group.deliver(*id, consumer, now, 1);
+                            to_propagate.push(claim_as_is(
+                                key,
+                                group_name,
+                                *id,
+                                &group.pending[id],
+                            ));
+                        }
+                    }
+                    let reader = group.consumers.get_mut(consumer).expect("touched above");
+                    reader.active_time = Some(now);
+                    to_propagate.push(Command::XGroupSetId(
+                        key.clone(),
+                        group_name.clone(),
+                        XReadFrom::After(group.last_id),
+                        group.entries_read,
+                    ));
 
-        // debug_peek("Before getting rdb_file", &proxy.bstream, 64).await;
-        // waiting for RDBFILE now
-        let rdb_file = proxy.receive_file().await?;
-        println!("rdb_file received: {rdb_file:?}");
+                    let entries = entries.iter().map(|(id, fields)| (*id, fields)).collect();
+                    per_stream.push(vec![key.into(), stream_entries_value(entries)].into());
+                }
+                XReadGroupFrom::Pending(after) => {
+                    let ids: Vec<StreamId> = match after.next() {
+                        Some(start) => group.consumers[consumer]
+                            .pending
+                            .range(start..)
+                            .take(count.unwrap_or(usize::MAX))
+                            .copied()
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    // delivered again, except for entries no longer in the stream
+                    let mut entries: Vec<Value> = Vec::with_capacity(ids.len());
+                    for id in ids {
+                        let Some(fields) = stream.get(id) else {
+                            entries.push(vec![stream_id_value(id), Value::NullArray].into());
+                            continue;
+                        };
+                        entries.push(stream_entry_value(id, fields));
+                        let group = stream.groups.get_mut(group_name).expect("checked above");
+                        let delivery_count = group.pending[&id].delivery_count + 1;
+                        group.deliver(id, consumer, now, delivery_count);
+                        to_propagate.push(claim_as_is(key, group_name, id, &group.pending[&id]));
+                    }
+                    per_stream.push(vec![key.into(), entries.into()].into());
+                }
+            }
+        }
 
-        // handle_stream_async(proxy.bstream, repl_tx, true).await;
-        Ok(())
+        for cmd in &to_propagate {
+            self.propagate(cmd);
+        }
+        Ok((!per_stream.is_empty()).then(|| per_stream.into()))
     }
 
-    pub async fn execute(&mut self, query: &Query, sx1: Sender<QueryResult>) -> QueryResult {
-        use Command::*;
+    /// Replies with the number of entries that were pending
+    fn exec_xack(&mut self, key: &Bytes, group: &Bytes, ids: &[StreamId]) -> Result<Value, Value> {
+        let Some(stream) = self.get_typed(key, DbVal::as_stream_mut)? else {
+            return Ok(Value::Int(0));
+        };
+        let Some(cg) = stream.groups.get_mut(group) else {
+            return Ok(Value::Int(0));
+        };
+        let n_acked = ids.iter().filter(|id| cg.ack(**id)).count();
+        if n_acked > 0 {
+            self.propagate(&Command::XAck(key.clone(), group.clone(), ids.to_vec()));
+        }
+        Ok(Value::Int(n_acked as i64))
+    }
 
-        // self.repl_byte_cnt += query.deser_byte_cnt;
+    /// XPENDING: a summary of the group's pending entries, or those in `range`
+    fn exec_xpending(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        range: &Option<XPendingRange>,
+    ) -> Result<Value, Value> {
+        let now = self.clock.now_millis();
+        let cg = &self.stream_with_group(key, group)?.groups[group];
+        let Some(range) = range else {
+            let (Some(first), Some(last)) = (cg.pending.keys().next(), cg.pending.keys().last())
+            else {
+                let none = vec![
+                    Value::NullBulkString,
+                    Value::NullBulkString,
+                    Value::NullArray,
+                ];
+                return Ok([vec![Value::Int(0)], none].concat().into());
+            };
+            let per_consumer: Vec<Value> = cg
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| {
+                    let n_pending = consumer.pending.len().to_string();
+                    vec![name.into(), n_pending.as_str().into()].into()
+                })
+                .collect();
+            return Ok(vec![
+                Value::Int(cg.pending.len() as i64),
+                stream_id_value(*first),
+                stream_id_value(*last),
+                per_consumer.into(),
+            ]
+            .into());
+        };
 
-        let result: Vec<Value> = match &query.cmd {
-            Ping => vec![s_str("PONG")],
-            Echo(a) => vec![Value::BulkString(a.clone())],
-            SetKV(key, val, ex) => vec![self.exec_set(key, val, ex).await],
-            Get(key) => vec![self.exec_get(key)],
-            Info(arg) => vec![self.exec_info(arg)],
-            Psync(id, offset) if id == "?" && *offset == -1 => {
-                let reply_str = format!("FULLRESYNC {repl_id} 0", repl_id = self.replication_id);
+        if range.start > range.end {
+            return Ok(Vec::<Value>::new().into());
+        }
+        let ids: Box<dyn Iterator<Item = &StreamId>> = match &range.consumer {
+            Some(name) => match cg.consumers.get(name) {
+                Some(consumer) => Box::new(consumer.pending.range(range.start..=range.end)),
+                None => Box::new(std::iter::empty()),
+            },
+            None => Box::new(cg.pending.range(range.start..=range.end).map(|(id, _)| id)),
+        };
+        let entries: Vec<Value> = ids
+            .map(|id| (*id, &cg.pending[id]))
+            .map(|(id, entry)| (id, entry, now.saturating_sub(entry.delivery_time)))
+            .filter(|(_, _, idle)| *idle >= range.min_idle)
+            .take(range.count)
+            .map(|(id, entry, idle)| {
+                vec![
+                    stream_id_value(id),
+                    (&entry.consumer).into(),
+                    Value::Int(idle as i64),
+                    Value::Int(entry.delivery_count as i64),
+                ]
+                .into()
+            })
+            .collect();
+        Ok(entries.into())
+    }
 
-                return QueryResult {
-                    vals: vec![
-                        s_str(&reply_str),
-                        Value::FileContents(get_empty_rdb_bytes().into()),
-                    ],
-                    repl_byte_cnt_inc: 0,
-                    pass_stream: true,
-                };
-            }
-            Psync(_, _) => {
-                panic!("Can't reply to {cmd:?} yet", cmd = query.cmd)
-            }
-            ReplConf(key, val) => {
-                vec![self.exec_repl_conf(key, val)]
+    /// XCLAIM: hand the pending entries among `ids` that are idle for at least `min_idle`
+    /// millis over to `consumer`
+    fn exec_xclaim(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        consumer: &Bytes,
+        min_idle: u64,
+        ids: &[StreamId],
+        opts: &XClaimOpts,
+    ) -> Result<Value, Value> {
+        let now = self.clock.now_millis();
+        let delivery_time = match (opts.idle, opts.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (_, Some(time)) => time.min(now),
+            _ => now,
+        };
+        let stream = self.stream_with_group(key, group)?;
+        let mut to_propagate: Vec<Command> = Vec::new();
+        if let Some(last_id) = opts.last_id {
+            let cg = stream.groups.get_mut(group).expect("checked above");
+            if last_id > cg.last_id {
+                cg.last_id = last_id;
+                to_propagate.push(Command::XGroupSetId(
+                    key.clone(),
+                    group.clone(),
+                    XReadFrom::After(last_id),
+                    cg.entries_read,
+                ));
             }
-            ReplConfGetAck(_) => {
-                vec![self.exec_repl_conf_get_ack()]
+        }
+
+        let mut claimed = Vec::new();
+        for id in ids {
+            let in_stream = stream.get(*id).is_some();
+            let cg = stream.groups.get_mut(group).expect("checked above");
+            if !in_stream {
+                // deleted since it was delivered, it can't be delivered again
+                if cg.ack(*id) {
+                    to_propagate.push(Command::XAck(key.clone(), group.clone(), vec![*id]));
+                }
+                continue;
             }
-            ReplConfAck(byte_cnt) => {
-                self.exec_repl_conf_ack(*byte_cnt as u64, &query.client_info);
-                vec![]
+            let delivery_count = match cg.pending.get(id) {
+                Some(entry) if now.saturating_sub(entry.delivery_time) < min_idle => continue,
+                Some(entry) => entry.delivery_count,
+                None if opts.force => 0,
+                None => continue,
+            };
+            let delivery_count = match opts.retry_count {
+                Some(retry_count) => retry_count,
+                None if opts.justid => delivery_count,
+                None => delivery_count + 1,
+            };
+            cg.touch_consumer(consumer, now);
+            cg.deliver(*id, consumer, delivery_time, delivery_count);
+            cg.consumers
+                .get_mut(consumer)
+                .expect("touched above")
+                .active_time = Some(now);
+            to_propagate.push(claim_as_is(key, group, *id, &cg.pending[id]));
+            claimed.push(*id);
+        }
+
+        let claimed: Vec<Value> = claimed
+            .into_iter()
+            .map(|id| match opts.justid {
+                true => stream_id_value(id),
+                false => stream_entry_value(id, stream.get(id).expect("checked above")),
+            })
+            .collect();
+        for cmd in &to_propagate {
+            self.propagate(cmd);
+        }
+        Ok(claimed.into())
+    }
+
+    /// XAUTOCLAIM: like XCLAIM, for the pending entries from `start` on. Replies with the id
+    /// to continue from (0-0 when done), the entries claimed, and the ids of those that
+    /// were deleted from the stream meanwhile, which are dropped from the PEL.
+    #[allow(clippy::too_many_arguments)]
+    fn exec_xautoclaim(
+        &mut self,
+        key: &Bytes,
+        group: &Bytes,
+        consumer: &Bytes,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        justid: bool,
+    ) -> Result<Value, Value> {
+        let now = self.clock.now_millis();
+        let stream = self.stream_with_group(key, group)?;
+        let cg = stream.groups.get_mut(group).expect("checked above");
+        let mut to_propagate: Vec<Command> = Vec::new();
+        // the consumer exists from now on, even if it ends up claiming nothing
+        if cg.touch_consumer(consumer, now) {
+            to_propagate.push(Command::XGroupCreateConsumer(
+                key.clone(),
+                group.clone(),
+                consumer.clone(),
+            ));
+        }
+        // looking at up to 10 PEL entries per entry wanted, as redis does
+        let candidates: Vec<StreamId> = cg
+            .pending
+            .range(start..)
+            .map(|(id, _)| *id)
+            .take(count * 10)
+            .collect();
+
+        let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+        let mut last_seen = None;
+        for id in candidates {
+            if claimed.len() + deleted.len() == count {
+                break;
             }
-            Wait(n_repls, timeout) => {
-                let maybe_val = self.exec_wait(*n_repls as usize, true, *timeout, query, sx1).await;
-                match maybe_val {
-                    Some(val) => vec![val],
-                    None => vec![],
-                }
+            last_seen = Some(id);
+            let in_stream = stream.get(id).is_some();
+            let cg = stream.groups.get_mut(group).expect("checked above");
+            if !in_stream {
+                cg.ack(id);
+                to_propagate.push(Command::XAck(key.clone(), group.clone(), vec![id]));
+                deleted.push(stream_id_value(id));
+                continue;
             }
-            WaitInternal(n_repls, timeout) => {
-                let maybe_val = self.exec_wait(*n_repls as usize, false, *timeout, query, sx1).await;
-                match maybe_val {
-                    Some(val) => vec![val],
-                    None => vec![],
-                }
+            let entry = &cg.pending[&id];
+            if now.saturating_sub(entry.delivery_time) < min_idle {
+                continue;
             }
+            let delivery_count = entry.delivery_count + u64::from(!justid);
+            cg.deliver(id, consumer, now, delivery_count);
+            cg.consumers
+                .get_mut(consumer)
+                .expect("touched above")
+                .active_time = Some(now);
+            to_propagate.push(claim_as_is(key, group, id, &cg.pending[&id]));
+            claimed.push(id);
+        }
+
+        let cg = &stream.groups[group];
+        let next = last_seen
+            .and_then(|id| id.next())
+            .and_then(|from| cg.pending.range(from..).next())
+            .map_or(StreamId::MIN, |(id, _)| *id);
+        let claimed: Vec<Value> = claimed
+            .into_iter()
+            .map(|id| match justid {
+                true => stream_id_value(id),
+                false => stream_entry_value(id, stream.get(id).expect("checked above")),
+            })
+            .collect();
+        for cmd in &to_propagate {
+            self.propagate(cmd);
+        }
+        Ok(vec![stream_id_value(next), claimed.into(), deleted.into()].into())
+    }
+
+    /// EXPIRE & co., `deadline` being absolute. A deadline in the past deletes the key.
+    fn exec_expire(&mut self, key: &Bytes, deadline: i64, flags: &ExpireFlags) -> Value {
+        let Some(val_ex) = self.live_entry(key) else {
+            return Value::Int(0);
         };
+        // no expiry is u64::MAX, which already behaves as an infinite ttl for GT / LT
+        let current = val_ex.ex;
+        let has_expiry = current != u64::MAX;
+        let new = deadline.max(0) as u64;
+        if flags.nx && has_expiry
+            || flags.xx && !has_expiry
+            || flags.gt && new <= current
+            || flags.lt && new >= current
+        {
+            return Value::Int(0);
+        }
 
-        QueryResult {
-            vals: result,
-            repl_byte_cnt_inc: query.deser_byte_cnt,
-            pass_stream: false,
+        if new <= self.clock.now_millis() {
+            self.remove_entry(key);
+            self.propagate(&Command::Del(vec![key.clone()]));
+        } else {
+            self.set_expiry(key, new);
+            self.propagate(&Command::ExpireAt(
+                key.clone(),
+                new as i64,
+                ExpireFlags::default(),
+            ));
+        }
+        Value::Int(1)
+    }
+
+    /// TTL / PTTL: -2 if the key does not exist, -1 if it has no expiry
+    fn exec_ttl(&mut self, key: &Bytes, from_millis: impl Fn(i64) -> i64) -> Value {
+        match self.live_entry(key) {
+            None => Value::Int(-2),
+            Some(val_ex) if val_ex.ex == u64::MAX => Value::Int(-1),
+            Some(val_ex) => Value::Int(from_millis(val_ex.ex.saturating_sub(self.clock.now_millis()) as i64)),
+        }
+    }
+
+    /// EXPIRETIME / PEXPIRETIME: -2 if the key does not exist, -1 if it has no expiry
+    fn exec_expiretime(&mut self, key: &Bytes, from_millis: impl Fn(i64) -> i64) -> Value {
+        match self.live_entry(key) {
+            None => Value::Int(-2),
+            Some(val_ex) if val_ex.ex == u64::MAX => Value::Int(-1),
+            Some(val_ex) => Value::Int(from_millis(val_ex.ex as i64)),
+        }
+    }
+
+    fn exec_persist(&mut self, key: &Bytes) -> Value {
+        match self.live_entry(key) {
+            Some(val_ex) if val_ex.ex != u64::MAX => {
+                self.set_expiry(key, u64::MAX);
+                self.propagate(&Command::Persist(key.clone()));
+                Value::Int(1)
+            }
+            _ => Value::Int(0),
         }
     }
 
-    async fn exec_set(&mut self, key: &Bytes, val: &Bytes, ex: &Option<u64>) -> Value {
+    /// Copy of all live entries, as they would be written to an rdb file
+    fn rdb_entries(&self) -> Vec<RdbEntry> {
+        let now = self.clock.now_millis();
         self.h
-            .insert(key.clone(), ValAndExpiry::new(val.clone(), *ex));
+            .iter()
+            .filter(|(_, val_ex)| val_ex.ex > now)
+            .map(|(key, val_ex)| RdbEntry {
+                key: key.clone(),
+                val: val_ex.val.clone(),
+                expiry: (val_ex.ex != u64::MAX).then_some(val_ex.ex),
+            })
+            .collect()
+    }
 
-        if !self.replicas.is_empty() {
-            let cmd = Command::SetKV(key.clone(), val.clone(), *ex);
-            let value = cmd.to_bulk_array();
-            let bytes = serialize(&value).unwrap().into_inner();
-            self.replication_offset += bytes.len() as u64;
+    fn exec_save(&mut self) -> Value {
+        if self.bgsave_in_progress {
+            return Value::SimpleError("ERR Background save already in progress".into());
+        }
+        let now_secs = self.clock.now_millis() / 1000;
+        match write_rdb_file(&self.cfg.rdb_path(), &self.rdb_entries(), now_secs) {
+            Ok(()) => {
+                self.last_save = now_secs;
+                Value::ok()
+            }
+            Err(e) => {
+                println!("Db::exec_save: failed: {e}");
+                Value::SimpleError(format!("ERR {e}"))
+            }
+        }
+    }
 
-            println!("Db::exec_set: attempting replication to {n} replicas.", n=self.replicas.len());
+    fn exec_bgsave(&mut self) -> Value {
+        if self.bgsave_in_progress {
+            return Value::SimpleError("ERR Background save already in progress".into());
+        }
+        self.bgsave_in_progress = true;
 
-            for (repl_key, replica) in self.replicas.iter() {
-                // send_bytes_to_replica(replica, &bytes, "attempting replication").await
+        // The copy taken here plays the role of redis' fork: later writes don't affect the file
+        let entries = self.rdb_entries();
+        let path = self.cfg.rdb_path();
+        let tx = self.tx.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            let now_secs = clock.now_millis() / 1000;
+            let join_res =
+                tokio::task::spawn_blocking(move || write_rdb_file(&path, &entries, now_secs))
+                    .await;
+            let res = match join_res {
+                Ok(Ok(())) => Ok(now_secs),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            tx.send(ToDb::BgSaveFinished(res))
+                .await
+                .unwrap_or_else(|e| println!("BGSAVE: could not notify Db, e:{e:?}"));
+        });
 
-                let msg_to_replica = ToReplica::Bytes(
-                    bytes.clone(),
-                    format!("attempting replication to {repl_key} -- {cmd:?}"),
-                );
+        s_str("Background saving started")
+    }
 
-                replica
-                    .sender
-                    .send(msg_to_replica)
-                    .await
-                    .unwrap_or_else(|e| {
-                        println!("Unable to send msg to replica via channel, e:{e:?} ")
-                    });
-            }
+    fn exec_bgrewriteaof(&mut self) -> Value {
+        if self.aof_rewrite_in_progress {
+            return Value::SimpleError(
+                "ERR Background append only file rewriting already in progress".into(),
+            );
+        }
+        self.aof_rewrite_in_progress = true;
 
+        // Commands recreating each live key, with absolute expiries so that replaying them
+        // later is exact
+        let cmds: Vec<Command> = self
+            .rdb_entries()
+            .into_iter()
+            .flat_map(rewrite_commands)
+            .collect();
+        // writes from now on are buffered by the Aof until the rewritten file is in place
+        if let Some(aof) = &mut self.aof {
+            aof.start_rewrite();
         }
 
-        Value::ok()
+        let tmp_path = self.cfg.aof_path().with_file_name(format!(
+            "temp-rewriteaof-bg-{pid}.aof",
+            pid = std::process::id()
+        ));
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let join_res = tokio::task::spawn_blocking(move || {
+                write_aof_file(&tmp_path, &cmds).map(|_| tmp_path)
+            })
+            .await;
+            let res = match join_res {
+                Ok(Ok(tmp_path)) => Ok(tmp_path),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            tx.send(ToDb::AofRewriteFinished(res))
+                .await
+                .unwrap_or_else(|e| println!("BGREWRITEAOF: could not notify Db, e:{e:?}"));
+        });
+
+        s_str("Background append only file rewriting started")
     }
 
-    fn exec_get(&self, key: &Bytes) -> Value {
-        match self.h.get(key) {
-            Some(val_ex) => {
-                if val_ex.ex > now_millis() {
-                    // not yet expired
-                    Value::BulkString(val_ex.val.clone())
-                } else {
-                    Value::NullBulkString
-                }
-            }
-            None => {
-                println!("Key not found: `{key:?}`");
-                Value::NullBulkString
+    fn finish_aof_rewrite(&mut self, tmp_path: &Path) -> Result<()> {
+        match &mut self.aof {
+            Some(aof) => aof.finish_rewrite(tmp_path),
+            // not logging writes, nothing was buffered
+            None => Ok(std::fs::rename(tmp_path, self.cfg.aof_path())?),
+        }
+    }
+
+    /// Checks HELLO's options, the connection switches protocols if this succeeds
+    fn exec_hello(
+        &self,
+        protocol: Protocol,
+        auth: &Option<(Bytes, Bytes)>,
+        name: &Option<Bytes>,
+        client_id: u64,
+    ) -> Result<Value, Value> {
+        // there are no users or passwords yet: the default user is the only one, and without
+        // a password of its own (nopass in redis' ACL terms) any password given for it is right
+        if let Some((username, _)) = auth {
+            if username.as_bytes() != b"default" {
+                return Err(Value::SimpleError(
+                    "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                ));
             }
         }
+        if name
+            .as_ref()
+            .is_some_and(|name| name.as_bytes().iter().any(|c| !(b'!'..=b'~').contains(c)))
+        {
+            return Err(Value::SimpleError(
+                "ERR Client names cannot contain spaces, newlines or special characters.".into(),
+            ));
+        }
+
+        let role = match self.cfg.role() {
+            Role::Master => "master",
+            Role::Slave => "replica",
+        };
+        Ok(Value::Map(vec![
+            ("server".into(), "redis".into()),
+            ("version".into(), REDIS_VERSION.into()),
+            ("proto".into(), Value::Int(protocol.version())),
+            ("id".into(), Value::Int(client_id as i64)),
+            ("mode".into(), "standalone".into()),
+            ("role".into(), role.into()),
+            ("modules".into(), Value::Array(vec![])),
+        ]))
     }
 
     fn exec_info(&self, arg: &str) -> Value {
         match arg {
-            "replication" => {
-                let parts = [
+            "replication" | "default" | "all" | "everything" => {
+                // a replica reports the replication stream of its master
+                let (replid, offset) = match (self.cfg.role(), &self.master_replid) {
+                    (Role::Slave, Some(master_replid)) => {
+                        (master_replid.as_str(), self.repl_byte_cnt as u64)
+                    }
+                    _ => (self.replication_id.as_str(), self.replication_offset),
+                };
+                let mut parts = vec![
                     format!("role:{role}", role = self.cfg.role()),
-                    format!("master_replid:{replid}", replid = self.replication_id),
-                    format!(
-                        "master_repl_offset:{offset}",
-                        offset = self.replication_offset
-                    ),
+                    format!("master_replid:{replid}"),
+                    format!("master_repl_offset:{offset}"),
                 ];
+                if let Some(backlog) = &self.backlog {
+                    parts.push("repl_backlog_active:1".to_string());
+                    parts.push(format!(
+                        "repl_backlog_size:{size}",
+                        size = self.cfg.repl_backlog_size
+                    ));
+                    // redis reports 1-based offsets here
+                    parts.push(format!(
+                        "repl_backlog_first_byte_offset:{first}",
+                        first = backlog.start_offset() + 1
+                    ));
+                    parts.push(format!(
+                        "repl_backlog_histlen:{histlen}",
+                        histlen = backlog.end_offset() - backlog.start_offset()
+                    ));
+                }
 
                 Value::BulkString(parts.join("\r\n").as_str().into())
             }
@@ -330,60 +2833,45 @@ impl Db {
 
     fn exec_repl_conf_ack(&mut self, byte_cnt: u64, client_info: &ClientInfo) {
         println!("!!! exec_repl_conf_ack: byte_cnt={byte_cnt} client_info={client_info:?}");
-        let repl_key = format!(
-            "{host}:{port}",
-            host = client_info.host,
-            port = client_info.port
-        );
+        let repl_key = client_info.addr();
 
-        let replica = self.replicas.get_mut(&repl_key);
+        let replica = self.replicas.get_mut(repl_key);
         if let Some(rep) = replica {
             rep.acked_byte_cnt = byte_cnt;
         } else {
-            let valid_keys: Vec<&String> = self.replicas.keys().into_iter().collect();
+            let valid_keys: Vec<&String> = self.replicas.keys().collect();
             println!("No replica for key=`{repl_key}`, valid keys are={valid_keys:?}")
         }
     }
 
+    /// Ask replicas for their offset. Like any other command sent to them, the GETACK is
+    /// part of the replication stream: it counts in the offset and goes to the backlog.
+    fn request_acks(&mut self) {
+        println!(
+            "Db::request_acks: requesting acks from {n} replicas",
+            n = self.replicas.len()
+        );
+        self.propagate_to_replicas(&Command::ReplConfGetAck("*".to_string()));
+    }
+
     async fn exec_wait(
         &self,
         n_repls: usize,
-        req_acks: bool,
-        timeout: i64,
+        offset: u64, // what replicas have to ack
+        deadline: i64, // millis since epoch
         qry: &Query,
         rsx: Sender<QueryResult>,
     ) -> Option<Value> {
-
-        if req_acks {
-            // Request acks from replicas
-            let get_ack_cmd = Command::ReplConfGetAck("*".to_string());
-            let cmd_bytes = serialize(&get_ack_cmd.to_bulk_array())
-                .unwrap()
-                .into_inner();
-
-            println!("Db::exec_set: requesting acks from {n} replicas", n=self.replicas.len());
-            for (_repl_key, replica) in self.replicas.iter() {
-                replica
-                    .sender
-                    .send(ToReplica::Bytes(
-                        cmd_bytes.clone(),
-                        "requesting getack".into(),
-                    ))
-                    .await
-                    .unwrap();
-            }
-        }
-
         let acked_repl_cnt = self
             .replicas
-            .iter()
-            .map(|(_rkey, ri)| {
+            .values()
+            .map(|ri| {
                 /* println!(
                     "rkey: {rkey} ri.acked_byte_cnt: {rac:?}  my_offset={o}",
                     rac = ri.acked_byte_cnt,
                     o = self.replication_offset
                 );*/
-                if ri.acked_byte_cnt >= self.replication_offset {
+                if ri.acked_byte_cnt >= offset {
                     1
                 } else {
                     0
@@ -391,11 +2879,14 @@ impl Db {
             })
             .sum::<usize>();
 
-        if acked_repl_cnt >= n_repls || timeout < 0 {
+        // inside a transaction there is no waiting
+        let no_wait = self.in_exec || self.clock.now_millis() as i64 >= deadline;
+        if acked_repl_cnt >= n_repls || no_wait {
             Some(Value::Int(acked_repl_cnt as i64))
         } else {
+            // check again a bit later
             let lapse = 100u64;
-            let new_cmd = Command::WaitInternal(n_repls as i64, timeout - (lapse as i64));
+            let new_cmd = Command::WaitInternal(n_repls as i64, deadline, offset);
             let mut new_qry = qry.clone();
             new_qry.cmd = new_cmd;
             let tx1 = self.tx.clone();
@@ -405,6 +2896,67 @@ impl Db {
     }
 }
 
+/// Exec functions that can fail return their error reply as Err, so that they can use `?`
+fn reply(res: Result<Value, Value>) -> Value {
+    res.unwrap_or_else(|err| err)
+}
+
+fn wrong_type_err() -> Value {
+    Value::SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+}
+
+/// A score in the flat list of members and scores ZRANGE gives RESP2 clients, a bulk string
+/// as it always was there. RESP3 clients get [member, score] pairs with scores as doubles.
+fn score_value(score: f64) -> Value {
+    Value::BulkString(format_double(score).as_str().into())
+}
+
+fn xadd_id_too_small_err() -> Value {
+    Value::SimpleError(
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item".into(),
+    )
+}
+
+/// Stream entries as replied by XRANGE & co.
+fn stream_entries_value(entries: Vec<(StreamId, &StreamFields)>) -> Value {
+    entries
+        .into_iter()
+        .map(|(id, fields)| stream_entry_value(id, fields))
+        .collect::<Vec<Value>>()
+        .into()
+}
+
+/// An array of the entry's id and its fields and values, interleaved
+fn stream_entry_value(id: StreamId, fields: &StreamFields) -> Value {
+    let mut parts: Vec<Value> = Vec::with_capacity(fields.len() * 2);
+    for (field, val) in fields {
+        parts.extend([field.into(), val.into()]);
+    }
+    vec![stream_id_value(id), parts.into()].into()
+}
+
+fn stream_id_value(id: StreamId) -> Value {
+    id.to_string().as_str().into()
+}
+
+/// NOGROUP reply of XREADGROUP & co., with `suffix` telling more about the command
+fn no_group_err(key: &Bytes, group: &Bytes, suffix: &str) -> Value {
+    Value::SimpleError(format!(
+        "NOGROUP No such key '{k}' or consumer group '{g}'{suffix}",
+        k = String::from_utf8_lossy(key.as_bytes()),
+        g = String::from_utf8_lossy(group.as_bytes())
+    ))
+}
+
+/// The inclusive range of positions that LRANGE & co. cover in a collection of `len` items,
+/// negative indices counting from the end. None if the range is empty.
+fn index_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    (start <= stop).then_some((start as usize, stop as usize))
+}
+
 async fn wait_again(for_millis: u64, new_qry: Query, tx: Sender<ToDb>, rsx: Sender<QueryResult>) {
     let dur = Duration::from_millis(for_millis);
     tokio::time::sleep(dur).await;
@@ -414,14 +2966,27 @@ async fn wait_again(for_millis: u64, new_qry: Query, tx: Sender<ToDb>, rsx: Send
 }
 
 pub struct ProxyToMaster {
-    bstream: BufStream<TcpStream>,
+    conn: RespDeserializer<BufStream<TcpStream>>,
+}
+
+impl std::fmt::Debug for ProxyToMaster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ProxyToMaster({addr})",
+            addr = peer_addr_str_v2(self.conn.get_ref())
+        )
+    }
 }
 
 impl ProxyToMaster {
-    async fn new(master_host_port: &str) -> Self {
+    async fn connect(master_host_port: &str) -> Result<Self> {
         let host_port = master_host_port.replace(' ', ":");
-        let bstream = BufStream::new(TcpStream::connect(host_port).await.unwrap());
-        Self { bstream }
+        let bstream = BufStream::new(TcpStream::connect(host_port).await?);
+        // no limits from the config, those are for clients: the master accepted it all already
+        Ok(Self {
+            conn: RespDeserializer::new(bstream),
+        })
     }
 
     async fn send_command(&mut self, cmd: Command) -> Result<Value> {
@@ -429,10 +2994,10 @@ impl ProxyToMaster {
 
         let cmd_as_value = cmd.to_bulk_array();
         let serialized = serialize(&cmd_as_value)?;
-        self.bstream.write_all(serialized.as_bytes()).await?;
-        self.bstream.flush().await?;
+        self.conn.get_mut().write_all(serialized.as_bytes()).await?;
+        self.conn.get_mut().flush().await?;
 
-        let (val, _) = deserialize(&mut self.bstream).await?;
+        let (val, _) = self.conn.deserialize().await?;
         Ok(val)
     }
 
@@ -442,15 +3007,72 @@ impl ProxyToMaster {
     } */
 
     async fn receive_file(&mut self) -> Result<Value> {
-        let mut deser = RespDeserializer::from_reader(&mut self.bstream);
-
-        let val = deser.deserialize_file().await?;
-        Ok(val)
+        self.conn.deserialize_file().await
     }
 }
 
-const EMPTY_RDB_FILE_HEX: &str = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+/// What a replica gets from its master in reply to PSYNC
+#[derive(Debug)]
+pub enum PsyncOutcome {
+    FullResync {
+        replid: String,
+        offset: u64,
+        contents: RdbContents,
+    },
+    Continue {
+        replid: String,
+    },
+}
+
+/// Replica side handshake: connect to the master, PING, REPLCONF and PSYNC
+/// with the given replid and offset, then receive the snapshot if the master asks for a full resync.
+async fn sync_with_master(
+    master_host_port: &str,
+    listening_port: u32,
+    replid: &str,
+    offset: i64,
+) -> Result<(ProxyToMaster, PsyncOutcome)> {
+    let mut proxy = ProxyToMaster::connect(master_host_port).await?;
 
-fn get_empty_rdb_bytes() -> Vec<u8> {
-    hex_decode(EMPTY_RDB_FILE_HEX).unwrap()
+    let ping_resp = proxy.send_command(Command::Ping).await?;
+    println!("master's response to ping: {ping_resp:?}");
+
+    let repl_conf_1 = Command::ReplConf("listening-port".into(), format!("{listening_port}"));
+    let repl_conf_1_resp = proxy.send_command(repl_conf_1).await?;
+    println!("master's response to repl_conf_1: {repl_conf_1_resp:?}");
+
+    let repl_conf_2 = Command::ReplConf("capa".into(), "psync2".into());
+    let repl_conf_2_resp = proxy.send_command(repl_conf_2).await?;
+    println!("master's response to repl_conf_2: {repl_conf_2_resp:?}");
+
+    let psync = Command::Psync(replid.into(), offset);
+    let psync_resp = proxy.send_command(psync).await?;
+    println!("master's response to psync: {psync_resp:?}");
+
+    let resp_str = psync_resp.try_to_string()?;
+    let parts: Vec<&str> = resp_str.split(' ').collect();
+    let outcome = match parts.as_slice() {
+        ["FULLRESYNC", replid, offset] => {
+            // waiting for RDBFILE now
+            let rdb_file = proxy.receive_file().await?;
+            let contents = match &rdb_file {
+                Value::FileContents(bs) => parse_rdb(bs.as_bytes())?,
+                _ => {
+                    return Err(format_err!(
+                        "Expected rdb file from master, got: {rdb_file:?}"
+                    ))
+                }
+            };
+            PsyncOutcome::FullResync {
+                replid: replid.to_string(),
+                offset: offset.parse::<u64>()?,
+                contents,
+            }
+        }
+        ["CONTINUE", replid] => PsyncOutcome::Continue {
+            replid: replid.to_string(),
+        },
+        _ => return Err(format_err!("Unexpected reply to PSYNC: {resp_str}")),
+    };
+    Ok((proxy, outcome))
 }
@@ -0,0 +1,98 @@
+// The values Db can hold, one variant per data type.
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::common::Bytes;
+use crate::stream::Stream;
+use crate::zset::SortedSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbVal {
+    Str(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl DbVal {
+    /// As reported by TYPE
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DbVal::Str(_) => "string",
+            DbVal::List(_) => "list",
+            DbVal::Hash(_) => "hash",
+            DbVal::Set(_) => "set",
+            DbVal::ZSet(_) => "zset",
+            DbVal::Stream(_) => "stream",
+        }
+    }
+
+    // Accessors to pass to Db::get_typed, None when the value is of another type
+
+    pub fn as_str_mut(&mut self) -> Option<&mut Bytes> {
+        match self {
+            DbVal::Str(bs) => Some(bs),
+            _ => None,
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Option<&mut VecDeque<Bytes>> {
+        match self {
+            DbVal::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Option<&mut HashMap<Bytes, Bytes>> {
+        match self {
+            DbVal::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn as_set_mut(&mut self) -> Option<&mut HashSet<Bytes>> {
+        match self {
+            DbVal::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    pub fn as_zset_mut(&mut self) -> Option<&mut SortedSet> {
+        match self {
+            DbVal::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    pub fn as_stream_mut(&mut self) -> Option<&mut Stream> {
+        match self {
+            DbVal::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    /// Collections are deleted once empty, as redis never keeps an empty one around.
+    /// Streams are the exception: they stay, remembering their last id.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            DbVal::Str(_) | DbVal::Stream(_) => false,
+            DbVal::List(list) => list.is_empty(),
+            DbVal::Hash(hash) => hash.is_empty(),
+            DbVal::Set(set) => set.is_empty(),
+            DbVal::ZSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+impl From<Bytes> for DbVal {
+    fn from(bs: Bytes) -> Self {
+        DbVal::Str(bs)
+    }
+}
+
+impl From<&str> for DbVal {
+    fn from(s: &str) -> Self {
+        DbVal::Str(s.into())
+    }
+}
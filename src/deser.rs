@@ -0,0 +1,330 @@
+// Synchronous, incremental RESP parser over a read buffer.
+//
+// Bytes are appended to a `BytesMut` as they arrive, and `RespParser::parse` is called after each
+// read. Until a whole value is in the buffer it returns `ParseError::Incomplete`, remembering how
+// far it got so that the next call doesn't scan the same bytes again. Once a value is complete it
+// is split off the front of the buffer, and big strings in it are slices of those bytes, not
+// copies. Small ones are copied out, so that a key stored for long doesn't keep the whole read
+// buffer it came in alive.
+//
+// Lengths in headers are checked against `ProtoLimits` before anything is waited for, so a client
+// can't make the server buffer more than the limits allow, nor nest aggregates arbitrarily deep.
+use std::fmt;
+
+use bytes::{Buf, BytesMut};
+
+use crate::common::Bytes;
+use crate::resp::{is_type_byte, split_inline, Value};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    // more bytes are needed, the buffer is left as it was
+    Incomplete,
+    // not RESP: the stream can't be parsed any further
+    Invalid(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Incomplete => write!(f, "incomplete value"),
+            ParseError::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn invalid<T>(msg: &str) -> Result<T, ParseError> {
+    Err(ParseError::Invalid(msg.to_string()))
+}
+
+// longest line without a CRLF: inline commands and headers of values
+const MAX_LINE_LEN: usize = 64 * 1024;
+// strings at least this long are slices of the read buffer, like redis' PROTO_MBULK_BIG_ARG
+const BIG_STRING_LEN: usize = 32 * 1024;
+
+/// How much a value may ask the parser to hold, with redis' defaults where it has a setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtoLimits {
+    pub max_bulk_len: usize,      // proto-max-bulk-len
+    pub max_multibulk_len: usize, // elements in one aggregate
+    pub max_nesting: usize,       // aggregates inside one another
+    pub max_query_buf: usize,     // client-query-buffer-limit, bytes of an incomplete value
+}
+
+impl Default for ProtoLimits {
+    fn default() -> Self {
+        ProtoLimits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: i32::MAX as usize,
+            max_nesting: 128,
+            max_query_buf: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RespParser {
+    limits: ProtoLimits,
+    scanned: usize, // bytes at the front of the buffer known to be part of the next value
+    // elements still to come in each aggregate the scan is in, innermost last
+    missing: Vec<usize>,
+    skipped: usize, // bytes of empty inline commands before the next value, already dropped
+}
+
+impl RespParser {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ProtoLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Take the next value off the front of `buf`, with the number of bytes it took.
+    /// On `Incomplete`, call again with the same buffer once more bytes were appended to it.
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<(Value, usize), ParseError> {
+        match self.parse_next(buf) {
+            Err(ParseError::Incomplete) if buf.len() > self.limits.max_query_buf => {
+                invalid("client query buffer limit exceeded")
+            }
+            res => res,
+        }
+    }
+
+    fn parse_next(&mut self, buf: &mut BytesMut) -> Result<(Value, usize), ParseError> {
+        loop {
+            if self.scanned == 0 && !buf.is_empty() && !is_type_byte(buf[0]) {
+                match self.parse_inline(buf)? {
+                    Some(val) => return Ok(val),
+                    None => continue, // an empty line, skipped
+                }
+            }
+            while !self.scan_next(buf)? {}
+            let len = self.scanned;
+            let mut frame = buf.split_to(len);
+            let val = build(&mut frame);
+            debug_assert!(frame.is_empty(), "scan and build disagree on the length");
+            let len = len + self.skipped;
+            *self = Self::with_limits(self.limits);
+            return Ok((val, len));
+        }
+    }
+
+    /// An inline command, or None for an empty line
+    fn parse_inline(&mut self, buf: &mut BytesMut) -> Result<Option<(Value, usize)>, ParseError> {
+        let Some(lf) = buf.iter().position(|byte| *byte == b'\n') else {
+            if buf.len() > MAX_LINE_LEN {
+                return invalid("too big inline request");
+            }
+            return Err(ParseError::Incomplete);
+        };
+        let line = buf.split_to(lf + 1);
+        let text = line[..lf].strip_suffix(b"\r").unwrap_or(&line[..lf]);
+        let words = split_inline(text).map_err(|e| ParseError::Invalid(e.to_string()))?;
+        if words.is_empty() {
+            self.skipped += line.len();
+            return Ok(None);
+        }
+        let len = line.len() + self.skipped;
+        self.skipped = 0;
+        let val = Value::Array(words.into_iter().map(Value::BulkString).collect());
+        Ok(Some((val, len)))
+    }
+
+    /// Check the element at `scanned`, and move past it if it's all there.
+    /// Returns true once the whole value has been scanned.
+    fn scan_next(&mut self, buf: &BytesMut) -> Result<bool, ParseError> {
+        let start = self.scanned;
+        let (type_byte, line, after_line) = header_at(buf, start)?;
+        let n_elems = match type_byte {
+            b'+' | b'-' => 0,
+            b':' => {
+                int_of(line, "invalid integer")?;
+                0
+            }
+            b'_' if line.is_empty() => 0,
+            b'#' if line == b"t" || line == b"f" => 0,
+            b',' => {
+                double_of(line)?;
+                0
+            }
+            b'(' if is_big_number(line) => 0,
+            b'$' | b'!' | b'=' => {
+                let len = int_of(line, "invalid bulk length")?;
+                if type_byte == b'$' && len == -1 {
+                    return Ok(self.element_done(after_line));
+                }
+                let len = usize::try_from(len)
+                    .ok()
+                    .filter(|len| *len <= self.limits.max_bulk_len)
+                    .map_or_else(|| invalid("invalid bulk length"), Ok)?;
+                let end = after_line.saturating_add(len).saturating_add(2);
+                if buf.len() < end {
+                    return Err(ParseError::Incomplete);
+                }
+                if &buf[end - 2..end] != b"\r\n" {
+                    return invalid("expected CRLF after bulk data");
+                }
+                if type_byte == b'=' && (len < 4 || buf[after_line + 3] != b':') {
+                    return invalid("invalid verbatim string");
+                }
+                return Ok(self.element_done(end));
+            }
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let n = int_of(line, "invalid multibulk length")?;
+                if type_byte == b'*' && n == -1 {
+                    return Ok(self.element_done(after_line));
+                }
+                let n = usize::try_from(n)
+                    .ok()
+                    .filter(|n| *n <= self.limits.max_multibulk_len)
+                    .map_or_else(|| invalid("invalid multibulk length"), Ok)?;
+                match type_byte {
+                    b'%' => n.saturating_mul(2),
+                    // the attributes, then the value they are about
+                    b'|' => n.saturating_mul(2).saturating_add(1),
+                    _ => n,
+                }
+            }
+            b'_' | b'#' | b'(' => return invalid(&format!("invalid '{}' line", type_byte as char)),
+            other => return invalid(&format!("expected '$', got '{}'", other as char)),
+        };
+        if n_elems > 0 {
+            if self.missing.len() >= self.limits.max_nesting {
+                return invalid("too deeply nested aggregates");
+            }
+            self.scanned = after_line;
+            self.missing.push(n_elems);
+            return Ok(false);
+        }
+        Ok(self.element_done(after_line))
+    }
+
+    /// An element ended at `end`, and with it maybe the aggregates it was the last element of
+    fn element_done(&mut self, end: usize) -> bool {
+        self.scanned = end;
+        while let Some(n) = self.missing.last_mut() {
+            *n -= 1;
+            if *n > 0 {
+                return false;
+            }
+            self.missing.pop();
+        }
+        true
+    }
+}
+
+/// Type byte, rest of the line and where the next line starts, for the line at `start`
+fn header_at(buf: &BytesMut, start: usize) -> Result<(u8, &[u8], usize), ParseError> {
+    let Some(cr) = buf[start..].windows(2).position(|w| w == b"\r\n") else {
+        if buf.len() - start > MAX_LINE_LEN {
+            return invalid(match buf[start] {
+                b'*' => "too big mbulk count string",
+                b'$' => "too big bulk count string",
+                _ => "too big line",
+            });
+        }
+        return Err(ParseError::Incomplete);
+    };
+    let cr = start + cr;
+    if cr == start {
+        return invalid("empty line");
+    }
+    Ok((buf[start], &buf[start + 1..cr], cr + 2))
+}
+
+fn int_of(line: &[u8], err: &str) -> Result<i64, ParseError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map_or_else(|| invalid(err), Ok)
+}
+
+fn double_of(line: &[u8]) -> Result<f64, ParseError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map_or_else(|| invalid("invalid double"), Ok)
+}
+
+fn is_big_number(line: &[u8]) -> bool {
+    let digits = line.strip_prefix(b"-").unwrap_or(line);
+    !digits.is_empty() && digits.iter().all(u8::is_ascii_digit)
+}
+
+/// Split the value at the front of `frame` off it. The value was already scanned, so it is
+/// known to be complete and well formed.
+fn build(frame: &mut BytesMut) -> Value {
+    let cr = frame
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .expect("scanned values have whole lines");
+    let mut line = frame.split_to(cr + 2);
+    let type_byte = line[0];
+    line.advance(1);
+    line.truncate(cr - 1);
+    let text = || String::from_utf8_lossy(&line).to_string();
+    let num = || {
+        text()
+            .parse::<i64>()
+            .expect("scanned values have valid numbers")
+    };
+
+    match type_byte {
+        b'+' => Value::SimpleString(string_of(line)),
+        b'-' => Value::SimpleError(text()),
+        b':' => Value::Int(num()),
+        b'_' => Value::Null,
+        b'#' => Value::Boolean(&line[..] == b"t"),
+        b',' => Value::Double(text().parse().expect("scanned values have valid doubles")),
+        b'(' => Value::BigNumber(string_of(line)),
+        b'$' | b'!' | b'=' => {
+            let len = num();
+            if len == -1 {
+                return Value::NullBulkString;
+            }
+            let mut data = frame.split_to(len as usize);
+            frame.advance(2);
+            match type_byte {
+                b'$' => Value::BulkString(string_of(data)),
+                b'!' => Value::BulkError(String::from_utf8_lossy(&data).to_string()),
+                _ => {
+                    let format = data.split_to(3);
+                    data.advance(1);
+                    Value::Verbatim(string_of(format), string_of(data))
+                }
+            }
+        }
+        _ => {
+            let n = num();
+            if n == -1 {
+                return Value::NullArray;
+            }
+            let n = n as usize;
+            match type_byte {
+                b'*' => Value::Array((0..n).map(|_| build(frame)).collect()),
+                b'~' => Value::Set((0..n).map(|_| build(frame)).collect()),
+                b'>' => Value::Push((0..n).map(|_| build(frame)).collect()),
+                b'%' => Value::Map((0..n).map(|_| (build(frame), build(frame))).collect()),
+                _ => {
+                    let attrs = (0..n).map(|_| (build(frame), build(frame))).collect();
+                    Value::Attribute(attrs, Box::new(build(frame)))
+                }
+            }
+        }
+    }
+}
+
+fn string_of(data: BytesMut) -> Bytes {
+    if data.len() >= BIG_STRING_LEN {
+        data.into()
+    } else {
+        data[..].into()
+    }
+}
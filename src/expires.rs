@@ -0,0 +1,67 @@
+// Keys that have an expiry, like redis' `expires` dict, so that the active expire cycle
+// can sample them at random without scanning the whole keyspace.
+use std::collections::HashMap;
+
+use crate::common::Bytes;
+
+#[derive(Debug)]
+pub struct VolatileKeys {
+    keys: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>, // index of each key in `keys`
+    rng_state: u64,
+}
+
+impl VolatileKeys {
+    pub fn new(seed: u64) -> Self {
+        VolatileKeys {
+            keys: Vec::new(),
+            positions: HashMap::new(),
+            // xorshift gets stuck at 0
+            rng_state: seed | 1,
+        }
+    }
+
+    pub fn insert(&mut self, key: &Bytes) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.clone(), self.keys.len());
+            self.keys.push(key.clone());
+        }
+    }
+
+    pub fn remove(&mut self, key: &Bytes) {
+        if let Some(pos) = self.positions.remove(key) {
+            self.keys.swap_remove(pos);
+            if let Some(moved) = self.keys.get(pos) {
+                self.positions.insert(moved.clone(), pos);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.positions.clear();
+    }
+
+    /// Up to `n` keys picked at random. Picks are independent, so a key may come up twice.
+    pub fn sample(&mut self, n: usize) -> Vec<Bytes> {
+        if self.keys.len() <= n {
+            return self.keys.clone();
+        }
+        (0..n)
+            .map(|_| {
+                let idx = (self.next_random() % self.keys.len() as u64) as usize;
+                self.keys[idx].clone()
+            })
+            .collect()
+    }
+
+    // xorshift64*, plenty for picking keys
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+}
@@ -0,0 +1,68 @@
+// Glob-style pattern matching, as used by KEYS: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes.
+
+/// True if all of `s` matches `pattern`
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // pattern position right after the last `*` seen, and where in `s` that `*` stops matching
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        let consumed = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, i));
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_class(&pattern[p + 1..], s[i]) {
+                (true, len) => Some(1 + len),
+                (false, _) => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == s[i]).then_some(2),
+            Some(c) => (*c == s[i]).then_some(1),
+            None => None,
+        };
+
+        match (consumed, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                i += 1;
+            }
+            // let the last `*` swallow one more byte and retry from there
+            (None, Some((star_p, star_i))) => {
+                p = star_p;
+                i = star_i + 1;
+                backtrack = Some((star_p, i));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Match `c` against a character class, `class` starting right after the `[`.
+/// Returns whether it matched and how many pattern bytes the class takes, including the `]`.
+/// Like redis, an unterminated class extends to the end of the pattern.
+fn match_class(class: &[u8], c: u8) -> (bool, usize) {
+    let negate = class.first() == Some(&b'^');
+    let mut j = usize::from(negate);
+    let mut matched = false;
+
+    while j < class.len() && class[j] != b']' {
+        if class[j] == b'\\' && j + 1 < class.len() {
+            matched |= class[j + 1] == c;
+            j += 2;
+        } else if j + 2 < class.len() && class[j + 1] == b'-' && class[j + 2] != b']' {
+            let (lo, hi) = (class[j].min(class[j + 2]), class[j].max(class[j + 2]));
+            matched |= (lo..=hi).contains(&c);
+            j += 3;
+        } else {
+            matched |= class[j] == c;
+            j += 1;
+        }
+    }
+    let len = (j + 1).min(class.len());
+
+    (matched != negate, len)
+}
@@ -1,17 +1,17 @@
 // use std::time::Duration;
 
-use tokio::io::{AsyncBufReadExt, BufStream};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 
 use crate::common::Bytes;
 
-pub async fn debug_peek(msg: &str, bstream: &mut BufStream<TcpStream>, n: usize) {
+#[allow(dead_code)]
+pub async fn debug_peek<R: AsyncBufRead + Unpin>(msg: &str, bstream: &mut R, n: usize) {
     let output = peek(bstream, n).await;
     println!("{msg} PEEKED ({n}): `{output:?}`", n = output.len());
 }
 
 #[allow(dead_code)]
-pub async fn peek(bstream: &mut BufStream<TcpStream>, n: usize) -> Bytes {
+pub async fn peek<R: AsyncBufRead + Unpin>(bstream: &mut R, n: usize) -> Bytes {
     // let mut vec = vec![0u8; n]; // Vec::<u8>::with_capacity(n);
     // let mut buf = ReadBuf::new(&mut vec);
 
@@ -1,10 +1,24 @@
+pub mod aof;
 pub mod async_deser;
+pub mod clock;
 pub mod commands;
 pub mod common;
 pub mod config;
 pub mod db;
+pub mod deser;
+pub mod db_val;
+pub mod expires;
+pub mod glob;
 pub mod io_util;
+pub mod listpack;
+pub mod long_double;
 pub mod misc_util;
+pub mod pubsub;
+pub mod rdb;
+pub mod repl_backlog;
 pub mod replica_handler;
 pub mod resp;
+pub mod stream;
 pub mod svc;
+pub mod watch;
+pub mod zset;
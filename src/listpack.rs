@@ -0,0 +1,182 @@
+// Listpack: the compact list encoding redis uses inside rdb files, here for stream entries.
+// Format reference: https://github.com/antirez/listpack/blob/master/listpack.md
+use anyhow::{format_err, Result};
+
+use crate::common::Bytes;
+
+const HEADER_LEN: usize = 6; // total bytes (u32) and number of elements (u16)
+const EOF: u8 = 0xFF;
+
+/// One element: either stored as an integer or as a string
+#[derive(Debug, Clone, PartialEq)]
+pub enum LpElem {
+    Int(i64),
+    Str(Bytes),
+}
+
+impl LpElem {
+    /// Integers written by others may come as strings too
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            LpElem::Int(i) => Some(*i),
+            LpElem::Str(bs) => std::str::from_utf8(bs.as_bytes()).ok()?.parse().ok(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            LpElem::Int(i) => i.to_string().as_str().into(),
+            LpElem::Str(bs) => bs,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ListpackWriter {
+    elems: Vec<u8>,
+    n_elems: usize,
+}
+
+impl ListpackWriter {
+    pub fn int(&mut self, i: i64) {
+        let start = self.elems.len();
+        if (0..=127).contains(&i) {
+            self.elems.push(i as u8);
+        } else if (-4096..=4095).contains(&i) {
+            let u = (i as u16) & 0x1FFF;
+            self.elems
+                .extend_from_slice(&[0xC0 | (u >> 8) as u8, u as u8]);
+        } else if let Ok(i) = i16::try_from(i) {
+            self.elems.push(0xF1);
+            self.elems.extend_from_slice(&i.to_le_bytes());
+        } else if (-(1 << 23)..1 << 23).contains(&i) {
+            self.elems.push(0xF2);
+            self.elems.extend_from_slice(&(i as i32).to_le_bytes()[..3]);
+        } else if let Ok(i) = i32::try_from(i) {
+            self.elems.push(0xF3);
+            self.elems.extend_from_slice(&i.to_le_bytes());
+        } else {
+            self.elems.push(0xF4);
+            self.elems.extend_from_slice(&i.to_le_bytes());
+        }
+        self.end_elem(start);
+    }
+
+    pub fn str(&mut self, data: &[u8]) {
+        let start = self.elems.len();
+        let len = data.len();
+        if len < 64 {
+            self.elems.push(0x80 | len as u8);
+        } else if len < 4096 {
+            self.elems
+                .extend_from_slice(&[0xE0 | (len >> 8) as u8, len as u8]);
+        } else {
+            self.elems.push(0xF0);
+            self.elems.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.elems.extend_from_slice(data);
+        self.end_elem(start);
+    }
+
+    /// Every element ends with its own length, so that the list can be walked backwards:
+    /// 7 bits per byte, most significant first, all but the first byte flagged with 0x80
+    fn end_elem(&mut self, start: usize) {
+        let len = self.elems.len() - start;
+        let n_bytes = backlen_size(len);
+        for i in (0..n_bytes).rev() {
+            let group = ((len >> (7 * i)) & 0x7F) as u8;
+            let flag = if i == n_bytes - 1 { 0 } else { 0x80 };
+            self.elems.push(group | flag);
+        }
+        self.n_elems += 1;
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let total_len = HEADER_LEN + self.elems.len() + 1;
+        let mut buf = Vec::with_capacity(total_len);
+        buf.extend_from_slice(&(total_len as u32).to_le_bytes());
+        // u16::MAX means "too many to count here"
+        buf.extend_from_slice(&(self.n_elems.min(u16::MAX as usize) as u16).to_le_bytes());
+        buf.extend_from_slice(&self.elems);
+        buf.push(EOF);
+        buf
+    }
+}
+
+pub fn parse_listpack(data: &[u8]) -> Result<Vec<LpElem>> {
+    let truncated = || format_err!("Truncated listpack");
+    if data.len() < HEADER_LEN + 1 {
+        return Err(truncated());
+    }
+    let total_len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+    if total_len != data.len() {
+        return Err(format_err!(
+            "Listpack length {total_len} does not match its data ({n} bytes)",
+            n = data.len()
+        ));
+    }
+
+    let mut elems = Vec::new();
+    let mut pos = HEADER_LEN;
+    loop {
+        let first = *data.get(pos).ok_or_else(truncated)?;
+        if first == EOF {
+            break;
+        }
+        let take = |from: usize, n: usize| data.get(from..from + n).ok_or_else(truncated);
+        let (elem, len) = if first & 0x80 == 0 {
+            (LpElem::Int(first as i64), 1)
+        } else if first & 0xC0 == 0x80 {
+            let n = (first & 0x3F) as usize;
+            (LpElem::Str(take(pos + 1, n)?.into()), 1 + n)
+        } else if first & 0xE0 == 0xC0 {
+            let u = ((first & 0x1F) as u16) << 8 | take(pos + 1, 1)?[0] as u16;
+            // sign extend from 13 bits
+            (LpElem::Int(((u << 3) as i16 >> 3) as i64), 2)
+        } else if first & 0xF0 == 0xE0 {
+            let n = ((first & 0x0F) as usize) << 8 | take(pos + 1, 1)?[0] as usize;
+            (LpElem::Str(take(pos + 2, n)?.into()), 2 + n)
+        } else {
+            match first {
+                0xF0 => {
+                    let n = u32::from_le_bytes(take(pos + 1, 4)?.try_into().unwrap()) as usize;
+                    (LpElem::Str(take(pos + 5, n)?.into()), 5 + n)
+                }
+                0xF1 => {
+                    let i = i16::from_le_bytes(take(pos + 1, 2)?.try_into().unwrap());
+                    (LpElem::Int(i as i64), 3)
+                }
+                0xF2 => {
+                    let b = take(pos + 1, 3)?;
+                    // sign extend from 24 bits
+                    let i = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                    (LpElem::Int(i as i64), 4)
+                }
+                0xF3 => {
+                    let i = i32::from_le_bytes(take(pos + 1, 4)?.try_into().unwrap());
+                    (LpElem::Int(i as i64), 5)
+                }
+                0xF4 => {
+                    let i = i64::from_le_bytes(take(pos + 1, 8)?.try_into().unwrap());
+                    (LpElem::Int(i), 9)
+                }
+                _ => return Err(format_err!("Invalid listpack encoding byte 0x{first:02x}")),
+            }
+        };
+        elems.push(elem);
+        pos += len + backlen_size(len);
+    }
+    Ok(elems)
+}
+
+/// Bytes taken by the length stored after an element of `len` bytes. The bounds are
+/// redis' own, which sometimes uses a byte more than needed.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
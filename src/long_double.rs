@@ -0,0 +1,469 @@
+// Just enough of C's long double (x87's 80-bit extended precision) for INCRBYFLOAT to store
+// what redis stores: it reads both numbers with strtold, adds them as long doubles, and prints
+// the sum with "%.17Lf". Done in f64 the same steps give e.g. 0.30000000000000004 for 0.1 + 0.2.
+use std::cmp::Ordering;
+use std::fmt;
+
+// bits of the significand, the leading one included
+const MANT_BITS: u64 = 64;
+// finite values are below 2^16384
+const MAX_EXP: i64 = 16384;
+// and multiples of the smallest subnormal one, 2^-16445
+const MIN_QUANTUM: i64 = -16445;
+// digits after the point redis prints
+const FRACTION_DIGITS: usize = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LongDouble {
+    /// `mant * 2^exp`, where `mant` uses all its bits unless it is 0
+    Finite {
+        neg: bool,
+        mant: u64,
+        exp: i64,
+    },
+    Infinite {
+        neg: bool,
+    },
+}
+
+impl LongDouble {
+    fn finite(neg: bool, mant: u64, exp: i64) -> Self {
+        Self::Finite { neg, mant, exp }
+    }
+
+    /// Sign, significand and exponent, unless infinite
+    fn parts(self) -> Option<(bool, u64, i64)> {
+        match self {
+            Self::Finite { neg, mant, exp } => Some((neg, mant, exp)),
+            Self::Infinite { .. } => None,
+        }
+    }
+
+    /// A number the way redis' string2ld reads it: whatever strtold reads, decimal or
+    /// hexadecimal, as long as that is all of `bs`, isn't NaN and doesn't overflow or underflow
+    pub fn parse(bs: &[u8]) -> Option<Self> {
+        let (neg, rest) = match bs {
+            [b'-', rest @ ..] => (true, rest),
+            [b'+', rest @ ..] => (false, rest),
+            _ => (false, bs),
+        };
+        if rest.eq_ignore_ascii_case(b"inf") || rest.eq_ignore_ascii_case(b"infinity") {
+            return Some(Self::Infinite { neg });
+        }
+        let (mant, exp) = match rest {
+            [b'0', b'x' | b'X', hex @ ..] => parse_hex(hex)?,
+            _ => parse_decimal(rest)?,
+        };
+        // strtold fails with ERANGE past the largest value, and redis takes that as not a number
+        if exp + MANT_BITS as i64 > MAX_EXP {
+            return None;
+        }
+        Some(Self::finite(neg, mant, exp))
+    }
+
+    /// The sum, rounded to nearest like the FPU does, or None for infinities and NaN
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let ((a_neg, a_mant, a_exp), (b_neg, b_mant, b_exp)) = (self.parts()?, other.parts()?);
+        if a_mant == 0 {
+            if b_mant == 0 {
+                // -0 only if both are
+                return Some(Self::finite(a_neg && b_neg, 0, 0));
+            }
+            return Some(other);
+        }
+        if b_mant == 0 {
+            return Some(self);
+        }
+
+        // exactly, then rounded once
+        let base = a_exp.min(b_exp);
+        let a = BigUint::from(a_mant).shl((a_exp - base) as u64);
+        let b = BigUint::from(b_mant).shl((b_exp - base) as u64);
+        let (neg, sum) = if a_neg == b_neg {
+            (a_neg, a.add(&b))
+        } else {
+            match a.cmp(&b) {
+                Ordering::Greater => (a_neg, a.sub(&b)),
+                Ordering::Less => (b_neg, b.sub(&a)),
+                Ordering::Equal => return Some(Self::finite(false, 0, 0)),
+            }
+        };
+        let (mant, exp) = round(&sum, base, false);
+        if exp + MANT_BITS as i64 > MAX_EXP {
+            return None;
+        }
+        Some(Self::finite(neg, mant, exp))
+    }
+}
+
+/// As redis prints INCRBYFLOAT results: "%.17Lf" without the fraction's trailing zeros,
+/// so never with an exponent, and 0 rather than -0
+impl fmt::Display for LongDouble {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (neg, mant, exp) = match *self {
+            Self::Finite { neg, mant, exp } => (neg, mant, exp),
+            Self::Infinite { neg } => return f.write_str(if neg { "-inf" } else { "inf" }),
+        };
+        let mut scaled = BigUint::from(mant);
+        for _ in 0..FRACTION_DIGITS {
+            scaled.mul_add_small(10, 0);
+        }
+        let scaled = if exp >= 0 {
+            scaled.shl(exp as u64)
+        } else {
+            shr_round(&scaled, exp.unsigned_abs())
+        };
+
+        let digits = format!(
+            "{:0>width$}",
+            scaled.to_decimal(),
+            width = FRACTION_DIGITS + 1
+        );
+        let (int_part, frac_part) = digits.split_at(digits.len() - FRACTION_DIGITS);
+        let frac_part = frac_part.trim_end_matches('0');
+        if neg && !scaled.is_zero() {
+            f.write_str("-")?;
+        }
+        f.write_str(int_part)?;
+        if !frac_part.is_empty() {
+            write!(f, ".{frac_part}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The leading digits of `bs` in `radix`, and what follows them
+fn split_digits(bs: &[u8], radix: u32) -> (&[u8], &[u8]) {
+    let n = bs
+        .iter()
+        .take_while(|b| char::from(**b).is_digit(radix))
+        .count();
+    bs.split_at(n)
+}
+
+/// `[digits][.digits][e[+-]digits]` rounded to a significand and exponent, None for an
+/// invalid number or one so small that it rounds to 0
+fn parse_decimal(bs: &[u8]) -> Option<(u64, i64)> {
+    let (int_part, rest) = split_digits(bs, 10);
+    let (frac_part, rest) = match rest {
+        [b'.', rest @ ..] => split_digits(rest, 10),
+        _ => (&rest[..0], rest),
+    };
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+    let exp10 = match rest {
+        [] => 0,
+        [b'e' | b'E', exp @ ..] => parse_exponent(exp)?,
+        _ => return None,
+    };
+    let mut digits = BigUint::default();
+    for digit in int_part.iter().chain(frac_part) {
+        digits.mul_add_small(10, u32::from(digit - b'0'));
+    }
+    if digits.is_zero() {
+        return Some((0, 0));
+    }
+    let exp10 = exp10 - frac_part.len() as i64;
+    // a first guess of the magnitude, so that nothing huge gets computed for what can't fit
+    let log10 = digits.bit_len() as f64 * std::f64::consts::LOG10_2 + exp10 as f64;
+    if !(-4955.0..4935.0).contains(&log10) {
+        return None;
+    }
+
+    let (mant, exp) = if exp10 >= 0 {
+        for _ in 0..exp10 {
+            digits.mul_add_small(10, 0);
+        }
+        round(&digits, 0, false)
+    } else {
+        let mut divisor = BigUint::from(1);
+        for _ in 0..-exp10 {
+            divisor.mul_add_small(10, 0);
+        }
+        // enough quotient bits to round to MANT_BITS, the rest only matters as a remainder
+        let shift = (divisor.bit_len() + MANT_BITS + 2).saturating_sub(digits.bit_len());
+        let (quot, rem) = digits.shl(shift).div_rem(&divisor);
+        round(&quot, -(shift as i64), !rem.is_zero())
+    };
+    (mant != 0).then_some((mant, exp))
+}
+
+/// Like parse_decimal, for what follows "0x": `[hexdigits][.hexdigits][p[+-]digits]`
+fn parse_hex(bs: &[u8]) -> Option<(u64, i64)> {
+    let (int_part, rest) = split_digits(bs, 16);
+    let (frac_part, rest) = match rest {
+        [b'.', rest @ ..] => split_digits(rest, 16),
+        _ => (&rest[..0], rest),
+    };
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+    let exp2 = match rest {
+        [] => 0,
+        [b'p' | b'P', exp @ ..] => parse_exponent(exp)?,
+        _ => return None,
+    };
+    let mut digits = BigUint::default();
+    for digit in int_part.iter().chain(frac_part) {
+        let digit = char::from(*digit)
+            .to_digit(16)
+            .expect("split as hex digits");
+        digits.mul_add_small(16, digit);
+    }
+    if digits.is_zero() {
+        return Some((0, 0));
+    }
+    let (mant, exp) = round(&digits, exp2 - 4 * frac_part.len() as i64, false);
+    (mant != 0).then_some((mant, exp))
+}
+
+/// An exponent's `[+-]digits`, saturated well past where any value is out of range
+fn parse_exponent(bs: &[u8]) -> Option<i64> {
+    let (neg, digits) = match bs {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, bs),
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let exp = digits.iter().fold(0i64, |exp, digit| {
+        (exp * 10 + i64::from(digit - b'0')).min(1_000_000)
+    });
+    Some(if neg { -exp } else { exp })
+}
+
+/// `n * 2^exp` rounded to what a long double can hold, half to even: MANT_BITS significant
+/// bits, and nothing below MIN_QUANTUM. `sticky` is whether something non-zero was already
+/// dropped below `n`. Gives the significand with all its bits used, unless it is 0.
+fn round(n: &BigUint, exp: i64, sticky: bool) -> (u64, i64) {
+    let dropped = (n.bit_len() as i64 - MANT_BITS as i64).max(MIN_QUANTUM - exp);
+    let (mut mant, mut exp) = (n.low_u64(), exp);
+    if dropped > 0 {
+        let dropped = dropped as u64;
+        mant = n.shr(dropped).low_u64();
+        exp += dropped as i64;
+        let half = n.bit(dropped - 1);
+        if half && (sticky || n.any_below(dropped - 1) || mant & 1 == 1) {
+            mant = mant.wrapping_add(1);
+            if mant == 0 {
+                mant = 1 << (MANT_BITS - 1);
+                exp += 1;
+            }
+        }
+    }
+    if mant == 0 {
+        return (0, 0);
+    }
+    let shift = mant.leading_zeros();
+    (mant << shift, exp - i64::from(shift))
+}
+
+/// `n / 2^shift` rounded to an integer, half to even
+fn shr_round(n: &BigUint, shift: u64) -> BigUint {
+    let quot = n.shr(shift);
+    let half = n.bit(shift - 1);
+    if half && (n.any_below(shift - 1) || quot.bit(0)) {
+        quot.add(&BigUint::from(1))
+    } else {
+        quot
+    }
+}
+
+/// Unsigned integers of any size, with only what the conversions above need
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BigUint {
+    words: Vec<u32>, // least significant first, without leading zero words
+}
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> Self {
+        let mut big = Self {
+            words: vec![n as u32, (n >> 32) as u32],
+        };
+        big.trim();
+        big
+    }
+}
+
+impl BigUint {
+    fn trim(&mut self) {
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    fn bit_len(&self) -> u64 {
+        match self.words.last() {
+            Some(top) => self.words.len() as u64 * 32 - u64::from(top.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    fn bit(&self, i: u64) -> bool {
+        let word = self.words.get((i / 32) as usize).copied().unwrap_or(0);
+        word >> (i % 32) & 1 == 1
+    }
+
+    /// Whether any of the bits below the `i`th is set
+    fn any_below(&self, i: u64) -> bool {
+        let (full, rest) = ((i / 32) as usize, i % 32);
+        let words = &self.words[..full.min(self.words.len())];
+        words.iter().any(|w| *w != 0)
+            || (rest > 0
+                && self
+                    .words
+                    .get(full)
+                    .is_some_and(|w| w & ((1 << rest) - 1) != 0))
+    }
+
+    fn low_u64(&self) -> u64 {
+        let word = |i| u64::from(self.words.get(i).copied().unwrap_or(0));
+        word(0) | word(1) << 32
+    }
+
+    /// `self * m + a`, in place
+    fn mul_add_small(&mut self, m: u32, a: u32) {
+        let mut carry = u64::from(a);
+        for word in &mut self.words {
+            let n = u64::from(*word) * u64::from(m) + carry;
+            *word = n as u32;
+            carry = n >> 32;
+        }
+        if carry > 0 {
+            self.words.push(carry as u32);
+        }
+    }
+
+    fn shl(&self, shift: u64) -> Self {
+        let (words, bits) = ((shift / 32) as usize, shift % 32);
+        let mut out = vec![0; words];
+        let mut carry = 0;
+        for word in &self.words {
+            out.push(word << bits | carry);
+            carry = if bits == 0 { 0 } else { word >> (32 - bits) };
+        }
+        out.push(carry);
+        let mut big = Self { words: out };
+        big.trim();
+        big
+    }
+
+    fn shr(&self, shift: u64) -> Self {
+        let (words, bits) = ((shift / 32) as usize, shift % 32);
+        let src = self.words.get(words..).unwrap_or_default();
+        let mut out = Vec::with_capacity(src.len());
+        for (i, word) in src.iter().enumerate() {
+            let next = if bits == 0 {
+                0
+            } else {
+                src.get(i + 1).map_or(0, |n| n << (32 - bits))
+            };
+            out.push(word >> bits | next);
+        }
+        let mut big = Self { words: out };
+        big.trim();
+        big
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut out = Vec::with_capacity(self.words.len().max(other.words.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.words.len().max(other.words.len()) {
+            let word = |big: &Self| u64::from(big.words.get(i).copied().unwrap_or(0));
+            let n = word(self) + word(other) + carry;
+            out.push(n as u32);
+            carry = n >> 32;
+        }
+        out.push(carry as u32);
+        let mut big = Self { words: out };
+        big.trim();
+        big
+    }
+
+    /// `self - other`, which must not be negative
+    fn sub(&self, other: &Self) -> Self {
+        let mut out = Vec::with_capacity(self.words.len());
+        let mut borrow = 0;
+        for (i, word) in self.words.iter().enumerate() {
+            let rhs = i64::from(other.words.get(i).copied().unwrap_or(0)) + borrow;
+            let mut n = i64::from(*word) - rhs;
+            borrow = 0;
+            if n < 0 {
+                n += 1 << 32;
+                borrow = 1;
+            }
+            out.push(n as u32);
+        }
+        debug_assert_eq!(borrow, 0, "subtracted a bigger number");
+        let mut big = Self { words: out };
+        big.trim();
+        big
+    }
+
+    /// Quotient and remainder, one bit at a time. Quick only for small quotients, which
+    /// are all that parsing needs.
+    fn div_rem(&self, divisor: &Self) -> (Self, Self) {
+        let mut quot = vec![0u32; self.words.len()];
+        // the bits above these are smaller than the divisor, no quotient bits there
+        let low_bits = self.bit_len().saturating_sub(divisor.bit_len() - 1);
+        let mut rem = self.shr(low_bits);
+        for i in (0..low_bits).rev() {
+            rem = rem.shl(1);
+            if self.bit(i) {
+                match rem.words.first_mut() {
+                    Some(low) => *low |= 1,
+                    None => rem.words.push(1),
+                }
+            }
+            if rem >= *divisor {
+                rem = rem.sub(divisor);
+                quot[(i / 32) as usize] |= 1 << (i % 32);
+            }
+        }
+        let mut quot = Self { words: quot };
+        quot.trim();
+        (quot, rem)
+    }
+
+    fn to_decimal(&self) -> String {
+        // 9 digits at a time, least significant first
+        let mut chunks = Vec::new();
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let mut rem = 0u64;
+            for word in n.words.iter_mut().rev() {
+                let cur = rem << 32 | u64::from(*word);
+                *word = (cur / 1_000_000_000) as u32;
+                rem = cur % 1_000_000_000;
+            }
+            n.trim();
+            chunks.push(rem);
+        }
+        let mut out = chunks.pop().map_or("0".to_string(), |top| top.to_string());
+        for chunk in chunks.iter().rev() {
+            out.push_str(&format!("{chunk:09}"));
+        }
+        out
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.words
+            .len()
+            .cmp(&other.words.len())
+            .then_with(|| self.words.iter().rev().cmp(other.words.iter().rev()))
+    }
+}
@@ -1,17 +1,32 @@
 // Uncomment this block to pass the first stage
 use anyhow::Result;
 
+mod aof;
 mod async_deser;
+mod clock;
 mod commands;
 mod common;
 mod config;
 mod db;
+mod deser;
+mod db_val;
+mod expires;
+mod glob;
 mod io_util;
+mod listpack;
+mod long_double;
 mod misc_util;
+mod pubsub;
+mod rdb;
+mod repl_backlog;
 mod replica_handler;
 mod resp;
+mod stream;
 mod svc;
+mod watch;
+mod zset;
 
+use async_deser::RespDeserializer;
 use config::InstanceConfig;
 use db::Db;
 use log::info;
@@ -28,6 +43,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let config = InstanceConfig::from_command_args();
     info!("Config from args: {config:?}");
     let port = config.port();
+    let limits = config.proto_limits;
 
     info!("Logs from your program will appear here!");
 
@@ -35,7 +51,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let (tx, rx): (Sender<ToDb>, Receiver<ToDb>) = mpsc::channel(100);
 
     println!("main: Setting up Db object.");
-    let db = Db::new(config, tx.clone());
+    let mut db = Db::new(config, tx.clone());
+    db.load_data().await?;
     tokio::spawn(db.run(rx));
 
     tokio::time::sleep(Duration::from_millis(3000)).await;
@@ -46,8 +63,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Ok((stream, addr)) => {
                 println!("Accepted new client (on {port}): peer={addr:?}");
                 let tx1 = tx.clone();
-                let bstream = BufStream::new(stream);
-                tokio::spawn(async move { svc::handle_stream_async(bstream, tx1, false).await });
+                let conn = RespDeserializer::with_limits(BufStream::new(stream), limits);
+                tokio::spawn(async move {
+                    svc::handle_stream_async(conn, addr.to_string(), tx1, false).await
+                });
             }
             Err(e) => println!("couldn't get client: {:?}", e),
         }
@@ -27,13 +27,15 @@ pub fn peer_addr_str_v2(stream: &BufStream<TcpStream>) -> String {
         .unwrap_or("<undefined>".to_string())
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct InvalidDigit;
 
+#[allow(dead_code)]
 pub fn hex_decode(input: &str) -> Result<Vec<u8>, InvalidDigit> {
     let input_bytes = input.as_bytes();
     let n_bytes = input_bytes.len();
-    assert!(n_bytes % 2 == 0);
+    assert_eq!(n_bytes % 2, 0);
 
     let mut output = Vec::with_capacity(n_bytes / 2);
 
@@ -0,0 +1,247 @@
+// Pub/Sub: the channels and patterns clients are subscribed to, and delivery of published messages.
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::common::Bytes;
+use crate::glob::glob_match;
+use crate::resp::Value;
+
+/// Where values pushed to a client, outside of request / reply, are sent
+pub type PushSender = Sender<Pushed>;
+
+// Messages a client can have waiting to be written out. One that doesn't keep up is
+// disconnected, like redis does past its client-output-buffer-limit for pubsub clients.
+pub const PUSH_QUEUE_LIMIT: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pushed {
+    Message(Value),
+    // the client fell too far behind and is no longer subscribed to anything
+    Overflowed,
+}
+
+/// A client's queue of pushed values
+pub fn push_channel() -> (PushSender, Receiver<Pushed>) {
+    // one more slot for Overflowed, which has to fit when nothing else does
+    channel(PUSH_QUEUE_LIMIT + 1)
+}
+
+#[derive(Default)]
+pub struct PubSub {
+    // subscribers of each channel and of each pattern, by client id
+    channels: HashMap<Bytes, HashSet<u64>>,
+    patterns: HashMap<Bytes, HashSet<u64>>,
+    clients: HashMap<u64, Subscriber>,
+}
+
+struct Subscriber {
+    pushes: PushSender,
+    channels: BTreeSet<Bytes>,
+    patterns: BTreeSet<Bytes>,
+}
+
+impl Subscriber {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+/// Channels or patterns: the two kinds of subscriptions, handled alike
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubKind {
+    Channel,
+    Pattern,
+}
+
+impl SubKind {
+    fn subscribe_word(&self) -> &'static str {
+        match self {
+            SubKind::Channel => "subscribe",
+            SubKind::Pattern => "psubscribe",
+        }
+    }
+
+    fn unsubscribe_word(&self) -> &'static str {
+        match self {
+            SubKind::Channel => "unsubscribe",
+            SubKind::Pattern => "punsubscribe",
+        }
+    }
+}
+
+impl PubSub {
+    /// Number of channels and patterns `client` is subscribed to.
+    /// While it is not 0, the client can only run a few commands.
+    pub fn subscription_count(&self, client: u64) -> usize {
+        self.clients.get(&client).map_or(0, Subscriber::count)
+    }
+
+    /// Subscribe `client` to each of `names`, replying to each with the client's total
+    /// count of subscriptions. Messages will be pushed through `pushes`.
+    pub fn subscribe(
+        &mut self,
+        client: u64,
+        pushes: &PushSender,
+        kind: SubKind,
+        names: &[Bytes],
+    ) -> Vec<Value> {
+        let sub = self.clients.entry(client).or_insert_with(|| Subscriber {
+            pushes: pushes.clone(),
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        });
+        let (subscribed, others, registry) = match kind {
+            SubKind::Channel => (&mut sub.channels, &sub.patterns, &mut self.channels),
+            SubKind::Pattern => (&mut sub.patterns, &sub.channels, &mut self.patterns),
+        };
+
+        let mut replies = Vec::with_capacity(names.len());
+        for name in names {
+            if subscribed.insert(name.clone()) {
+                registry.entry(name.clone()).or_default().insert(client);
+            }
+            let count = subscribed.len() + others.len();
+            replies.push(sub_reply(kind.subscribe_word(), Some(name), count));
+        }
+        replies
+    }
+
+    /// Unsubscribe `client` from each of `names`, or from everything of `kind` if `names`
+    /// is empty, replying to each with the subscriptions the client has left.
+    pub fn unsubscribe(&mut self, client: u64, kind: SubKind, names: &[Bytes]) -> Vec<Value> {
+        let word = kind.unsubscribe_word();
+        let Some(sub) = self.clients.get_mut(&client) else {
+            return match names {
+                [] => vec![sub_reply(word, None, 0)],
+                _ => names
+                    .iter()
+                    .map(|name| sub_reply(word, Some(name), 0))
+                    .collect(),
+            };
+        };
+        let (subscribed, others, registry) = match kind {
+            SubKind::Channel => (&mut sub.channels, &sub.patterns, &mut self.channels),
+            SubKind::Pattern => (&mut sub.patterns, &sub.channels, &mut self.patterns),
+        };
+
+        let names = match names {
+            [] => subscribed.iter().cloned().collect(),
+            _ => names.to_vec(),
+        };
+        let mut replies = Vec::with_capacity(names.len().max(1));
+        for name in &names {
+            if subscribed.remove(name) {
+                remove_subscriber(registry, name, client);
+            }
+            let count = subscribed.len() + others.len();
+            replies.push(sub_reply(word, Some(name), count));
+        }
+        if replies.is_empty() {
+            replies.push(sub_reply(word, None, sub.count()));
+        }
+        if sub.count() == 0 {
+            self.clients.remove(&client);
+        }
+        replies
+    }
+
+    /// Drop all subscriptions of a client that went away
+    pub fn remove_client(&mut self, client: u64) {
+        let Some(sub) = self.clients.remove(&client) else {
+            return;
+        };
+        for channel in &sub.channels {
+            remove_subscriber(&mut self.channels, channel, client);
+        }
+        for pattern in &sub.patterns {
+            remove_subscriber(&mut self.patterns, pattern, client);
+        }
+    }
+
+    /// Push `message` to the subscribers of `channel`, and to those of every pattern matching it.
+    /// Returns the number of clients that got it, counting a client once per subscription.
+    pub fn publish(&mut self, channel: &Bytes, message: &Bytes) -> usize {
+        let mut deliveries: Vec<(u64, Value)> = Vec::new();
+        if let Some(clients) = self.channels.get(channel) {
+            for client in clients {
+                let msg = vec!["message".into(), channel.into(), message.into()];
+                deliveries.push((*client, Value::Push(msg)));
+            }
+        }
+        for (pattern, clients) in &self.patterns {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            for client in clients {
+                let msg = vec![
+                    "pmessage".into(),
+                    pattern.into(),
+                    channel.into(),
+                    message.into(),
+                ];
+                deliveries.push((*client, Value::Push(msg)));
+            }
+        }
+
+        let mut n_received = 0;
+        let mut gone = Vec::new();
+        for (client, msg) in deliveries {
+            let Some(sub) = self.clients.get(&client) else {
+                continue;
+            };
+            if sub.pushes.capacity() > 1 {
+                match sub.pushes.try_send(Pushed::Message(msg)) {
+                    Ok(()) => n_received += 1,
+                    Err(_) => gone.push(client),
+                }
+            } else {
+                // the connection closes once it gets to this
+                let _ = sub.pushes.try_send(Pushed::Overflowed);
+                gone.push(client);
+            }
+        }
+        // normally clients are removed when their connection ends,
+        // this is for those that went away or can't keep up
+        for client in gone {
+            self.remove_client(client);
+        }
+        n_received
+    }
+
+    /// Channels with at least one subscriber, optionally only those matching `pattern`
+    pub fn active_channels(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        let mut channels: Vec<Bytes> = self
+            .channels
+            .keys()
+            .filter(|ch| pattern.is_none_or(|p| glob_match(p.as_bytes(), ch.as_bytes())))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    pub fn num_subscribers(&self, channel: &Bytes) -> usize {
+        self.channels.get(channel).map_or(0, HashSet::len)
+    }
+
+    /// Number of distinct patterns subscribed to, by any client
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+fn remove_subscriber(registry: &mut HashMap<Bytes, HashSet<u64>>, name: &Bytes, client: u64) {
+    if let Some(clients) = registry.get_mut(name) {
+        clients.remove(&client);
+        if clients.is_empty() {
+            registry.remove(name);
+        }
+    }
+}
+
+/// Confirmation of a (un)subscription, as `[kind, channel or pattern, subscriptions left]`
+fn sub_reply(word: &str, name: Option<&Bytes>, count: usize) -> Value {
+    let name = name.map_or(Value::NullBulkString, Value::from);
+    Value::Push(vec![word.into(), name, Value::Int(count as i64)])
+}
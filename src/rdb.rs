@@ -0,0 +1,811 @@
+// Reading and writing of RDB snapshot files.
+// Format reference: https://rdb.fnordig.de/file_format.html
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{format_err, Result};
+
+use crate::common::Bytes;
+use crate::db_val::DbVal;
+use crate::listpack::{parse_listpack, ListpackWriter, LpElem};
+use crate::stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamFields, StreamId};
+use crate::zset::SortedSet;
+
+const MAGIC: &[u8] = b"REDIS";
+const VERSION: u32 = 11;
+const REDIS_VER: &str = "7.2.0";
+
+// Op-codes
+const OP_MODULE_AUX: u8 = 0xF7;
+const OP_FUNCTION: u8 = 0xF5;
+const OP_AUX: u8 = 0xFA;
+const OP_RESIZEDB: u8 = 0xFB;
+const OP_EXPIRETIME_MS: u8 = 0xFC;
+const OP_EXPIRETIME: u8 = 0xFD;
+const OP_SELECTDB: u8 = 0xFE;
+const OP_EOF: u8 = 0xFF;
+
+// Value types. Only the plain encodings, not the compact ones (ziplist, listpack, intset...),
+// except for streams which only come as listpacks
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3; // scores as strings
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5; // scores as binary doubles
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19; // + first id, max deleted id and entries added
+const TYPE_STREAM_LISTPACKS_3: u8 = 21; // + consumers' active time
+
+// Stream entries are stored in listpacks of up to this many entries, each entry relative to
+// the first one of its listpack, the "master entry"
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2; // same fields as the master entry, not repeated
+
+// Special string encodings (length byte starting with 0b11)
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RdbEntry {
+    pub key: Bytes,
+    pub val: DbVal,
+    pub expiry: Option<u64>, // absolute expiry time in millis since epoch
+}
+
+#[derive(Debug, Default)]
+pub struct RdbContents {
+    pub version: u32,
+    pub aux: Vec<(Bytes, Bytes)>,
+    pub entries: Vec<RdbEntry>,
+}
+
+pub fn read_rdb_file(path: &Path) -> Result<RdbContents> {
+    let data = std::fs::read(path)
+        .map_err(|e| format_err!("Could not read rdb file `{p}`: {e}", p = path.display()))?;
+    parse_rdb(&data)
+}
+
+pub fn parse_rdb(data: &[u8]) -> Result<RdbContents> {
+    let mut rdr = RdbReader { data, pos: 0 };
+    let mut output = RdbContents::default();
+
+    let magic = rdr.take(MAGIC.len())?;
+    if magic != MAGIC {
+        return Err(format_err!(
+            "Not an rdb file, magic={m:?}",
+            m = Bytes::from(magic)
+        ));
+    }
+    let version_str = String::from_utf8(rdr.take(4)?.to_vec())?;
+    output.version = version_str
+        .parse::<u32>()
+        .map_err(|e| format_err!("Invalid rdb version `{version_str}`: {e}"))?;
+
+    // expiry read from an op-code applies to the key/value pair that follows it
+    let mut pending_expiry: Option<u64> = None;
+    loop {
+        let op = rdr.u8()?;
+        match op {
+            OP_EOF => break,
+            OP_AUX => {
+                let key = rdr.string()?;
+                let val = rdr.string()?;
+                output.aux.push((key, val));
+            }
+            OP_SELECTDB => {
+                let db_num = rdr.length()?;
+                if db_num != 0 {
+                    println!("parse_rdb: loading keys of db={db_num} into the only db we have");
+                }
+            }
+            OP_RESIZEDB => {
+                let hash_size = rdr.length()?;
+                let _expires_size = rdr.length()?;
+                // only a hint, don't trust it blindly
+                output.entries.reserve((hash_size as usize).min(1 << 16));
+            }
+            OP_EXPIRETIME_MS => {
+                pending_expiry = Some(u64::from_le_bytes(rdr.array::<8>()?));
+            }
+            OP_EXPIRETIME => {
+                let secs = u32::from_le_bytes(rdr.array::<4>()?);
+                pending_expiry = Some(secs as u64 * 1000);
+            }
+            OP_MODULE_AUX | OP_FUNCTION => {
+                return Err(format_err!("rdb op-code 0x{op:02x} is not supported"));
+            }
+            TYPE_STRING
+            | TYPE_LIST
+            | TYPE_SET
+            | TYPE_ZSET
+            | TYPE_HASH
+            | TYPE_ZSET_2
+            | TYPE_STREAM_LISTPACKS
+            | TYPE_STREAM_LISTPACKS_2
+            | TYPE_STREAM_LISTPACKS_3 => {
+                let key = rdr.string()?;
+                let val = rdr.value(op)?;
+                output.entries.push(RdbEntry {
+                    key,
+                    val,
+                    expiry: pending_expiry.take(),
+                });
+            }
+            _ => {
+                return Err(format_err!(
+                    "rdb value type {op} (at byte {pos}) is not supported",
+                    pos = rdr.pos - 1
+                ))
+            }
+        }
+    }
+
+    // 8 byte checksum of everything before it follows EOF. Zero means checksumming was disabled.
+    let checked_len = rdr.pos;
+    if output.version >= 5 {
+        let checksum = u64::from_le_bytes(rdr.array::<8>()?);
+        let expected = crc64(&data[..checked_len]);
+        if checksum != 0 && checksum != expected {
+            return Err(format_err!(
+                "Bad rdb checksum: found {checksum:016x}, expected {expected:016x}"
+            ));
+        }
+    }
+    Ok(output)
+}
+
+/// Serialize entries into a complete rdb file (header, aux fields, db 0 and checksum).
+pub fn serialize_rdb(entries: &[RdbEntry], ctime_secs: u64) -> Vec<u8> {
+    let mut wtr = RdbWriter::default();
+
+    wtr.buf.extend_from_slice(MAGIC);
+    wtr.buf
+        .extend_from_slice(format!("{VERSION:04}").as_bytes());
+
+    wtr.aux("redis-ver", REDIS_VER);
+    wtr.aux("redis-bits", "64");
+    wtr.aux("ctime", &ctime_secs.to_string());
+    wtr.aux("aof-base", "0");
+
+    if !entries.is_empty() {
+        let n_expires = entries.iter().filter(|e| e.expiry.is_some()).count();
+        wtr.buf.push(OP_SELECTDB);
+        wtr.length(0);
+        wtr.buf.push(OP_RESIZEDB);
+        wtr.length(entries.len() as u64);
+        wtr.length(n_expires as u64);
+
+        for entry in entries {
+            if let Some(ex) = entry.expiry {
+                wtr.buf.push(OP_EXPIRETIME_MS);
+                wtr.buf.extend_from_slice(&ex.to_le_bytes());
+            }
+            wtr.value(&entry.key, &entry.val);
+        }
+    }
+
+    wtr.buf.push(OP_EOF);
+    let checksum = crc64(&wtr.buf);
+    wtr.buf.extend_from_slice(&checksum.to_le_bytes());
+    wtr.buf
+}
+
+/// Write an rdb file to a temporary file next to `path` and then rename it into place,
+/// so that a crash mid-write never leaves a truncated snapshot behind.
+pub fn write_rdb_file(path: &Path, entries: &[RdbEntry], ctime_secs: u64) -> Result<()> {
+    let data = serialize_rdb(entries, ctime_secs);
+    let tmp_path = path.with_file_name(format!("temp-{pid}.rdb", pid = std::process::id()));
+
+    let mut file = std::fs::File::create(&tmp_path)
+        .map_err(|e| format_err!("Could not create `{p}`: {e}", p = tmp_path.display()))?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+struct RdbReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+#[derive(Default)]
+struct RdbWriter {
+    buf: Vec<u8>,
+}
+
+impl RdbWriter {
+    fn length(&mut self, len: u64) {
+        if len < 1 << 6 {
+            self.buf.push(len as u8);
+        } else if len < 1 << 14 {
+            self.buf.push(0x40 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else if len <= u32::MAX as u64 {
+            self.buf.push(0x80);
+            self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+        } else {
+            self.buf.push(0x81);
+            self.buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+
+    fn string(&mut self, data: &[u8]) {
+        // Strings holding small integers in canonical form are stored int-encoded, like redis does
+        if let Some(i) = as_canonical_i32(data) {
+            if let Ok(i) = i8::try_from(i) {
+                self.buf.extend_from_slice(&[0xC0 | ENC_INT8, i as u8]);
+            } else if let Ok(i) = i16::try_from(i) {
+                self.buf.push(0xC0 | ENC_INT16);
+                self.buf.extend_from_slice(&i.to_le_bytes());
+            } else {
+                self.buf.push(0xC0 | ENC_INT32);
+                self.buf.extend_from_slice(&i.to_le_bytes());
+            }
+            return;
+        }
+        self.length(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Type byte, key and value of one entry
+    fn value(&mut self, key: &Bytes, val: &DbVal) {
+        let type_byte = match val {
+            DbVal::Str(_) => TYPE_STRING,
+            DbVal::List(_) => TYPE_LIST,
+            DbVal::Set(_) => TYPE_SET,
+            DbVal::Hash(_) => TYPE_HASH,
+            DbVal::ZSet(_) => TYPE_ZSET_2,
+            DbVal::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+        };
+        self.buf.push(type_byte);
+        self.string(key.as_bytes());
+
+        match val {
+            DbVal::Str(bs) => self.string(bs.as_bytes()),
+            DbVal::List(list) => {
+                self.length(list.len() as u64);
+                list.iter().for_each(|elem| self.string(elem.as_bytes()));
+            }
+            DbVal::Set(set) => {
+                self.length(set.len() as u64);
+                set.iter().for_each(|member| self.string(member.as_bytes()));
+            }
+            DbVal::Hash(hash) => {
+                self.length(hash.len() as u64);
+                for (field, val) in hash {
+                    self.string(field.as_bytes());
+                    self.string(val.as_bytes());
+                }
+            }
+            DbVal::ZSet(zset) => {
+                self.length(zset.len() as u64);
+                for (member, score) in zset.iter() {
+                    self.string(member.as_bytes());
+                    self.buf.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            DbVal::Stream(stream) => self.stream(stream),
+        }
+    }
+
+    /// Listpacks keyed by the id of their master entry, then the stream's metadata
+    fn stream(&mut self, stream: &Stream) {
+        let entries: Vec<(&StreamId, &StreamFields)> = stream.iter().collect();
+        let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES);
+        self.length(nodes.len() as u64);
+        for node in nodes {
+            let (master_id, master_fields) = node[0];
+            let mut node_key = master_id.ms.to_be_bytes().to_vec();
+            node_key.extend_from_slice(&master_id.seq.to_be_bytes());
+            self.raw_string(&node_key);
+            self.raw_string(&stream_node_listpack(*master_id, master_fields, node));
+        }
+
+        self.length(stream.len() as u64);
+        self.stream_id(stream.last_id);
+        self.stream_id(stream.first_id().unwrap_or_default());
+        self.stream_id(stream.max_deleted_id);
+        self.length(stream.entries_added);
+
+        self.length(stream.groups.len() as u64);
+        for (name, group) in &stream.groups {
+            self.raw_string(name.as_bytes());
+            self.stream_id(group.last_id);
+            // -1 for unknown
+            self.length(group.entries_read.unwrap_or(u64::MAX));
+            self.length(group.pending.len() as u64);
+            for (id, entry) in &group.pending {
+                self.raw_stream_id(*id);
+                self.millis(entry.delivery_time as i64);
+                self.length(entry.delivery_count);
+            }
+            self.length(group.consumers.len() as u64);
+            for (name, consumer) in &group.consumers {
+                self.raw_string(name.as_bytes());
+                self.millis(consumer.seen_time as i64);
+                self.millis(consumer.active_time.map_or(-1, |time| time as i64));
+                // only ids, the rest is in the group's PEL
+                self.length(consumer.pending.len() as u64);
+                for id in &consumer.pending {
+                    self.raw_stream_id(*id);
+                }
+            }
+        }
+    }
+
+    fn stream_id(&mut self, id: StreamId) {
+        self.length(id.ms);
+        self.length(id.seq);
+    }
+
+    /// 128 bits, big endian
+    fn raw_stream_id(&mut self, id: StreamId) {
+        self.buf.extend_from_slice(&id.ms.to_be_bytes());
+        self.buf.extend_from_slice(&id.seq.to_be_bytes());
+    }
+
+    fn millis(&mut self, ms: i64) {
+        self.buf.extend_from_slice(&ms.to_le_bytes());
+    }
+
+    /// A string as is, never int-encoded
+    fn raw_string(&mut self, data: &[u8]) {
+        self.length(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn aux(&mut self, key: &str, val: &str) {
+        self.buf.push(OP_AUX);
+        self.string(key.as_bytes());
+        self.string(val.as_bytes());
+    }
+}
+
+/// One listpack of stream entries. Its master entry has the fields of the first entry,
+/// the entries having the same fields then only store their values.
+fn stream_node_listpack(
+    master_id: StreamId,
+    master_fields: &StreamFields,
+    node: &[(&StreamId, &StreamFields)],
+) -> Vec<u8> {
+    let mut lp = ListpackWriter::default();
+    lp.int(node.len() as i64);
+    lp.int(0); // deleted entries
+    lp.int(master_fields.len() as i64);
+    for (field, _) in master_fields {
+        lp.str(field.as_bytes());
+    }
+    lp.int(0); // end of the master entry
+
+    for (id, fields) in node {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields.iter())
+                .all(|((field, _), (master_field, _))| field == master_field);
+        lp.int(if same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        });
+        // differences wrap around like redis' int64 arithmetic, e.g. for a lower seq
+        lp.int(id.ms.wrapping_sub(master_id.ms) as i64);
+        lp.int(id.seq.wrapping_sub(master_id.seq) as i64);
+        if !same_fields {
+            lp.int(fields.len() as i64);
+        }
+        for (field, val) in fields.iter() {
+            if !same_fields {
+                lp.str(field.as_bytes());
+            }
+            lp.str(val.as_bytes());
+        }
+        // number of elements of this entry before this one, to walk it backwards
+        let n_elems = if same_fields {
+            fields.len() + 3
+        } else {
+            2 * fields.len() + 4
+        };
+        lp.int(n_elems as i64);
+    }
+    lp.finish()
+}
+
+/// Add the entries of one listpack written by stream_node_listpack, or by redis
+fn read_stream_node(stream: &mut Stream, master_id: StreamId, elems: Vec<LpElem>) -> Result<()> {
+    let mut elems = elems.into_iter();
+
+    let n_entries = lp_count(&mut elems)? + lp_count(&mut elems)?; // valid + deleted
+    let n_master_fields = lp_count(&mut elems)?;
+    let master_fields = (0..n_master_fields)
+        .map(|_| lp_bytes(&mut elems))
+        .collect::<Result<Vec<_>>>()?;
+    lp_int(&mut elems)?; // end of the master entry
+
+    for _ in 0..n_entries {
+        let flags = lp_int(&mut elems)?;
+        let id = StreamId::new(
+            master_id.ms.wrapping_add(lp_int(&mut elems)? as u64),
+            master_id.seq.wrapping_add(lp_int(&mut elems)? as u64),
+        );
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), lp_bytes(&mut elems)?)))
+                .collect::<Result<StreamFields>>()?
+        } else {
+            let n_fields = lp_count(&mut elems)?;
+            (0..n_fields)
+                .map(|_| Ok((lp_bytes(&mut elems)?, lp_bytes(&mut elems)?)))
+                .collect::<Result<StreamFields>>()?
+        };
+        lp_int(&mut elems)?; // lp-count
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            stream.insert(id, fields);
+        }
+    }
+    Ok(())
+}
+
+fn lp_bytes(elems: &mut impl Iterator<Item = LpElem>) -> Result<Bytes> {
+    let elem = elems
+        .next()
+        .ok_or_else(|| format_err!("Truncated stream listpack"))?;
+    Ok(elem.into_bytes())
+}
+
+fn lp_int(elems: &mut impl Iterator<Item = LpElem>) -> Result<i64> {
+    let elem = elems
+        .next()
+        .ok_or_else(|| format_err!("Truncated stream listpack"))?;
+    elem.as_int()
+        .ok_or_else(|| format_err!("Expected an integer in stream listpack, got {elem:?}"))
+}
+
+fn lp_count(elems: &mut impl Iterator<Item = LpElem>) -> Result<usize> {
+    let i = lp_int(elems)?;
+    usize::try_from(i).map_err(|_| format_err!("Negative count {i} in stream listpack"))
+}
+
+fn as_canonical_i32(data: &[u8]) -> Option<i32> {
+    if data.is_empty() || data.len() > 11 {
+        return None;
+    }
+    let i = std::str::from_utf8(data).ok()?.parse::<i32>().ok()?;
+    // "007" or "+7" must round trip byte for byte, so they stay strings
+    if i.to_string().as_bytes() == data {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+/// CRC-64 with the Jones polynomial, as used by redis for rdb checksums
+/// (reflected input and output, zero init, no final xor).
+pub fn crc64(data: &[u8]) -> u64 {
+    const POLY_REFLECTED: u64 = 0x95AC_9329_AC4B_C9B5;
+
+    let mut table = [0u64; 256];
+    for (i, slot) in table.iter_mut().enumerate() {
+        let mut crc = i as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY_REFLECTED
+            } else {
+                crc >> 1
+            };
+        }
+        *slot = crc;
+    }
+
+    data.iter().fold(0u64, |crc, byte| {
+        table[((crc ^ *byte as u64) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Either a plain length or a marker for one of the special string encodings.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+impl<'a> RdbReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() - self.pos {
+            return Err(format_err!(
+                "Unexpected end of rdb data: wanted {n} bytes at {pos}, have {len}",
+                pos = self.pos,
+                len = self.data.len()
+            ));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut output = [0u8; N];
+        output.copy_from_slice(self.take(N)?);
+        Ok(output)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn length_or_encoding(&mut self) -> Result<Length> {
+        let first = self.u8()?;
+        match first >> 6 {
+            0b00 => Ok(Length::Len((first & 0x3F) as u64)),
+            0b01 => {
+                let second = self.u8()?;
+                Ok(Length::Len((((first & 0x3F) as u64) << 8) | second as u64))
+            }
+            0b10 => match first {
+                0x80 => Ok(Length::Len(u32::from_be_bytes(self.array::<4>()?) as u64)),
+                0x81 => Ok(Length::Len(u64::from_be_bytes(self.array::<8>()?))),
+                _ => Err(format_err!("Invalid length byte: 0x{first:02x}")),
+            },
+            _ => Ok(Length::Encoded(first & 0x3F)),
+        }
+    }
+
+    fn length(&mut self) -> Result<u64> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(enc) => Err(format_err!(
+                "Expected a length but found string encoding {enc}"
+            )),
+        }
+    }
+
+    fn string(&mut self) -> Result<Bytes> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => Ok(self.take(len as usize)?.into()),
+            Length::Encoded(ENC_INT8) => {
+                let i = self.u8()? as i8;
+                Ok(i.to_string().as_str().into())
+            }
+            Length::Encoded(ENC_INT16) => {
+                let i = i16::from_le_bytes(self.array::<2>()?);
+                Ok(i.to_string().as_str().into())
+            }
+            Length::Encoded(ENC_INT32) => {
+                let i = i32::from_le_bytes(self.array::<4>()?);
+                Ok(i.to_string().as_str().into())
+            }
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.length()? as usize;
+                let uncompressed_len = self.length()? as usize;
+                let compressed = self.take(compressed_len)?;
+                Ok(lzf_decompress(compressed, uncompressed_len)?.into())
+            }
+            Length::Encoded(enc) => Err(format_err!("Unknown string encoding: {enc}")),
+        }
+    }
+
+    /// Element count of a collection. Capacity reserved from it is capped, it could be garbage.
+    fn collection_len(&mut self) -> Result<(usize, usize)> {
+        let len = self.length()? as usize;
+        Ok((len, len.min(1 << 16)))
+    }
+
+    fn value(&mut self, type_byte: u8) -> Result<DbVal> {
+        match type_byte {
+            TYPE_STRING => Ok(DbVal::Str(self.string()?)),
+            TYPE_LIST => {
+                let (len, capacity) = self.collection_len()?;
+                let mut list = VecDeque::with_capacity(capacity);
+                for _ in 0..len {
+                    list.push_back(self.string()?);
+                }
+                Ok(DbVal::List(list))
+            }
+            TYPE_SET => {
+                let (len, capacity) = self.collection_len()?;
+                let mut set = HashSet::with_capacity(capacity);
+                for _ in 0..len {
+                    set.insert(self.string()?);
+                }
+                Ok(DbVal::Set(set))
+            }
+            TYPE_HASH => {
+                let (len, capacity) = self.collection_len()?;
+                let mut hash = HashMap::with_capacity(capacity);
+                for _ in 0..len {
+                    let field = self.string()?;
+                    hash.insert(field, self.string()?);
+                }
+                Ok(DbVal::Hash(hash))
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let (len, _) = self.collection_len()?;
+                let mut zset = SortedSet::default();
+                for _ in 0..len {
+                    let member = self.string()?;
+                    let score = if type_byte == TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.array::<8>()?)
+                    } else {
+                        self.string_score()?
+                    };
+                    if score.is_nan() {
+                        return Err(format_err!("NaN score in sorted set"));
+                    }
+                    zset.insert(member, score);
+                }
+                Ok(DbVal::ZSet(zset))
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Ok(DbVal::Stream(self.stream(type_byte)?))
+            }
+            _ => Err(format_err!("rdb value type {type_byte} is not supported")),
+        }
+    }
+
+    fn stream(&mut self, type_byte: u8) -> Result<Stream> {
+        let mut stream = Stream::default();
+        let n_nodes = self.length()?;
+        for _ in 0..n_nodes {
+            let node_key = self.string()?;
+            let node_key: [u8; 16] = node_key
+                .as_bytes()
+                .try_into()
+                .map_err(|_| format_err!("Stream node key is not a 128 bit id"))?;
+            let master_id = StreamId::new(
+                u64::from_be_bytes(node_key[..8].try_into().unwrap()),
+                u64::from_be_bytes(node_key[8..].try_into().unwrap()),
+            );
+            let elems = parse_listpack(self.string()?.as_bytes())?;
+            read_stream_node(&mut stream, master_id, elems)?;
+        }
+
+        let len = self.length()?;
+        if len != stream.len() as u64 {
+            return Err(format_err!(
+                "Stream length {len} does not match its {n} entries",
+                n = stream.len()
+            ));
+        }
+        stream.last_id = self.stream_id()?;
+        if type_byte >= TYPE_STREAM_LISTPACKS_2 {
+            let _first_id = self.stream_id()?;
+            stream.max_deleted_id = self.stream_id()?;
+            stream.entries_added = self.length()?;
+        } else {
+            stream.entries_added = len;
+        }
+
+        let n_groups = self.length()?;
+        for _ in 0..n_groups {
+            let name = self.string()?;
+            let mut group = ConsumerGroup::new(self.stream_id()?, None);
+            if type_byte >= TYPE_STREAM_LISTPACKS_2 {
+                // -1 for unknown
+                group.entries_read = Some(self.length()?).filter(|n| *n != u64::MAX);
+            }
+            let n_pending = self.length()?;
+            let mut delivery_info = BTreeMap::new();
+            for _ in 0..n_pending {
+                let id = self.raw_stream_id()?;
+                let delivery_time = self.millis()?.max(0) as u64;
+                delivery_info.insert(id, (delivery_time, self.length()?));
+            }
+            // the PEL is complete once consumers say which entries are theirs
+            let n_consumers = self.length()?;
+            for _ in 0..n_consumers {
+                let consumer_name = self.string()?;
+                let mut consumer = Consumer {
+                    seen_time: self.millis()?.max(0) as u64,
+                    ..Default::default()
+                };
+                consumer.active_time = if type_byte >= TYPE_STREAM_LISTPACKS_3 {
+                    u64::try_from(self.millis()?).ok()
+                } else {
+                    Some(consumer.seen_time)
+                };
+                let n_pending = self.length()?;
+                for _ in 0..n_pending {
+                    let id = self.raw_stream_id()?;
+                    let (delivery_time, delivery_count) =
+                        delivery_info.remove(&id).ok_or_else(|| {
+                            format_err!("Consumer has entry {id} which is not in its group's PEL")
+                        })?;
+                    let entry = PendingEntry {
+                        consumer: consumer_name.clone(),
+                        delivery_time,
+                        delivery_count,
+                    };
+                    group.pending.insert(id, entry);
+                    consumer.pending.insert(id);
+                }
+                group.consumers.insert(consumer_name, consumer);
+            }
+            if let Some(id) = delivery_info.keys().next() {
+                return Err(format_err!("Entry {id} of the PEL has no consumer"));
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+
+    fn stream_id(&mut self) -> Result<StreamId> {
+        Ok(StreamId::new(self.length()?, self.length()?))
+    }
+
+    fn raw_stream_id(&mut self) -> Result<StreamId> {
+        let ms = u64::from_be_bytes(self.array::<8>()?);
+        Ok(StreamId::new(ms, u64::from_be_bytes(self.array::<8>()?)))
+    }
+
+    fn millis(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.array::<8>()?))
+    }
+
+    /// Score of the old zset encoding: a length byte then the number as text,
+    /// with special lengths for nan and infinities
+    fn string_score(&mut self) -> Result<f64> {
+        match self.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let text = std::str::from_utf8(self.take(len as usize)?)?;
+                text.parse::<f64>()
+                    .map_err(|e| format_err!("Invalid zset score `{text}`: {e}"))
+            }
+        }
+    }
+}
+
+// Most bytes lzf can make of one byte of input: a 3 byte back reference copies 264 bytes
+const LZF_MAX_EXPANSION: usize = 88;
+
+fn lzf_decompress(input: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    // the length comes from the file, only trusted as far as the input can fill it
+    let capacity = expected_len.min(input.len().saturating_mul(LZF_MAX_EXPANSION));
+    let mut output: Vec<u8> = Vec::with_capacity(capacity);
+    let mut i = 0usize;
+    let bad_input = || format_err!("Corrupt lzf compressed string");
+
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // literal run of ctrl + 1 bytes
+            let run = input.get(i..i + ctrl + 1).ok_or_else(bad_input)?;
+            output.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // back reference
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(i).ok_or_else(bad_input)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1F) << 8) + *input.get(i).ok_or_else(bad_input)? as usize + 1;
+            i += 1;
+            if offset > output.len() {
+                return Err(bad_input());
+            }
+            let start = output.len() - offset;
+            // byte by byte as source and destination ranges may overlap
+            for j in 0..len + 2 {
+                output.push(output[start + j]);
+            }
+        }
+    }
+
+    if output.len() != expected_len {
+        return Err(format_err!(
+            "lzf decompressed length {n} does not match expected {expected_len}",
+            n = output.len()
+        ));
+    }
+    Ok(output)
+}
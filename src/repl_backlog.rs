@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+
+/// Bounded buffer with the most recent bytes of the replication stream, used to serve
+/// partial resynchronizations (`PSYNC replid offset`) to replicas that reconnect.
+#[derive(Debug)]
+pub struct ReplBacklog {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // replication offset of buf[0]
+    start_offset: u64,
+}
+
+impl ReplBacklog {
+    pub fn new(capacity: usize, start_offset: u64) -> Self {
+        ReplBacklog {
+            buf: VecDeque::with_capacity(capacity.min(1 << 20)),
+            capacity,
+            start_offset,
+        }
+    }
+
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.buf.extend(bytes);
+        let excess = self.buf.len().saturating_sub(self.capacity);
+        if excess > 0 {
+            self.buf.drain(..excess);
+            self.start_offset += excess as u64;
+        }
+    }
+
+    pub fn start_offset(&self) -> u64 {
+        self.start_offset
+    }
+
+    /// Replication offset right after the last byte in the backlog
+    pub fn end_offset(&self) -> u64 {
+        self.start_offset + self.buf.len() as u64
+    }
+
+    /// All bytes from `offset` up to the end of the backlog, or None if some of them
+    /// are no longer (or not yet) in the backlog.
+    pub fn bytes_from(&self, offset: u64) -> Option<Vec<u8>> {
+        if offset < self.start_offset || offset > self.end_offset() {
+            return None;
+        }
+        let skip = (offset - self.start_offset) as usize;
+        Some(self.buf.range(skip..).copied().collect())
+    }
+}
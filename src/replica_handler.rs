@@ -1,89 +1,89 @@
 use std::io;
 
 use anyhow::Result;
-use tokio::io::{AsyncWriteExt, BufStream};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{Sender, UnboundedReceiver};
 
-use crate::async_deser;
-use crate::misc_util::peer_addr_str_v2;
-use crate::resp::QueryResult;
+use crate::async_deser::RespDeserializer;
 use crate::resp::Value;
-use crate::svc::{do_reply, process_input_async, ToDb, ToReplica};
+use crate::resp::{Protocol, QueryResult};
+use crate::svc::{do_reply, process_input_async, Connection, ToDb, ToReplica};
 
-pub async fn handle_replica(
-    mut bstream: BufStream<TcpStream>,
-    mut repl_recv: Receiver<ToReplica>,
+pub async fn handle_replica<S: Connection>(
+    mut conn: RespDeserializer<S>,
+    addr: String,
+    mut repl_recv: UnboundedReceiver<ToReplica>,
     tx: Sender<ToDb>,
 ) {
-    let addr = peer_addr_str_v2(&bstream);
     println!("\n\nStarting handle_replica from: {addr}\n");
 
-    let mut eof_cnt = 0usize;
-
     loop {
-        let value_from_replica = async_deser::deserialize(&mut bstream);
-        let msg_to_replica = repl_recv.recv();
-
+        // a partial value from the replica stays buffered in conn when a message wins
         tokio::select! {
-            deser_res = value_from_replica => {
-                eof_cnt = handle_incoming_val_from_replica(deser_res, &mut bstream, &tx, eof_cnt).await;
-                if eof_cnt > 10000 {
-                    // probably disconnected
-                    // break
+            deser_res = conn.deserialize() => {
+                let connected = handle_incoming_val_from_replica(deser_res, conn.get_mut(), &addr, &tx).await;
+                if !connected {
+                    break;
                 }
             }
-            opt_msg = msg_to_replica => {
+            opt_msg = repl_recv.recv() => {
                 match opt_msg {
                     Some(ToReplica::Bytes(bytes, action)) => {
-                        send_bytes_to_replica(&mut bstream, &bytes, &action).await;
+                        send_bytes_to_replica(conn.get_mut(), &addr, &bytes, &action).await;
                     }
                     None => {
-
+                        // Db dropped this replica
+                        break;
                     }
                 }
             }
         };
     } // loop
-    // println!("\n\nEND of handle_replica -- from: {addr}\n\n");
+    println!("\n\nEND of handle_replica -- from: {addr}\n\n");
+    tx.send(ToDb::ReplicaDisconnected(addr))
+        .await
+        .unwrap_or_else(|e| println!("handle_replica: could not notify Db, e:{e:?}"));
 }
 
-async fn handle_incoming_val_from_replica(
+async fn handle_incoming_val_from_replica<W: AsyncWrite + Unpin>(
     deser_res: Result<(Value, usize)>,
-    bstream: &mut BufStream<TcpStream>,
+    bstream: &mut W,
+    addr: &str,
     tx: &Sender<ToDb>,
-    eof_cnt: usize,
-) -> usize {
-    let addr = peer_addr_str_v2(&bstream);
+) -> bool {
     match deser_res {
         Ok((input_value, deser_byte_cnt)) => {
             println!("handle_replica: processing_input from:{addr}, value: {input_value:?}");
 
             let query_result: QueryResult =
-                process_input_async(input_value, deser_byte_cnt, &addr, &tx).await;
+                process_input_async(input_value, deser_byte_cnt, addr, tx).await;
 
             // if should_reply(is_replication, &query_result) {
-            do_reply(bstream, &query_result).await;
+            do_reply(bstream, &query_result, Protocol::Resp2).await;
             // }
         }
         Err(err) => {
             if let Some(io_err) = err.downcast_ref::<io::Error>() {
-                if io_err.kind() == io::ErrorKind::UnexpectedEof {
-                    return eof_cnt + 1;
-                }
+                println!("handle_replica: io error from {addr}: {io_err:?}");
             } else {
                 println!("EERRRORR: Failed to deserialize value. err:{err:?}");
             }
+            return false;
         }
     } // match deser_res
-    eof_cnt
+    true
 }
 
-async fn send_bytes_to_replica(bstream: &mut BufStream<TcpStream>, bytes: &[u8], action: &str) {
-    bstream.write_all(bytes).await.unwrap_or_else(|e| {
-        let addr = peer_addr_str_v2(bstream);
-        println!("ERROR when {action} to {addr}, err={e:?}")
-    });
+async fn send_bytes_to_replica<W: AsyncWrite + Unpin>(
+    bstream: &mut W,
+    addr: &str,
+    bytes: &[u8],
+    action: &str,
+) {
+    bstream
+        .write_all(bytes)
+        .await
+        .unwrap_or_else(|e| println!("ERROR when {action} to {addr}, err={e:?}"));
 
     bstream
         .flush()
@@ -1,12 +1,15 @@
 use anyhow::{format_err, Result};
+use bytes::buf::Writer;
+use bytes::{BufMut, BytesMut};
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 
-use crate::common::Bytes;
+use crate::common::{parse_int, Bytes};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     NullBulkString,
+    NullArray,
     SimpleString(Bytes),
     Array(Vec<Value>),
     Int(i64),
@@ -14,9 +17,36 @@ pub enum Value {
     FileContents(Bytes),
     SimpleError(String),
     BulkError(String),
+    // RESP3 only. Clients that did not ask for RESP3 get the closest RESP2 type instead
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(Bytes),       // decimal digits, with an optional leading '-'
+    Verbatim(Bytes, Bytes), // format, e.g. "txt", and the text
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    Push(Vec<Value>), // out of band data, like pub/sub messages
+    Attribute(Vec<(Value, Value)>, Box<Value>), // metadata about the value that follows
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// The version of the protocol a connection speaks, 2 until the client asks for 3 with HELLO
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct QueryResult {
     pub vals: Vec<Value>,
     pub pass_stream: bool,
@@ -64,7 +94,8 @@ impl Value {
     pub fn try_to_int(&self) -> Result<i64> {
         match self {
             Int(i) => Ok(*i),
-            _ => self.try_to_string()?.parse::<i64>().map_err(|e| e.into()),
+            _ => parse_int(self.try_to_string()?.as_bytes())
+                .ok_or_else(|| format_err!("Value is not an integer, self={self:?}")),
         }
     }
 }
@@ -114,29 +145,36 @@ pub fn serialize(value: &Value) -> Result<Bytes> {
     let mut serializer = RespSerializer::default();
     serializer.serialize(value)?;
     // Ok(serializer.get().into())
-    Ok(serializer.writer.into_inner()?.into())
+    Ok(serializer.writer.into_inner().into())
 }
 
-pub fn serialize_many(values: &[Value]) -> Result<Bytes> {
-    let mut serializer = RespSerializer::default();
+/// Serialize replies for a client speaking `protocol`
+pub fn serialize_many(values: &[Value], protocol: Protocol) -> Result<Bytes> {
+    let mut serializer = RespSerializer::with_protocol(protocol);
 
     for value in values {
         serializer.serialize(value)?;
     }
 
     // Ok(serializer.get().into())
-    Ok(serializer.writer.into_inner()?.into())
+    Ok(serializer.writer.into_inner().into())
 }
 
 pub struct RespSerializer {
     // buf: Vec<u8>,
-    writer: BufWriter<Vec<u8>>,
+    writer: Writer<BytesMut>,
+    protocol: Protocol,
 }
 
 impl RespSerializer {
     pub fn new() -> Self {
+        Self::with_protocol(Protocol::Resp2)
+    }
+
+    pub fn with_protocol(protocol: Protocol) -> Self {
         Self {
-            writer: BufWriter::new(Vec::new()),
+            writer: BytesMut::new().writer(),
+            protocol,
         }
     }
 
@@ -145,11 +183,19 @@ impl RespSerializer {
 
         let mut cnt = 0usize; // accumulator count of bytes written
 
+        let resp3 = self.protocol == Protocol::Resp3;
         match &value {
-            NullBulkString => {
+            NullBulkString | NullArray | Null if resp3 => {
+                cnt += self.writeln(b"_")?;
+            }
+            NullBulkString | Null => {
                 cnt += self.write(b"$")?;
                 cnt += self.writeln("-1".as_bytes())?;
             }
+            NullArray => {
+                cnt += self.write(b"*")?;
+                cnt += self.writeln("-1".as_bytes())?;
+            }
             SimpleString(v) => {
                 cnt += self.write(b"+")?;
                 cnt += self.writeln(v.as_bytes())?;
@@ -176,14 +222,70 @@ impl RespSerializer {
                 cnt += self.write(b"-")?;
                 cnt += self.writeln(msg.replace('\r', "\\r").replace('\n', "\\n").as_bytes())?
             }
-            BulkError(msg) => {
+            BulkError(msg) if resp3 => {
                 cnt += self.write_len_line(b'!', msg.len())?;
                 cnt += self.writeln(msg.as_bytes())?;
             }
+            BulkError(msg) => cnt += self.serialize(&SimpleError(msg.clone()))?,
+            Boolean(b) if resp3 => {
+                cnt += self.writeln(if *b { b"#t" } else { b"#f" })?;
+            }
+            Boolean(b) => cnt += self.serialize(&Int(*b as i64))?,
+            Double(d) if resp3 => {
+                cnt += self.write(b",")?;
+                cnt += self.writeln(format_double(*d).as_bytes())?;
+            }
+            Double(d) => cnt += self.serialize(&BulkString(format_double(*d).as_str().into()))?,
+            BigNumber(digits) if resp3 => {
+                cnt += self.write(b"(")?;
+                cnt += self.writeln(digits.as_bytes())?;
+            }
+            BigNumber(digits) => cnt += self.serialize(&BulkString(digits.clone()))?,
+            Verbatim(format, text) if resp3 => {
+                cnt += self.write_len_line(b'=', format.len() + 1 + text.len())?;
+                cnt += self.write(format.as_bytes())?;
+                cnt += self.write(b":")?;
+                cnt += self.writeln(text.as_bytes())?;
+            }
+            Verbatim(_, text) => cnt += self.serialize(&BulkString(text.clone()))?,
+            // a RESP2 map is a flat array of keys and values
+            Map(pairs) => {
+                let (prefix, len) = if resp3 {
+                    (b'%', pairs.len())
+                } else {
+                    (b'*', 2 * pairs.len())
+                };
+                cnt += self.write_len_line(prefix, len)?;
+                cnt += self.serialize_pairs(pairs)?;
+            }
+            Set(elems) | Push(elems) => {
+                let prefix = match value {
+                    Set(_) if resp3 => b'~',
+                    Push(_) if resp3 => b'>',
+                    _ => b'*',
+                };
+                cnt += self.write_len_line(prefix, elems.len())?;
+                cnt += elems.iter().try_fold(0usize, |acc, elem| {
+                    self.serialize(elem).map(|cnt| acc + cnt)
+                })?;
+            }
+            Attribute(attrs, val) if resp3 => {
+                cnt += self.write_len_line(b'|', attrs.len())?;
+                cnt += self.serialize_pairs(attrs)?;
+                cnt += self.serialize(val)?;
+            }
+            // RESP2 has no way to send attributes, they are just left out
+            Attribute(_, val) => cnt += self.serialize(val)?,
         };
         Ok(cnt)
     }
 
+    fn serialize_pairs(&mut self, pairs: &[(Value, Value)]) -> Result<usize> {
+        pairs.iter().try_fold(0usize, |acc, (key, val)| {
+            Ok(acc + self.serialize(key)? + self.serialize(val)?)
+        })
+    }
+
     pub fn writeln(&mut self, data: &[u8]) -> io::Result<usize> {
         Ok(self.writer.write(data)? + self.writer.write(b"\r\n")?)
     }
@@ -204,6 +306,126 @@ impl Default for RespSerializer {
     }
 }
 
+/// A double the way redis prints scores and replies (d2string): integers as such, anything
+/// else with the shortest digits that read back the same, in scientific notation when very big
+/// or small, e.g. `1e+300` or `1.5e-7`. Infinities are `inf` and `-inf`, NaN is `nan`.
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".to_string();
+    }
+    if d.is_infinite() {
+        return if d > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // like redis' double2ll, that leaves the largest ones to the general case
+    const LL_MAX_HALF: f64 = (i64::MAX / 2) as f64;
+    if d.fract() == 0.0 && (-LL_MAX_HALF..=LL_MAX_HALF).contains(&d) {
+        let sign = if d.is_sign_negative() { "-" } else { "" };
+        return format!("{sign}{}", d.abs() as i64);
+    }
+
+    // the rest like fpconv_dtoa, from the shortest digits: `digits * 10^k`
+    let sci = format!("{:e}", d.abs());
+    let (mantissa, exp) = sci.split_once('e').expect("{:e} always has an exponent");
+    let digits = mantissa.replace('.', "");
+    let n_digits = digits.len() as i64;
+    let k = exp.parse::<i64>().expect("{:e} exponents are integers") - (n_digits - 1);
+    let exp = (k + n_digits - 1).abs();
+    let sign = if d < 0.0 { "-" } else { "" };
+    if k >= 0 && exp < n_digits + 7 {
+        format!("{sign}{digits}{}", "0".repeat(k as usize))
+    } else if k < 0 && (k > -7 || exp < 4) {
+        let int_len = n_digits + k;
+        if int_len <= 0 {
+            format!(
+                "{sign}0.{}{digits}",
+                "0".repeat(int_len.unsigned_abs() as usize)
+            )
+        } else {
+            let (int_part, frac_part) = digits.split_at(int_len as usize);
+            format!("{sign}{int_part}.{frac_part}")
+        }
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exp_sign = if k + n_digits - 1 < 0 { '-' } else { '+' };
+        format!("{sign}{first}{point}{rest}e{exp_sign}{exp}")
+    }
+}
+
+/// Whether a message starting with `byte` is in RESP, rather than an inline command
+pub fn is_type_byte(byte: u8) -> bool {
+    b"+-:$*_#,(!=%~>|".contains(&byte)
+}
+
+/// Split an inline command, like `SET k "hello world"`, into its words as redis does.
+/// Words are separated by spaces and can be quoted: double quotes allow escapes like
+/// `\n` or `\x41`, single quotes only `\'`.
+pub fn split_inline(line: &[u8]) -> Result<Vec<Bytes>> {
+    let mut words = Vec::new();
+    let mut rest = line;
+    loop {
+        rest = rest.trim_ascii_start();
+        if rest.is_empty() {
+            return Ok(words);
+        }
+        let (word, after) = inline_word(rest)?;
+        words.push(word.into());
+        rest = after;
+    }
+}
+
+/// The word `s` starts with, and what follows it
+fn inline_word(mut s: &[u8]) -> Result<(Vec<u8>, &[u8])> {
+    let unbalanced = || format_err!("unbalanced quotes in request");
+    let mut word = Vec::new();
+    let mut quote = None;
+    loop {
+        match (quote, s) {
+            (None, []) => return Ok((word, s)),
+            (None, [c, rest @ ..]) if c.is_ascii_whitespace() => return Ok((word, rest)),
+            (None, [q @ (b'"' | b'\''), rest @ ..]) => {
+                quote = Some(*q);
+                s = rest;
+            }
+            (Some(_), []) => return Err(unbalanced()),
+            (Some(q), [c, rest @ ..]) if *c == q => {
+                // the closing quote must end the word
+                if rest.first().is_some_and(|c| !c.is_ascii_whitespace()) {
+                    return Err(unbalanced());
+                }
+                return Ok((word, rest));
+            }
+            (Some(b'"'), [b'\\', b'x', hi, lo, rest @ ..])
+                if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() =>
+            {
+                let hex = [*hi, *lo];
+                let hex = std::str::from_utf8(&hex).expect("hex digits are ascii");
+                word.push(u8::from_str_radix(hex, 16).expect("checked to be hex digits"));
+                s = rest;
+            }
+            (Some(b'"'), [b'\\', c, rest @ ..]) => {
+                word.push(match c {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'b' => 0x08,
+                    b'a' => 0x07,
+                    c => *c,
+                });
+                s = rest;
+            }
+            (Some(b'\''), [b'\\', b'\'', rest @ ..]) => {
+                word.push(b'\'');
+                s = rest;
+            }
+            (_, [c, rest @ ..]) => {
+                word.push(*c);
+                s = rest;
+            }
+        }
+    }
+}
+
 pub fn parse_len(bytes: &[u8]) -> Result<usize> {
     String::from_utf8(Vec::from(bytes))?
         .trim_end()
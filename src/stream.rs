@@ -0,0 +1,316 @@
+// Stream: an append-only log of field-value entries, ordered by their `ms-seq` ids.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::common::Bytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// `ms-seq`, or just `ms` in which case the sequence number is `missing_seq`
+    pub fn parse(bs: &Bytes, missing_seq: u64) -> Option<Self> {
+        let s = std::str::from_utf8(bs.as_bytes()).ok()?;
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, Some(seq)),
+            None => (s, None),
+        };
+        Some(StreamId {
+            ms: parse_u64(ms)?,
+            seq: match seq {
+                Some(seq) => parse_u64(seq)?,
+                None => missing_seq,
+            },
+        })
+    }
+
+    /// The smallest id after this one, None for the last possible id
+    pub fn next(&self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The largest id before this one, None for 0-0
+    pub fn prev(&self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+/// Digits only: no sign, no spaces
+fn parse_u64(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{ms}-{seq}", ms = self.ms, seq = self.seq)
+    }
+}
+
+/// Field-value pairs of one entry, in the order they were given
+pub type StreamFields = Vec<(Bytes, Bytes)>;
+
+/// Which entries XTRIM, or XADD with trimming, keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    MaxLen(u64),     // the newest ones, at most this many
+    MinId(StreamId), // the ones with at least this id
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    // Kept when entries are trimmed, so ids are never reused
+    pub last_id: StreamId,        // the highest id ever added
+    pub entries_added: u64,       // all entries ever added
+    pub max_deleted_id: StreamId, // the highest id trimmed away
+    pub groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&StreamFields> {
+        self.entries.get(&id)
+    }
+
+    pub fn first_id(&self) -> Option<StreamId> {
+        self.entries.keys().next().copied()
+    }
+
+    /// Id of the newest entry still there, which may be below `last_id`
+    pub fn last_entry_id(&self) -> Option<StreamId> {
+        self.entries.keys().next_back().copied()
+    }
+
+    /// Add an entry. `id` must be greater than `last_id`, callers check that.
+    pub fn insert(&mut self, id: StreamId, fields: StreamFields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// The id XADD gives an entry added at time `now_ms` with `*`, None once ids are used up
+    pub fn next_auto_id(&self, now_ms: u64) -> Option<StreamId> {
+        if now_ms > self.last_id.ms {
+            Some(StreamId::new(now_ms, 0))
+        } else {
+            // the clock went backwards, or several entries in the same milli
+            self.last_id.next()
+        }
+    }
+
+    /// Entries with ids from `start` to `end`, both included, oldest first unless `rev`
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<(StreamId, &StreamFields)> {
+        if start > end {
+            return Vec::new();
+        }
+        let range = self
+            .entries
+            .range(start..=end)
+            .map(|(id, fields)| (*id, fields));
+        let count = count.unwrap_or(usize::MAX);
+        if rev {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+
+    /// Remove the oldest entries until `strategy` holds, at most `limit` of them.
+    /// Returns how many were removed.
+    pub fn trim(&mut self, strategy: TrimStrategy, limit: Option<usize>) -> usize {
+        let mut n_removed = 0;
+        while limit.is_none_or(|limit| n_removed < limit) {
+            let Some(first) = self.first_id() else {
+                break;
+            };
+            let done = match strategy {
+                TrimStrategy::MaxLen(max_len) => self.len() as u64 <= max_len,
+                TrimStrategy::MinId(min_id) => first >= min_id,
+            };
+            if done {
+                break;
+            }
+            self.entries.remove(&first);
+            self.max_deleted_id = self.max_deleted_id.max(first);
+            n_removed += 1;
+        }
+        n_removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&StreamId, &StreamFields)> {
+        self.entries.iter()
+    }
+
+    /// How many entries were added up to `id` included, as far as it can be told
+    /// from the first and last ids and the counters. Same estimate as redis makes.
+    pub fn entries_added_up_to(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if id == self.last_id || (self.is_empty() && id < self.last_id) {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+        let first_id = self.first_id()?;
+        // ids deleted after the first entry leave gaps that can't be counted
+        if self.max_deleted_id != StreamId::MIN && self.max_deleted_id >= first_id {
+            return None;
+        }
+        let before_first = self.entries_added - self.len() as u64;
+        match id.cmp(&first_id) {
+            Ordering::Less => Some(before_first),
+            Ordering::Equal => Some(before_first + 1),
+            Ordering::Greater => None,
+        }
+    }
+
+    /// Move group `name` past `id`, just delivered to it, keeping its read counter
+    /// up to date when possible
+    pub fn advance_group(&mut self, name: &Bytes, id: StreamId) {
+        let no_gaps_ahead =
+            self.is_empty() || self.max_deleted_id == StreamId::MIN || self.max_deleted_id < id;
+        let estimate = self.entries_added_up_to(id);
+        let entries_added = self.entries_added;
+        let Some(group) = self.groups.get_mut(name) else {
+            return;
+        };
+        if id <= group.last_id {
+            return;
+        }
+        group.last_id = id;
+        group.entries_read = match group.entries_read {
+            Some(n) if no_gaps_ahead => Some(n + 1),
+            _ if entries_added > 0 => estimate,
+            unknown => unknown,
+        };
+    }
+}
+
+/// A group of consumers sharing the entries of a stream: each entry goes to one of them,
+/// and stays pending until it is acknowledged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerGroup {
+    pub last_id: StreamId,         // the last entry delivered to the group
+    pub entries_read: Option<u64>, // entries of the stream up to last_id, None if unknown
+    pub pending: BTreeMap<StreamId, PendingEntry>, // the PEL: delivered but not acked yet
+    pub consumers: BTreeMap<Bytes, Consumer>,
+}
+
+/// An entry delivered to a consumer, until the consumer acks it or another one claims it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    pub delivery_time: u64, // millis since epoch, of the last delivery
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Consumer {
+    pub seen_time: u64,              // millis since epoch, of its last read or claim
+    pub active_time: Option<u64>,    // same, of the last one that got it entries
+    pub pending: BTreeSet<StreamId>, // its part of the group's PEL
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup {
+            last_id,
+            entries_read,
+            ..Default::default()
+        }
+    }
+
+    /// Note that consumer `name` showed up at time `now`, creating it if needed.
+    /// Returns whether it was created.
+    pub fn touch_consumer(&mut self, name: &Bytes, now: u64) -> bool {
+        match self.consumers.get_mut(name) {
+            Some(consumer) => {
+                consumer.seen_time = now;
+                false
+            }
+            None => {
+                let consumer = Consumer {
+                    seen_time: now,
+                    ..Default::default()
+                };
+                self.consumers.insert(name.clone(), consumer);
+                true
+            }
+        }
+    }
+
+    /// Make `id` pending for `consumer`, taking it from whichever consumer had it.
+    /// The consumer must exist.
+    pub fn deliver(&mut self, id: StreamId, consumer: &Bytes, time: u64, count: u64) {
+        let entry = PendingEntry {
+            consumer: consumer.clone(),
+            delivery_time: time,
+            delivery_count: count,
+        };
+        if let Some(prev) = self.pending.insert(id, entry) {
+            if let Some(prev_consumer) = self.consumers.get_mut(&prev.consumer) {
+                prev_consumer.pending.remove(&id);
+            }
+        }
+        let consumer = self.consumers.get_mut(consumer).expect("consumer exists");
+        consumer.pending.insert(id);
+    }
+
+    /// Remove `id` from the PEL, returns whether it was there
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+
+    /// Remove a consumer along with its pending entries, returns how many it had
+    pub fn remove_consumer(&mut self, name: &Bytes) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+}
@@ -1,24 +1,47 @@
+use std::fmt::Debug;
 use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::Result;
 
 use tokio::{
-    io::{AsyncWriteExt, BufStream},
-    net::TcpStream,
-    sync::mpsc::{self, Sender},
+    io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::mpsc::{self, Receiver, Sender},
 };
 
 use crate::{
-    async_deser,
+    async_deser::RespDeserializer,
     commands::{parse_cmd, Command},
-    misc_util::peer_addr_str_v2,
-    resp::{self, b_str, serialize_many, QueryResult, Value},
+    common::Bytes,
+    db::{ProxyToMaster, PsyncOutcome},
+    pubsub::{push_channel, PushSender, Pushed},
+    resp::{self, b_str, serialize_many, Protocol, QueryResult, Value},
 };
 
+/// What clients and replicas are served over: a buffered TCP stream when running,
+/// but anything that reads and writes bytes will do, e.g. `tokio::io::duplex` pipes in tests
+pub trait Connection: AsyncBufRead + AsyncWrite + Unpin + Send + Debug + 'static {}
+
+impl<T: AsyncBufRead + AsyncWrite + Unpin + Send + Debug + 'static> Connection for T {}
+
 #[derive(Debug)]
 pub enum ToDb {
     QueryAndSender(Query, Sender<QueryResult>),
-    PassedReplStream(BufStream<TcpStream>),
+    // A replica's connection after PSYNC, with its address and client id
+    PassedReplStream(RespDeserializer<Box<dyn Connection>>, String, u64),
+    ReplicaDisconnected(String),
+    // Replica side: (re)established link to master and the outcome of PSYNC
+    MasterLinkUp(ProxyToMaster, PsyncOutcome),
+    MasterLinkDown,
+    // Sent by the BGSAVE task when done, with the unix time of the save or an error message
+    BgSaveFinished(Result<u64, String>),
+    // Sent by the BGREWRITEAOF task when done, with the path of the rewritten file or an error
+    AofRewriteFinished(Result<PathBuf, String>),
+    // EXEC of the transaction a client queued since MULTI
+    Exec(Query, Transaction, Sender<QueryResult>),
+    // A client connection ended, by its address
+    ClientClosed(u64),
 }
 
 #[derive(Debug)]
@@ -27,10 +50,17 @@ pub enum ToReplica {
     Bytes(Vec<u8>, String),
 }
 
+/// Who sent a query. The address is only a label, as the connection gave it: host:port for
+/// TCP, but a unix socket or a test pipe may have anything there, or nothing.
 #[derive(Debug, Clone)]
 pub struct ClientInfo {
-    pub host: String,
-    pub port: String,
+    addr: String,
+}
+
+impl ClientInfo {
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +69,10 @@ pub struct Query {
     pub deser_byte_cnt: usize,
     // pub is_repl_update: bool,
     pub client_info: ClientInfo,
+    // where pub/sub messages for the client go, None for clients that can't subscribe
+    pub pushes: Option<PushSender>,
+    pub protocol: Protocol, // what the client's connection speaks
+    pub client_id: u64,     // 0 if not from a client connection
 }
 
 impl Query {
@@ -49,31 +83,51 @@ impl Query {
     ) -> Self {
         // let addr = peer_addr_str(stream);
 
-        #[allow(clippy::single_char_pattern)]
-        let addr_parts: Vec<&str> = addr.split(":").collect();
         Query {
             cmd,
             deser_byte_cnt,
             // is_repl_update,
-            client_info: ClientInfo {
-                host: addr_parts[0].to_string(),
-                port: addr_parts[1].to_string(),
-            },
+            client_info: ClientInfo { addr },
+            pushes: None,
+            protocol: Protocol::Resp2,
+            client_id: 0,
         }
     }
 }
 
+/// A connection's MULTI state: the commands queued so far, to run all at once on EXEC
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub queued: Vec<Query>,
+    pub has_errors: bool, // some command could not be queued, EXEC will abort
+    // bytes of the MULTI itself, counted towards the replication offset along with the EXEC
+    pub multi_byte_cnt: usize,
+}
+
+// ids of client connections, in the order they were accepted
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Where the reply to a command comes from
+enum Reply {
+    Now(QueryResult),
+    Later(Receiver<QueryResult>),
+}
+
 // long running coroutine that gets requests directly from the buffered stream
-// and replies to them.
-pub async fn handle_stream_async(
-    mut bstream: BufStream<TcpStream>,
+// and replies to them. `addr` labels the peer in logs and INFO, e.g. its host:port.
+pub async fn handle_stream_async<S: Connection>(
+    mut conn: RespDeserializer<S>,
+    addr: String,
     tx: Sender<ToDb>,
     is_replication: bool,
 ) {
-    let addr = peer_addr_str_v2(&bstream);
     println!("\n\nStarting handle_stream_async(replication={is_replication}) from: {addr}\n");
-
-    let mut _eof_cnt = 0usize;
+    let (push_s, mut push_r) = push_channel();
+    let mut multi: Option<Transaction> = None;
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    // switched by HELLO
+    let mut protocol = Protocol::Resp2;
+    let mut name: Option<Bytes> = None;
 
     // debug_peek(format!("before loop (replication={is_replication})").as_str(), &bstream, 64).await;
     loop {
@@ -84,7 +138,20 @@ pub async fn handle_stream_async(
         //    continue
         // }
 
-        let deser_res = async_deser::deserialize(&mut bstream).await;
+        // values pushed by Db, like pub/sub messages, are written out between commands
+        let pushed = tokio::select! {
+            pushed = push_r.recv() => pushed,
+            _ = conn.readable() => None,
+        };
+        if let Some(pushed) = pushed {
+            if !do_push(conn.get_mut(), pushed, &mut push_r, protocol).await {
+                println!("handle_stream_async: {addr} can't keep up with its pushes, closing");
+                break;
+            }
+            continue;
+        }
+
+        let deser_res = conn.deserialize().await;
 
         match deser_res {
             Ok((input_value, deser_byte_cnt)) => {
@@ -92,32 +159,102 @@ pub async fn handle_stream_async(
                     "handle_stream_async(replication={is_replication}): processing_input from:{addr}, value: {input_value:?}"
                 );
 
-                let query_result: QueryResult =
-                    process_input_async(input_value, deser_byte_cnt, &addr, &tx).await;
+                // only bytes received over the master link count towards the replication offset
+                let repl_byte_cnt = if is_replication { deser_byte_cnt } else { 0 };
+                let mut hello = None;
+                let mut may_block = false;
+                let submitted = match make_query(&input_value, repl_byte_cnt, &addr).await {
+                    Ok(query) => {
+                        if let Command::Hello(version, _, new_name) = &query.cmd {
+                            hello = Some((*version, new_name.clone()));
+                        }
+                        may_block = query.cmd.may_block();
+                        let query = Query {
+                            pushes: (!is_replication).then(|| push_s.clone()),
+                            protocol,
+                            client_id,
+                            ..query
+                        };
+                        submit_or_queue(query, &mut multi, &tx).await
+                    }
+                    Err(e) => {
+                        println!("handle_stream_async: bad command from {addr}: {e}");
+                        if let Some(multi) = &mut multi {
+                            multi.has_errors = true;
+                        }
+                        Reply::Now(error_result(e.to_string()))
+                    }
+                };
+                let query_result: QueryResult = match submitted {
+                    Reply::Now(query_result) => query_result,
+                    Reply::Later(val_r) if is_replication || !may_block => {
+                        receive_reply(val_r).await
+                    }
+                    // e.g. blocked in BLPOP. Dropping val_r when the client goes away
+                    // tells Db that nobody is waiting for the reply anymore.
+                    // Other commands are answered right away, even to a client that
+                    // closed its side already, like `nc -N` does.
+                    Reply::Later(val_r) => tokio::select! {
+                        query_result = receive_reply(val_r) => query_result,
+                        _ = peer_closed(&mut conn) => {
+                            println!("handle_stream_async: {addr} closed while waiting for a reply");
+                            break;
+                        }
+                    },
+                };
+
+                // a successful HELLO switches the protocol, starting with its own reply
+                if let (Some((version, new_name)), Some(Value::Map(_))) =
+                    (hello, query_result.vals.first())
+                {
+                    protocol = version.unwrap_or(protocol);
+                    name = new_name.or(name);
+                }
 
                 // Send result, but NOT if we are in replica mode
                 if should_reply(is_replication, &query_result) {
-                    do_reply(&mut bstream, &query_result).await;
+                    do_reply(conn.get_mut(), &query_result, protocol).await;
                 }
 
                 if query_result.pass_stream {
-                    tx.send(ToDb::PassedReplStream(bstream)).await.unwrap();
+                    let conn = conn.map_stream(|bstream| Box::new(bstream) as Box<dyn Connection>);
+                    tx.send(ToDb::PassedReplStream(conn, addr.clone(), client_id))
+                        .await
+                        .unwrap();
                     break;
                 }
             }
             Err(err) => {
                 if let Some(io_err) = err.downcast_ref::<io::Error>() {
-                    if io_err.kind() == io::ErrorKind::UnexpectedEof {
-                        _eof_cnt += 1;
-                    }
+                    // connection closed or broken, nothing more will come
+                    println!("handle_stream_async: io error from {addr}: {io_err:?}");
                 } else {
+                    // we can't tell where the next command starts, so like redis we
+                    // reply with an error and close the connection
                     println!("EERRRORR: Failed to deserialize value. err:{err:?}");
+                    if !is_replication {
+                        let query_result = error_result(format!("ERR Protocol error: {err}"));
+                        do_reply(conn.get_mut(), &query_result, protocol).await;
+                    }
+                }
+                if is_replication {
+                    tx.send(ToDb::MasterLinkDown).await.unwrap_or_else(|e| {
+                        println!("handle_stream_async: could not notify Db, e:{e:?}")
+                    });
                 }
-                // tokio::time::sleep(Duration::from_millis(1)).await;
+                break;
             }
         } // match deser_res
     } // loop
-    println!("\n\nEND of handle_stream_async(replication={is_replication}) -- from: {addr}\n\n");
+    if !is_replication {
+        tx.send(ToDb::ClientClosed(client_id))
+            .await
+            .unwrap_or_else(|e| println!("handle_stream_async: could not notify Db, e:{e:?}"));
+    }
+    let name = name
+        .and_then(|name| name.to_string().ok())
+        .unwrap_or_default();
+    println!("\n\nEND of handle_stream_async(replication={is_replication}) -- from: {addr} (name: {name})\n\n");
 }
 
 pub async fn process_input_async(
@@ -127,40 +264,137 @@ pub async fn process_input_async(
     // bstream: &mut BufStream<TcpStream>,
     send_to_db: &Sender<ToDb>,
 ) -> QueryResult {
+    match submit_query(input_val, deser_byte_cnt, addr, send_to_db).await {
+        Ok(val_r) => receive_reply(val_r).await,
+        Err(err_result) => err_result,
+    }
+}
+
+/// Parse a command and hand it to Db. Returns where the reply will come from,
+/// or the error to reply with if the command could not be parsed.
+async fn submit_query(
+    input_val: resp::Value,
+    deser_byte_cnt: usize,
+    addr: &str,
+    send_to_db: &Sender<ToDb>,
+) -> Result<Receiver<QueryResult>, QueryResult> {
     // debug_peek("before calling deserialize", &mut bstream, 64).await;
 
-    let query = make_query(&input_val, deser_byte_cnt, addr).await.unwrap();
-    let dbg_msg_qry = query.clone(); // only used for dbg message below...
-    let (val_s, mut val_r) = mpsc::channel(1);
+    let query = match make_query(&input_val, deser_byte_cnt, addr).await {
+        Ok(query) => query,
+        Err(e) => {
+            println!("process_input_async: bad command from {addr}: {e}");
+            return Err(error_result(e.to_string()));
+        }
+    };
+    Ok(submit(send_to_db, |val_s| ToDb::QueryAndSender(query, val_s)).await)
+}
+
+/// Hand something to Db, returns where the reply will come from
+async fn submit(
+    send_to_db: &Sender<ToDb>,
+    msg: impl FnOnce(Sender<QueryResult>) -> ToDb,
+) -> Receiver<QueryResult> {
+    let (val_s, val_r) = mpsc::channel(1);
+    send_to_db.send(msg(val_s)).await.unwrap();
+    val_r
+}
 
-    send_to_db
-        .send(ToDb::QueryAndSender(query, val_s))
-        .await
-        .unwrap();
+/// Hand `query` to Db, or queue it if the connection is in a MULTI.
+/// MULTI, EXEC and DISCARD change the connection's state instead.
+async fn submit_or_queue(
+    query: Query,
+    multi: &mut Option<Transaction>,
+    send_to_db: &Sender<ToDb>,
+) -> Reply {
+    match (&query.cmd, multi.as_mut()) {
+        (Command::Multi, None) => {
+            *multi = Some(Transaction {
+                multi_byte_cnt: query.deser_byte_cnt,
+                ..Transaction::default()
+            });
+            Reply::Now(simple_result("OK"))
+        }
+        (Command::Multi, Some(_)) => Reply::Now(error_result(
+            "ERR MULTI calls can not be nested".to_string(),
+        )),
+        (Command::Watch(_), Some(_)) => Reply::Now(error_result(
+            "ERR WATCH inside MULTI is not allowed".to_string(),
+        )),
+        (Command::Hello(..), Some(transaction)) => {
+            transaction.has_errors = true;
+            Reply::Now(error_result(
+                "ERR Command not allowed inside a transaction".to_string(),
+            ))
+        }
+        (Command::Exec, Some(_)) => {
+            let transaction = multi.take().expect("matched above");
+            Reply::Later(submit(send_to_db, |sx| ToDb::Exec(query, transaction, sx)).await)
+        }
+        (Command::Exec, None) => Reply::Now(error_result("ERR EXEC without MULTI".to_string())),
+        (Command::Discard, None) => {
+            Reply::Now(error_result("ERR DISCARD without MULTI".to_string()))
+        }
+        // Db forgets the watched keys
+        (Command::Discard, Some(_)) => {
+            *multi = None;
+            Reply::Later(submit(send_to_db, |sx| ToDb::QueryAndSender(query, sx)).await)
+        }
+        (_, Some(transaction)) => {
+            transaction.queued.push(query);
+            Reply::Now(simple_result("QUEUED"))
+        }
+        (_, None) => Reply::Later(submit(send_to_db, |sx| ToDb::QueryAndSender(query, sx)).await),
+    }
+}
 
+async fn receive_reply(mut val_r: Receiver<QueryResult>) -> QueryResult {
     // output_res.unwrap_or_else(|e| vec![resp::s_err(&e.to_string())].into())
     match val_r.recv().await {
         Some(qres) => qres,
         None => {
             println!(
-                "process_input_async: Did not get reply from db for query: {dbg_msg_qry:?}, returning result with empty vals array");
-            QueryResult{vals: vec![], pass_stream: false, repl_byte_cnt_inc: 0}
+                "receive_reply: Did not get reply from db, returning result with empty vals array"
+            );
+            QueryResult {
+                vals: vec![],
+                pass_stream: false,
+                repl_byte_cnt_inc: 0,
+            }
         }
     }
 }
 
+/// Resolves once the peer has closed the connection. If it sent more data instead, we can't
+/// tell until that is read, after the current command, so then it never resolves.
+/// Data read meanwhile stays buffered in `conn`.
+async fn peer_closed<R: AsyncRead + Unpin>(conn: &mut RespDeserializer<R>) {
+    if conn.readable().await {
+        std::future::pending().await
+    }
+}
+
 async fn make_query(input_val: &resp::Value, deser_byte_cnt: usize, addr: &str) -> Result<Query> {
-    let cmd_res = parse_cmd(input_val);
-    match cmd_res {
-        Ok(cmd) => {
-            println!("Command parsed: {cmd:?} (from: {addr})", addr = addr);
-            let query = Query::new(cmd, deser_byte_cnt, addr.to_string());
-            Ok(query)
-        }
-        Err(e) => {
-            panic!("parse_cmd failed: {e}");
-            // Err(anyhow::format_err!("parse_cmd failed: {e}")),
-        }
+    let cmd = parse_cmd(input_val)?;
+    println!("Command parsed: {cmd:?} (from: {addr})", addr = addr);
+    let query = Query::new(cmd, deser_byte_cnt, addr.to_string());
+    Ok(query)
+}
+
+fn simple_result(msg: &str) -> QueryResult {
+    QueryResult {
+        vals: vec![Value::SimpleString(msg.into())],
+        pass_stream: false,
+        repl_byte_cnt_inc: 0,
+    }
+}
+
+/// Reply with a single error, e.g. for a command that could not be parsed
+fn error_result(msg: String) -> QueryResult {
+    QueryResult {
+        vals: vec![Value::SimpleError(msg)],
+        pass_stream: false,
+        repl_byte_cnt_inc: 0,
     }
 }
 
@@ -169,18 +403,48 @@ fn should_reply(is_replication: bool, query_result: &QueryResult) -> bool {
         return true;
     }
     // True if value is `REPLCONF ACK anything``
-    if let Value::Array(parts) = &query_result.vals[0] {
+    if let Some(Value::Array(parts)) = query_result.vals.first() {
         parts.len() >= 2 && parts[0] == b_str("REPLCONF") && parts[1] == b_str("ACK")
     } else {
         false
     }
 }
 
-pub async fn do_reply(bstream: &mut BufStream<TcpStream>, query_result: &QueryResult) {
+/// Write `pushed` and whatever else was pushed meanwhile, in one go.
+/// Returns false if the client fell too far behind, and is to be disconnected.
+async fn do_push<W: AsyncWrite + Unpin>(
+    bstream: &mut W,
+    pushed: Pushed,
+    push_r: &mut Receiver<Pushed>,
+    protocol: Protocol,
+) -> bool {
+    let mut vals = Vec::new();
+    let mut next = Some(pushed);
+    while let Some(pushed) = next {
+        match pushed {
+            Pushed::Message(val) => vals.push(val),
+            Pushed::Overflowed => return false,
+        }
+        next = push_r.try_recv().ok();
+    }
+    let pushed = QueryResult {
+        vals,
+        pass_stream: false,
+        repl_byte_cnt_inc: 0,
+    };
+    do_reply(bstream, &pushed, protocol).await;
+    true
+}
+
+pub async fn do_reply<W: AsyncWrite + Unpin>(
+    bstream: &mut W,
+    query_result: &QueryResult,
+    protocol: Protocol,
+) {
     if query_result.vals.is_empty() {
         println!("do_reply: 0 output vals; {query_result:?}")
     }
-    let serialized = serialize_many(&query_result.vals).unwrap();
+    let serialized = serialize_many(&query_result.vals, protocol).unwrap();
 
     let write_result = bstream.write_all(serialized.as_bytes()).await;
     if let Err(err) = write_result {
@@ -0,0 +1,74 @@
+// WATCH: the keys each client watches, with a version per key that every change bumps,
+// so that EXEC can tell whether any of them changed since they were watched.
+use std::collections::HashMap;
+
+use crate::common::Bytes;
+
+#[derive(Debug, Default)]
+pub struct WatchedKeys {
+    versions: HashMap<Bytes, KeyVersion>, // only keys someone watches
+    clients: HashMap<u64, Vec<(Bytes, u64)>>, // by client id, keys and their version then
+}
+
+#[derive(Debug)]
+struct KeyVersion {
+    version: u64,
+    n_watchers: usize,
+}
+
+impl WatchedKeys {
+    pub fn watch(&mut self, client: u64, keys: &[Bytes]) {
+        let watched = self.clients.entry(client).or_default();
+        for key in keys {
+            if watched.iter().any(|(k, _)| k == key) {
+                continue;
+            }
+            let kv = self.versions.entry(key.clone()).or_insert(KeyVersion {
+                version: 0,
+                n_watchers: 0,
+            });
+            kv.n_watchers += 1;
+            watched.push((key.clone(), kv.version));
+        }
+    }
+
+    /// Record a change to `key`
+    pub fn touch(&mut self, key: &Bytes) {
+        if let Some(kv) = self.versions.get_mut(key) {
+            kv.version += 1;
+        }
+    }
+
+    /// Record a change to every key, e.g. when the dataset is replaced
+    pub fn touch_all(&mut self) {
+        for kv in self.versions.values_mut() {
+            kv.version += 1;
+        }
+    }
+
+    pub fn keys_of(&self, client: u64) -> Vec<Bytes> {
+        self.clients.get(&client).map_or(Vec::new(), |watched| {
+            watched.iter().map(|(key, _)| key.clone()).collect()
+        })
+    }
+
+    /// Stop watching all of `client`'s keys. Returns false if any of them changed meanwhile.
+    pub fn unwatch_all(&mut self, client: u64) -> bool {
+        let Some(watched) = self.clients.remove(&client) else {
+            return true;
+        };
+        let mut unchanged = true;
+        for (key, version) in watched {
+            let kv = self
+                .versions
+                .get_mut(&key)
+                .expect("watched keys have a version");
+            unchanged &= kv.version == version;
+            kv.n_watchers -= 1;
+            if kv.n_watchers == 0 {
+                self.versions.remove(&key);
+            }
+        }
+        unchanged
+    }
+}
@@ -0,0 +1,189 @@
+// Sorted set: members ordered by (score, member), with score lookup by member.
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::commands::parse_float;
+use crate::common::Bytes;
+use crate::resp::format_double;
+
+/// A score with a total order, so that it can be part of a BTreeSet key.
+/// Sorted sets never hold NaN, callers reject it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// One end of a score range, as in `ZRANGE key (1 +inf BYSCORE`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    /// `1.5`, `(1.5`, `-inf`, `+inf`...
+    pub fn parse(bs: &Bytes) -> Option<Self> {
+        match bs.as_bytes().split_first() {
+            Some((b'(', rest)) => parse_float(&rest.into()).map(ScoreBound::Exclusive),
+            _ => parse_float(bs).map(ScoreBound::Inclusive),
+        }
+    }
+
+    fn below(&self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(min) => *min <= score,
+            ScoreBound::Exclusive(min) => *min < score,
+        }
+    }
+
+    fn above(&self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(max) => score <= *max,
+            ScoreBound::Exclusive(max) => score < *max,
+        }
+    }
+}
+
+impl fmt::Display for ScoreBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoreBound::Inclusive(score) => f.write_str(&format_double(*score)),
+            ScoreBound::Exclusive(score) => write!(f, "({}", format_double(*score)),
+        }
+    }
+}
+
+/// One end of a member range, as in `ZRANGE key [a (c BYLEX`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    Min, // `-`
+    Max, // `+`
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    pub fn parse(bs: &Bytes) -> Option<Self> {
+        match bs.as_bytes() {
+            b"-" => Some(LexBound::Min),
+            b"+" => Some(LexBound::Max),
+            [b'[', rest @ ..] => Some(LexBound::Inclusive(rest.into())),
+            [b'(', rest @ ..] => Some(LexBound::Exclusive(rest.into())),
+            _ => None,
+        }
+    }
+
+    fn below(&self, member: &Bytes) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => min <= member,
+            LexBound::Exclusive(min) => min < member,
+        }
+    }
+
+    fn above(&self, member: &Bytes) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= max,
+            LexBound::Exclusive(max) => member < max,
+        }
+    }
+}
+
+impl fmt::Display for LexBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexBound::Min => write!(f, "-"),
+            LexBound::Max => write!(f, "+"),
+            LexBound::Inclusive(member) => {
+                write!(f, "[{}", String::from_utf8_lossy(member.as_bytes()))
+            }
+            LexBound::Exclusive(member) => {
+                write!(f, "({}", String::from_utf8_lossy(member.as_bytes()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` or update its score. Returns the previous score, if any.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        // -0.0 and 0.0 are the same score, but not for total_cmp
+        let score = score + 0.0;
+        let prev = self.scores.insert(member.clone(), score);
+        if let Some(prev) = prev {
+            self.ordered.remove(&(Score(prev), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        prev
+    }
+
+    pub fn remove(&mut self, member: &Bytes) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.ordered.remove(&(Score(score), member.clone()));
+        Some(score)
+    }
+
+    /// All members with their scores, lowest score first, ties broken by member
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> + '_ {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// Members with a score between `min` and `max`, lowest first
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> + '_ {
+        let (ScoreBound::Inclusive(lowest) | ScoreBound::Exclusive(lowest)) = min;
+        // the empty member sorts first among those with the lowest score
+        self.ordered
+            .range((Score(lowest), Bytes::from(Vec::new()))..)
+            .map(|(score, member)| (member, score.0))
+            .filter(move |(_, score)| min.below(*score) && max.above(*score))
+    }
+
+    /// Members between `min` and `max`, lowest first. Only meaningful when all members
+    /// have the same score, as in redis.
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: &'a LexBound,
+        max: &'a LexBound,
+    ) -> impl DoubleEndedIterator<Item = (&'a Bytes, f64)> + 'a {
+        self.iter()
+            .filter(move |(member, _)| min.below(member) && max.above(member))
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use redis_starter_rust::*;
+
+use aof::{read_aof_file, write_aof_file, Aof, FsyncPolicy};
+use commands::{Command, SetExpiry, SetFlags};
+
+fn tmp_aof_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{name}-{pid}.aof", pid = std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn aof_roundtrip() {
+    let path = tmp_aof_path("aof_roundtrip");
+    let cmds = vec![
+        Command::SetKV("foo".into(), "bar".into(), None, SetFlags::default()),
+        Command::SetKV(
+            "ttl".into(),
+            "x".into(),
+            Some(SetExpiry::Px(1500)),
+            SetFlags::default(),
+        ),
+    ];
+
+    let mut aof = Aof::open(&path, FsyncPolicy::Always).unwrap();
+    for cmd in &cmds {
+        aof.append(cmd).unwrap();
+    }
+
+    assert_eq!(read_aof_file(&path).unwrap(), cmds);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn aof_truncated_tail_is_dropped() {
+    let path = tmp_aof_path("aof_truncated");
+    let mut aof = Aof::open(&path, FsyncPolicy::No).unwrap();
+    aof.append(&Command::SetKV(
+        "foo".into(),
+        "bar".into(),
+        None,
+        SetFlags::default(),
+    ))
+    .unwrap();
+    let complete_len = std::fs::metadata(&path).unwrap().len();
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nba").unwrap();
+
+    let cmds = read_aof_file(&path).unwrap();
+    assert_eq!(
+        cmds,
+        vec![Command::SetKV(
+            "foo".into(),
+            "bar".into(),
+            None,
+            SetFlags::default()
+        )]
+    );
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), complete_len);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn aof_rewrite_keeps_buffered_writes() {
+    let path = tmp_aof_path("aof_rewrite");
+    let tmp_path = tmp_aof_path("aof_rewrite_tmp");
+    let mut aof = Aof::open(&path, FsyncPolicy::No).unwrap();
+    aof.append(&Command::SetKV(
+        "foo".into(),
+        "1".into(),
+        None,
+        SetFlags::default(),
+    ))
+    .unwrap();
+    aof.append(&Command::SetKV(
+        "foo".into(),
+        "2".into(),
+        None,
+        SetFlags::default(),
+    ))
+    .unwrap();
+
+    aof.start_rewrite();
+    let snapshot = vec![Command::SetKV(
+        "foo".into(),
+        "2".into(),
+        Some(SetExpiry::PxAt(1_700_000_000_000)),
+        SetFlags::default(),
+    )];
+    write_aof_file(&tmp_path, &snapshot).unwrap();
+    aof.append(&Command::SetKV(
+        "bar".into(),
+        "3".into(),
+        None,
+        SetFlags::default(),
+    ))
+    .unwrap();
+    aof.finish_rewrite(&tmp_path).unwrap();
+    aof.append(&Command::SetKV(
+        "baz".into(),
+        "4".into(),
+        None,
+        SetFlags::default(),
+    ))
+    .unwrap();
+
+    assert_eq!(
+        read_aof_file(&path).unwrap(),
+        vec![
+            snapshot[0].clone(),
+            Command::SetKV("bar".into(), "3".into(), None, SetFlags::default()),
+            Command::SetKV("baz".into(), "4".into(), None, SetFlags::default()),
+        ]
+    );
+    assert!(!tmp_path.exists());
+    std::fs::remove_file(&path).unwrap();
+}
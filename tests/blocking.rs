@@ -0,0 +1,157 @@
+mod db_util;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use redis_starter_rust::*;
+
+use clock::{ManualClock, SystemClock};
+use config::InstanceConfig;
+use db::Db;
+use db_util::{query, run, start, test_db, START};
+use resp::{
+    QueryResult,
+    Value::{self, *},
+};
+use svc::ToDb;
+use tokio::sync::mpsc::{channel, error::TryRecvError, Receiver};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn bulks(words: &str) -> Value {
+    Array(words.split(' ').map(bulk).collect())
+}
+
+/// The single reply a blocked client got, if it got one already
+fn reply(rx: &mut Receiver<QueryResult>) -> Option<Value> {
+    match rx.try_recv() {
+        Ok(mut res) => {
+            assert_eq!(res.vals.len(), 1, "replies: {:?}", res.vals);
+            Some(res.vals.remove(0))
+        }
+        Err(TryRecvError::Empty) => None,
+        Err(e) => panic!("no reply will come: {e:?}"),
+    }
+}
+
+#[tokio::test]
+async fn pops_without_blocking_when_possible() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "RPUSH b 1 2").await;
+    assert_eq!(run(&mut db, "BLPOP a b 0").await, bulks("b 1"));
+    assert_eq!(run(&mut db, "BRPOP a b 0").await, bulks("b 2"));
+    assert_eq!(run(&mut db, "EXISTS b").await, Int(0));
+
+    run(&mut db, "SET s x").await;
+    assert_eq!(
+        run(&mut db, "BLPOP a s 0").await,
+        SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+    );
+}
+
+#[tokio::test]
+async fn push_wakes_the_oldest_waiter() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    let mut first = start(&mut db, "BLPOP a b 0").await;
+    let mut second = start(&mut db, "BRPOP b 0").await;
+    assert_eq!(reply(&mut first), None);
+
+    assert_eq!(run(&mut db, "RPUSH b x").await, Int(1));
+    assert_eq!(reply(&mut first), Some(bulks("b x")));
+    assert_eq!(reply(&mut second), None);
+    // the element went to the waiter, not into the list
+    assert_eq!(run(&mut db, "LLEN b").await, Int(0));
+
+    // one push of several elements can serve several waiters
+    let mut third = start(&mut db, "BLPOP b 0").await;
+    assert_eq!(run(&mut db, "RPUSH b y z w").await, Int(3));
+    assert_eq!(reply(&mut second), Some(bulks("b w")));
+    assert_eq!(reply(&mut third), Some(bulks("b y")));
+    assert_eq!(run(&mut db, "LRANGE b 0 -1").await, bulks("z"));
+}
+
+#[tokio::test]
+async fn disconnected_waiters_are_skipped() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    let gone = start(&mut db, "BLPOP k 0").await;
+    let mut waiting = start(&mut db, "BLPOP k 0").await;
+    drop(gone);
+
+    run(&mut db, "LPUSH k v").await;
+    assert_eq!(reply(&mut waiting), Some(bulks("k v")));
+}
+
+#[tokio::test]
+async fn blmove_chains_to_other_waiters() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    let mut mover = start(&mut db, "BLMOVE src dst RIGHT LEFT 0").await;
+    let mut popper = start(&mut db, "BLPOP dst 0").await;
+
+    run(&mut db, "RPUSH src a b").await;
+    assert_eq!(reply(&mut mover), Some(bulk("b")));
+    assert_eq!(reply(&mut popper), Some(bulks("dst b")));
+    assert_eq!(run(&mut db, "LRANGE src 0 -1").await, bulks("a"));
+    assert_eq!(run(&mut db, "EXISTS dst").await, Int(0));
+
+    assert_eq!(run(&mut db, "LMOVE src src LEFT RIGHT").await, bulk("a"));
+    assert_eq!(
+        run(&mut db, "LMOVE nope src LEFT RIGHT").await,
+        NullBulkString
+    );
+}
+
+#[tokio::test]
+async fn blocked_pop_times_out() {
+    let (tx, rx) = channel(100);
+    let db = Db::with_clock(InstanceConfig::default(), tx.clone(), Arc::new(SystemClock));
+    tokio::spawn(db.run(rx));
+
+    let started = Instant::now();
+    let (sx, mut reply_rx) = channel(1);
+    tx.send(ToDb::QueryAndSender(query("BLPOP k 0.1"), sx))
+        .await
+        .unwrap();
+    let res = reply_rx.recv().await.unwrap();
+    assert_eq!(res.vals, vec![NullArray]);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let (sx, mut reply_rx) = channel(1);
+    tx.send(ToDb::QueryAndSender(query("BLMOVE k d LEFT LEFT 0.05"), sx))
+        .await
+        .unwrap();
+    assert_eq!(reply_rx.recv().await.unwrap().vals, vec![NullBulkString]);
+}
+
+#[tokio::test]
+async fn timeouts_follow_the_clock() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    let mut pop = start(&mut db, "BLPOP k 1.5").await;
+    let mut read = start(&mut db, "XREAD BLOCK 500 STREAMS s $").await;
+    let mut forever = start(&mut db, "BLPOP k 0").await;
+
+    clock.advance(499);
+    db.unblock_timed_out_clients();
+    assert_eq!(reply(&mut read), None);
+    clock.advance(1);
+    db.unblock_timed_out_clients();
+    assert_eq!(reply(&mut read), Some(NullArray));
+    assert_eq!(reply(&mut pop), None);
+
+    clock.advance(1000);
+    db.unblock_timed_out_clients();
+    assert_eq!(reply(&mut pop), Some(NullArray));
+    assert_eq!(reply(&mut forever), None);
+
+    // only the one left waiting gets the element
+    run(&mut db, "RPUSH k v").await;
+    assert_eq!(reply(&mut forever), Some(bulks("k v")));
+    assert_eq!(run(&mut db, "LLEN k").await, Int(0));
+}
@@ -1,6 +1,12 @@
-use commands::{parse_cmd, Command};
+use commands::{
+    parse_cmd, Command, ExpireFlags, ListEnd, SetExpiry, SetFlags, StreamTrim, XAddId, XAddOpts,
+    XClaimOpts, XPendingRange, XReadFrom, XReadGroupFrom, ZAddFlags, ZRangeBy, ZRangeSpec,
+};
+use pubsub::SubKind;
 use redis_starter_rust::*;
-use resp::Value::*;
+use resp::{Protocol, Value::*};
+use stream::{StreamId, TrimStrategy};
+use zset::ScoreBound;
 
 #[test]
 fn parse_echo() {
@@ -28,3 +34,680 @@ fn parse_bulk_ping() {
 
     assert_eq!(cmd, expected);
 }
+
+#[test]
+fn parse_unknown_command() {
+    let val = Array(vec![BulkString("FOO".into()), BulkString("bar".into())]);
+    let err = parse_cmd(&val).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "ERR unknown command 'FOO', with args beginning with: 'bar' "
+    );
+}
+
+#[test]
+fn parse_wrong_number_of_arguments() {
+    let val = Array(vec![BulkString("GET".into())]);
+    let err = parse_cmd(&val).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "ERR wrong number of arguments for 'get' command"
+    );
+
+    for cmd in ["PSYNC", "REPLCONF", "WAIT"] {
+        let val = Array(vec![BulkString(cmd.into())]);
+        assert!(parse_cmd(&val)
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR wrong number of arguments"));
+    }
+}
+
+#[test]
+fn parse_bad_integer_argument() {
+    let val = Array(vec![
+        BulkString("WAIT".into()),
+        BulkString("one".into()),
+        BulkString("100".into()),
+    ]);
+    let err = parse_cmd(&val).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "ERR value is not an integer or out of range"
+    );
+}
+
+#[test]
+fn parse_lowercase_commands() {
+    let val = Array(vec![
+        BulkString("set".into()),
+        BulkString("k".into()),
+        BulkString("v".into()),
+        BulkString("Px".into()),
+        BulkString("100".into()),
+    ]);
+    let expected = Command::SetKV(
+        "k".into(),
+        "v".into(),
+        Some(SetExpiry::Px(100)),
+        SetFlags::default(),
+    );
+    assert_eq!(parse_cmd(&val).unwrap(), expected);
+
+    let val = Array(vec![BulkString("get".into()), BulkString("k".into())]);
+    assert_eq!(parse_cmd(&val).unwrap(), Command::Get("k".into()));
+
+    let val = Array(vec![
+        BulkString("replconf".into()),
+        BulkString("getack".into()),
+        BulkString("*".into()),
+    ]);
+    assert_eq!(
+        parse_cmd(&val).unwrap(),
+        Command::ReplConfGetAck("*".into())
+    );
+
+    assert_eq!(
+        parse_cmd(&SimpleString("ping".into())).unwrap(),
+        Command::Ping
+    );
+}
+
+fn bulk_cmd(args: &[&str]) -> resp::Value {
+    Array(args.iter().map(|arg| BulkString((*arg).into())).collect())
+}
+
+fn set_cmd(args: &[&str]) -> resp::Value {
+    bulk_cmd(&[&["SET"], args].concat())
+}
+
+#[test]
+fn parse_set_options() {
+    let cmd = parse_cmd(&set_cmd(&["k", "v", "EX", "10", "nx", "GET"])).unwrap();
+    let expected = Command::SetKV(
+        "k".into(),
+        "v".into(),
+        Some(SetExpiry::Px(10_000)),
+        SetFlags {
+            nx: true,
+            xx: false,
+            get: true,
+        },
+    );
+    assert_eq!(cmd, expected);
+
+    let cmd = parse_cmd(&set_cmd(&["k", "v", "exat", "1700000000", "XX"])).unwrap();
+    let expected = Command::SetKV(
+        "k".into(),
+        "v".into(),
+        Some(SetExpiry::PxAt(1_700_000_000_000)),
+        SetFlags {
+            nx: false,
+            xx: true,
+            get: false,
+        },
+    );
+    assert_eq!(cmd, expected);
+
+    let cmd = parse_cmd(&set_cmd(&["k", "v", "KEEPTTL"])).unwrap();
+    let expected = Command::SetKV(
+        "k".into(),
+        "v".into(),
+        Some(SetExpiry::KeepTtl),
+        SetFlags::default(),
+    );
+    assert_eq!(cmd, expected);
+    // and back to the same command
+    assert_eq!(parse_cmd(&cmd.to_bulk_array()).unwrap(), cmd);
+}
+
+#[test]
+fn parse_set_bad_options() {
+    for args in [
+        &["k", "v", "NX", "XX"][..],
+        &["k", "v", "EX", "10", "PX", "10"],
+        &["k", "v", "KEEPTTL", "EX", "10"],
+        &["k", "v", "PX"],
+        &["k", "v", "FOO"],
+    ] {
+        let err = parse_cmd(&set_cmd(args)).unwrap_err();
+        assert_eq!(err.to_string(), "ERR syntax error", "args: {args:?}");
+    }
+
+    for args in [&["k", "v", "EX", "0"][..], &["k", "v", "PX", "-5"]] {
+        let err = parse_cmd(&set_cmd(args)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR invalid expire time in 'set' command",
+            "args: {args:?}"
+        );
+    }
+}
+
+#[test]
+fn parse_keyspace_commands() {
+    let cmd = bulk_cmd;
+
+    assert_eq!(
+        parse_cmd(&cmd(&["DEL", "a", "b"])).unwrap(),
+        Command::Del(vec!["a".into(), "b".into()])
+    );
+    assert_eq!(
+        parse_cmd(&cmd(&["keys", "user:*"])).unwrap(),
+        Command::Keys("user:*".into())
+    );
+    assert_eq!(
+        parse_cmd(&cmd(&["COPY", "a", "b", "db", "0", "REPLACE"])).unwrap(),
+        Command::Copy("a".into(), "b".into(), true)
+    );
+    assert_eq!(
+        parse_cmd(&cmd(&["COPY", "a", "b", "DB", "1"]))
+            .unwrap_err()
+            .to_string(),
+        "ERR DB index is out of range"
+    );
+    for bad in [&["EXISTS"][..], &["RENAME", "a"], &["TYPE", "a", "b"]] {
+        assert!(parse_cmd(&cmd(bad))
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR wrong number of arguments"));
+    }
+}
+
+#[test]
+fn parse_expire_commands() {
+    let cmd = parse_cmd(&bulk_cmd(&["EXPIRE", "k", "10", "xx", "GT"])).unwrap();
+    let expected = Command::Expire(
+        "k".into(),
+        10_000,
+        ExpireFlags {
+            xx: true,
+            gt: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(cmd, expected);
+    // propagated in millis
+    assert_eq!(parse_cmd(&cmd.to_bulk_array()).unwrap(), cmd);
+
+    let cmd = parse_cmd(&bulk_cmd(&["pexpireat", "k", "-1"])).unwrap();
+    assert_eq!(
+        cmd,
+        Command::ExpireAt("k".into(), -1, ExpireFlags::default())
+    );
+
+    for (args, err) in [
+        (
+            &["EXPIRE", "k", "10", "NX", "LT"][..],
+            "ERR NX and XX, GT or LT options at the same time are not compatible",
+        ),
+        (
+            &["EXPIRE", "k", "10", "GT", "LT"],
+            "ERR GT and LT options at the same time are not compatible",
+        ),
+        (&["EXPIRE", "k", "10", "FOO"], "ERR Unsupported option FOO"),
+        (
+            &["EXPIRE", "k", "9223372036854775807"],
+            "ERR invalid expire time in 'expire' command",
+        ),
+        (&["TTL"], "ERR wrong number of arguments for 'ttl' command"),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
+
+#[test]
+fn parse_string_commands() {
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["decrby", "n", "5"])).unwrap(),
+        Command::IncrBy("n".into(), -5)
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["GETEX", "k", "EXAT", "10"])).unwrap(),
+        Command::GetEx("k".into(), SetExpiry::PxAt(10_000))
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["MSET", "a", "1", "b", "2"])).unwrap(),
+        Command::MSet(vec![("a".into(), "1".into()), ("b".into(), "2".into())])
+    );
+
+    for (args, err) in [
+        (
+            &["DECRBY", "n", "-9223372036854775808"][..],
+            "ERR decrement would overflow",
+        ),
+        (
+            &["INCRBY", "n", "007"],
+            "ERR value is not an integer or out of range",
+        ),
+        (
+            &["INCRBY", "n", "+1"],
+            "ERR value is not an integer or out of range",
+        ),
+        (
+            &["INCRBYFLOAT", "n", "abc"],
+            "ERR value is not a valid float",
+        ),
+        (&["SETRANGE", "k", "-1", "x"], "ERR offset is out of range"),
+        (&["GETEX", "k", "PERSIST", "EX", "10"], "ERR syntax error"),
+        (
+            &["MSET", "a", "1", "b"],
+            "ERR wrong number of arguments for 'mset' command",
+        ),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
+
+#[test]
+fn parse_collection_commands() {
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["rpop", "l", "2"])).unwrap(),
+        Command::Pop("l".into(), Some(2), ListEnd::Right)
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["BLPOP", "a", "b", "1.5"])).unwrap(),
+        Command::BPop(vec!["a".into(), "b".into()], ListEnd::Left, 1500)
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["ZADD", "z", "xx", "CH", "1", "a", "-inf", "b"])).unwrap(),
+        Command::ZAdd(
+            "z".into(),
+            vec![(1.0, "a".into()), (f64::NEG_INFINITY, "b".into())],
+            ZAddFlags {
+                xx: true,
+                ch: true,
+                ..Default::default()
+            }
+        )
+    );
+    // with REV, BYSCORE takes max first
+    let args: Vec<&str> = "ZRANGE z +inf (1 BYSCORE REV LIMIT 0 2 WITHSCORES"
+        .split(' ')
+        .collect();
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&args)).unwrap(),
+        Command::ZRange(
+            "z".into(),
+            ZRangeSpec {
+                by: ZRangeBy::Score(
+                    ScoreBound::Exclusive(1.0),
+                    ScoreBound::Inclusive(f64::INFINITY)
+                ),
+                rev: true,
+                limit: Some((0, 2)),
+                with_scores: true,
+            }
+        )
+    );
+
+    for (args, err) in [
+        (
+            &["LPUSH", "l"][..],
+            "ERR wrong number of arguments for 'lpush' command",
+        ),
+        (
+            &["LPOP", "l", "-1"],
+            "ERR value is out of range, must be positive",
+        ),
+        (&["BLPOP", "l", "-1"], "ERR timeout is negative"),
+        (
+            &["BRPOP", "l", "soon"],
+            "ERR timeout is not a float or out of range",
+        ),
+        (&["BLMOVE", "a", "b", "LEFT", "UP", "0"], "ERR syntax error"),
+        (
+            &["HSET", "h", "f"],
+            "ERR wrong number of arguments for 'hset' command",
+        ),
+        (&["ZADD", "z", "1", "a", "2"], "ERR syntax error"),
+        (&["ZADD", "z", "x", "a"], "ERR value is not a valid float"),
+        (
+            &["ZADD", "z", "NX", "XX", "1", "a"],
+            "ERR XX and NX options at the same time are not compatible",
+        ),
+        (
+            &["ZADD", "z", "GT", "NX", "1", "a"],
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        ),
+        (
+            &["ZADD", "z", "INCR", "1", "a", "2", "b"],
+            "ERR INCR option supports a single increment-element pair",
+        ),
+        (
+            &["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"],
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        ),
+        (
+            &["ZRANGE", "z", "a", "1", "BYSCORE"],
+            "ERR min or max is not a float",
+        ),
+        (
+            &["ZRANGE", "z", "a", "b", "BYLEX"],
+            "ERR min or max not valid string range item",
+        ),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
+
+#[test]
+fn parse_stream_commands() {
+    let args: Vec<&str> = "XADD s NOMKSTREAM MAXLEN ~ 10 LIMIT 5 5-* f v"
+        .split(' ')
+        .collect();
+    let xadd = parse_cmd(&bulk_cmd(&args)).unwrap();
+    assert_eq!(
+        xadd,
+        Command::XAdd(
+            "s".into(),
+            XAddId::AutoSeq(5),
+            vec![("f".into(), "v".into())],
+            XAddOpts {
+                nomkstream: true,
+                trim: Some(StreamTrim {
+                    strategy: TrimStrategy::MaxLen(10),
+                    approx: true,
+                    limit: Some(5),
+                }),
+            }
+        )
+    );
+    // what replicas and the aof get parses back the same
+    assert_eq!(parse_cmd(&xadd.to_bulk_array()).unwrap(), xadd);
+
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["XADD", "s", "MINID", "3", "*", "f", "v"])).unwrap(),
+        Command::XAdd(
+            "s".into(),
+            XAddId::Auto,
+            vec![("f".into(), "v".into())],
+            XAddOpts {
+                nomkstream: false,
+                trim: Some(StreamTrim {
+                    strategy: TrimStrategy::MinId(StreamId::new(3, 0)),
+                    approx: false,
+                    limit: None,
+                }),
+            }
+        )
+    );
+    // a lone ms covers the whole milli, exclusive bounds are made inclusive
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["XRANGE", "s", "(1-5", "2", "COUNT", "3"])).unwrap(),
+        Command::XRange(
+            "s".into(),
+            StreamId::new(1, 6),
+            StreamId::new(2, u64::MAX),
+            Some(3),
+            false
+        )
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["XREVRANGE", "s", "+", "-"])).unwrap(),
+        Command::XRange("s".into(), StreamId::MIN, StreamId::MAX, None, true)
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&[
+            "XREAD", "COUNT", "2", "BLOCK", "0", "STREAMS", "a", "b", "1-1", "$"
+        ]))
+        .unwrap(),
+        Command::XRead(
+            vec![
+                ("a".into(), XReadFrom::After(StreamId::new(1, 1))),
+                ("b".into(), XReadFrom::Last),
+            ],
+            Some(2),
+            Some(0)
+        )
+    );
+
+    let invalid_id = "ERR Invalid stream ID specified as stream command argument";
+    for (args, err) in [
+        (
+            &["XADD", "s", "0-0", "f", "v"][..],
+            "ERR The ID specified in XADD must be greater than 0-0",
+        ),
+        (&["XADD", "s", "1-x", "f", "v"], invalid_id),
+        (&["XADD", "s", "-1", "f", "v"], invalid_id),
+        (
+            &["XADD", "s", "*", "f"],
+            "ERR wrong number of arguments for 'xadd' command",
+        ),
+        (
+            &["XADD", "s", "MAXLEN", "1", "LIMIT", "1", "*", "f", "v"],
+            "ERR syntax error, LIMIT cannot be used without the special ~ option",
+        ),
+        (
+            &["XTRIM", "s", "MAXLEN", "-1"],
+            "ERR The MAXLEN argument must be >= 0.",
+        ),
+        (&["XTRIM", "s", "LEN", "1"], "ERR syntax error"),
+        (&["XRANGE", "s", "(-", "+"], invalid_id),
+        (
+            &["XRANGE", "s", "-", "(0-0"],
+            "ERR invalid end ID for the interval",
+        ),
+        (
+            &["XREAD", "STREAMS", "a", "b", "0"],
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        ),
+        (&["XREAD", "COUNT", "1", "a", "0"], "ERR syntax error"),
+        (
+            &["XREAD", "BLOCK", "-1", "STREAMS", "a", "0"],
+            "ERR timeout is negative",
+        ),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
+
+#[test]
+fn parse_consumer_group_commands() {
+    let cmd = |line: &str| parse_cmd(&bulk_cmd(&line.split(' ').collect::<Vec<_>>()));
+
+    assert_eq!(
+        cmd("xgroup create s g $ MKSTREAM ENTRIESREAD -1").unwrap(),
+        Command::XGroupCreate("s".into(), "g".into(), XReadFrom::Last, true, None)
+    );
+    assert_eq!(
+        cmd("XREADGROUP GROUP g alice COUNT 2 NOACK STREAMS a b > 0").unwrap(),
+        Command::XReadGroup(
+            "g".into(),
+            "alice".into(),
+            vec![
+                ("a".into(), XReadGroupFrom::New),
+                ("b".into(), XReadGroupFrom::Pending(StreamId::MIN)),
+            ],
+            Some(2),
+            None,
+            true
+        )
+    );
+    assert_eq!(
+        cmd("XPENDING s g IDLE 100 - (5 10 bob").unwrap(),
+        Command::XPending(
+            "s".into(),
+            "g".into(),
+            Some(XPendingRange {
+                min_idle: 100,
+                start: StreamId::MIN,
+                end: StreamId::new(5, u64::MAX - 1),
+                count: 10,
+                consumer: Some("bob".into()),
+            })
+        )
+    );
+    // ids go on until the first option
+    assert_eq!(
+        cmd("XCLAIM s g bob 10 1 2-2 IDLE -5 RETRYCOUNT 3 JUSTID").unwrap(),
+        Command::XClaim(
+            "s".into(),
+            "g".into(),
+            "bob".into(),
+            10,
+            vec![StreamId::new(1, 0), StreamId::new(2, 2)],
+            XClaimOpts {
+                idle: Some(0),
+                retry_count: Some(3),
+                justid: true,
+                ..Default::default()
+            }
+        )
+    );
+
+    // what replicas and the aof get parses back the same
+    for line in [
+        "XGROUP SETID s g 5-1 ENTRIESREAD 3",
+        "XGROUP DELCONSUMER s g bob",
+        "XREADGROUP GROUP g c BLOCK 10 STREAMS s >",
+        "XACK s g 1-1 2-2",
+        "XCLAIM s g c 0 1-1 TIME 1700000000000 RETRYCOUNT 2 FORCE JUSTID LASTID 3-0",
+        "XAUTOCLAIM s g c 5 (1-1 COUNT 7 JUSTID",
+    ] {
+        let parsed = cmd(line).unwrap();
+        assert_eq!(parse_cmd(&parsed.to_bulk_array()).unwrap(), parsed);
+    }
+
+    for (line, err) in [
+        ("XGROUP FOO s g", "ERR unknown subcommand 'FOO'. Try XGROUP HELP."),
+        (
+            "XGROUP DESTROY s",
+            "ERR wrong number of arguments for 'xgroup|destroy' command",
+        ),
+        (
+            "XGROUP SETID s g 0 MKSTREAM",
+            "ERR unknown subcommand or wrong number of arguments for 'SETID'. Try XGROUP HELP.",
+        ),
+        (
+            "XGROUP CREATE s g 0 ENTRIESREAD -2",
+            "ERR value for ENTRIESREAD must be positive or -1",
+        ),
+        ("XREADGROUP STREAMS s >", "ERR Missing GROUP option for XREADGROUP"),
+        (
+            "XREAD GROUP g c STREAMS s 0",
+            "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+        ),
+        (
+            "XREAD STREAMS s >",
+            "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.",
+        ),
+        ("XACK s g x", "ERR Invalid stream ID specified as stream command argument"),
+        (
+            "XCLAIM s g c x 1",
+            "ERR Invalid min-idle-time argument for XCLAIM",
+        ),
+        ("XCLAIM s g c 0 1 BOGUS", "ERR Unrecognized XCLAIM option 'BOGUS'"),
+        (
+            "XCLAIM s g c 0 1 TIME soon",
+            "ERR Invalid TIME option argument for XCLAIM",
+        ),
+        ("XAUTOCLAIM s g c 0 0 COUNT 0", "ERR COUNT must be > 0"),
+        ("XPENDING s g - +", "ERR syntax error"),
+    ] {
+        assert_eq!(cmd(line).unwrap_err().to_string(), err, "{line}");
+    }
+}
+
+#[test]
+fn parse_pubsub_commands() {
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["psubscribe", "a*", "b?"])).unwrap(),
+        Command::Subscribe(SubKind::Pattern, vec!["a*".into(), "b?".into()])
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["UNSUBSCRIBE"])).unwrap(),
+        Command::Unsubscribe(SubKind::Channel, vec![])
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["PUBSUB", "numsub"])).unwrap(),
+        Command::PubSubNumSub(vec![])
+    );
+    // PUBLISH goes to replicas as is
+    let publish = Command::Publish("ch".into(), "hi there".into());
+    assert_eq!(parse_cmd(&publish.to_bulk_array()).unwrap(), publish);
+
+    for (args, err) in [
+        (
+            &["SUBSCRIBE"][..],
+            "ERR wrong number of arguments for 'subscribe' command",
+        ),
+        (
+            &["PUBLISH", "ch"],
+            "ERR wrong number of arguments for 'publish' command",
+        ),
+        (
+            &["PUBSUB", "CHANNELS", "a", "b"],
+            "ERR wrong number of arguments for 'pubsub|channels' command",
+        ),
+        (
+            &["PUBSUB", "shout"],
+            "ERR unknown subcommand 'shout'. Try PUBSUB HELP.",
+        ),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
+
+#[test]
+fn parse_transaction_commands() {
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["watch", "a", "b"])).unwrap(),
+        Command::Watch(vec!["a".into(), "b".into()])
+    );
+    // MULTI and EXEC go to replicas and the aof around the writes of a transaction
+    for cmd in [Command::Multi, Command::Exec] {
+        assert_eq!(parse_cmd(&cmd.to_bulk_array()).unwrap(), cmd);
+    }
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["MULTI", "now"]))
+            .unwrap_err()
+            .to_string(),
+        "ERR wrong number of arguments for 'multi' command"
+    );
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["WATCH"])).unwrap_err().to_string(),
+        "ERR wrong number of arguments for 'watch' command"
+    );
+}
+
+#[test]
+fn parse_hello() {
+    assert_eq!(
+        parse_cmd(&bulk_cmd(&["HELLO"])).unwrap(),
+        Command::Hello(None, None, None)
+    );
+    let cmd = parse_cmd(&bulk_cmd(&[
+        "hello", "3", "setname", "me", "AUTH", "default", "pw",
+    ]))
+    .unwrap();
+    assert_eq!(
+        cmd,
+        Command::Hello(
+            Some(Protocol::Resp3),
+            Some(("default".into(), "pw".into())),
+            Some("me".into())
+        )
+    );
+    assert_eq!(parse_cmd(&cmd.to_bulk_array()).unwrap(), cmd);
+
+    for (args, err) in [
+        (&["HELLO", "4"][..], "NOPROTO unsupported protocol version"),
+        (
+            &["HELLO", "three"],
+            "ERR Protocol version is not an integer or out of range",
+        ),
+        (
+            &["HELLO", "3", "AUTH", "default"],
+            "ERR Syntax error in HELLO option 'AUTH'",
+        ),
+        (
+            &["HELLO", "2", "LOUDLY"],
+            "ERR Syntax error in HELLO option 'LOUDLY'",
+        ),
+    ] {
+        assert_eq!(parse_cmd(&bulk_cmd(args)).unwrap_err().to_string(), err);
+    }
+}
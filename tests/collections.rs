@@ -0,0 +1,138 @@
+mod db_util;
+
+use redis_starter_rust::*;
+
+use clock::ManualClock;
+use db_util::{run, test_db, START};
+use resp::Value::{self, *};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn bulks(words: &str) -> Value {
+    Array(words.split(' ').map(bulk).collect())
+}
+
+fn wrong_type() -> Value {
+    SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+}
+
+#[tokio::test]
+async fn lists() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "RPUSH l b c").await, Int(2));
+    assert_eq!(run(&mut db, "LPUSH l a z").await, Int(4));
+    assert_eq!(run(&mut db, "LRANGE l 0 -1").await, bulks("z a b c"));
+    assert_eq!(run(&mut db, "LRANGE l -2 100").await, bulks("b c"));
+    assert_eq!(run(&mut db, "LRANGE l 3 1").await, Array(vec![]));
+
+    assert_eq!(run(&mut db, "LPOP l").await, bulk("z"));
+    assert_eq!(run(&mut db, "RPOP l 2").await, bulks("c b"));
+    assert_eq!(run(&mut db, "LLEN l").await, Int(1));
+    assert_eq!(run(&mut db, "LPOP l 5").await, bulks("a"));
+    // emptied lists are deleted
+    assert_eq!(run(&mut db, "EXISTS l").await, Int(0));
+    assert_eq!(run(&mut db, "LPOP l").await, NullBulkString);
+    assert_eq!(run(&mut db, "LPOP l 1").await, NullArray);
+}
+
+#[tokio::test]
+async fn hashes_and_sets() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "HSET h f 1 g 2").await, Int(2));
+    assert_eq!(run(&mut db, "HSET h f 3").await, Int(0));
+    assert_eq!(run(&mut db, "HGET h f").await, bulk("3"));
+    assert_eq!(run(&mut db, "HGET h nope").await, NullBulkString);
+    let mut all = match run(&mut db, "HGETALL h").await {
+        Map(all) => all,
+        other => panic!("HGETALL should reply with a map, got {other:?}"),
+    };
+    all.sort_by_key(|(field, _)| field.try_to_string().unwrap());
+    assert_eq!(all, vec![(bulk("f"), bulk("3")), (bulk("g"), bulk("2"))]);
+    assert_eq!(run(&mut db, "HDEL h f g nope").await, Int(2));
+    assert_eq!(run(&mut db, "TYPE h").await, SimpleString("none".into()));
+
+    assert_eq!(run(&mut db, "SADD s a b a").await, Int(2));
+    assert_eq!(run(&mut db, "SISMEMBER s a").await, Int(1));
+    assert_eq!(run(&mut db, "SISMEMBER s c").await, Int(0));
+    assert_eq!(run(&mut db, "SREM s a c").await, Int(1));
+    assert_eq!(run(&mut db, "SMEMBERS s").await, Set(vec![bulk("b")]));
+    assert_eq!(run(&mut db, "TYPE s").await, SimpleString("set".into()));
+}
+
+#[tokio::test]
+async fn sorted_sets() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "ZADD z 1 a 2 b 3 c -inf m").await, Int(4));
+    assert_eq!(run(&mut db, "ZRANGE z 0 -1").await, bulks("m a b c"));
+    assert_eq!(
+        run(&mut db, "ZRANGE z 0 1 REV WITHSCORES").await,
+        bulks("c 3 b 2")
+    );
+    assert_eq!(run(&mut db, "ZRANGE z (1 +inf BYSCORE").await, bulks("b c"));
+    assert_eq!(
+        run(&mut db, "ZRANGE z +inf -inf BYSCORE REV LIMIT 1 2").await,
+        bulks("b a")
+    );
+    assert_eq!(run(&mut db, "ZSCORE z m").await, Double(f64::NEG_INFINITY));
+
+    // NX / XX / GT / LT, CH and INCR
+    assert_eq!(run(&mut db, "ZADD z NX 10 a 4 d").await, Int(1));
+    assert_eq!(run(&mut db, "ZADD z XX CH 10 a 10 e").await, Int(1));
+    assert_eq!(run(&mut db, "ZADD z GT CH 5 a 11 b").await, Int(1));
+    assert_eq!(run(&mut db, "ZADD z INCR 0.5 a").await, Double(10.5));
+    assert_eq!(run(&mut db, "ZADD z LT INCR 1 a").await, NullBulkString);
+    assert_eq!(
+        run(&mut db, "ZRANGE z 0 -1 WITHSCORES").await,
+        bulks("m -inf c 3 d 4 a 10.5 b 11")
+    );
+    assert_eq!(
+        run(&mut db, "ZADD z INCR +inf m").await,
+        SimpleError("ERR resulting score is not a number (NaN)".into())
+    );
+    // XX doesn't create the key
+    assert_eq!(run(&mut db, "ZADD y XX 1 a").await, Int(0));
+    assert_eq!(run(&mut db, "EXISTS y").await, Int(0));
+
+    assert_eq!(run(&mut db, "ZREM z a b nope").await, Int(2));
+    assert_eq!(run(&mut db, "ZRANGE z [c [d BYLEX").await, bulks("c d"));
+
+    // scores are printed like redis does, not with every digit
+    run(&mut db, "ZADD big 1e300 a 0.1 b").await;
+    assert_eq!(
+        run(&mut db, "ZRANGE big 0 -1 WITHSCORES").await,
+        bulks("b 0.1 a 1e+300")
+    );
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "SET str v").await;
+    run(&mut db, "RPUSH list a").await;
+    for cmd in [
+        "LPUSH str a",
+        "HGET str f",
+        "SADD str a",
+        "ZSCORE str a",
+        "GET list",
+        "APPEND list x",
+        "INCR list",
+        "SET list x GET",
+    ] {
+        assert_eq!(run(&mut db, cmd).await, wrong_type(), "{cmd}");
+    }
+    // MGET reads other types as missing, SET replaces them
+    assert_eq!(
+        run(&mut db, "MGET str list").await,
+        Array(vec![bulk("v"), NullBulkString])
+    );
+    assert_eq!(run(&mut db, "TYPE list").await, SimpleString("list".into()));
+    assert_eq!(run(&mut db, "SET list x").await, SimpleString("OK".into()));
+    assert_eq!(run(&mut db, "GET list").await, bulk("x"));
+}
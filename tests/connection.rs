@@ -0,0 +1,290 @@
+// Whole client connections served over in memory pipes instead of sockets
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use async_deser::RespDeserializer;
+use clock::SystemClock;
+use config::InstanceConfig;
+use db::Db;
+use deser::ProtoLimits;
+use resp::Value::{self, *};
+use svc::{handle_stream_async, ToDb};
+use tokio::io::{duplex, AsyncWriteExt, BufStream, DuplexStream};
+use tokio::sync::mpsc::{channel, Sender};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn start_db() -> Sender<ToDb> {
+    let (tx, rx) = channel(100);
+    let db = Db::with_clock(InstanceConfig::default(), tx.clone(), Arc::new(SystemClock));
+    tokio::spawn(db.run(rx));
+    tx
+}
+
+type Client = RespDeserializer<BufStream<DuplexStream>>;
+
+/// The client's end of a new connection to the Db behind `tx`
+fn connect(tx: &Sender<ToDb>, addr: &str) -> Client {
+    connect_with_limits(tx, addr, ProtoLimits::default())
+}
+
+fn connect_with_limits(tx: &Sender<ToDb>, addr: &str, limits: ProtoLimits) -> Client {
+    let (client, server) = duplex(1024);
+    tokio::spawn(handle_stream_async(
+        RespDeserializer::with_limits(BufStream::new(server), limits),
+        addr.to_string(),
+        tx.clone(),
+        false,
+    ));
+    RespDeserializer::new(BufStream::new(client))
+}
+
+async fn send(client: &mut Client, data: &str) {
+    client.get_mut().write_all(data.as_bytes()).await.unwrap();
+    client.get_mut().flush().await.unwrap();
+}
+
+async fn recv(client: &mut Client) -> Value {
+    client.deserialize().await.unwrap().0
+}
+
+#[tokio::test]
+async fn pipelined_commands_are_answered_in_order() {
+    let tx = start_db();
+    let mut client = connect(&tx, "alice:1");
+
+    send(
+        &mut client,
+        "*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$7\r\na\r\nb c \r\nGET k\r\nHSET h f 1\nHGETALL h\r\n",
+    )
+    .await;
+    assert_eq!(recv(&mut client).await, SimpleString("OK".into()));
+    assert_eq!(recv(&mut client).await, bulk("a\r\nb c "));
+    assert_eq!(recv(&mut client).await, Int(1));
+    assert_eq!(recv(&mut client).await, Array(vec![bulk("f"), bulk("1")]));
+
+    // from its own reply on, HELLO 3 switches the connection to RESP3
+    send(&mut client, "HELLO 3\r\nHGETALL h\r\nGET nope\r\n").await;
+    assert!(matches!(recv(&mut client).await, Map(_)));
+    assert_eq!(recv(&mut client).await, Map(vec![(bulk("f"), bulk("1"))]));
+    assert_eq!(recv(&mut client).await, Null);
+}
+
+#[tokio::test]
+async fn half_closed_clients_still_get_replies() {
+    let tx = start_db();
+
+    // like `printf 'PING\r\n' | nc -N`
+    for _ in 0..20 {
+        let mut client = connect(&tx, "alice:1");
+        send(&mut client, "SET k v\r\nPING\r\n").await;
+        client.get_mut().shutdown().await.unwrap();
+        assert_eq!(recv(&mut client).await, SimpleString("OK".into()));
+        assert_eq!(recv(&mut client).await, SimpleString("PONG".into()));
+        assert!(client.deserialize().await.is_err());
+    }
+
+    // a blocked client that closes gives up waiting, and takes nothing
+    let mut blocked = connect(&tx, "bob:1");
+    send(&mut blocked, "BLPOP l 0\r\n").await;
+    blocked.get_mut().shutdown().await.unwrap();
+    assert!(blocked.deserialize().await.is_err());
+
+    let mut client = connect(&tx, "carol:1");
+    send(&mut client, "RPUSH l x\r\nLLEN l\r\nGET k\r\n").await;
+    assert_eq!(recv(&mut client).await, Int(1));
+    assert_eq!(recv(&mut client).await, Int(1));
+    assert_eq!(recv(&mut client).await, bulk("v"));
+}
+
+#[tokio::test]
+async fn peer_addresses_are_only_labels() {
+    let tx = start_db();
+    // a unix socket has no port, an ipv6 address has colons of its own
+    for addr in ["/tmp/redis.sock", "", "[::1]:6379"] {
+        let mut client = connect(&tx, addr);
+        send(&mut client, "SET k v\r\nGET k\r\n").await;
+        assert_eq!(recv(&mut client).await, SimpleString("OK".into()));
+        assert_eq!(recv(&mut client).await, bulk("v"));
+    }
+}
+
+#[tokio::test]
+async fn transactions_are_queued_by_the_connection() {
+    let tx = start_db();
+    let mut client = connect(&tx, "alice:1");
+    let ok = || SimpleString("OK".into());
+
+    send(&mut client, "EXEC\r\nDISCARD\r\n").await;
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR EXEC without MULTI".into())
+    );
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR DISCARD without MULTI".into())
+    );
+
+    // nothing runs before EXEC, and a nested MULTI is refused without ending the transaction
+    let mut other = connect(&tx, "bob:2");
+    send(&mut client, "MULTI\r\nSET k 1\r\nMULTI\r\nINCR k\r\n").await;
+    assert_eq!(recv(&mut client).await, ok());
+    assert_eq!(recv(&mut client).await, SimpleString("QUEUED".into()));
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR MULTI calls can not be nested".into())
+    );
+    assert_eq!(recv(&mut client).await, SimpleString("QUEUED".into()));
+    send(&mut other, "GET k\r\n").await;
+    assert_eq!(recv(&mut other).await, NullBulkString);
+    send(&mut client, "EXEC\r\n").await;
+    assert_eq!(recv(&mut client).await, Array(vec![ok(), Int(2)]));
+
+    // a command that can't be queued aborts the whole transaction
+    send(
+        &mut client,
+        "MULTI\r\nSET k 5\r\nNOSUCHCMD\r\nGET\r\nEXEC\r\n",
+    )
+    .await;
+    assert_eq!(recv(&mut client).await, ok());
+    assert_eq!(recv(&mut client).await, SimpleString("QUEUED".into()));
+    assert!(matches!(recv(&mut client).await, SimpleError(_)));
+    assert!(matches!(recv(&mut client).await, SimpleError(_)));
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("EXECABORT Transaction discarded because of previous errors.".into())
+    );
+    send(&mut client, "GET k\r\nEXEC\r\n").await;
+    assert_eq!(recv(&mut client).await, bulk("2"));
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR EXEC without MULTI".into())
+    );
+
+    // a key another connection changes after WATCH aborts EXEC
+    send(&mut client, "WATCH k\r\nMULTI\r\nINCR k\r\n").await;
+    assert_eq!(recv(&mut client).await, ok());
+    assert_eq!(recv(&mut client).await, ok());
+    assert_eq!(recv(&mut client).await, SimpleString("QUEUED".into()));
+    send(&mut other, "SET k 10\r\n").await;
+    assert_eq!(recv(&mut other).await, ok());
+    send(&mut client, "EXEC\r\nGET k\r\n").await;
+    assert_eq!(recv(&mut client).await, NullArray);
+    assert_eq!(recv(&mut client).await, bulk("10"));
+}
+
+#[tokio::test]
+async fn protocol_errors_close_the_connection() {
+    let tx = start_db();
+    let mut client = connect(&tx, "alice:1");
+
+    send(&mut client, "*1\r\n$x\r\nPING\r\n").await;
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR Protocol error: invalid bulk length".into())
+    );
+    assert!(client.deserialize().await.is_err());
+}
+
+#[tokio::test]
+async fn oversized_requests_close_the_connection() {
+    let tx = start_db();
+    let limits = ProtoLimits {
+        max_bulk_len: 16,
+        max_multibulk_len: 4,
+        max_nesting: 1,
+        max_query_buf: 32,
+    };
+    let cases = [
+        // refused from the header alone, before any of the data
+        (
+            "*2\r\n$3\r\nSET\r\n$1000000000000\r\n",
+            "invalid bulk length",
+        ),
+        ("*5\r\n", "invalid multibulk length"),
+        ("*1\r\n*1\r\n$4\r\nPING\r\n", "too deeply nested aggregates"),
+        (
+            "*4\r\n$3\r\nSET\r\n$1\r\nk\r\n$16\r\n0123456789abcdef\r\n$16\r\n",
+            "client query buffer limit exceeded",
+        ),
+    ];
+    for (sent, err) in cases {
+        let mut client = connect_with_limits(&tx, "alice:1", limits);
+        // whatever came before the bad value is answered
+        send(&mut client, "PING\r\n").await;
+        send(&mut client, sent).await;
+        assert_eq!(recv(&mut client).await, SimpleString("PONG".into()));
+        assert_eq!(
+            recv(&mut client).await,
+            SimpleError(format!("ERR Protocol error: {err}")),
+            "{sent:?}"
+        );
+        assert!(client.deserialize().await.is_err());
+    }
+
+    // within the limits all is well
+    let mut client = connect_with_limits(&tx, "alice:1", limits);
+    send(&mut client, "SET k 0123456789abcdef\r\nGET k\r\n").await;
+    assert_eq!(recv(&mut client).await, SimpleString("OK".into()));
+    assert_eq!(recv(&mut client).await, bulk("0123456789abcdef"));
+}
+
+#[tokio::test]
+async fn values_under_the_limit_can_take_many_reads() {
+    let tx = start_db();
+    let limits = ProtoLimits {
+        max_query_buf: 4096,
+        ..ProtoLimits::default()
+    };
+    // a 4 digit length, as in the header
+    let value = "v".repeat(4067);
+    let header = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n${}\r\n", value.len());
+    let sent = format!("{header}{value}\r\n");
+    assert_eq!(sent.len(), 4096);
+
+    let mut client = connect_with_limits(&tx, "alice:1", limits);
+    for piece in sent.as_bytes().chunks(100) {
+        client.get_mut().write_all(piece).await.unwrap();
+        client.get_mut().flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(recv(&mut client).await, SimpleString("OK".into()));
+    send(&mut client, "STRLEN k\r\n").await;
+    assert_eq!(recv(&mut client).await, Int(4067));
+
+    // one more byte is too many
+    let mut client = connect_with_limits(&tx, "alice:1", limits);
+    let over = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4069\r\n{value}vvv");
+    assert_eq!(over.len(), 4097);
+    for piece in over.as_bytes().chunks(100) {
+        client.get_mut().write_all(piece).await.unwrap();
+        client.get_mut().flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(
+        recv(&mut client).await,
+        SimpleError("ERR Protocol error: client query buffer limit exceeded".into())
+    );
+}
+
+#[tokio::test]
+async fn messages_are_pushed_to_subscribers() {
+    let tx = start_db();
+    let mut alice = connect(&tx, "alice:1");
+    let mut bob = connect(&tx, "bob:1");
+
+    send(&mut alice, "SUBSCRIBE news\r\n").await;
+    assert_eq!(
+        recv(&mut alice).await,
+        Array(vec![bulk("subscribe"), bulk("news"), Int(1)])
+    );
+    send(&mut bob, "PUBLISH news hi\r\n").await;
+    assert_eq!(recv(&mut bob).await, Int(1));
+    assert_eq!(
+        recv(&mut alice).await,
+        Array(vec![bulk("message"), bulk("news"), bulk("hi")])
+    );
+}
@@ -0,0 +1,48 @@
+// Helpers to run commands directly against a Db, without any networking
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use clock::ManualClock;
+use commands::parse_cmd;
+use config::InstanceConfig;
+use db::Db;
+use resp::{
+    QueryResult,
+    Value::{self, *},
+};
+use svc::Query;
+use tokio::sync::mpsc::{channel, Receiver};
+
+#[allow(dead_code)]
+pub const START: u64 = 1_700_000_000_000;
+
+#[allow(dead_code)]
+pub fn test_db(clock: &ManualClock) -> Db {
+    let (tx, _rx) = channel(100);
+    Db::with_clock(InstanceConfig::default(), tx, Arc::new(clock.clone()))
+}
+
+/// A query for a command given as space separated words
+pub fn query(cmd: &str) -> Query {
+    let val = Array(cmd.split(' ').map(|w| BulkString(w.into())).collect());
+    Query::new(parse_cmd(&val).unwrap(), 0, "test:0".to_string())
+}
+
+/// Run a command given as space separated words, return its single reply
+#[allow(dead_code)]
+pub async fn run(db: &mut Db, cmd: &str) -> Value {
+    let (sx, _rx) = channel(1);
+    let mut vals = db.execute(&query(cmd), sx).await.vals;
+    assert_eq!(vals.len(), 1, "replies to {cmd}: {vals:?}");
+    vals.remove(0)
+}
+
+/// Run a command that blocks, return where its reply will arrive
+#[allow(dead_code)]
+pub async fn start(db: &mut Db, cmd: &str) -> Receiver<QueryResult> {
+    let (sx, rx) = channel(1);
+    let vals = db.execute(&query(cmd), sx).await.vals;
+    assert!(vals.is_empty(), "{cmd} should block, got: {vals:?}");
+    rx
+}
@@ -0,0 +1,275 @@
+use bytes::BytesMut;
+
+use redis_starter_rust::*;
+
+use deser::{ParseError, ProtoLimits, RespParser};
+use resp::{
+    serialize_many, Protocol,
+    Value::{self, *},
+};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn parse(input: &str) -> Result<(Value, usize), ParseError> {
+    RespParser::new().parse(&mut BytesMut::from(input))
+}
+
+fn invalid(msg: &str) -> Result<(Value, usize), ParseError> {
+    Err(ParseError::Invalid(msg.to_string()))
+}
+
+#[test]
+fn values_arrive_in_pieces() {
+    let vals = vec![
+        Array(vec![bulk("SET"), bulk("k"), bulk("a\r\nb")]),
+        NullBulkString,
+        NullArray,
+        Int(-12),
+        Map(vec![(bulk("k"), Set(vec![Double(1.5), Boolean(true)]))]),
+        Attribute(vec![(bulk("ttl"), Int(3))], Box::new(Null)),
+        Array(vec![Array(vec![]), Push(vec![SimpleString("OK".into())])]),
+    ];
+    let input = serialize_many(&vals, Protocol::Resp3).unwrap();
+    // RESP3 serializes nulls alike
+    let vals: Vec<Value> = vals
+        .into_iter()
+        .map(|val| match val {
+            NullBulkString | NullArray => Null,
+            val => val,
+        })
+        .collect();
+
+    // one byte at a time
+    let mut parser = RespParser::new();
+    let mut buf = BytesMut::new();
+    let mut parsed = Vec::new();
+    let mut n_parsed = 0;
+    for byte in input.as_bytes() {
+        buf.extend_from_slice(&[*byte]);
+        match parser.parse(&mut buf) {
+            Ok((val, len)) => {
+                parsed.push(val);
+                n_parsed += len;
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(err) => panic!("{err}"),
+        }
+    }
+    assert_eq!(parsed, vals);
+    assert_eq!(n_parsed, input.len());
+    assert!(buf.is_empty());
+
+    // all at once
+    let mut buf = BytesMut::from(input.as_bytes());
+    for val in vals {
+        assert_eq!(parser.parse(&mut buf).unwrap().0, val);
+    }
+    assert_eq!(parser.parse(&mut buf), Err(ParseError::Incomplete));
+}
+
+#[test]
+fn big_strings_are_slices_of_the_buffer() {
+    let big = "x".repeat(32 * 1024);
+    let input = format!(
+        "*2\r\n$4\r\nkey1\r\n${}\r\n{big}\r\n*1\r\n$4\r\nPI",
+        big.len()
+    );
+    let mut buf = BytesMut::from(input.as_str());
+    let start = buf.as_ptr() as usize;
+    let (val, len) = RespParser::new().parse(&mut buf).unwrap();
+    assert_eq!(len, input.len() - 10);
+    let Array(parts) = val else {
+        panic!("expected an array, got {val:?}");
+    };
+    let ptrs: Vec<usize> = parts
+        .iter()
+        .map(|part| match part {
+            BulkString(bs) => bs.as_bytes().as_ptr() as usize,
+            _ => panic!("expected a bulk string, got {part:?}"),
+        })
+        .collect();
+    // small strings are copied out, so that keeping them doesn't keep the buffer
+    assert!(ptrs[0] < start || ptrs[0] >= start + input.len());
+    assert_eq!(ptrs[1], start + 22);
+    // and clones share the bytes too
+    let BulkString(bs) = parts[1].clone() else {
+        unreachable!()
+    };
+    assert_eq!(bs.as_bytes().as_ptr() as usize, start + 22);
+    // the start of the next command is left for later
+    assert_eq!(&buf[..], b"*1\r\n$4\r\nPI");
+}
+
+#[test]
+fn inline_commands_parse_too() {
+    let mut parser = RespParser::new();
+    let mut buf = BytesMut::from("\r\nGET 'a b'\r\n*1\r\n$4\r\nPING\r\nECHO x");
+    assert_eq!(
+        parser.parse(&mut buf),
+        // the empty line counts with the command after it
+        Ok((Array(vec![bulk("GET"), bulk("a b")]), 13))
+    );
+    assert_eq!(parser.parse(&mut buf), Ok((Array(vec![bulk("PING")]), 14)));
+    assert_eq!(parser.parse(&mut buf), Err(ParseError::Incomplete));
+    buf.extend_from_slice(b"\n");
+    assert_eq!(
+        parser.parse(&mut buf),
+        Ok((Array(vec![bulk("ECHO"), bulk("x")]), 7))
+    );
+}
+
+#[test]
+fn malformed_values_are_errors() {
+    assert_eq!(parse("*x\r\n"), invalid("invalid multibulk length"));
+    assert_eq!(parse("*-2\r\n"), invalid("invalid multibulk length"));
+    assert_eq!(parse("$-2\r\n"), invalid("invalid bulk length"));
+    assert_eq!(
+        parse("$3\r\nabcd\r\n"),
+        invalid("expected CRLF after bulk data")
+    );
+    assert_eq!(
+        parse("*2\r\n$3\r\nGET\r\n?\r\n"),
+        invalid("expected '$', got '?'")
+    );
+    assert_eq!(parse(":1.5\r\n"), invalid("invalid integer"));
+    assert_eq!(parse("#x\r\n"), invalid("invalid '#' line"));
+    assert_eq!(
+        parse("GET \"k\r\n"),
+        invalid("unbalanced quotes in request")
+    );
+    // nothing wrong yet
+    assert_eq!(parse("*2\r\n$3\r\nGET\r\n"), Err(ParseError::Incomplete));
+}
+
+#[test]
+fn lengths_are_checked_against_the_limits() {
+    let limits = ProtoLimits {
+        max_bulk_len: 3,
+        max_multibulk_len: 2,
+        max_nesting: 2,
+        max_query_buf: 32,
+    };
+    let parse = |input: &str| RespParser::with_limits(limits).parse(&mut BytesMut::from(input));
+
+    assert_eq!(parse("$3\r\nabc\r\n"), Ok((bulk("abc"), 9)));
+    assert_eq!(parse("$4\r\n"), invalid("invalid bulk length"));
+    assert_eq!(
+        parse("!99999999999999999999\r\n"),
+        invalid("invalid bulk length")
+    );
+    assert_eq!(parse("*3\r\n"), invalid("invalid multibulk length"));
+    // a map of 2 has 4 elements, still fine
+    assert_eq!(parse("%2\r\n:1\r\n"), Err(ParseError::Incomplete));
+    assert_eq!(
+        parse("*1\r\n*1\r\n*1\r\n"),
+        invalid("too deeply nested aggregates")
+    );
+    // empty aggregates don't nest anything
+    assert_eq!(
+        parse("*1\r\n*1\r\n*0\r\n"),
+        Ok((Array(vec![Array(vec![Array(vec![])])]), 12))
+    );
+    assert_eq!(
+        parse("%2\r\n$3\r\nabc\r\n$3\r\nabc\r\n$3\r\nabc\r\n$3\r\nab"),
+        invalid("client query buffer limit exceeded")
+    );
+
+    // without a CRLF, lines can't grow forever either
+    let long = |head: &str| format!("{head}{}", "1".repeat(64 * 1024));
+    for (head, err) in [
+        ("*", "too big mbulk count string"),
+        ("$", "too big bulk count string"),
+        (":", "too big line"),
+        ("GET ", "too big inline request"),
+    ] {
+        assert_eq!(
+            RespParser::new().parse(&mut BytesMut::from(long(head).as_str())),
+            invalid(err)
+        );
+    }
+}
+
+/// A small xorshift generator, so that the inputs are the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, below: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % below as u64) as usize
+    }
+}
+
+/// Everything `parse` makes of `pieces` appended one after another, as text to compare
+fn parse_pieces(limits: ProtoLimits, pieces: &[&[u8]]) -> Vec<String> {
+    let mut parser = RespParser::with_limits(limits);
+    let mut buf = BytesMut::new();
+    let mut parsed = Vec::new();
+    let mut n_parsed = 0;
+    for piece in pieces {
+        buf.extend_from_slice(piece);
+        loop {
+            match parser.parse(&mut buf) {
+                Ok((val, len)) => {
+                    n_parsed += len;
+                    parsed.push(format!("{val:?}"));
+                }
+                Err(ParseError::Incomplete) => break,
+                Err(err) => {
+                    parsed.push(err.to_string());
+                    return parsed;
+                }
+            }
+        }
+    }
+    // empty lines before an incomplete value were dropped, but not counted yet
+    let total: usize = pieces.iter().map(|piece| piece.len()).sum();
+    assert!(n_parsed + buf.len() <= total, "bytes were counted twice");
+    parsed
+}
+
+#[test]
+fn mangled_input_never_panics() {
+    let limits = ProtoLimits {
+        max_bulk_len: 64,
+        max_multibulk_len: 16,
+        max_nesting: 4,
+        max_query_buf: 1024,
+    };
+    let vals = vec![
+        Array(vec![bulk("SET"), bulk("k"), bulk("v\r\n")]),
+        Map(vec![(bulk("k"), Set(vec![Double(-1.5), Boolean(false)]))]),
+        Attribute(vec![(Int(1), Null)], Box::new(BigNumber("-123".into()))),
+        Verbatim("txt".into(), "hi".into()),
+        NullBulkString,
+    ];
+    let valid = serialize_many(&vals, Protocol::Resp3).unwrap();
+    let valid = format!("{}PING 'a b'\r\n", valid.to_string().unwrap()).into_bytes();
+    let alphabet = b"*$%~>|=!+-:_#,(\r\n0123456789-tf' \"\\x";
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..5_000 {
+        let mut input = valid.clone();
+        for _ in 0..1 + rng.next(4) {
+            let at = rng.next(input.len());
+            match rng.next(3) {
+                0 => input[at] = alphabet[rng.next(alphabet.len())],
+                1 => input.insert(at, alphabet[rng.next(alphabet.len())]),
+                _ => {
+                    input.remove(at);
+                }
+            }
+        }
+        // however the bytes arrive, the parser makes the same of them
+        let split = rng.next(input.len() + 1);
+        assert_eq!(
+            parse_pieces(limits, &[&input]),
+            parse_pieces(limits, &[&input[..split], &input[split..]]),
+            "{:?}",
+            String::from_utf8_lossy(&input)
+        );
+    }
+}
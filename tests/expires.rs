@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+
+use redis_starter_rust::*;
+
+use common::Bytes;
+use expires::VolatileKeys;
+
+#[test]
+fn volatile_keys_insert_remove() {
+    let mut vk = VolatileKeys::new(42);
+    let keys: Vec<Bytes> = ["a", "b", "c", "d"].into_iter().map(Bytes::from).collect();
+    for key in &keys {
+        vk.insert(key);
+    }
+    // inserting again does not duplicate
+    vk.insert(&keys[0]);
+    vk.remove(&keys[1]);
+    vk.remove(&"not-there".into());
+
+    let mut sample = vk.sample(10);
+    sample.sort_by(|k1, k2| k1.as_bytes().cmp(k2.as_bytes()));
+    assert_eq!(
+        sample,
+        vec![keys[0].clone(), keys[2].clone(), keys[3].clone()]
+    );
+
+    vk.clear();
+    assert!(vk.sample(10).is_empty());
+}
+
+#[test]
+fn volatile_keys_sample_is_spread() {
+    let mut vk = VolatileKeys::new(7);
+    for i in 0..1000 {
+        vk.insert(&Bytes::from(format!("key:{i}").as_str()));
+    }
+    for i in 0..500 {
+        vk.remove(&Bytes::from(format!("key:{i}").as_str()));
+    }
+
+    let mut seen = HashSet::new();
+    for _ in 0..50 {
+        let sample = vk.sample(20);
+        assert_eq!(sample.len(), 20);
+        for key in sample {
+            let i: usize = key.to_string().unwrap()[4..].parse().unwrap();
+            assert!(i >= 500, "removed key sampled: {i}");
+            seen.insert(i);
+        }
+    }
+    // 1000 random picks among 500 keys should hit most of them
+    assert!(
+        seen.len() > 300,
+        "only {n} distinct keys sampled",
+        n = seen.len()
+    );
+}
@@ -0,0 +1,143 @@
+mod db_util;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use aof::read_aof_file;
+use clock::ManualClock;
+use commands::Command;
+use config::InstanceConfig;
+use db::Db;
+use db_util::{query, run, test_db, START};
+use resp::Value::{self, *};
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn keys_expire_when_the_clock_moves() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    assert_eq!(run(&mut db, "SET k v PX 1000").await, Value::ok());
+    clock.advance(999);
+    assert_eq!(run(&mut db, "GET k").await, BulkString("v".into()));
+    assert_eq!(run(&mut db, "PTTL k").await, Int(1));
+    clock.advance(1);
+    assert_eq!(run(&mut db, "GET k").await, NullBulkString);
+    assert_eq!(run(&mut db, "TTL k").await, Int(-2));
+    assert_eq!(run(&mut db, "EXISTS k").await, Int(0));
+}
+
+#[tokio::test]
+async fn ttl_commands_follow_the_clock() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    run(&mut db, "SET k v").await;
+    assert_eq!(run(&mut db, "TTL k").await, Int(-1));
+    assert_eq!(run(&mut db, "EXPIRE k 10").await, Int(1));
+    assert_eq!(
+        run(&mut db, "PEXPIRETIME k").await,
+        Int(START as i64 + 10_000)
+    );
+
+    clock.advance(4_400);
+    assert_eq!(run(&mut db, "TTL k").await, Int(6));
+    // KEEPTTL keeps the absolute deadline
+    run(&mut db, "SET k v2 KEEPTTL").await;
+    assert_eq!(run(&mut db, "PTTL k").await, Int(5_600));
+
+    clock.set(START + 10_000);
+    assert_eq!(run(&mut db, "GET k").await, NullBulkString);
+
+    run(&mut db, "SET k v").await;
+    assert_eq!(
+        run(&mut db, &format!("PEXPIREAT k {t}", t = START + 9_000)).await,
+        Int(1)
+    );
+    // already in the past: deleted right away
+    assert_eq!(run(&mut db, "EXISTS k").await, Int(0));
+}
+
+/// A Db logging to an aof in a directory of its own, with a replica attached
+async fn logging_db(clock: &ManualClock, name: &str) -> (Db, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("{name}-{pid}", pid = std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cfg = InstanceConfig {
+        dir: dir.to_str().unwrap().to_string(),
+        appendonly: true,
+        ..InstanceConfig::default()
+    };
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg, tx, Arc::new(clock.clone()));
+    db.load_aof().await.unwrap();
+    let (sx, _rx) = channel(1);
+    db.execute(&query("PSYNC ? -1"), sx).await;
+    (db, dir)
+}
+
+async fn master_repl_offset(db: &mut Db) -> usize {
+    let info = run(db, "INFO replication").await.try_to_string().unwrap();
+    info.split("\r\n")
+        .find_map(|line| line.strip_prefix("master_repl_offset:"))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Bytes of DEL `key` in the replication stream
+fn del_len(key: &str) -> usize {
+    format!("*2\r\n$3\r\nDEL\r\n${n}\r\n{key}\r\n", n = key.len()).len()
+}
+
+#[tokio::test]
+async fn expired_keys_read_are_deleted_everywhere() {
+    let clock = ManualClock::new(START);
+    let (mut db, dir) = logging_db(&clock, "expiry-lazy").await;
+
+    run(&mut db, "SET k v PX 100").await;
+    run(&mut db, "SET other v").await;
+    let offset = master_repl_offset(&mut db).await;
+    clock.advance(100);
+    assert_eq!(run(&mut db, "GET k").await, NullBulkString);
+    // the aof and replicas don't have a clock of their own, they are told
+    assert_eq!(master_repl_offset(&mut db).await, offset + del_len("k"));
+    drop(db);
+    let logged = read_aof_file(&dir.join("appendonly.aof")).unwrap();
+    assert_eq!(logged.last(), Some(&query("DEL k").cmd));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn the_active_cycle_deletes_keys_nobody_reads() {
+    let clock = ManualClock::new(START);
+    let (mut db, dir) = logging_db(&clock, "expiry-active").await;
+
+    for i in 0..100 {
+        run(&mut db, &format!("SET k{i} v PX {px}", px = 100 + i % 2)).await;
+    }
+    run(&mut db, "SET persistent v").await;
+    run(&mut db, "SET later v PX 1000").await;
+    let offset = master_repl_offset(&mut db).await;
+
+    // nothing expired yet
+    db.active_expire_cycle();
+    assert_eq!(master_repl_offset(&mut db).await, offset);
+
+    clock.advance(101);
+    db.active_expire_cycle();
+    let deleted: usize = (0..100).map(|i| del_len(&format!("k{i}"))).sum();
+    assert_eq!(master_repl_offset(&mut db).await, offset + deleted);
+    drop(db);
+    let logged = read_aof_file(&dir.join("appendonly.aof")).unwrap();
+    let dels = logged
+        .iter()
+        .filter(|cmd| matches!(cmd, Command::Del(_)))
+        .count();
+    assert_eq!(dels, 100);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
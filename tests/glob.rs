@@ -0,0 +1,36 @@
+use redis_starter_rust::*;
+
+use glob::glob_match;
+
+fn m(pattern: &str, s: &str) -> bool {
+    glob_match(pattern.as_bytes(), s.as_bytes())
+}
+
+#[test]
+fn glob_wildcards() {
+    assert!(m("*", ""));
+    assert!(m("*", "anything"));
+    assert!(m("h?llo", "hello"));
+    assert!(!m("h?llo", "hllo"));
+    assert!(m("h*llo", "hllo"));
+    assert!(m("h*llo", "heeeello"));
+    assert!(m("*llo*", "hello world"));
+    assert!(!m("h*llo", "hello!"));
+    assert!(m("a*b*c", "aXbYbZc"));
+    assert!(!m("a*b*c", "aXbYbZ"));
+}
+
+#[test]
+fn glob_classes_and_escapes() {
+    assert!(m("h[ae]llo", "hello"));
+    assert!(m("h[ae]llo", "hallo"));
+    assert!(!m("h[ae]llo", "hillo"));
+    assert!(m("h[^e]llo", "hallo"));
+    assert!(!m("h[^e]llo", "hello"));
+    assert!(m("h[a-b]llo", "hbllo"));
+    assert!(m("h[b-a]llo", "hallo"));
+    assert!(!m("h[a-b]llo", "hcllo"));
+    assert!(m("user:\\*", "user:*"));
+    assert!(!m("user:\\*", "user:1"));
+    assert!(m("[\\]]", "]"));
+}
@@ -0,0 +1,64 @@
+use redis_starter_rust::*;
+
+use async_deser::RespDeserializer;
+use resp::{split_inline, Value::*};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+fn words(line: &str) -> Vec<String> {
+    split_inline(line.as_bytes())
+        .unwrap()
+        .iter()
+        .map(|word| String::from_utf8_lossy(word.as_bytes()).to_string())
+        .collect()
+}
+
+#[test]
+fn inline_commands_split_like_redis() {
+    assert_eq!(words("  SET  k\tv "), ["SET", "k", "v"]);
+    assert_eq!(words(""), Vec::<String>::new());
+    assert_eq!(
+        words(r#"SET "hello world" 'it\'s'"#),
+        ["SET", "hello world", "it's"]
+    );
+    assert_eq!(
+        words(r#"ECHO "a\"b\n\x41\x4" '\n' """#),
+        ["ECHO", "a\"b\nAx4", "\\n", ""]
+    );
+    for line in [r#"GET "k"#, "GET 'k", r#"GET "k"x"#, "GET 'a'b"] {
+        assert_eq!(
+            split_inline(line.as_bytes()).unwrap_err().to_string(),
+            "unbalanced quotes in request",
+            "{line}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn inline_commands_mix_with_resp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let sent = "PING\r\n\r\n*1\r\n$4\r\nPING\r\nset k \"a b\"\n";
+    client.write_all(sent.as_bytes()).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let mut conn = RespDeserializer::new(server);
+
+    let mut n_read = 0;
+    for expected in [
+        vec![BulkString("PING".into())],
+        vec![BulkString("PING".into())],
+        vec![
+            BulkString("set".into()),
+            BulkString("k".into()),
+            BulkString("a b".into()),
+        ],
+    ] {
+        let (val, cnt) = conn.deserialize().await.unwrap();
+        assert_eq!(val, Array(expected));
+        n_read += cnt;
+    }
+    // the empty line in between counts too
+    assert_eq!(n_read, sent.len());
+}
@@ -0,0 +1,203 @@
+mod db_util;
+
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use aof::read_aof_file;
+use clock::ManualClock;
+use config::InstanceConfig;
+use db::Db;
+use db_util::{query, run, test_db, START};
+use resp::Value::{self, *};
+use tokio::sync::mpsc::channel;
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn ok() -> Value {
+    SimpleString("OK".into())
+}
+
+fn wrong_type() -> Value {
+    SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+}
+
+/// KEYS replies in no particular order
+async fn keys(db: &mut Db, pattern: &str) -> Vec<String> {
+    let mut keys: Vec<String> = match run(db, &format!("KEYS {pattern}")).await {
+        Array(keys) => keys.iter().map(|k| k.try_to_string().unwrap()).collect(),
+        other => panic!("KEYS should reply with an array, got {other:?}"),
+    };
+    keys.sort();
+    keys
+}
+
+#[tokio::test]
+async fn del_exists_and_type() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "SET s x").await;
+    run(&mut db, "RPUSH l a").await;
+    run(&mut db, "HSET h f 1").await;
+    run(&mut db, "SADD set a").await;
+    run(&mut db, "ZADD z 1 a").await;
+    run(&mut db, "XADD x 1-1 f v").await;
+    for (key, kind) in [
+        ("s", "string"),
+        ("l", "list"),
+        ("h", "hash"),
+        ("set", "set"),
+        ("z", "zset"),
+        ("x", "stream"),
+        ("nope", "none"),
+    ] {
+        assert_eq!(
+            run(&mut db, &format!("TYPE {key}")).await,
+            SimpleString(kind.into())
+        );
+    }
+
+    // each key counts as many times as it is given
+    assert_eq!(run(&mut db, "EXISTS s s nope l").await, Int(3));
+    assert_eq!(run(&mut db, "DEL s l nope s").await, Int(2));
+    assert_eq!(run(&mut db, "EXISTS s l").await, Int(0));
+    assert_eq!(run(&mut db, "GET s").await, NullBulkString);
+    assert_eq!(run(&mut db, "DEL s").await, Int(0));
+}
+
+#[tokio::test]
+async fn keys_match_a_glob_pattern() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    for key in ["hello", "hallo", "hxllo", "hllo", "heeeello", "h*llo"] {
+        run(&mut db, &format!("SET {key} 1")).await;
+    }
+    assert_eq!(
+        keys(&mut db, "h?llo").await,
+        vec!["h*llo", "hallo", "hello", "hxllo"]
+    );
+    assert_eq!(keys(&mut db, "h[ae]llo").await, vec!["hallo", "hello"]);
+    assert_eq!(
+        keys(&mut db, "h[^e]llo").await,
+        vec!["h*llo", "hallo", "hxllo"]
+    );
+    assert_eq!(keys(&mut db, "h\\*llo").await, vec!["h*llo"]);
+    assert_eq!(keys(&mut db, "*e*").await, vec!["heeeello", "hello"]);
+    assert_eq!(keys(&mut db, "nope*").await, Vec::<String>::new());
+
+    // expired keys are gone, even before anything deletes them
+    run(&mut db, "PEXPIRE hello 100").await;
+    clock.advance(100);
+    assert_eq!(keys(&mut db, "he*").await, vec!["heeeello"]);
+}
+
+#[tokio::test]
+async fn rename() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+    let no_such_key = SimpleError("ERR no such key".into());
+
+    assert_eq!(run(&mut db, "RENAME nope k").await, no_such_key);
+    assert_eq!(run(&mut db, "RENAMENX nope k").await, no_such_key);
+
+    // the value and its expiry move, replacing whatever was there, of any type
+    run(&mut db, "RPUSH l a b").await;
+    run(&mut db, "PEXPIRE l 1000").await;
+    run(&mut db, "SET s x").await;
+    assert_eq!(run(&mut db, "RENAME l s").await, ok());
+    assert_eq!(run(&mut db, "EXISTS l").await, Int(0));
+    assert_eq!(run(&mut db, "GET s").await, wrong_type());
+    assert_eq!(run(&mut db, "LLEN s").await, Int(2));
+    assert_eq!(run(&mut db, "PTTL s").await, Int(1000));
+    assert_eq!(run(&mut db, "RENAME s s").await, ok());
+    assert_eq!(run(&mut db, "LLEN s").await, Int(2));
+
+    run(&mut db, "SET t y").await;
+    assert_eq!(run(&mut db, "RENAMENX s t").await, Int(0));
+    assert_eq!(run(&mut db, "GET t").await, bulk("y"));
+    assert_eq!(run(&mut db, "RENAMENX s u").await, Int(1));
+    assert_eq!(run(&mut db, "TYPE u").await, SimpleString("list".into()));
+
+    // an expired key is as good as missing, as source or as destination
+    clock.advance(1000);
+    assert_eq!(run(&mut db, "RENAME u v").await, no_such_key);
+    run(&mut db, "PEXPIRE t 10").await;
+    clock.advance(10);
+    run(&mut db, "SET w z").await;
+    assert_eq!(run(&mut db, "RENAMENX w t").await, Int(1));
+    assert_eq!(run(&mut db, "GET t").await, bulk("z"));
+}
+
+#[tokio::test]
+async fn copy() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "COPY nope k").await, Int(0));
+    assert_eq!(
+        run(&mut db, "COPY k k").await,
+        SimpleError("ERR source and destination objects are the same".into())
+    );
+
+    run(&mut db, "SADD s a b").await;
+    run(&mut db, "PEXPIRE s 500").await;
+    assert_eq!(run(&mut db, "COPY s c").await, Int(1));
+    assert_eq!(run(&mut db, "PTTL c").await, Int(500));
+    // the copy is a value of its own
+    run(&mut db, "SREM s a").await;
+    assert_eq!(run(&mut db, "SISMEMBER c a").await, Int(1));
+    assert_eq!(run(&mut db, "GET c").await, wrong_type());
+
+    run(&mut db, "SET str x").await;
+    assert_eq!(run(&mut db, "COPY str c").await, Int(0));
+    assert_eq!(run(&mut db, "SISMEMBER c a").await, Int(1));
+    assert_eq!(run(&mut db, "COPY str c REPLACE").await, Int(1));
+    assert_eq!(run(&mut db, "GET c").await, bulk("x"));
+    assert_eq!(run(&mut db, "PTTL c").await, Int(-1));
+}
+
+#[tokio::test]
+async fn only_changes_are_propagated() {
+    let dir = std::env::temp_dir().join(format!("keyspace-{pid}", pid = std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cfg = InstanceConfig {
+        dir: dir.to_str().unwrap().to_string(),
+        appendonly: true,
+        ..InstanceConfig::default()
+    };
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg.clone(), tx, Arc::new(ManualClock::new(START)));
+    db.load_aof().await.unwrap();
+
+    let changes = [
+        "SET a 1",
+        "SET b 2",
+        "RENAME a c",
+        "RENAMENX c d",
+        "COPY d e",
+        "DEL b",
+    ];
+    for cmd in changes {
+        run(&mut db, cmd).await;
+    }
+    // none of these change anything
+    for cmd in [
+        "RENAME nope x",
+        "RENAMENX e d",
+        "COPY nope x",
+        "COPY d e",
+        "DEL nope b",
+        "EXISTS d",
+        "KEYS *",
+    ] {
+        run(&mut db, cmd).await;
+    }
+    drop(db);
+
+    let logged = changes.iter().map(|cmd| query(cmd).cmd).collect::<Vec<_>>();
+    assert_eq!(read_aof_file(&cfg.aof_path()).unwrap(), logged);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,82 @@
+use redis_starter_rust::*;
+
+use long_double::LongDouble;
+
+/// `a + b` as INCRBYFLOAT stores it, None if either isn't a number or the sum isn't finite
+fn sum(a: &str, b: &str) -> Option<String> {
+    let a = LongDouble::parse(a.as_bytes())?;
+    let b = LongDouble::parse(b.as_bytes())?;
+    a.checked_add(b).map(|sum| sum.to_string())
+}
+
+fn valid(s: &str) -> bool {
+    LongDouble::parse(s.as_bytes()).is_some()
+}
+
+// expected values are what redis replies, on x86-64
+#[test]
+fn sums_round_like_long_doubles() {
+    assert_eq!(sum("0.1", "0.2").as_deref(), Some("0.3"));
+    assert_eq!(sum("10.5", "0.1").as_deref(), Some("10.6"));
+    assert_eq!(sum("10.6", "-5.6").as_deref(), Some("5"));
+    // past their 64 bits of precision the noise shows
+    assert_eq!(
+        sum("1000", "0.1").as_deref(),
+        Some("1000.09999999999999998")
+    );
+    assert_eq!(
+        sum("12345678901234567", "1").as_deref(),
+        Some("12345678901234568")
+    );
+    assert_eq!(
+        sum("3.0000000000000000000000001", "0").as_deref(),
+        Some("3")
+    );
+    assert_eq!(sum("-0", "-0").as_deref(), Some("0"));
+    assert_eq!(sum("1.1e4932", "1.1e4932"), None);
+    assert_eq!(sum("inf", "1"), None);
+}
+
+#[test]
+fn printed_with_17_decimals_and_no_exponent() {
+    assert_eq!(sum("0", "1e20").as_deref(), Some("100000000000000000000"));
+    assert_eq!(sum("1e20", "-1e20").as_deref(), Some("0"));
+    assert_eq!(sum("0", "1.5e-5").as_deref(), Some("0.000015"));
+    assert_eq!(sum("1.5e-17", "0").as_deref(), Some("0.00000000000000002"));
+    assert_eq!(sum("0.5e-17", "0").as_deref(), Some("0"));
+    assert_eq!(sum("-0.000000000000000001", "0").as_deref(), Some("0"));
+    let big = sum("1.1e4932", "0").unwrap();
+    assert_eq!(big.len(), 4933);
+    assert!(big.starts_with("10999999999999999999743731"), "{big}");
+}
+
+#[test]
+fn parsed_like_strtold() {
+    for s in [
+        "1",
+        "-1.5",
+        "+.5",
+        "1.",
+        "007",
+        "1e+2",
+        "1E-2",
+        "inf",
+        "-Infinity",
+    ] {
+        assert!(valid(s), "{s}");
+    }
+    assert_eq!(sum("007", ".5").as_deref(), Some("7.5"));
+    assert_eq!(sum("0x10", "0x.8").as_deref(), Some("16.5"));
+    // subnormals are fine, what rounds to 0 or overflows is not
+    assert!(valid("0x1p-16445"));
+    assert!(valid("1e-4950"));
+    assert!(!valid("0x1p-16446"));
+    assert!(!valid("1e-4960"));
+    assert!(valid("1.1e4932"));
+    assert!(!valid("1.2e4932"));
+    for s in [
+        "", ".", "1e", "0x", "nan", " 1", "1 ", "1,5", "--1", "1e2.5",
+    ] {
+        assert!(!valid(s), "{s:?}");
+    }
+}
@@ -0,0 +1,210 @@
+mod db_util;
+
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use clock::{ManualClock, SystemClock};
+use commands::parse_cmd;
+use config::InstanceConfig;
+use db::Db;
+use db_util::{query, run, test_db, START};
+use pubsub::{push_channel, Pushed, PUSH_QUEUE_LIMIT};
+use resp::Value::{self, *};
+use svc::{Query, ToDb};
+use tokio::sync::mpsc::{channel, Receiver};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+/// A reply or message given as `kind name count`, or with more words as all bulk strings
+fn msg(words: &str) -> Value {
+    let words: Vec<&str> = words.split(' ').collect();
+    match words[..] {
+        [kind, name, count] if count.parse::<i64>().is_ok() => {
+            Push(vec![bulk(kind), bulk(name), Int(count.parse().unwrap())])
+        }
+        _ => Push(words.into_iter().map(bulk).collect()),
+    }
+}
+
+/// A client that can subscribe, with its connection's id
+struct Client {
+    id: u64,
+    pushes: Receiver<Pushed>,
+    push_sender: pubsub::PushSender,
+}
+
+impl Client {
+    fn new(id: u64) -> Self {
+        let (push_sender, pushes) = push_channel();
+        Client {
+            id,
+            pushes,
+            push_sender,
+        }
+    }
+
+    /// A query for a command given as space separated words
+    fn query(&self, cmd: &str) -> Query {
+        let val = Array(cmd.split(' ').map(bulk).collect());
+        Query {
+            pushes: Some(self.push_sender.clone()),
+            client_id: self.id,
+            ..Query::new(parse_cmd(&val).unwrap(), 0, "test:0".to_string())
+        }
+    }
+
+    /// Run a command given as space separated words, return all its replies
+    async fn run(&self, db: &mut Db, cmd: &str) -> Vec<Value> {
+        let (sx, _rx) = channel(1);
+        db.execute(&self.query(cmd), sx).await.vals
+    }
+
+    fn pushed(&mut self) -> Vec<Value> {
+        let mut vals = Vec::new();
+        while let Ok(pushed) = self.pushes.try_recv() {
+            match pushed {
+                Pushed::Message(val) => vals.push(val),
+                Pushed::Overflowed => panic!("client {} overflowed", self.id),
+            }
+        }
+        vals
+    }
+}
+
+#[tokio::test]
+async fn messages_reach_channel_and_pattern_subscribers() {
+    let mut db = test_db(&ManualClock::new(START));
+    let mut alice = Client::new(1);
+    let mut bob = Client::new(2);
+
+    assert_eq!(
+        alice.run(&mut db, "SUBSCRIBE news sports news").await,
+        vec![
+            msg("subscribe news 1"),
+            msg("subscribe sports 2"),
+            msg("subscribe news 2")
+        ]
+    );
+    assert_eq!(
+        bob.run(&mut db, "PSUBSCRIBE n*s").await,
+        vec![msg("psubscribe n*s 1")]
+    );
+
+    assert_eq!(run(&mut db, "PUBLISH news hello").await, Int(2));
+    assert_eq!(run(&mut db, "PUBLISH weather rain").await, Int(0));
+    assert_eq!(alice.pushed(), vec![msg("message news hello")]);
+    assert_eq!(bob.pushed(), vec![msg("pmessage n*s news hello")]);
+
+    assert_eq!(
+        run(&mut db, "PUBSUB CHANNELS").await,
+        Array(vec![bulk("news"), bulk("sports")])
+    );
+    assert_eq!(
+        run(&mut db, "PUBSUB CHANNELS s*").await,
+        Array(vec![bulk("sports")])
+    );
+    assert_eq!(
+        run(&mut db, "PUBSUB NUMSUB news weather").await,
+        Map(vec![(bulk("news"), Int(1)), (bulk("weather"), Int(0))])
+    );
+    assert_eq!(run(&mut db, "PUBSUB NUMPAT").await, Int(1));
+
+    // no names means all of them
+    assert_eq!(
+        alice.run(&mut db, "UNSUBSCRIBE").await,
+        vec![msg("unsubscribe news 1"), msg("unsubscribe sports 0")]
+    );
+    assert_eq!(
+        alice.run(&mut db, "UNSUBSCRIBE").await,
+        vec![Push(vec![bulk("unsubscribe"), NullBulkString, Int(0)])]
+    );
+    assert_eq!(run(&mut db, "PUBLISH news again").await, Int(1));
+    assert_eq!(alice.pushed(), vec![]);
+}
+
+#[tokio::test]
+async fn subscriptions_end_with_the_connection() {
+    let (tx, rx) = channel(100);
+    let db = Db::with_clock(InstanceConfig::default(), tx.clone(), Arc::new(SystemClock));
+    tokio::spawn(db.run(rx));
+    let alice = Client::new(1);
+
+    let (sx, mut reply_rx) = channel(1);
+    tx.send(ToDb::QueryAndSender(alice.query("PSUBSCRIBE *"), sx))
+        .await
+        .unwrap();
+    assert_eq!(
+        reply_rx.recv().await.unwrap().vals,
+        vec![msg("psubscribe * 1")]
+    );
+
+    tx.send(ToDb::ClientClosed(alice.id)).await.unwrap();
+    let (sx, mut reply_rx) = channel(1);
+    tx.send(ToDb::QueryAndSender(query("PUBSUB NUMPAT"), sx))
+        .await
+        .unwrap();
+    assert_eq!(reply_rx.recv().await.unwrap().vals, vec![Int(0)]);
+}
+
+#[tokio::test]
+async fn subscribed_clients_can_only_run_a_few_commands() {
+    let mut db = test_db(&ManualClock::new(START));
+    let alice = Client::new(1);
+
+    alice.run(&mut db, "SUBSCRIBE news").await;
+    assert_eq!(
+        alice.run(&mut db, "GET k").await,
+        vec![SimpleError(
+            "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".into()
+        )]
+    );
+    assert_eq!(
+        alice.run(&mut db, "PING").await,
+        vec![Array(vec![bulk("pong"), bulk("")])]
+    );
+    assert_eq!(
+        alice.run(&mut db, "PUNSUBSCRIBE x*").await,
+        vec![msg("punsubscribe x* 1")]
+    );
+
+    alice.run(&mut db, "UNSUBSCRIBE news").await;
+    assert_eq!(alice.run(&mut db, "GET k").await, vec![NullBulkString]);
+    assert_eq!(
+        alice.run(&mut db, "PING").await,
+        vec![SimpleString("PONG".into())]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_that_fall_behind_are_dropped() {
+    let mut db = test_db(&ManualClock::new(START));
+    let mut alice = Client::new(1);
+    let mut bob = Client::new(2);
+    alice.run(&mut db, "SUBSCRIBE news").await;
+    bob.run(&mut db, "PSUBSCRIBE n*").await;
+
+    // bob keeps up, alice reads nothing
+    for _ in 0..PUSH_QUEUE_LIMIT {
+        assert_eq!(run(&mut db, "PUBLISH news hello").await, Int(2));
+        assert_eq!(bob.pushed().len(), 1);
+    }
+    assert_eq!(run(&mut db, "PUBLISH news hello").await, Int(1));
+    assert_eq!(bob.pushed().len(), 1);
+    assert_eq!(
+        run(&mut db, "PUBSUB NUMSUB news").await,
+        Map(vec![(bulk("news"), Int(0))])
+    );
+
+    // what was queued is still there, followed by the word to disconnect
+    for _ in 0..PUSH_QUEUE_LIMIT {
+        assert_eq!(
+            alice.pushes.try_recv().unwrap(),
+            Pushed::Message(msg("message news hello"))
+        );
+    }
+    assert_eq!(alice.pushes.try_recv().unwrap(), Pushed::Overflowed);
+    assert!(alice.pushes.try_recv().is_err());
+}
@@ -0,0 +1,227 @@
+use redis_starter_rust::*;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use common::Bytes;
+use db_val::DbVal;
+use misc_util::hex_decode;
+use rdb::{crc64, parse_rdb, serialize_rdb, RdbEntry};
+use stream::{ConsumerGroup, Stream, StreamId, TrimStrategy};
+use zset::SortedSet;
+
+const EMPTY_RDB_FILE_HEX: &str = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+
+#[test]
+fn parse_empty_rdb() {
+    let contents = parse_rdb(&hex_decode(EMPTY_RDB_FILE_HEX).unwrap()).unwrap();
+
+    assert_eq!(contents.version, 11);
+    assert!(contents.entries.is_empty());
+    assert_eq!(contents.aux[0], ("redis-ver".into(), "7.2.0".into()));
+    assert_eq!(contents.aux[1], ("redis-bits".into(), "64".into()));
+}
+
+#[test]
+fn parse_rdb_with_keys() {
+    let mut data: Vec<u8> = b"REDIS0011".to_vec();
+    data.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x04, 0x02]);
+    // plain string
+    data.extend_from_slice(b"\x00\x03foo\x03bar");
+    // int encoded value, with expiry in millis
+    data.push(0xFC);
+    data.extend_from_slice(&1_700_000_000_123u64.to_le_bytes());
+    data.extend_from_slice(b"\x00\x03num\xC1");
+    data.extend_from_slice(&(-1234i16).to_le_bytes());
+    // lzf compressed value, with expiry in seconds
+    data.push(0xFD);
+    data.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+    data.extend_from_slice(b"\x00\x03lzf\xC3\x06\x06\x02abc\x20\x02");
+    // 14-bit length
+    data.extend_from_slice(&[0x00, 0x04]);
+    data.extend_from_slice(b"long");
+    data.extend_from_slice(&[0x41, 0x00]);
+    data.extend_from_slice(&[b'x'; 256]);
+    data.push(0xFF);
+    data.extend_from_slice(&[0u8; 8]);
+
+    let contents = parse_rdb(&data).unwrap();
+    assert_eq!(
+        contents.entries,
+        vec![
+            RdbEntry {
+                key: "foo".into(),
+                val: "bar".into(),
+                expiry: None
+            },
+            RdbEntry {
+                key: "num".into(),
+                val: "-1234".into(),
+                expiry: Some(1_700_000_000_123)
+            },
+            RdbEntry {
+                key: "lzf".into(),
+                val: "abcabc".into(),
+                expiry: Some(1_700_000_000_000)
+            },
+            RdbEntry {
+                key: "long".into(),
+                val: Bytes::from(vec![b'x'; 256]).into(),
+                expiry: None
+            },
+        ]
+    );
+}
+
+#[test]
+fn parse_rdb_rejects_garbage() {
+    assert!(parse_rdb(b"NOTREDIS").is_err());
+    assert!(parse_rdb(b"REDIS0011\x00\x05ab").is_err());
+    // an lzf string claiming to decompress to way more than its input can
+    let mut data: Vec<u8> = b"REDIS0011\x00\x03lzf\xC3\x06\x81".to_vec();
+    data.extend_from_slice(&(u64::MAX / 2).to_be_bytes());
+    data.extend_from_slice(b"\x02abc\x20\x02\xFF");
+    assert!(parse_rdb(&data).is_err());
+}
+
+#[test]
+fn crc64_check_value() {
+    assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+}
+
+#[test]
+fn rdb_roundtrip() {
+    let entries = vec![
+        RdbEntry {
+            key: "foo".into(),
+            val: "bar".into(),
+            expiry: None,
+        },
+        RdbEntry {
+            key: "counter".into(),
+            val: "-70000".into(),
+            expiry: Some(1_700_000_000_123),
+        },
+        RdbEntry {
+            key: "not-canonical".into(),
+            val: "007".into(),
+            expiry: None,
+        },
+        RdbEntry {
+            key: "big".into(),
+            val: Bytes::from(vec![b'y'; 70_000]).into(),
+            expiry: Some(1),
+        },
+    ];
+
+    let data = serialize_rdb(&entries, 1_700_000_000);
+    let contents = parse_rdb(&data).unwrap();
+
+    assert_eq!(contents.version, 11);
+    assert!(contents
+        .aux
+        .contains(&("ctime".into(), "1700000000".into())));
+    assert_eq!(contents.entries, entries);
+}
+
+#[test]
+fn rdb_roundtrip_collections() {
+    let mut zset = SortedSet::default();
+    zset.insert("a".into(), 1.5);
+    zset.insert("b".into(), f64::NEG_INFINITY);
+    zset.insert("c".into(), f64::INFINITY);
+
+    let entries = vec![
+        RdbEntry {
+            key: "list".into(),
+            val: DbVal::List(VecDeque::from(["x".into(), "y".into(), "x".into()])),
+            expiry: None,
+        },
+        RdbEntry {
+            key: "hash".into(),
+            val: DbVal::Hash(HashMap::from([
+                ("f".into(), "v".into()),
+                ("n".into(), "12".into()),
+            ])),
+            expiry: Some(1_700_000_000_123),
+        },
+        RdbEntry {
+            key: "set".into(),
+            val: DbVal::Set(HashSet::from(["m".into(), "1".into()])),
+            expiry: None,
+        },
+        RdbEntry {
+            key: "zset".into(),
+            val: DbVal::ZSet(zset),
+            expiry: None,
+        },
+    ];
+
+    let data = serialize_rdb(&entries, 1_700_000_000);
+    assert_eq!(parse_rdb(&data).unwrap().entries, entries);
+}
+
+#[test]
+fn rdb_roundtrip_stream() {
+    // enough entries for several listpacks, some with fields other than the first one's
+    let mut stream = Stream::default();
+    for i in 0..250u64 {
+        let mut fields = vec![("n".into(), i.to_string().as_str().into())];
+        if i % 7 == 0 {
+            fields.push(("extra".into(), Bytes::from(vec![b'x'; 100 + i as usize])));
+        }
+        stream.insert(StreamId::new(1_700_000_000_000 + i / 3, i % 3), fields);
+    }
+    stream.insert(
+        StreamId::new(u64::MAX - 1, 5),
+        vec![("big".into(), "-1".into())],
+    );
+    stream.trim(TrimStrategy::MaxLen(200), None);
+
+    // a group with entries pending for one consumer, and one that never read anything
+    let mut group = ConsumerGroup::new(StreamId::new(1_700_000_000_080, 0), Some(241));
+    group.touch_consumer(&"idle".into(), 1_700_000_000_500);
+    group.touch_consumer(&"busy".into(), 1_700_000_000_600);
+    group
+        .consumers
+        .get_mut(&Bytes::from("busy"))
+        .unwrap()
+        .active_time = Some(1_700_000_000_600);
+    for seq in 0..3 {
+        let id = StreamId::new(1_700_000_000_080, seq);
+        group.deliver(id, &"busy".into(), 1_700_000_000_600 + seq, seq + 1);
+    }
+    stream.groups.insert("workers".into(), group);
+    stream
+        .groups
+        .insert("late".into(), ConsumerGroup::new(StreamId::MIN, None));
+
+    // emptied streams keep their ids
+    let mut empty = Stream::default();
+    empty.insert(StreamId::new(5, 1), vec![("f".into(), "v".into())]);
+    empty.trim(TrimStrategy::MaxLen(0), None);
+
+    let entries = vec![
+        RdbEntry {
+            key: "stream".into(),
+            val: DbVal::Stream(stream),
+            expiry: None,
+        },
+        RdbEntry {
+            key: "empty".into(),
+            val: DbVal::Stream(empty),
+            expiry: Some(1_700_000_000_123),
+        },
+    ];
+
+    let data = serialize_rdb(&entries, 1_700_000_000);
+    assert_eq!(parse_rdb(&data).unwrap().entries, entries);
+}
+
+#[test]
+fn rdb_bad_checksum() {
+    let mut data = serialize_rdb(&[], 0);
+    let len = data.len();
+    data[len - 1] ^= 0x01;
+
+    assert!(parse_rdb(&data).is_err());
+}
@@ -0,0 +1,26 @@
+use redis_starter_rust::*;
+
+use repl_backlog::ReplBacklog;
+
+#[test]
+fn backlog_serves_missing_bytes() {
+    let mut backlog = ReplBacklog::new(16, 100);
+    backlog.append(b"hello ");
+    backlog.append(b"world");
+
+    assert_eq!(backlog.end_offset(), 111);
+    assert_eq!(backlog.bytes_from(106), Some(b"world".to_vec()));
+    assert_eq!(backlog.bytes_from(111), Some(vec![]));
+    assert_eq!(backlog.bytes_from(99), None);
+    assert_eq!(backlog.bytes_from(112), None);
+}
+
+#[test]
+fn backlog_drops_oldest_bytes() {
+    let mut backlog = ReplBacklog::new(8, 0);
+    backlog.append(b"0123456789");
+
+    assert_eq!(backlog.start_offset(), 2);
+    assert_eq!(backlog.bytes_from(1), None);
+    assert_eq!(backlog.bytes_from(2), Some(b"23456789".to_vec()));
+}
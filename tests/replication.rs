@@ -0,0 +1,223 @@
+// A master and its replicas, each a Db of its own
+mod db_util;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use redis_starter_rust::*;
+
+use async_deser::RespDeserializer;
+use clock::{ManualClock, SystemClock};
+use config::{InstanceConfig, Role};
+use db::Db;
+use db_util::{query, run, test_db, START};
+use db_val::DbVal;
+use rdb::parse_rdb;
+use resp::Value::{self, *};
+use svc::{handle_stream_async, Query, ToDb};
+use tokio::io::{duplex, AsyncWriteExt, BufStream};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Sender};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn start_db(cfg: InstanceConfig) -> Sender<ToDb> {
+    let (tx, rx) = channel(100);
+    let db = Db::with_clock(cfg, tx.clone(), Arc::new(SystemClock));
+    tokio::spawn(db.run(rx));
+    tx
+}
+
+/// A master accepting connections on a free port, returns the port
+async fn start_master(tx: &Sender<ToDb>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, addr)) = listener.accept().await {
+            let conn = RespDeserializer::new(BufStream::new(stream));
+            tokio::spawn(handle_stream_async(
+                conn,
+                addr.to_string(),
+                tx.clone(),
+                false,
+            ));
+        }
+    });
+    port
+}
+
+/// Run a query on the Db behind `tx`, return all its replies
+async fn ask(tx: &Sender<ToDb>, query: Query) -> Vec<Value> {
+    let (sx, mut rx) = channel(1);
+    tx.send(ToDb::QueryAndSender(query, sx)).await.unwrap();
+    rx.recv().await.unwrap().vals
+}
+
+/// Ask `cmd` until the reply is `expected`, for up to 5s
+async fn wait_for(tx: &Sender<ToDb>, cmd: &str, expected: Value) {
+    for _ in 0..50 {
+        if ask(tx, query(cmd)).await == vec![expected.clone()] {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{cmd} never replied {expected:?}");
+}
+
+#[tokio::test]
+async fn full_resync_sends_the_dataset() {
+    let master = start_db(InstanceConfig::default());
+    ask(&master, query("SET k v")).await;
+    ask(&master, query("RPUSH l a b")).await;
+    let port = start_master(&master).await;
+
+    // the rdb after +FULLRESYNC is a snapshot of what the master holds
+    let (client, server) = duplex(1024);
+    tokio::spawn(handle_stream_async(
+        RespDeserializer::new(BufStream::new(server)),
+        "replica:1".to_string(),
+        master.clone(),
+        false,
+    ));
+    let mut client = RespDeserializer::new(BufStream::new(client));
+    let psync = b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n";
+    client.get_mut().write_all(psync).await.unwrap();
+    client.get_mut().flush().await.unwrap();
+    match client.deserialize().await.unwrap().0 {
+        SimpleString(s) => assert!(s.as_bytes().starts_with(b"FULLRESYNC "), "{s:?}"),
+        other => panic!("unexpected reply to PSYNC: {other:?}"),
+    }
+    let rdb = match client.deserialize_file().await.unwrap() {
+        FileContents(rdb) => parse_rdb(rdb.as_bytes()).unwrap(),
+        other => panic!("expected the rdb file, got: {other:?}"),
+    };
+    let mut entries: Vec<_> = rdb
+        .entries
+        .into_iter()
+        .map(|entry| (entry.key.to_string().unwrap(), entry.val))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        entries,
+        vec![
+            ("k".to_string(), DbVal::Str("v".into())),
+            (
+                "l".to_string(),
+                DbVal::List(["a".into(), "b".into()].into())
+            ),
+        ]
+    );
+
+    // a replica loads it, then gets what is written after
+    let replica = start_db(InstanceConfig {
+        port: 0,
+        role: Role::Slave,
+        replicaof: Some(format!("127.0.0.1 {port}")),
+        ..InstanceConfig::default()
+    });
+    wait_for(&replica, "GET k", bulk("v")).await;
+    wait_for(&replica, "LRANGE l 0 -1", Array(vec![bulk("a"), bulk("b")])).await;
+    ask(&master, query("SET k2 v2")).await;
+    wait_for(&replica, "GET k2", bulk("v2")).await;
+}
+
+#[tokio::test]
+async fn replicas_gone_before_the_handover_are_forgotten() {
+    let master = start_db(InstanceConfig::default());
+    let psync = Query {
+        client_id: 7,
+        ..query("PSYNC ? -1")
+    };
+    ask(&master, psync).await;
+    // WAIT counts replicas that got everything written, so far that is nothing
+    assert_eq!(ask(&master, query("WAIT 0 0")).await, vec![Int(1)]);
+
+    // its connection closed instead of being passed on as a replica's
+    master.send(ToDb::ClientClosed(7)).await.unwrap();
+    assert_eq!(ask(&master, query("WAIT 0 0")).await, vec![Int(0)]);
+}
+
+/// PSYNC's replies: +FULLRESYNC followed by the rdb, or just +CONTINUE
+async fn psync(db: &mut Db, replid: &str, offset: u64) -> Vec<String> {
+    let (sx, _rx) = channel(1);
+    let cmd = format!("PSYNC {replid} {offset}");
+    let res = db.execute(&query(&cmd), sx).await;
+    assert!(res.pass_stream, "PSYNC hands the connection over");
+    res.vals
+        .into_iter()
+        .map(|val| match val {
+            SimpleString(s) => s.to_string().unwrap(),
+            FileContents(_) => "<rdb>".to_string(),
+            other => panic!("unexpected reply to PSYNC: {other:?}"),
+        })
+        .collect()
+}
+
+async fn master_repl_offset(db: &mut Db) -> u64 {
+    let info = match run(db, "INFO replication").await {
+        BulkString(info) => info.to_string().unwrap(),
+        other => panic!("unexpected reply to INFO: {other:?}"),
+    };
+    info.split("\r\n")
+        .find_map(|line| line.strip_prefix("master_repl_offset:"))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn replicas_continue_from_the_backlog() {
+    let mut db = test_db(&ManualClock::new(START));
+    let full = psync(&mut db, "?", 0).await;
+    let replid = match full[0].split(' ').collect::<Vec<_>>()[..] {
+        ["FULLRESYNC", replid, "0"] => replid.to_string(),
+        _ => panic!("unexpected reply to PSYNC: {full:?}"),
+    };
+    assert_eq!(full[1], "<rdb>");
+
+    run(&mut db, "SET k v").await;
+    let end = master_repl_offset(&mut db).await;
+    assert!(end > 0);
+    let cont = vec![format!("CONTINUE {replid}")];
+    // offsets are those of the next byte the replica needs, 1-based
+    assert_eq!(psync(&mut db, &replid, 1).await, cont);
+    assert_eq!(psync(&mut db, &replid, end + 1).await, cont);
+
+    // bytes the backlog never had, or those of some other history, take a full resync
+    let full = format!("FULLRESYNC {replid} {end}");
+    assert_eq!(
+        psync(&mut db, &replid, end + 2).await,
+        vec![full.clone(), "<rdb>".into()]
+    );
+    let other_id = "0".repeat(40);
+    assert_eq!(
+        psync(&mut db, &other_id, 1).await,
+        vec![full, "<rdb>".into()]
+    );
+}
+
+#[tokio::test]
+async fn acks_requested_by_wait_are_part_of_the_stream() {
+    let mut db = test_db(&ManualClock::new(START));
+    let full = psync(&mut db, "?", 0).await;
+    let replid = full[0].split(' ').nth(1).unwrap().to_string();
+    run(&mut db, "SET k v").await;
+    let before = master_repl_offset(&mut db).await;
+
+    // replicas count the REPLCONF GETACK * they get, so the master does too
+    assert_eq!(run(&mut db, "WAIT 0 0").await, Int(0));
+    let getack = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+    let after = master_repl_offset(&mut db).await;
+    assert_eq!(after, before + getack.len() as u64);
+    let cont = vec![format!("CONTINUE {replid}")];
+    assert_eq!(psync(&mut db, &replid, after + 1).await, cont);
+
+    // a replica answers with its offset before the GETACK, which is all WAIT waits for
+    let ack = query(&format!("REPLCONF ACK {after}"));
+    let (sx, _rx) = channel(1);
+    db.execute(&ack, sx).await;
+    assert_eq!(run(&mut db, "WAIT 1 0").await, Int(1));
+}
@@ -1,36 +1,75 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
 use redis_starter_rust::*;
 
+use async_deser::RespDeserializer;
 use resp::Value;
 use resp::Value::*;
-use resp::{b_str, s_str, RespDeserializer};
+use resp::{b_str, s_str};
+use tokio::io::AsyncWriteExt;
 
-pub fn deser_str(data: &str) -> Result<Value> {
-    let mut deser = RespDeserializer::new(Vec::from(data.as_bytes()));
-    deser.deserialize()
+pub async fn deser_str(data: &str) -> Result<Value> {
+    let mut deser = RespDeserializer::new(data.as_bytes());
+    let (val, _) = deser.deserialize().await?;
+    Ok(val)
 }
 
-#[test]
-fn parse_simple_str() {
+#[tokio::test]
+async fn parse_simple_str() {
     let input = "+teo\r\n";
     let expected = s_str("teo");
 
-    assert_eq!(deser_str(input).unwrap(), expected);
+    assert_eq!(deser_str(input).await.unwrap(), expected);
 }
 
-#[test]
-fn parse_array_2() {
+#[tokio::test]
+async fn parse_array_2() {
     let input = "*2\r\n$4\r\nECHO\r\n$5\r\npears\r\n";
     let expected = Array(vec![b_str("ECHO"), b_str("pears")]);
 
-    assert_eq!(deser_str(input).unwrap(), expected);
+    assert_eq!(deser_str(input).await.unwrap(), expected);
 }
 
-#[test]
-fn parse_array_ping() {
+#[tokio::test]
+async fn parse_array_ping() {
     let input = "*1\r\n$4\r\nPING\r\n";
     let expected = Array(vec![b_str("PING")]);
 
-    assert_eq!(deser_str(input).unwrap(), expected);
+    assert_eq!(deser_str(input).await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn values_split_across_reads() {
+    // a tiny pipe, so that every value takes several reads
+    let input = "*2\r\n$4\r\nECHO\r\n$5\r\npears\r\n:42\r\n";
+    let (mut writer, reader) = tokio::io::duplex(3);
+    tokio::spawn(async move { writer.write_all(input.as_bytes()).await });
+    let mut deser = RespDeserializer::new(reader);
+
+    let (val, cnt) = deser.deserialize().await.unwrap();
+    assert_eq!(val, Array(vec![b_str("ECHO"), b_str("pears")]));
+    assert_eq!(cnt, 25);
+    assert_eq!(deser.deserialize().await.unwrap(), (Int(42), 5));
+    assert!(deser.deserialize().await.is_err());
+}
+
+#[tokio::test]
+async fn cancelled_reads_lose_nothing() {
+    let (mut writer, reader) = tokio::io::duplex(64);
+    let mut deser = RespDeserializer::new(reader);
+
+    writer
+        .write_all(b"*2\r\n$4\r\nECHO\r\n$5\r\npe")
+        .await
+        .unwrap();
+    // gives up waiting for the rest, like a select! in which another branch won
+    let waited = tokio::time::timeout(Duration::from_millis(50), deser.deserialize()).await;
+    assert!(waited.is_err());
+
+    writer.write_all(b"ars\r\n").await.unwrap();
+    let (val, cnt) = deser.deserialize().await.unwrap();
+    assert_eq!(val, Array(vec![b_str("ECHO"), b_str("pears")]));
+    assert_eq!(cnt, 25);
 }
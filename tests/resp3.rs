@@ -0,0 +1,240 @@
+mod db_util;
+
+use redis_starter_rust::*;
+
+use async_deser::RespDeserializer;
+use clock::ManualClock;
+use db_util::{query, run, test_db, START};
+use resp::{
+    serialize_many, Protocol,
+    Value::{self, *},
+};
+use svc::Query;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::channel;
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn serialized(vals: &[Value], protocol: Protocol) -> String {
+    serialize_many(vals, protocol).unwrap().to_string().unwrap()
+}
+
+#[test]
+fn doubles_are_formatted_like_redis() {
+    let cases = [
+        (3.0, "3"),
+        (-0.0, "-0"),
+        (-2.5, "-2.5"),
+        (0.1, "0.1"),
+        (0.1 + 0.2, "0.30000000000000004"),
+        (123.456, "123.456"),
+        (0.0001, "0.0001"),
+        (1.5e-5, "0.000015"),
+        (1e-7, "1e-7"),
+        (1.2345678e-5, "1.2345678e-5"),
+        (1e18, "1000000000000000000"),
+        (1e19, "1e+19"),
+        (1.5e20, "1.5e+20"),
+        (12345678901234567890.0, "12345678901234567000"),
+        (1e300, "1e+300"),
+        (-1e-300, "-1e-300"),
+        (f64::INFINITY, "inf"),
+    ];
+    for (d, expected) in cases {
+        assert_eq!(resp::format_double(d), expected, "{d:?}");
+    }
+}
+
+#[test]
+fn resp3_types_have_resp2_fallbacks() {
+    let cases = [
+        (Null, "$-1\r\n", "_\r\n"),
+        (NullArray, "*-1\r\n", "_\r\n"),
+        (Boolean(true), ":1\r\n", "#t\r\n"),
+        (Double(1.5), "$3\r\n1.5\r\n", ",1.5\r\n"),
+        (Double(f64::NEG_INFINITY), "$4\r\n-inf\r\n", ",-inf\r\n"),
+        (Double(f64::NAN), "$3\r\nnan\r\n", ",nan\r\n"),
+        (Double(3.0), "$1\r\n3\r\n", ",3\r\n"),
+        (Boolean(false), ":0\r\n", "#f\r\n"),
+        (
+            BulkError("ERR two\r\nlines".into()),
+            "-ERR two\\r\\nlines\r\n",
+            "!14\r\nERR two\r\nlines\r\n",
+        ),
+        (
+            BigNumber("-12345678901234567890".into()),
+            "$21\r\n-12345678901234567890\r\n",
+            "(-12345678901234567890\r\n",
+        ),
+        (
+            Verbatim("txt".into(), "hi".into()),
+            "$2\r\nhi\r\n",
+            "=6\r\ntxt:hi\r\n",
+        ),
+        (
+            Map(vec![(bulk("a"), Int(1))]),
+            "*2\r\n$1\r\na\r\n:1\r\n",
+            "%1\r\n$1\r\na\r\n:1\r\n",
+        ),
+        (Set(vec![Int(1)]), "*1\r\n:1\r\n", "~1\r\n:1\r\n"),
+        (Push(vec![Int(1)]), "*1\r\n:1\r\n", ">1\r\n:1\r\n"),
+        (
+            Attribute(vec![(bulk("ttl"), Int(3))], Box::new(Int(1))),
+            ":1\r\n",
+            "|1\r\n$3\r\nttl\r\n:3\r\n:1\r\n",
+        ),
+        // nested values are downgraded too
+        (
+            Push(vec![
+                Map(vec![(Null, Set(vec![Boolean(true)]))]),
+                Double(0.5),
+            ]),
+            "*2\r\n*2\r\n$-1\r\n*1\r\n:1\r\n$3\r\n0.5\r\n",
+            ">2\r\n%1\r\n_\r\n~1\r\n#t\r\n,0.5\r\n",
+        ),
+        (
+            Array(vec![NullArray, Verbatim("txt".into(), "x".into())]),
+            "*2\r\n*-1\r\n$1\r\nx\r\n",
+            "*2\r\n_\r\n=5\r\ntxt:x\r\n",
+        ),
+    ];
+    for (val, resp2, resp3) in cases {
+        let vals = [val];
+        assert_eq!(serialized(&vals, Protocol::Resp2), resp2, "{vals:?}");
+        assert_eq!(serialized(&vals, Protocol::Resp3), resp3, "{vals:?}");
+    }
+}
+
+#[tokio::test]
+async fn resp3_types_deserialize() {
+    let vals = vec![
+        Null,
+        Boolean(false),
+        Double(-0.25),
+        BigNumber("12345678901234567890".into()),
+        Verbatim("mkd".into(), "# hi".into()),
+        SimpleError("ERR oops".into()),
+        BulkError("ERR more\r\noops".into()),
+        Map(vec![(bulk("k"), Set(vec![Int(1), bulk("x")]))]),
+        Push(vec![bulk("message"), bulk("ch"), bulk("hi")]),
+        Attribute(vec![(bulk("ttl"), Int(3))], Box::new(bulk("v"))),
+    ];
+    let bytes = serialize_many(&vals, Protocol::Resp3).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.write_all(bytes.as_bytes()).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let mut conn = RespDeserializer::new(server);
+
+    let mut n_read = 0;
+    for val in vals {
+        let (read, cnt) = conn.deserialize().await.unwrap();
+        assert_eq!(read, val);
+        n_read += cnt;
+    }
+    assert_eq!(n_read, bytes.as_bytes().len());
+}
+
+#[tokio::test]
+async fn hello_negotiates_the_protocol() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    let fields = match run(&mut db, "HELLO 3 SETNAME me").await {
+        Map(fields) => fields,
+        other => panic!("HELLO should reply with a map, got {other:?}"),
+    };
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| *key == bulk(name))
+            .map(|(_, val)| val.clone())
+    };
+    assert_eq!(field("proto"), Some(Int(3)));
+    assert_eq!(field("role"), Some(bulk("master")));
+    // without a version the protocol stays what it was
+    let hello = run(&mut db, "HELLO").await;
+    assert!(
+        matches!(&hello, Map(fields) if fields.contains(&(bulk("proto"), Int(2)))),
+        "{hello:?}"
+    );
+
+    // while no password is configured, any password is right for the default user
+    for hello in ["HELLO 3 AUTH default anything", "HELLO 3 AUTH default "] {
+        let reply = run(&mut db, hello).await;
+        assert!(
+            matches!(&reply, Map(fields) if fields.contains(&(bulk("proto"), Int(3)))),
+            "{hello}: {reply:?}"
+        );
+    }
+    assert_eq!(
+        run(&mut db, "HELLO 3 AUTH alice secret").await,
+        SimpleError("WRONGPASS invalid username-password pair or user is disabled.".into())
+    );
+    assert_eq!(
+        run(&mut db, "HELLO 3 SETNAME a\nb").await,
+        SimpleError(
+            "ERR Client names cannot contain spaces, newlines or special characters.".into()
+        )
+    );
+}
+
+#[tokio::test]
+async fn resp3_scores_come_in_pairs() {
+    let mut db = test_db(&ManualClock::new(START));
+    run(&mut db, "ZADD z 1.5 a 2 b").await;
+
+    let resp3 = Query {
+        protocol: Protocol::Resp3,
+        ..query("ZRANGE z 0 -1 WITHSCORES")
+    };
+    let (sx, _rx) = channel(1);
+    assert_eq!(
+        db.execute(&resp3, sx).await.vals,
+        vec![Array(vec![
+            Array(vec![bulk("a"), Double(1.5)]),
+            Array(vec![bulk("b"), Double(2.0)]),
+        ])]
+    );
+    // RESP2 clients keep getting a flat list
+    assert_eq!(
+        run(&mut db, "ZRANGE z 0 -1 WITHSCORES").await,
+        Array(vec![bulk("a"), bulk("1.5"), bulk("b"), bulk("2")])
+    );
+}
+
+#[tokio::test]
+async fn subscribed_resp3_clients_can_run_any_command() {
+    let mut db = test_db(&ManualClock::new(START));
+    let (push_sender, mut pushes) = pubsub::push_channel();
+    let resp3_query = |cmd: &str| Query {
+        pushes: Some(push_sender.clone()),
+        protocol: Protocol::Resp3,
+        ..query(cmd)
+    };
+    let (sx, _rx) = channel(1);
+
+    let res = db.execute(&resp3_query("SUBSCRIBE ch"), sx.clone()).await;
+    assert_eq!(
+        res.vals,
+        vec![Push(vec![bulk("subscribe"), bulk("ch"), Int(1)])]
+    );
+    let res = db.execute(&resp3_query("SET k v"), sx.clone()).await;
+    assert_eq!(res.vals, vec![Value::ok()]);
+    let res = db.execute(&resp3_query("GET k"), sx.clone()).await;
+    assert_eq!(res.vals, vec![bulk("v")]);
+    let res = db.execute(&resp3_query("PING"), sx.clone()).await;
+    assert_eq!(res.vals, vec![SimpleString("PONG".into())]);
+
+    let res = db.execute(&resp3_query("PUBLISH ch hi"), sx).await;
+    assert_eq!(res.vals, vec![Int(1)]);
+    assert_eq!(
+        pushes.try_recv().unwrap(),
+        pubsub::Pushed::Message(Push(vec![bulk("message"), bulk("ch"), bulk("hi")]))
+    );
+}
@@ -0,0 +1,377 @@
+mod db_util;
+
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use clock::ManualClock;
+use config::InstanceConfig;
+use db::Db;
+use db_util::{run, start, test_db, START};
+use resp::Value::{self, *};
+use tokio::sync::mpsc::{channel, error::TryRecvError};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+fn bulks(words: &str) -> Value {
+    Array(words.split(' ').map(bulk).collect())
+}
+
+/// An entry as XRANGE & co. reply with it, fields and values given as `f1 v1 f2 v2...`
+fn entry(id: &str, fields: &str) -> Value {
+    Array(vec![bulk(id), bulks(fields)])
+}
+
+fn err(msg: &str) -> Value {
+    SimpleError(msg.into())
+}
+
+#[tokio::test]
+async fn xadd_ids() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    assert_eq!(run(&mut db, "XADD s 1-1 f v").await, bulk("1-1"));
+    assert_eq!(run(&mut db, "XADD s 1-* f v").await, bulk("1-2"));
+    assert_eq!(run(&mut db, "XADD s 2-* f v").await, bulk("2-0"));
+    assert_eq!(run(&mut db, "XADD s 5 f v").await, bulk("5-0"));
+    let too_small =
+        err("ERR The ID specified in XADD is equal or smaller than the target stream top item");
+    assert_eq!(run(&mut db, "XADD s 5-0 f v").await, too_small);
+    assert_eq!(run(&mut db, "XADD s 4-* f v").await, too_small);
+
+    // auto ids follow the clock, or the last id if that is ahead
+    assert_eq!(
+        run(&mut db, "XADD s * f v").await,
+        bulk(&format!("{START}-0"))
+    );
+    assert_eq!(
+        run(&mut db, "XADD s * f v").await,
+        bulk(&format!("{START}-1"))
+    );
+    clock.advance(10);
+    assert_eq!(
+        run(&mut db, "XADD s * f v").await,
+        bulk(&format!("{}-0", START + 10))
+    );
+    clock.set(START);
+    assert_eq!(
+        run(&mut db, "XADD s * f v").await,
+        bulk(&format!("{}-1", START + 10))
+    );
+    assert_eq!(run(&mut db, "XLEN s").await, Int(8));
+    assert_eq!(run(&mut db, "TYPE s").await, SimpleString("stream".into()));
+
+    // the first entry of a stream may have ms 0
+    assert_eq!(run(&mut db, "XADD z 0-* f v").await, bulk("0-1"));
+    assert_eq!(
+        run(&mut db, "XADD none NOMKSTREAM * f v").await,
+        NullBulkString
+    );
+    assert_eq!(run(&mut db, "EXISTS none").await, Int(0));
+
+    run(&mut db, "SET str x").await;
+    assert_eq!(
+        run(&mut db, "XADD str * f v").await,
+        err("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn ranges_and_trimming() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    for i in 1..=5 {
+        run(&mut db, &format!("XADD s {i}-0 n {i} sq {}", i * i)).await;
+    }
+    assert_eq!(
+        run(&mut db, "XRANGE s 2 (4-0").await,
+        Array(vec![entry("2-0", "n 2 sq 4"), entry("3-0", "n 3 sq 9")])
+    );
+    assert_eq!(
+        run(&mut db, "XREVRANGE s + - COUNT 2").await,
+        Array(vec![entry("5-0", "n 5 sq 25"), entry("4-0", "n 4 sq 16")])
+    );
+    assert_eq!(run(&mut db, "XRANGE s 4 2").await, Array(vec![]));
+    assert_eq!(run(&mut db, "XRANGE s - + COUNT 0").await, NullArray);
+    assert_eq!(run(&mut db, "XRANGE nope - +").await, Array(vec![]));
+
+    assert_eq!(run(&mut db, "XTRIM s MAXLEN 3").await, Int(2));
+    assert_eq!(run(&mut db, "XTRIM s MINID ~ 5 LIMIT 1").await, Int(1));
+    assert_eq!(
+        run(&mut db, "XRANGE s - +").await,
+        Array(vec![entry("4-0", "n 4 sq 16"), entry("5-0", "n 5 sq 25")])
+    );
+    assert_eq!(run(&mut db, "XADD s MAXLEN 0 6-0 n 6").await, bulk("6-0"));
+    // streams stay when emptied, along with their last id
+    assert_eq!(run(&mut db, "XLEN s").await, Int(0));
+    assert_eq!(run(&mut db, "EXISTS s").await, Int(1));
+    assert_eq!(
+        run(&mut db, "XADD s 6-0 n 6").await,
+        err("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+    );
+}
+
+#[tokio::test]
+async fn xread() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "XADD a 1-0 f 1").await;
+    run(&mut db, "XADD a 2-0 f 2").await;
+    run(&mut db, "XADD b 3-0 g 3").await;
+    assert_eq!(
+        run(&mut db, "XREAD COUNT 1 STREAMS a b nope 0 2 0").await,
+        Array(vec![
+            Array(vec![bulk("a"), Array(vec![entry("1-0", "f 1")])]),
+            Array(vec![bulk("b"), Array(vec![entry("3-0", "g 3")])]),
+        ])
+    );
+    assert_eq!(run(&mut db, "XREAD STREAMS a 2-0").await, NullArray);
+    assert_eq!(run(&mut db, "XREAD STREAMS a $").await, NullArray);
+}
+
+#[tokio::test]
+async fn xread_blocks_until_an_entry_is_added() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "XADD a 1-0 f 1").await;
+    // `$` means entries added from now on, so both readers wait
+    let mut first = start(&mut db, "XREAD BLOCK 0 STREAMS a $").await;
+    let mut second = start(&mut db, "XREAD BLOCK 0 STREAMS b a 0 1-0").await;
+    assert_eq!(first.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    // an entry isn't taken by whoever reads it first
+    run(&mut db, "XADD a 2-0 f 2").await;
+    let read = Array(vec![Array(vec![
+        bulk("a"),
+        Array(vec![entry("2-0", "f 2")]),
+    ])]);
+    assert_eq!(first.try_recv().unwrap().vals, vec![read.clone()]);
+    assert_eq!(second.try_recv().unwrap().vals, vec![read]);
+    assert_eq!(run(&mut db, "XLEN a").await, Int(2));
+}
+
+#[tokio::test]
+async fn consumer_groups() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    assert_eq!(
+        run(&mut db, "XGROUP CREATE s g $").await,
+        err("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+    );
+    assert_eq!(
+        run(&mut db, "XGROUP CREATE s g $ MKSTREAM").await,
+        SimpleString("OK".into())
+    );
+    assert_eq!(
+        run(&mut db, "XGROUP CREATE s g 0").await,
+        err("BUSYGROUP Consumer Group name already exists")
+    );
+    for i in 1..=3 {
+        run(&mut db, &format!("XADD s {i}-0 n {i}")).await;
+    }
+
+    // every new entry goes to a single consumer
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP g alice COUNT 2 STREAMS s >").await,
+        Array(vec![Array(vec![
+            bulk("s"),
+            Array(vec![entry("1-0", "n 1"), entry("2-0", "n 2")]),
+        ])])
+    );
+    clock.advance(100);
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP g bob STREAMS s >").await,
+        Array(vec![Array(vec![
+            bulk("s"),
+            Array(vec![entry("3-0", "n 3")])
+        ])])
+    );
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP g bob STREAMS s >").await,
+        NullArray
+    );
+
+    assert_eq!(
+        run(&mut db, "XPENDING s g").await,
+        Array(vec![
+            Int(3),
+            bulk("1-0"),
+            bulk("3-0"),
+            Array(vec![bulks("alice 2"), bulks("bob 1")]),
+        ])
+    );
+    clock.advance(50);
+    assert_eq!(
+        run(&mut db, "XPENDING s g IDLE 100 - + 10").await,
+        Array(vec![
+            Array(vec![bulk("1-0"), bulk("alice"), Int(150), Int(1)]),
+            Array(vec![bulk("2-0"), bulk("alice"), Int(150), Int(1)]),
+        ])
+    );
+
+    // reading from an id goes over the consumer's own pending entries, counting a delivery
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP g alice STREAMS s 1-0").await,
+        Array(vec![Array(vec![
+            bulk("s"),
+            Array(vec![entry("2-0", "n 2")])
+        ])])
+    );
+    assert_eq!(
+        run(&mut db, "XPENDING s g - + 10 alice").await,
+        Array(vec![
+            Array(vec![bulk("1-0"), bulk("alice"), Int(150), Int(1)]),
+            Array(vec![bulk("2-0"), bulk("alice"), Int(0), Int(2)]),
+        ])
+    );
+
+    assert_eq!(run(&mut db, "XACK s g 1-0 2-0 9-0").await, Int(2));
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP g alice STREAMS s 0").await,
+        Array(vec![Array(vec![bulk("s"), Array(vec![])])])
+    );
+    assert_eq!(
+        run(&mut db, "XGROUP CREATECONSUMER s g alice").await,
+        Int(0)
+    );
+    assert_eq!(run(&mut db, "XGROUP DELCONSUMER s g bob").await, Int(1));
+    assert_eq!(
+        run(&mut db, "XPENDING s g").await,
+        Array(vec![Int(0), NullBulkString, NullBulkString, NullArray])
+    );
+
+    assert_eq!(
+        run(&mut db, "XREADGROUP GROUP nope c STREAMS s >").await,
+        err("NOGROUP No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option")
+    );
+    assert_eq!(run(&mut db, "XGROUP DESTROY s g").await, Int(1));
+    assert_eq!(run(&mut db, "XACK s g 3-0").await, Int(0));
+}
+
+#[tokio::test]
+async fn claiming_pending_entries() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    for i in 1..=4 {
+        run(&mut db, &format!("XADD s {i}-0 n {i}")).await;
+    }
+    run(&mut db, "XGROUP CREATE s g 0").await;
+    run(&mut db, "XREADGROUP GROUP g alice STREAMS s >").await;
+    clock.advance(1000);
+
+    // too recent for a min idle time of 2s
+    assert_eq!(run(&mut db, "XCLAIM s g bob 2000 1-0").await, Array(vec![]));
+    assert_eq!(
+        run(&mut db, "XCLAIM s g bob 500 1-0 2-0 JUSTID").await,
+        bulks("1-0 2-0")
+    );
+    assert_eq!(
+        run(&mut db, "XPENDING s g - + 10 bob").await,
+        Array(vec![
+            Array(vec![bulk("1-0"), bulk("bob"), Int(0), Int(1)]),
+            Array(vec![bulk("2-0"), bulk("bob"), Int(0), Int(1)]),
+        ])
+    );
+
+    // entries deleted meanwhile are dropped from the pending list
+    run(&mut db, "XTRIM s MINID 2").await;
+    assert_eq!(
+        run(&mut db, "XCLAIM s g carol 0 1-0 2-0").await,
+        Array(vec![entry("2-0", "n 2")])
+    );
+    clock.advance(10);
+    assert_eq!(
+        run(&mut db, "XAUTOCLAIM s g dave 5 0 COUNT 2").await,
+        Array(vec![
+            bulk("4-0"),
+            Array(vec![entry("2-0", "n 2"), entry("3-0", "n 3")]),
+            Array(vec![]),
+        ])
+    );
+    run(&mut db, "XTRIM s MAXLEN 0").await;
+    assert_eq!(
+        run(&mut db, "XAUTOCLAIM s g dave 0 (3-0").await,
+        Array(vec![bulk("0-0"), Array(vec![]), bulks("4-0")])
+    );
+    assert_eq!(
+        run(&mut db, "XPENDING s g").await,
+        Array(vec![
+            Int(2),
+            bulk("2-0"),
+            bulk("3-0"),
+            Array(vec![bulks("dave 2")]),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn xreadgroup_blocks_until_an_entry_is_added() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "XGROUP CREATE s g $ MKSTREAM").await;
+    let mut first = start(&mut db, "XREADGROUP GROUP g alice BLOCK 0 STREAMS s >").await;
+    let mut second = start(&mut db, "XREADGROUP GROUP g bob BLOCK 0 STREAMS s >").await;
+
+    // unlike XREAD, an entry goes to just one of the readers
+    run(&mut db, "XADD s 1-0 f 1").await;
+    assert_eq!(
+        first.try_recv().unwrap().vals,
+        vec![Array(vec![Array(vec![
+            bulk("s"),
+            Array(vec![entry("1-0", "f 1")]),
+        ])])]
+    );
+    assert_eq!(second.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    // the reader is woken up with an error if its group goes away
+    run(&mut db, "XGROUP DESTROY s g").await;
+    assert_eq!(
+        second.try_recv().unwrap().vals,
+        vec![err(
+            "NOGROUP No such key 's' or consumer group 'g' in XREADGROUP with GROUP option"
+        )]
+    );
+}
+
+#[tokio::test]
+async fn consumers_created_by_claims_are_propagated() {
+    let dir = std::env::temp_dir().join(format!("streams-{pid}", pid = std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cfg = InstanceConfig {
+        dir: dir.to_str().unwrap().to_string(),
+        appendonly: true,
+        ..InstanceConfig::default()
+    };
+    let clock = ManualClock::new(START);
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg.clone(), tx, Arc::new(clock.clone()));
+    db.load_aof().await.unwrap();
+
+    run(&mut db, "XADD s 1-0 n 1").await;
+    run(&mut db, "XGROUP CREATE s g 0").await;
+    // XCLAIM only creates the consumer if it claims something, XAUTOCLAIM always does
+    assert_eq!(run(&mut db, "XCLAIM s g frank 0 1-0").await, Array(vec![]));
+    assert_eq!(
+        run(&mut db, "XAUTOCLAIM s g erin 0 0").await,
+        Array(vec![bulk("0-0"), Array(vec![]), Array(vec![])])
+    );
+    drop(db);
+
+    // so does what replays the aof, as a replica would
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg, tx, Arc::new(clock.clone()));
+    db.load_aof().await.unwrap();
+    assert_eq!(run(&mut db, "XGROUP CREATECONSUMER s g erin").await, Int(0));
+    assert_eq!(
+        run(&mut db, "XGROUP CREATECONSUMER s g frank").await,
+        Int(1)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
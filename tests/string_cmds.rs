@@ -0,0 +1,134 @@
+mod db_util;
+
+use redis_starter_rust::*;
+
+use clock::ManualClock;
+use db_util::{run, test_db, START};
+use resp::Value::{self, *};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+#[tokio::test]
+async fn incr_family() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "INCR n").await, Int(1));
+    assert_eq!(run(&mut db, "INCRBY n 10").await, Int(11));
+    assert_eq!(run(&mut db, "DECRBY n 20").await, Int(-9));
+    assert_eq!(run(&mut db, "DECR n").await, Int(-10));
+    assert_eq!(run(&mut db, "GET n").await, bulk("-10"));
+
+    run(&mut db, "SET n 9223372036854775807").await;
+    assert_eq!(
+        run(&mut db, "INCR n").await,
+        SimpleError("ERR increment or decrement would overflow".into())
+    );
+    // only what INCR would have written back, like redis' string2ll
+    for val in ["9223372036854775808", "+1", "1.5", "", "007", "-0", "abc"] {
+        run(&mut db, &format!("SET s {val}")).await;
+        assert_eq!(
+            run(&mut db, "INCR s").await,
+            SimpleError("ERR value is not an integer or out of range".into()),
+            "value: {val}"
+        );
+    }
+
+    assert_eq!(run(&mut db, "INCRBYFLOAT f 10.5").await, bulk("10.5"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT f 0.1").await, bulk("10.6"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT f -5.6").await, bulk("5"));
+    // long doubles like in redis, printed with up to 17 decimals and never an exponent
+    assert_eq!(
+        run(&mut db, "INCRBYFLOAT f 1e20").await,
+        bulk("100000000000000000008")
+    );
+    assert_eq!(run(&mut db, "INCRBYFLOAT f -1e20").await, bulk("8"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT g 0.1").await, bulk("0.1"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT g 0.2").await, bulk("0.3"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT h 1.5e-5").await, bulk("0.000015"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT i 5.0e3").await, bulk("5000"));
+    assert_eq!(run(&mut db, "INCRBYFLOAT i 2.0e2").await, bulk("5200"));
+    assert_eq!(
+        run(&mut db, "INCRBYFLOAT s 1").await,
+        SimpleError("ERR value is not a valid float".into())
+    );
+}
+
+#[tokio::test]
+async fn incr_keeps_ttl() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    run(&mut db, "SET n 1 PX 1000").await;
+    run(&mut db, "INCR n").await;
+    run(&mut db, "APPEND n 0").await;
+    assert_eq!(run(&mut db, "PTTL n").await, Int(1000));
+    assert_eq!(run(&mut db, "GET n").await, bulk("20"));
+    clock.advance(1000);
+    assert_eq!(run(&mut db, "INCR n").await, Int(1));
+    assert_eq!(run(&mut db, "PTTL n").await, Int(-1));
+}
+
+#[tokio::test]
+async fn ranges_and_append() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "APPEND s Hello").await, Int(5));
+    assert_eq!(run(&mut db, "APPEND s World").await, Int(10));
+    assert_eq!(run(&mut db, "STRLEN s").await, Int(10));
+    assert_eq!(run(&mut db, "STRLEN nope").await, Int(0));
+
+    assert_eq!(run(&mut db, "GETRANGE s 0 4").await, bulk("Hello"));
+    assert_eq!(run(&mut db, "GETRANGE s -5 -1").await, bulk("World"));
+    assert_eq!(run(&mut db, "GETRANGE s 5 100").await, bulk("World"));
+    assert_eq!(run(&mut db, "GETRANGE s 6 2").await, bulk(""));
+    assert_eq!(run(&mut db, "GETRANGE nope 0 -1").await, bulk(""));
+
+    assert_eq!(run(&mut db, "SETRANGE s 5 Redis").await, Int(10));
+    assert_eq!(run(&mut db, "GET s").await, bulk("HelloRedis"));
+    assert_eq!(run(&mut db, "SETRANGE pad 3 x").await, Int(4));
+    assert_eq!(
+        run(&mut db, "GET pad").await,
+        BulkString(b"\0\0\0x"[..].into())
+    );
+    // an empty value does not create the key
+    assert_eq!(run(&mut db, "SETRANGE empty 3 ").await, Int(0));
+    assert_eq!(run(&mut db, "EXISTS empty").await, Int(0));
+}
+
+#[tokio::test]
+async fn getdel_getex() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+
+    run(&mut db, "SET k v").await;
+    assert_eq!(run(&mut db, "GETEX k EX 10").await, bulk("v"));
+    assert_eq!(run(&mut db, "TTL k").await, Int(10));
+    assert_eq!(run(&mut db, "GETEX k").await, bulk("v"));
+    assert_eq!(run(&mut db, "TTL k").await, Int(10));
+    assert_eq!(run(&mut db, "GETEX k PERSIST").await, bulk("v"));
+    assert_eq!(run(&mut db, "TTL k").await, Int(-1));
+    assert_eq!(run(&mut db, "GETEX nope PX 10").await, NullBulkString);
+
+    assert_eq!(run(&mut db, "GETDEL k").await, bulk("v"));
+    assert_eq!(run(&mut db, "GETDEL k").await, NullBulkString);
+}
+
+#[tokio::test]
+async fn multi_key_commands() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    assert_eq!(run(&mut db, "MSET a 1 b 2").await, Value::ok());
+    assert_eq!(
+        run(&mut db, "MGET a nope b").await,
+        Array(vec![bulk("1"), NullBulkString, bulk("2")])
+    );
+    assert_eq!(run(&mut db, "MSETNX b 3 c 3").await, Int(0));
+    assert_eq!(run(&mut db, "EXISTS c").await, Int(0));
+    assert_eq!(run(&mut db, "MSETNX c 3 d 4").await, Int(1));
+    assert_eq!(
+        run(&mut db, "MGET b c d").await,
+        Array(vec![bulk("2"), bulk("3"), bulk("4")])
+    );
+}
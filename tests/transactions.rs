@@ -0,0 +1,204 @@
+mod db_util;
+
+use std::sync::Arc;
+
+use redis_starter_rust::*;
+
+use aof::read_aof_file;
+use clock::ManualClock;
+use commands::Command;
+use config::InstanceConfig;
+use db::Db;
+use db_util::{query, run, start, test_db, START};
+use resp::Value::{self, *};
+use svc::{Query, Transaction};
+use tokio::sync::mpsc::{channel, error::TryRecvError};
+
+fn bulk(s: &str) -> Value {
+    BulkString(s.into())
+}
+
+/// MULTI, the given commands, EXEC
+fn transaction(cmds: &[&str]) -> Transaction {
+    Transaction {
+        queued: cmds.iter().map(|cmd| query(cmd)).collect(),
+        ..Transaction::default()
+    }
+}
+
+async fn exec(db: &mut Db, multi: &Transaction) -> Value {
+    let mut vals = db.execute_transaction(&query("EXEC"), multi).await.vals;
+    assert_eq!(vals.len(), 1, "replies to EXEC: {vals:?}");
+    vals.remove(0)
+}
+
+#[tokio::test]
+async fn exec_runs_queued_commands() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    run(&mut db, "SET s x").await;
+    // a command failing doesn't stop the others
+    let multi = transaction(&["SET n 1", "INCR n", "INCR s", "GET n"]);
+    assert_eq!(
+        exec(&mut db, &multi).await,
+        Array(vec![
+            SimpleString("OK".into()),
+            Int(2),
+            SimpleError("ERR value is not an integer or out of range".into()),
+            bulk("2"),
+        ])
+    );
+
+    let multi = Transaction {
+        has_errors: true,
+        ..transaction(&["SET n 10"])
+    };
+    assert_eq!(
+        exec(&mut db, &multi).await,
+        SimpleError("EXECABORT Transaction discarded because of previous errors.".into())
+    );
+    assert_eq!(run(&mut db, "GET n").await, bulk("2"));
+}
+
+#[tokio::test]
+async fn watched_keys_that_change_abort_exec() {
+    let clock = ManualClock::new(START);
+    let mut db = test_db(&clock);
+    let multi = transaction(&["INCR n"]);
+
+    run(&mut db, "WATCH n other").await;
+    assert_eq!(exec(&mut db, &multi).await, Array(vec![Int(1)]));
+
+    // EXEC unwatches, so this change goes unnoticed
+    run(&mut db, "SET n 5").await;
+    assert_eq!(exec(&mut db, &multi).await, Array(vec![Int(6)]));
+
+    run(&mut db, "WATCH n").await;
+    run(&mut db, "DEL n").await;
+    run(&mut db, "SET n 5").await;
+    assert_eq!(exec(&mut db, &multi).await, NullArray);
+    assert_eq!(run(&mut db, "GET n").await, bulk("5"));
+
+    // writes that change nothing don't count
+    run(&mut db, "SADD set a").await;
+    run(&mut db, "WATCH set").await;
+    run(&mut db, "SADD set a").await;
+    assert_eq!(exec(&mut db, &multi).await, Array(vec![Int(6)]));
+
+    // neither does anything after UNWATCH
+    run(&mut db, "WATCH n").await;
+    run(&mut db, "UNWATCH").await;
+    run(&mut db, "INCR n").await;
+    assert_eq!(exec(&mut db, &multi).await, Array(vec![Int(8)]));
+
+    // keys expiring count as changed
+    run(&mut db, "PEXPIRE n 100").await;
+    run(&mut db, "WATCH n").await;
+    clock.advance(100);
+    assert_eq!(exec(&mut db, &multi).await, NullArray);
+    assert_eq!(run(&mut db, "EXISTS n").await, Int(0));
+}
+
+#[tokio::test]
+async fn blocked_clients_only_see_the_end_result() {
+    let mut db = test_db(&ManualClock::new(START));
+
+    let mut blocked = start(&mut db, "BLPOP q 0").await;
+    // nothing blocks inside a transaction either
+    let multi = transaction(&["RPUSH q a b", "LPOP q", "BLPOP empty 0"]);
+    assert_eq!(
+        exec(&mut db, &multi).await,
+        Array(vec![Int(2), bulk("a"), NullArray])
+    );
+    assert_eq!(
+        blocked.try_recv().unwrap().vals,
+        vec![Array(vec![bulk("q"), bulk("b")])]
+    );
+
+    let mut blocked = start(&mut db, "BLPOP q 0").await;
+    exec(&mut db, &transaction(&["RPUSH q a", "LPOP q"])).await;
+    assert_eq!(blocked.try_recv().unwrap_err(), TryRecvError::Empty);
+}
+
+#[tokio::test]
+async fn transactions_reach_the_aof_as_a_block() {
+    let dir = std::env::temp_dir().join(format!("transactions-{pid}", pid = std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cfg = InstanceConfig {
+        dir: dir.to_str().unwrap().to_string(),
+        appendonly: true,
+        ..InstanceConfig::default()
+    };
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg.clone(), tx, Arc::new(ManualClock::new(START)));
+    db.load_aof().await.unwrap();
+
+    // only reads: nothing to log
+    exec(&mut db, &transaction(&["GET a", "EXISTS b"])).await;
+    exec(&mut db, &transaction(&["GET a", "SET a 1", "SET b 2"])).await;
+    let multi = Transaction {
+        multi_byte_cnt: 15,
+        ..transaction(&["SET c 3"])
+    };
+    let exec_query = Query {
+        deser_byte_cnt: 14,
+        ..query("EXEC")
+    };
+    let res = db.execute_transaction(&exec_query, &multi).await;
+    assert_eq!(res.repl_byte_cnt_inc, 15 + 14);
+
+    let set = |k: &str, v: &str| match query(&format!("SET {k} {v}")).cmd {
+        cmd @ Command::SetKV(..) => cmd,
+        _ => unreachable!(),
+    };
+    let mut logged = vec![Command::Multi, set("a", "1"), set("b", "2"), Command::Exec];
+    logged.extend([Command::Multi, set("c", "3"), Command::Exec]);
+    let aof_path = cfg.aof_path();
+    assert_eq!(read_aof_file(&aof_path).unwrap(), logged);
+
+    // a transaction without its EXEC is not replayed
+    drop(db);
+    let mut cmds = logged.clone();
+    cmds.extend([Command::Multi, set("d", "4")]);
+    aof::write_aof_file(&aof_path, &cmds).unwrap();
+    let (tx, _rx) = channel(100);
+    let mut db = Db::with_clock(cfg, tx, Arc::new(ManualClock::new(START)));
+    assert_eq!(db.load_aof().await.unwrap(), logged.len());
+    assert_eq!(run(&mut db, "EXISTS a b c d").await, Int(3));
+    // nor left in the file, where the next writes would have ended up inside it
+    run(&mut db, "SET e 5").await;
+    logged.push(set("e", "5"));
+    drop(db);
+    assert_eq!(read_aof_file(&aof_path).unwrap(), logged);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn watches_belong_to_a_connection_not_an_address() {
+    let mut db = test_db(&ManualClock::new(START));
+    // two connections over the same unix socket have the same (empty) address
+    let from = |client_id: u64, cmd: &str| Query {
+        client_id,
+        ..Query::new(query(cmd).cmd, 0, String::new())
+    };
+    let (sx, _rx) = channel(1);
+    db.execute(&from(1, "WATCH n"), sx.clone()).await;
+
+    // the other one's EXEC neither sees nor ends the first one's watch
+    run(&mut db, "SET n 1").await;
+    let multi = Transaction {
+        queued: vec![from(2, "INCR n")],
+        ..Transaction::default()
+    };
+    let vals = db.execute_transaction(&from(2, "EXEC"), &multi).await.vals;
+    assert_eq!(vals, vec![Array(vec![Int(2)])]);
+
+    let multi = Transaction {
+        queued: vec![from(1, "INCR n")],
+        ..Transaction::default()
+    };
+    let vals = db.execute_transaction(&from(1, "EXEC"), &multi).await.vals;
+    assert_eq!(vals, vec![NullArray]);
+}